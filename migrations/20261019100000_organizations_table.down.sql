@@ -0,0 +1,3 @@
+-- Remove organizations
+
+DROP TABLE organizations;
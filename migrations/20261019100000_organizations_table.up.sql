@@ -0,0 +1,9 @@
+-- Create organizations table
+
+CREATE TABLE organizations (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  waitlist_cutoff_minutes INTEGER NOT NULL DEFAULT 60,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
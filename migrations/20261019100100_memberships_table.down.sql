@@ -0,0 +1,3 @@
+-- Remove memberships
+
+DROP TABLE memberships;
@@ -0,0 +1,14 @@
+-- Create memberships table, an account has a single role per organization
+
+CREATE TABLE memberships (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  account_id TEXT NOT NULL,
+  role TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  UNIQUE(organization_id, account_id),
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(account_id) REFERENCES accounts(id)
+);
@@ -0,0 +1,3 @@
+-- Remove classes
+
+DROP TABLE classes;
@@ -0,0 +1,15 @@
+-- Create classes table
+
+CREATE TABLE classes (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  starts_at TEXT NOT NULL,
+  ends_at TEXT NOT NULL,
+  capacity INTEGER NOT NULL,
+  cancelled_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
@@ -0,0 +1,3 @@
+-- Remove bookings
+
+DROP TABLE bookings;
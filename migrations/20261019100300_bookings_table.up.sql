@@ -0,0 +1,16 @@
+-- Create bookings table
+
+CREATE TABLE bookings (
+  id TEXT PRIMARY KEY NOT NULL,
+  class_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  status TEXT NOT NULL,
+  cancelled_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(class_id) REFERENCES classes(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id)
+);
+
+CREATE INDEX bookings_class_id_status ON bookings(class_id, status);
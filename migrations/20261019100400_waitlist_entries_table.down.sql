@@ -0,0 +1,3 @@
+-- Remove waitlist_entries
+
+DROP TABLE waitlist_entries;
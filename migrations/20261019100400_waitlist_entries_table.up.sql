@@ -0,0 +1,18 @@
+-- Create waitlist_entries table, entries are promoted in position order
+
+CREATE TABLE waitlist_entries (
+  id TEXT PRIMARY KEY NOT NULL,
+  class_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  status TEXT NOT NULL,
+  booking_id TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(class_id) REFERENCES classes(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(booking_id) REFERENCES bookings(id)
+);
+
+CREATE INDEX waitlist_entries_class_id_status ON waitlist_entries(class_id, status, position);
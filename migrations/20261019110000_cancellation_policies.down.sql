@@ -0,0 +1,10 @@
+-- Remove penalties and cancellation policy settings
+
+DROP TABLE penalties;
+
+ALTER TABLE organizations DROP COLUMN penalty_block_days;
+ALTER TABLE organizations DROP COLUMN penalty_fee_amount;
+ALTER TABLE organizations DROP COLUMN no_show_penalty;
+ALTER TABLE organizations DROP COLUMN late_cancel_penalty;
+ALTER TABLE organizations DROP COLUMN cancellation_window_minutes;
+ALTER TABLE organizations DROP COLUMN currency;
@@ -0,0 +1,28 @@
+-- Add cancellation policy settings to organizations and create penalties table
+
+ALTER TABLE organizations ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+ALTER TABLE organizations ADD COLUMN cancellation_window_minutes INTEGER NOT NULL DEFAULT 720;
+ALTER TABLE organizations ADD COLUMN late_cancel_penalty TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE organizations ADD COLUMN no_show_penalty TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE organizations ADD COLUMN penalty_fee_amount INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE organizations ADD COLUMN penalty_block_days INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE penalties (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  booking_id TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  fee_amount INTEGER,
+  currency TEXT,
+  blocked_until TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(booking_id) REFERENCES bookings(id)
+);
+
+CREATE INDEX penalties_membership_id ON penalties(membership_id);
@@ -0,0 +1,6 @@
+-- Remove check-in tracking
+
+DROP INDEX memberships_check_in_token;
+ALTER TABLE memberships DROP COLUMN check_in_token;
+
+ALTER TABLE bookings DROP COLUMN checked_in_at;
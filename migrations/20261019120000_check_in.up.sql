@@ -0,0 +1,7 @@
+-- Track check-ins on bookings and give every membership a token to check in with
+
+ALTER TABLE bookings ADD COLUMN checked_in_at TEXT;
+
+ALTER TABLE memberships ADD COLUMN check_in_token TEXT;
+UPDATE memberships SET check_in_token = lower(hex(randomblob(16)));
+CREATE UNIQUE INDEX memberships_check_in_token ON memberships(check_in_token);
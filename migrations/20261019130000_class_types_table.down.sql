@@ -0,0 +1,7 @@
+-- Remove class_types and the bookings per day limit
+
+ALTER TABLE organizations DROP COLUMN max_bookings_per_day;
+
+ALTER TABLE classes DROP COLUMN class_type_id;
+
+DROP TABLE class_types;
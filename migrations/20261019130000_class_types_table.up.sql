@@ -0,0 +1,17 @@
+-- Create class_types table holding booking window rules, and limit bookings per day
+
+CREATE TABLE class_types (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  booking_opens_minutes_before INTEGER,
+  booking_closes_minutes_before INTEGER NOT NULL DEFAULT 0,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+ALTER TABLE classes ADD COLUMN class_type_id TEXT;
+
+ALTER TABLE organizations ADD COLUMN max_bookings_per_day INTEGER;
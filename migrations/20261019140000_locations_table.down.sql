@@ -0,0 +1,5 @@
+-- Remove locations
+
+ALTER TABLE classes DROP COLUMN location_id;
+
+DROP TABLE locations;
@@ -0,0 +1,14 @@
+-- Create locations table
+
+CREATE TABLE locations (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  address TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+ALTER TABLE classes ADD COLUMN location_id TEXT;
@@ -0,0 +1,9 @@
+-- Remove instructors and their availability
+
+ALTER TABLE organizations DROP COLUMN instructor_travel_gap_minutes;
+
+DROP INDEX classes_instructor_id;
+ALTER TABLE classes DROP COLUMN instructor_id;
+
+DROP TABLE instructor_availability;
+DROP TABLE instructors;
@@ -0,0 +1,34 @@
+-- Create instructors and their weekly availability, and the travel gap between locations
+
+CREATE TABLE instructors (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL UNIQUE,
+  bio TEXT NOT NULL DEFAULT '',
+  specialities TEXT NOT NULL DEFAULT '[]',
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id)
+);
+
+-- weekday counts from Monday = 1, minutes count from midnight UTC
+CREATE TABLE instructor_availability (
+  id TEXT PRIMARY KEY NOT NULL,
+  instructor_id TEXT NOT NULL,
+  weekday INTEGER NOT NULL,
+  start_minute INTEGER NOT NULL,
+  end_minute INTEGER NOT NULL,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(instructor_id) REFERENCES instructors(id)
+);
+
+CREATE INDEX instructor_availability_instructor_id ON instructor_availability(instructor_id);
+
+ALTER TABLE classes ADD COLUMN instructor_id TEXT;
+CREATE INDEX classes_instructor_id ON classes(instructor_id);
+
+ALTER TABLE organizations ADD COLUMN instructor_travel_gap_minutes INTEGER NOT NULL DEFAULT 30;
@@ -0,0 +1,3 @@
+-- Remove plans table
+
+DROP TABLE plans;
@@ -0,0 +1,23 @@
+-- Create plans table
+
+CREATE TABLE plans (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  description TEXT NOT NULL DEFAULT '',
+  kind TEXT NOT NULL,
+  price_amount INTEGER NOT NULL,
+  currency TEXT NOT NULL,
+  billing_interval TEXT,
+  trial_days INTEGER NOT NULL DEFAULT 0,
+  classes_per_week INTEGER,
+  class_count INTEGER,
+  validity_days INTEGER,
+  archived_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+CREATE INDEX plans_organization_id ON plans(organization_id);
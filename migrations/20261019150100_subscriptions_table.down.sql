@@ -0,0 +1,3 @@
+-- Remove subscriptions table
+
+DROP TABLE subscriptions;
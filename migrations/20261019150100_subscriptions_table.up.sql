@@ -0,0 +1,26 @@
+-- Create subscriptions table
+
+CREATE TABLE subscriptions (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  plan_id TEXT NOT NULL,
+  status TEXT NOT NULL,
+  billing_anchor_at TEXT NOT NULL,
+  periods_billed INTEGER NOT NULL,
+  current_period_start TEXT NOT NULL,
+  current_period_end TEXT NOT NULL,
+  trial_ends_at TEXT,
+  paused_at TEXT,
+  cancelled_at TEXT,
+  ended_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(plan_id) REFERENCES plans(id)
+);
+
+CREATE INDEX subscriptions_membership_id ON subscriptions(membership_id);
+CREATE INDEX subscriptions_current_period_end ON subscriptions(current_period_end);
@@ -0,0 +1,5 @@
+-- Remove the class credits ledger
+
+ALTER TABLE organizations DROP COLUMN booking_requires_entitlement;
+
+DROP TABLE credit_entries;
@@ -0,0 +1,28 @@
+-- Create the append-only class credits ledger
+
+CREATE TABLE credit_entries (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  -- positive for grants and refunds, negative for debits and expiries
+  amount INTEGER NOT NULL,
+  -- the grant a debit, refund or expiry draws from
+  grant_id TEXT,
+  plan_id TEXT,
+  booking_id TEXT,
+  expires_at TEXT,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(grant_id) REFERENCES credit_entries(id),
+  FOREIGN KEY(plan_id) REFERENCES plans(id),
+  FOREIGN KEY(booking_id) REFERENCES bookings(id)
+);
+
+CREATE INDEX credit_entries_membership_id ON credit_entries(membership_id);
+CREATE INDEX credit_entries_grant_id ON credit_entries(grant_id);
+CREATE INDEX credit_entries_booking_id ON credit_entries(booking_id);
+
+ALTER TABLE organizations ADD COLUMN booking_requires_entitlement BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+-- Remove invoices and tax rates
+
+DROP TABLE invoice_lines;
+DROP TABLE invoices;
+
+ALTER TABLE organizations DROP COLUMN last_invoice_number;
+ALTER TABLE plans DROP COLUMN tax_rate_id;
+
+DROP TABLE tax_rates;
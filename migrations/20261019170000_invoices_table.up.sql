@@ -0,0 +1,70 @@
+-- Create tax rates, invoices and their line items
+
+CREATE TABLE tax_rates (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  -- hundredths of a percent, 2000 is 20%
+  rate_basis_points INTEGER NOT NULL,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+ALTER TABLE plans ADD COLUMN tax_rate_id TEXT;
+
+-- the last invoice number handed out, bumped in the transaction creating the invoice
+ALTER TABLE organizations ADD COLUMN last_invoice_number INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE invoices (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  subscription_id TEXT,
+  number INTEGER NOT NULL,
+  status TEXT NOT NULL,
+  currency TEXT NOT NULL,
+  subtotal_amount INTEGER NOT NULL,
+  discount_amount INTEGER NOT NULL,
+  tax_amount INTEGER NOT NULL,
+  total_amount INTEGER NOT NULL,
+  period_start TEXT,
+  period_end TEXT,
+  issued_at TEXT NOT NULL,
+  paid_at TEXT,
+  voided_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(subscription_id) REFERENCES subscriptions(id),
+  UNIQUE(organization_id, number),
+  UNIQUE(subscription_id, period_start)
+);
+
+CREATE INDEX invoices_membership_id ON invoices(membership_id);
+
+CREATE TABLE invoice_lines (
+  id TEXT PRIMARY KEY NOT NULL,
+  invoice_id TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  description TEXT NOT NULL,
+  plan_id TEXT,
+  quantity INTEGER NOT NULL,
+  unit_amount INTEGER NOT NULL,
+  subtotal_amount INTEGER NOT NULL,
+  discount_amount INTEGER NOT NULL,
+  tax_rate_id TEXT,
+  tax_rate_basis_points INTEGER NOT NULL,
+  tax_amount INTEGER NOT NULL,
+  total_amount INTEGER NOT NULL,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(invoice_id) REFERENCES invoices(id),
+  FOREIGN KEY(plan_id) REFERENCES plans(id),
+  FOREIGN KEY(tax_rate_id) REFERENCES tax_rates(id)
+);
+
+CREATE INDEX invoice_lines_invoice_id ON invoice_lines(invoice_id);
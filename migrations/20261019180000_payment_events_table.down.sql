@@ -0,0 +1,6 @@
+-- Remove payment provider webhook events
+
+DROP TABLE payment_events;
+
+ALTER TABLE invoices DROP COLUMN payment_error;
+ALTER TABLE invoices DROP COLUMN charge_id;
@@ -0,0 +1,18 @@
+-- Record payment provider webhook events, and how invoices were paid
+
+ALTER TABLE invoices ADD COLUMN charge_id TEXT;
+ALTER TABLE invoices ADD COLUMN payment_error TEXT;
+
+CREATE TABLE payment_events (
+  id TEXT PRIMARY KEY NOT NULL,
+  provider TEXT NOT NULL,
+  event_id TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  invoice_id TEXT,
+  payload TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(invoice_id) REFERENCES invoices(id),
+  UNIQUE(provider, event_id)
+);
@@ -0,0 +1,10 @@
+-- Stop retrying failed invoice payments
+
+DROP TABLE payment_profiles;
+
+ALTER TABLE organizations DROP COLUMN dunning_final_action;
+
+DROP INDEX invoices_next_payment_attempt_at;
+
+ALTER TABLE invoices DROP COLUMN next_payment_attempt_at;
+ALTER TABLE invoices DROP COLUMN payment_attempts;
@@ -0,0 +1,18 @@
+-- Retry failed invoice payments, and keep members' saved payment methods
+
+ALTER TABLE invoices ADD COLUMN payment_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE invoices ADD COLUMN next_payment_attempt_at TEXT;
+
+CREATE INDEX invoices_next_payment_attempt_at ON invoices(next_payment_attempt_at);
+
+ALTER TABLE organizations ADD COLUMN dunning_final_action TEXT NOT NULL DEFAULT 'cancel';
+
+CREATE TABLE payment_profiles (
+  membership_id TEXT PRIMARY KEY NOT NULL,
+  customer_id TEXT NOT NULL,
+  payment_method_id TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(membership_id) REFERENCES memberships(id)
+);
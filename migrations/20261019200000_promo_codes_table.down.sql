@@ -0,0 +1,7 @@
+-- Remove promo codes
+
+ALTER TABLE invoice_lines DROP COLUMN promo_code_id;
+ALTER TABLE subscriptions DROP COLUMN promo_code_id;
+
+DROP TABLE promo_redemptions;
+DROP TABLE promo_codes;
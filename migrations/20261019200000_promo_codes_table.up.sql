@@ -0,0 +1,42 @@
+-- Create promo codes, their redemptions, and record the discounts they give
+
+CREATE TABLE promo_codes (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  code TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  percent_off INTEGER,
+  amount_off INTEGER,
+  currency TEXT,
+  duration_periods INTEGER,
+  starts_at TEXT,
+  ends_at TEXT,
+  max_redemptions INTEGER,
+  max_redemptions_per_member INTEGER,
+  plan_ids TEXT NOT NULL DEFAULT '[]',
+  archived_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  UNIQUE(organization_id, code)
+);
+
+CREATE TABLE promo_redemptions (
+  id TEXT PRIMARY KEY NOT NULL,
+  promo_code_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  subscription_id TEXT,
+  invoice_id TEXT,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(promo_code_id) REFERENCES promo_codes(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(subscription_id) REFERENCES subscriptions(id),
+  FOREIGN KEY(invoice_id) REFERENCES invoices(id)
+);
+
+CREATE INDEX promo_redemptions_promo_code_id ON promo_redemptions(promo_code_id);
+
+ALTER TABLE subscriptions ADD COLUMN promo_code_id TEXT;
+ALTER TABLE invoice_lines ADD COLUMN promo_code_id TEXT;
@@ -0,0 +1,6 @@
+-- Stop freezing subscriptions between dates
+
+DROP INDEX subscriptions_freeze_starts_at;
+
+ALTER TABLE subscriptions DROP COLUMN freeze_ends_at;
+ALTER TABLE subscriptions DROP COLUMN freeze_starts_at;
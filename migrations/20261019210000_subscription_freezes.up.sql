@@ -0,0 +1,6 @@
+-- Let subscriptions be frozen between two dates, resuming on their own
+
+ALTER TABLE subscriptions ADD COLUMN freeze_starts_at TEXT;
+ALTER TABLE subscriptions ADD COLUMN freeze_ends_at TEXT;
+
+CREATE INDEX subscriptions_freeze_starts_at ON subscriptions(freeze_starts_at);
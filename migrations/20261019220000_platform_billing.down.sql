@@ -0,0 +1,5 @@
+-- Stop billing organizations for Rustfit
+
+DROP TABLE platform_usage;
+DROP TABLE platform_subscriptions;
+DROP TABLE platform_plans;
@@ -0,0 +1,68 @@
+-- Bill organizations for Rustfit itself: platform plans, subscriptions and metered usage
+
+CREATE TABLE platform_plans (
+  id TEXT PRIMARY KEY NOT NULL,
+  code TEXT NOT NULL,
+  name TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  price_amount INTEGER NOT NULL,
+  currency TEXT NOT NULL,
+  billing_interval TEXT NOT NULL,
+  max_active_members INTEGER,
+  max_locations INTEGER,
+  features TEXT NOT NULL DEFAULT '[]',
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  UNIQUE(code)
+);
+
+INSERT INTO platform_plans (
+  id, code, name, position, price_amount, currency, billing_interval,
+  max_active_members, max_locations, features, inserted_at, updated_at
+) VALUES
+  (X'0192a4e0a1b270008000000000000001', 'starter', 'Starter', 1, 0, 'USD', 'month',
+    50, 1, '[]', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+  (X'0192a4e0a1b270008000000000000002', 'studio', 'Studio', 2, 4900, 'USD', 'month',
+    500, 3, '["promo_codes","freezes"]', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+  (X'0192a4e0a1b270008000000000000003', 'pro', 'Pro', 3, 14900, 'USD', 'month',
+    NULL, NULL, '["promo_codes","freezes"]', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now'));
+
+CREATE TABLE platform_subscriptions (
+  organization_id TEXT PRIMARY KEY NOT NULL,
+  platform_plan_id TEXT NOT NULL,
+  current_period_start TEXT NOT NULL,
+  current_period_end TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(platform_plan_id) REFERENCES platform_plans(id)
+);
+
+-- Existing organizations start on the free tier.
+INSERT INTO platform_subscriptions (
+  organization_id, platform_plan_id, current_period_start, current_period_end,
+  inserted_at, updated_at
+)
+SELECT
+  id, X'0192a4e0a1b270008000000000000001',
+  strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '+1 month'),
+  strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+FROM organizations;
+
+CREATE TABLE platform_usage (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  platform_plan_id TEXT NOT NULL,
+  period_start TEXT NOT NULL,
+  period_end TEXT NOT NULL,
+  active_members INTEGER NOT NULL,
+  locations INTEGER NOT NULL,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(platform_plan_id) REFERENCES platform_plans(id),
+  UNIQUE(organization_id, period_start)
+);
@@ -0,0 +1,8 @@
+-- Remove point-of-sale products, sales and cash-drawer counts
+
+ALTER TABLE credit_entries DROP COLUMN invoice_id;
+ALTER TABLE invoice_lines DROP COLUMN product_id;
+
+DROP TABLE cash_drawer_counts;
+DROP TABLE sales;
+DROP TABLE products;
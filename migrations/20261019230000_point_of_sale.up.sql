@@ -0,0 +1,65 @@
+-- Create products sold at the front desk, the sales of them and cash-drawer counts
+
+CREATE TABLE products (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  price_amount INTEGER NOT NULL,
+  currency TEXT NOT NULL,
+  tax_rate_id TEXT,
+  stock_count INTEGER,
+  validity_days INTEGER,
+  archived_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(tax_rate_id) REFERENCES tax_rates(id),
+  CHECK(stock_count IS NULL OR stock_count >= 0)
+);
+
+CREATE INDEX products_organization_id ON products(organization_id);
+
+CREATE TABLE sales (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  invoice_id TEXT NOT NULL,
+  payment_method TEXT NOT NULL,
+  status TEXT NOT NULL,
+  total_amount INTEGER NOT NULL,
+  currency TEXT NOT NULL,
+  business_date TEXT NOT NULL,
+  sold_by_membership_id TEXT NOT NULL,
+  voided_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(invoice_id) REFERENCES invoices(id),
+  FOREIGN KEY(sold_by_membership_id) REFERENCES memberships(id)
+);
+
+CREATE INDEX sales_organization_id_business_date ON sales(organization_id, business_date);
+
+CREATE TABLE cash_drawer_counts (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  business_date TEXT NOT NULL,
+  float_amount INTEGER NOT NULL,
+  expected_amount INTEGER NOT NULL,
+  counted_amount INTEGER NOT NULL,
+  difference_amount INTEGER NOT NULL,
+  note TEXT NOT NULL,
+  counted_by_membership_id TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(counted_by_membership_id) REFERENCES memberships(id),
+  UNIQUE(organization_id, business_date)
+);
+
+ALTER TABLE invoice_lines ADD COLUMN product_id TEXT;
+ALTER TABLE credit_entries ADD COLUMN invoice_id TEXT;
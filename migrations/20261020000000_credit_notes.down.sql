@@ -0,0 +1,6 @@
+-- Remove credit notes
+
+ALTER TABLE organizations DROP COLUMN billing_refund_role;
+ALTER TABLE organizations DROP COLUMN last_credit_note_number;
+
+DROP TABLE credit_notes;
@@ -0,0 +1,27 @@
+-- Create credit notes for refunds, numbered apart from invoices, and who may issue them
+
+CREATE TABLE credit_notes (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  invoice_id TEXT NOT NULL,
+  number INTEGER NOT NULL,
+  amount INTEGER NOT NULL,
+  currency TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  refund_id TEXT UNIQUE,
+  credits_restored INTEGER NOT NULL,
+  issued_by_membership_id TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(invoice_id) REFERENCES invoices(id),
+  FOREIGN KEY(issued_by_membership_id) REFERENCES memberships(id),
+  UNIQUE(organization_id, number)
+);
+
+CREATE INDEX credit_notes_invoice_id ON credit_notes(invoice_id);
+
+ALTER TABLE organizations ADD COLUMN last_credit_note_number INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE organizations ADD COLUMN billing_refund_role TEXT NOT NULL DEFAULT 'owner';
@@ -0,0 +1,6 @@
+-- Remove gift cards and the wallet ledger
+
+DROP TABLE ledger_entries;
+DROP TABLE ledger_transactions;
+DROP TABLE gift_cards;
+DROP TABLE ledger_accounts;
@@ -0,0 +1,67 @@
+-- Create the double-entry ledger behind members' wallets, and the gift cards redeemed into them
+
+CREATE TABLE ledger_accounts (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  owner_id TEXT NOT NULL,
+  currency TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  UNIQUE(organization_id, kind, owner_id, currency)
+);
+
+CREATE TABLE gift_cards (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  code TEXT NOT NULL,
+  initial_amount INTEGER NOT NULL,
+  currency TEXT NOT NULL,
+  ledger_account_id TEXT NOT NULL,
+  expires_at TEXT,
+  issued_by_membership_id TEXT NOT NULL,
+  redeemed_by_membership_id TEXT,
+  redeemed_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(ledger_account_id) REFERENCES ledger_accounts(id),
+  FOREIGN KEY(issued_by_membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(redeemed_by_membership_id) REFERENCES memberships(id),
+  UNIQUE(organization_id, code)
+);
+
+CREATE TABLE ledger_transactions (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  currency TEXT NOT NULL,
+  membership_id TEXT,
+  gift_card_id TEXT,
+  invoice_id TEXT,
+  credit_note_id TEXT,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id),
+  FOREIGN KEY(gift_card_id) REFERENCES gift_cards(id),
+  FOREIGN KEY(invoice_id) REFERENCES invoices(id),
+  FOREIGN KEY(credit_note_id) REFERENCES credit_notes(id)
+);
+
+CREATE INDEX ledger_transactions_invoice_id ON ledger_transactions(invoice_id);
+
+CREATE TABLE ledger_entries (
+  id TEXT PRIMARY KEY NOT NULL,
+  transaction_id TEXT NOT NULL,
+  account_id TEXT NOT NULL,
+  amount INTEGER NOT NULL,
+
+  FOREIGN KEY(transaction_id) REFERENCES ledger_transactions(id),
+  FOREIGN KEY(account_id) REFERENCES ledger_accounts(id)
+);
+
+CREATE INDEX ledger_entries_account_id ON ledger_entries(account_id);
+CREATE INDEX ledger_entries_transaction_id ON ledger_entries(transaction_id);
@@ -0,0 +1,4 @@
+-- Remove the email outbox and organizations' email templates
+
+DROP TABLE emails;
+DROP TABLE email_templates;
@@ -0,0 +1,36 @@
+-- Create organizations' email templates and the outbox email is sent from
+
+CREATE TABLE email_templates (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  body TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  UNIQUE(organization_id, kind)
+);
+
+CREATE TABLE emails (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  template TEXT NOT NULL,
+  to_address TEXT NOT NULL,
+  from_name TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  body TEXT NOT NULL,
+  status TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TEXT,
+  last_error TEXT,
+  sent_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+CREATE INDEX emails_organization_id ON emails(organization_id);
+CREATE INDEX emails_status_next_attempt_at ON emails(status, next_attempt_at);
@@ -0,0 +1,5 @@
+-- Remove notifications and members' notification preferences
+
+DROP TABLE notifications;
+DROP TABLE notification_channel_preferences;
+DROP TABLE notification_preferences;
@@ -0,0 +1,49 @@
+-- Create members' notification preferences and the notifications sent to them
+
+CREATE TABLE notification_preferences (
+  membership_id TEXT PRIMARY KEY NOT NULL,
+  phone TEXT,
+  webhook_url TEXT,
+  quiet_hours_start TEXT,
+  quiet_hours_end TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(membership_id) REFERENCES memberships(id)
+);
+
+CREATE TABLE notification_channel_preferences (
+  membership_id TEXT NOT NULL,
+  event TEXT NOT NULL,
+  channel TEXT NOT NULL,
+  enabled BOOLEAN NOT NULL,
+
+  PRIMARY KEY(membership_id, event, channel),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id)
+);
+
+CREATE TABLE notifications (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  membership_id TEXT NOT NULL,
+  event TEXT NOT NULL,
+  channel TEXT NOT NULL,
+  recipient TEXT NOT NULL,
+  from_name TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  body TEXT NOT NULL,
+  data TEXT NOT NULL,
+  status TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TEXT,
+  last_error TEXT,
+  sent_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(membership_id) REFERENCES memberships(id)
+);
+
+CREATE INDEX notifications_membership_id ON notifications(membership_id);
+CREATE INDEX notifications_status_next_attempt_at ON notifications(status, next_attempt_at);
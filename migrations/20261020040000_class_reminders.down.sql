@@ -0,0 +1,5 @@
+-- Remove class reminders and the job queue
+
+ALTER TABLE organizations DROP COLUMN class_reminder_hours;
+
+DROP TABLE jobs;
@@ -0,0 +1,18 @@
+-- Create the queue background jobs are scheduled on, and reminders before booked classes
+
+CREATE TABLE jobs (
+  id TEXT PRIMARY KEY NOT NULL,
+  kind TEXT NOT NULL,
+  key TEXT UNIQUE,
+  payload TEXT NOT NULL,
+  status TEXT NOT NULL,
+  run_at TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE INDEX jobs_status_run_at ON jobs(status, run_at);
+
+ALTER TABLE organizations ADD COLUMN class_reminder_hours INTEGER DEFAULT 24;
@@ -0,0 +1,7 @@
+-- Remove job claims and the dead-letter state
+
+UPDATE jobs SET status = 'failed' WHERE status = 'dead';
+
+UPDATE jobs SET status = 'pending' WHERE status = 'running';
+
+ALTER TABLE jobs DROP COLUMN locked_until;
@@ -0,0 +1,5 @@
+-- Let workers claim jobs from the queue, and keep the jobs that ran out of attempts apart
+
+ALTER TABLE jobs ADD COLUMN locked_until TEXT;
+
+UPDATE jobs SET status = 'dead' WHERE status = 'failed';
@@ -0,0 +1,5 @@
+-- Remove webhook endpoints and their deliveries
+
+DROP TABLE webhook_deliveries;
+
+DROP TABLE webhook_endpoints;
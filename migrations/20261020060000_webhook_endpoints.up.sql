@@ -0,0 +1,41 @@
+-- Create organizations' webhook endpoints and the log of deliveries to them
+
+CREATE TABLE webhook_endpoints (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  url TEXT NOT NULL,
+  description TEXT,
+  events TEXT NOT NULL,
+  secret TEXT NOT NULL,
+  archived_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+CREATE INDEX webhook_endpoints_organization_id ON webhook_endpoints(organization_id);
+
+CREATE TABLE webhook_deliveries (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL,
+  endpoint_id TEXT NOT NULL,
+  event_id TEXT NOT NULL,
+  event TEXT NOT NULL,
+  payload TEXT NOT NULL,
+  status TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  response_status INTEGER,
+  response_body TEXT,
+  last_error TEXT,
+  replay_of TEXT,
+  delivered_at TEXT,
+  inserted_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(endpoint_id) REFERENCES webhook_endpoints(id),
+  FOREIGN KEY(replay_of) REFERENCES webhook_deliveries(id)
+);
+
+CREATE INDEX webhook_deliveries_endpoint_id ON webhook_deliveries(endpoint_id, inserted_at);
@@ -0,0 +1,3 @@
+-- Remove the event log
+
+DROP TABLE events;
@@ -0,0 +1,14 @@
+-- Create the log of everything that changed, in the order it changed
+
+CREATE TABLE events (
+  sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+  id TEXT NOT NULL UNIQUE,
+  organization_id TEXT,
+  type TEXT NOT NULL,
+  data TEXT NOT NULL,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id)
+);
+
+CREATE INDEX events_organization_id ON events(organization_id, sequence);
@@ -0,0 +1,3 @@
+-- Remove the audit log
+
+DROP TABLE audit_log_entries;
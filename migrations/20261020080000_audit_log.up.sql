@@ -0,0 +1,31 @@
+-- Create the log of what staff and owners did in their organizations
+
+CREATE TABLE audit_log_entries (
+  sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+  id TEXT NOT NULL UNIQUE,
+  organization_id TEXT NOT NULL,
+  actor_account_id TEXT NOT NULL,
+  action TEXT NOT NULL,
+  target_type TEXT NOT NULL,
+  target_id TEXT NOT NULL,
+  changes TEXT NOT NULL,
+  ip TEXT,
+  user_agent TEXT,
+  inserted_at TEXT NOT NULL,
+
+  FOREIGN KEY(organization_id) REFERENCES organizations(id),
+  FOREIGN KEY(actor_account_id) REFERENCES accounts(id)
+);
+
+CREATE INDEX audit_log_entries_organization_id ON audit_log_entries(organization_id, sequence);
+
+-- Entries are never changed or removed, not even by mistake.
+CREATE TRIGGER audit_log_entries_no_update BEFORE UPDATE ON audit_log_entries
+BEGIN
+  SELECT RAISE(ABORT, 'audit log entries cannot be changed');
+END;
+
+CREATE TRIGGER audit_log_entries_no_delete BEFORE DELETE ON audit_log_entries
+BEGIN
+  SELECT RAISE(ABORT, 'audit log entries cannot be removed');
+END;
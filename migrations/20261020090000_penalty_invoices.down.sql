@@ -0,0 +1,3 @@
+-- Stop linking fee penalties to their invoices
+
+ALTER TABLE penalties DROP COLUMN invoice_id;
@@ -0,0 +1,3 @@
+-- Bill fee penalties on an invoice of their own
+
+ALTER TABLE penalties ADD COLUMN invoice_id TEXT;
@@ -0,0 +1 @@
+-- Class times stay in UTC, the offsets they were given with aren't kept
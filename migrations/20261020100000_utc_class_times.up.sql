@@ -0,0 +1,6 @@
+-- Store class times in UTC, so they compare correctly as text
+
+UPDATE classes SET starts_at = strftime('%Y-%m-%dT%H:%M:%SZ', starts_at)
+WHERE starts_at NOT LIKE '%Z';
+UPDATE classes SET ends_at = strftime('%Y-%m-%dT%H:%M:%SZ', ends_at)
+WHERE ends_at NOT LIKE '%Z';
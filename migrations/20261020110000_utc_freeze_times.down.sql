@@ -0,0 +1 @@
+-- Freeze times stay in UTC, the offsets they were given with aren't kept
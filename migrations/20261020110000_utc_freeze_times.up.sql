@@ -0,0 +1,6 @@
+-- Store freeze times in UTC, so they compare correctly as text
+
+UPDATE subscriptions SET freeze_starts_at = strftime('%Y-%m-%dT%H:%M:%SZ', freeze_starts_at)
+WHERE freeze_starts_at NOT LIKE '%Z';
+UPDATE subscriptions SET freeze_ends_at = strftime('%Y-%m-%dT%H:%M:%SZ', freeze_ends_at)
+WHERE freeze_ends_at NOT LIKE '%Z';
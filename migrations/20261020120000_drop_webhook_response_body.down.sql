@@ -0,0 +1,3 @@
+-- The responses dropped can't be brought back
+
+ALTER TABLE webhook_deliveries ADD COLUMN response_body TEXT;
@@ -0,0 +1,3 @@
+-- Endpoints' responses aren't kept any more, only their status
+
+ALTER TABLE webhook_deliveries DROP COLUMN response_body;
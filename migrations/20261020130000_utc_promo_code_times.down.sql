@@ -0,0 +1 @@
+-- Promo code times stay in UTC, the offsets they were given with aren't kept
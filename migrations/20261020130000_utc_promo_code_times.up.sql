@@ -0,0 +1,6 @@
+-- Store promo code start and end times in UTC, like every other time
+
+UPDATE promo_codes SET starts_at = strftime('%Y-%m-%dT%H:%M:%SZ', starts_at)
+WHERE starts_at NOT LIKE '%Z';
+UPDATE promo_codes SET ends_at = strftime('%Y-%m-%dT%H:%M:%SZ', ends_at)
+WHERE ends_at NOT LIKE '%Z';
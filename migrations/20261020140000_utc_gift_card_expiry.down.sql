@@ -0,0 +1 @@
+-- Gift card expiry times stay in UTC, the offsets they were given with aren't kept
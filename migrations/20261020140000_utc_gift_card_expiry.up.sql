@@ -0,0 +1,4 @@
+-- Store gift card expiry times in UTC, like every other time
+
+UPDATE gift_cards SET expires_at = strftime('%Y-%m-%dT%H:%M:%SZ', expires_at)
+WHERE expires_at NOT LIKE '%Z';
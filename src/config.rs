@@ -7,4 +7,87 @@ pub struct Config {
 
     #[clap(long, env)]
     pub port: u16,
+
+    /// Which payment provider to charge members through. Required, so a deployment can't
+    /// end up on the `fake` gateway by leaving it out.
+    #[clap(long, env, value_enum)]
+    pub payment_gateway: PaymentGatewayKind,
+
+    /// Base URL of the Stripe-compatible API used by the `stripe` payment gateway, e.g.
+    /// `https://api.stripe.com`, or `stripe-mock` over `http` in development.
+    #[clap(long, env, default_value = "http://localhost:12111")]
+    pub stripe_api_url: String,
+
+    /// Secret key for the Stripe-compatible API.
+    #[clap(long, env)]
+    pub stripe_api_key: Option<String>,
+
+    /// Secret the payment provider signs its webhook events with. Events are refused
+    /// while this isn't set.
+    #[clap(long, env)]
+    pub payment_webhook_secret: Option<String>,
+
+    /// Internal consumers such as analytics read the event feed with this as their token.
+    /// The feed is closed while this isn't set.
+    #[clap(long, env)]
+    pub events_api_key: Option<String>,
+
+    /// How often recurring background jobs such as renewals and payment retries run.
+    #[clap(long, env, default_value_t = 60)]
+    pub job_interval_seconds: u64,
+
+    /// How many jobs from the queue run at the same time.
+    #[clap(long, env, default_value_t = 4)]
+    pub job_workers: usize,
+
+    /// How email is sent.
+    #[clap(long, env, value_enum, default_value_t)]
+    pub mailer: MailerKind,
+
+    /// Directory the `file` mailer writes messages to.
+    #[clap(long, env, default_value = "mail")]
+    pub mail_dir: String,
+
+    /// SMTP server the `smtp` mailer hands messages to, e.g. Mailpit in development.
+    ///
+    /// The `smtp` mailer speaks plain SMTP without TLS or authentication, so a real provider
+    /// has to be reached through a relay on the same host or network, e.g. Postfix set up
+    /// with the provider's credentials.
+    #[clap(long, env, default_value = "localhost")]
+    pub smtp_host: String,
+
+    #[clap(long, env, default_value_t = 1025)]
+    pub smtp_port: u16,
+
+    /// Address email is sent from. Organizations' names are shown as the sender.
+    #[clap(long, env, default_value = "no-reply@rustfit.localhost")]
+    pub mail_from: String,
+
+    /// HTTPS API that text messages are posted to as `{"to": .., "body": ..}`. Text messages
+    /// are logged instead while this isn't set.
+    #[clap(long, env)]
+    pub sms_gateway_url: Option<String>,
+
+    /// Sent to the SMS gateway as a bearer token.
+    #[clap(long, env)]
+    pub sms_api_key: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+#[cfg_attr(test, derive(Default))]
+pub enum PaymentGatewayKind {
+    /// An in-memory gateway for development, nothing is actually charged.
+    #[cfg_attr(test, default)]
+    Fake,
+    Stripe,
+}
+
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum MailerKind {
+    /// Logs email instead of sending it, for development.
+    #[default]
+    Log,
+    /// Writes email to `.eml` files in `mail_dir`, for development.
+    File,
+    Smtp,
 }
@@ -1,5 +1,9 @@
 use crate::config::Config;
+use crate::integrations::WebhookClient;
+use crate::mail::DynMailer;
 use crate::models::DynStore;
+use crate::notify::Notifiers;
+use crate::payments::DynPaymentGateway;
 use std::sync::Arc;
 /// The core type through which handler functions can access common API state.
 /// This can be accessed by adding a parameter `State<ApiContext>` to a handler function's
@@ -17,4 +21,8 @@ use std::sync::Arc;
 pub struct ApiContext {
     pub config: Arc<Config>,
     pub store: DynStore,
+    pub payments: DynPaymentGateway,
+    pub mailer: DynMailer,
+    pub notifiers: Notifiers,
+    pub webhooks: WebhookClient,
 }
@@ -0,0 +1,42 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::audit::{AuditLogEntryDTO, AuditLogQuery};
+use crate::models::membership::Role;
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route(
+        "/api/organizations/:organization_id/audit-log",
+        get(list_audit_log_entries),
+    )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AuditLogBody<T> {
+    audit_log_entries: Vec<T>,
+}
+
+/// What staff did in the organization, e.g.
+/// `GET /api/organizations/:id/audit-log?action=booking.removed&before=310`. Owners only.
+async fn list_audit_log_entries(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Json<AuditLogBody<AuditLogEntryDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let audit_log_entries = ctx
+        .store
+        .audit()
+        .list_entries(organization_id, query)
+        .await?;
+
+    Ok(Json(AuditLogBody { audit_log_entries }))
+}
@@ -0,0 +1,325 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::booking::{BookingDTO, RosterEntryDTO};
+use crate::models::membership::{MembershipDTO, Role};
+use crate::models::penalty::PenaltyDTO;
+use crate::models::waitlist::WaitlistEntryDTO;
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/classes/:class_id/bookings",
+            get(list_bookings).post(create_booking),
+        )
+        .route("/api/bookings/:booking_id/cancel", post(cancel_booking))
+        .route("/api/bookings/:booking_id/no-show", post(mark_no_show))
+        .route("/api/bookings/:booking_id/check-in", post(check_in_booking))
+        .route("/api/bookings/:booking_id/remove", post(remove_booking))
+        .route("/api/classes/:class_id/roster", get(list_roster))
+        .route("/api/classes/:class_id/check-in", post(check_in_member))
+        .route(
+            "/api/classes/:class_id/waitlist",
+            get(list_waitlist).post(join_waitlist),
+        )
+        .route(
+            "/api/waitlist/:waitlist_entry_id/leave",
+            post(leave_waitlist),
+        )
+        .route(
+            "/api/memberships/:membership_id/history",
+            get(get_member_history),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BookingBody<T> {
+    booking: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BookingsBody<T> {
+    bookings: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RosterBody<T> {
+    roster: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CheckInBody<T> {
+    check_in: T,
+}
+
+#[derive(serde::Deserialize)]
+struct CheckIn {
+    check_in_token: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HistoryBody<T> {
+    history: T,
+}
+
+#[derive(serde::Serialize)]
+struct MemberHistory {
+    bookings: Vec<BookingDTO>,
+    penalties: Vec<PenaltyDTO>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WaitlistEntryBody<T> {
+    waitlist_entry: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WaitlistBody<T> {
+    waitlist: Vec<T>,
+}
+
+/// Looks up the class's organization and checks the account holds at least `role` in it.
+async fn require_class_role(
+    ctx: &ApiContext,
+    class_id: Uuid,
+    account_id: Uuid,
+    role: Role,
+) -> Result<MembershipDTO> {
+    let class = ctx.store.class().get_class(class_id).await?;
+
+    ctx.store
+        .membership()
+        .require_role(class.organization_id, account_id, role)
+        .await
+}
+
+async fn create_booking(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+) -> Result<Json<BookingBody<BookingDTO>>> {
+    let membership =
+        require_class_role(&ctx, class_id, auth_account.account_id, Role::Member).await?;
+
+    let booking = ctx
+        .store
+        .booking()
+        .create_booking(class_id, membership.id)
+        .await?;
+
+    Ok(Json(BookingBody { booking }))
+}
+
+async fn list_bookings(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+) -> Result<Json<BookingsBody<BookingDTO>>> {
+    require_class_role(&ctx, class_id, auth_account.account_id, Role::Staff).await?;
+
+    let bookings = ctx.store.booking().list_bookings(class_id).await?;
+
+    Ok(Json(BookingsBody { bookings }))
+}
+
+async fn cancel_booking(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(booking_id): Path<Uuid>,
+) -> Result<Json<BookingBody<BookingDTO>>> {
+    let booking = ctx.store.booking().get_booking(booking_id).await?;
+    let class = ctx.store.class().get_class(booking.class_id).await?;
+    let membership = ctx
+        .store
+        .membership()
+        .require_self_or_staff(
+            class.organization_id,
+            auth_account.account_id,
+            booking.membership_id,
+        )
+        .await?;
+
+    // Only members cancelling their own booking are held to the cancellation policy, staff
+    // cancelling for them are audited instead.
+    let actor = auth_account.actor();
+    let staff = (membership.id != booking.membership_id).then_some(&actor);
+
+    let booking = ctx
+        .store
+        .booking()
+        .cancel_booking(booking_id, staff)
+        .await?;
+
+    Ok(Json(BookingBody { booking }))
+}
+
+async fn mark_no_show(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(booking_id): Path<Uuid>,
+) -> Result<Json<BookingBody<BookingDTO>>> {
+    let booking = ctx.store.booking().get_booking(booking_id).await?;
+    require_class_role(&ctx, booking.class_id, auth_account.account_id, Role::Staff).await?;
+
+    let booking = ctx
+        .store
+        .booking()
+        .mark_no_show(booking_id, Some(&auth_account.actor()))
+        .await?;
+
+    Ok(Json(BookingBody { booking }))
+}
+
+async fn check_in_booking(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(booking_id): Path<Uuid>,
+) -> Result<Json<BookingBody<BookingDTO>>> {
+    let booking = ctx.store.booking().get_booking(booking_id).await?;
+    require_class_role(&ctx, booking.class_id, auth_account.account_id, Role::Staff).await?;
+
+    let booking = ctx
+        .store
+        .booking()
+        .check_in_booking(booking_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(BookingBody { booking }))
+}
+
+async fn check_in_member(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+    Json(req): Json<CheckInBody<CheckIn>>,
+) -> Result<Json<BookingBody<BookingDTO>>> {
+    require_class_role(&ctx, class_id, auth_account.account_id, Role::Staff).await?;
+
+    let booking = ctx
+        .store
+        .booking()
+        .check_in_member(class_id, req.check_in.check_in_token, &auth_account.actor())
+        .await?;
+
+    Ok(Json(BookingBody { booking }))
+}
+
+async fn remove_booking(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(booking_id): Path<Uuid>,
+) -> Result<Json<BookingBody<BookingDTO>>> {
+    let booking = ctx.store.booking().get_booking(booking_id).await?;
+    require_class_role(&ctx, booking.class_id, auth_account.account_id, Role::Staff).await?;
+
+    let booking = ctx
+        .store
+        .booking()
+        .remove_booking(booking_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(BookingBody { booking }))
+}
+
+async fn list_roster(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+) -> Result<Json<RosterBody<RosterEntryDTO>>> {
+    require_class_role(&ctx, class_id, auth_account.account_id, Role::Staff).await?;
+
+    let roster = ctx.store.booking().list_roster(class_id).await?;
+
+    Ok(Json(RosterBody { roster }))
+}
+
+async fn get_member_history(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<HistoryBody<MemberHistory>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let bookings = ctx
+        .store
+        .booking()
+        .list_member_bookings(membership_id)
+        .await?;
+    let penalties = ctx.store.penalty().list_penalties(membership_id).await?;
+
+    Ok(Json(HistoryBody {
+        history: MemberHistory {
+            bookings,
+            penalties,
+        },
+    }))
+}
+
+async fn join_waitlist(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+) -> Result<Json<WaitlistEntryBody<WaitlistEntryDTO>>> {
+    let membership =
+        require_class_role(&ctx, class_id, auth_account.account_id, Role::Member).await?;
+
+    let waitlist_entry = ctx
+        .store
+        .waitlist()
+        .join_waitlist(class_id, membership.id)
+        .await?;
+
+    Ok(Json(WaitlistEntryBody { waitlist_entry }))
+}
+
+async fn list_waitlist(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+) -> Result<Json<WaitlistBody<WaitlistEntryDTO>>> {
+    require_class_role(&ctx, class_id, auth_account.account_id, Role::Member).await?;
+
+    let waitlist = ctx.store.waitlist().list_waitlist(class_id).await?;
+
+    Ok(Json(WaitlistBody { waitlist }))
+}
+
+async fn leave_waitlist(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(waitlist_entry_id): Path<Uuid>,
+) -> Result<Json<WaitlistEntryBody<WaitlistEntryDTO>>> {
+    let waitlist_entry = ctx
+        .store
+        .waitlist()
+        .get_waitlist_entry(waitlist_entry_id)
+        .await?;
+    let class = ctx.store.class().get_class(waitlist_entry.class_id).await?;
+    let membership = ctx
+        .store
+        .membership()
+        .require_self_or_staff(
+            class.organization_id,
+            auth_account.account_id,
+            waitlist_entry.membership_id,
+        )
+        .await?;
+
+    let actor = auth_account.actor();
+    let staff = (membership.id != waitlist_entry.membership_id).then_some(&actor);
+
+    let waitlist_entry = ctx
+        .store
+        .waitlist()
+        .leave_waitlist(waitlist_entry_id, staff)
+        .await?;
+
+    Ok(Json(WaitlistEntryBody { waitlist_entry }))
+}
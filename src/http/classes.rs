@@ -0,0 +1,213 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::class::{ClassDTO, NewClass};
+use crate::models::class_type::{ClassTypeDTO, NewClassType, UpdateClassType};
+use crate::models::location::{LocationDTO, NewLocation};
+use crate::models::membership::Role;
+use axum::extract::{Path, State};
+use axum::routing::{get, patch, post};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/classes",
+            get(list_classes).post(create_class),
+        )
+        .route("/api/classes/:class_id/cancel", post(cancel_class))
+        .route(
+            "/api/organizations/:organization_id/class-types",
+            get(list_class_types).post(create_class_type),
+        )
+        .route("/api/class-types/:class_type_id", patch(update_class_type))
+        .route(
+            "/api/organizations/:organization_id/locations",
+            get(list_locations).post(create_location),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClassBody<T> {
+    class: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClassesBody<T> {
+    classes: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClassTypeBody<T> {
+    class_type: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClassTypesBody<T> {
+    class_types: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LocationBody<T> {
+    location: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LocationsBody<T> {
+    locations: Vec<T>,
+}
+
+async fn create_class(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<ClassBody<NewClass>>,
+) -> Result<Json<ClassBody<ClassDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let class = ctx
+        .store
+        .class()
+        .create_class(organization_id, req.class, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ClassBody { class }))
+}
+
+async fn list_classes(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<ClassesBody<ClassDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let classes = ctx.store.class().list_classes(organization_id).await?;
+
+    Ok(Json(ClassesBody { classes }))
+}
+
+/// Calls off a class, e.g. when the instructor is ill. Everyone booked is told.
+async fn cancel_class(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_id): Path<Uuid>,
+) -> Result<Json<ClassBody<ClassDTO>>> {
+    let class = ctx.store.class().get_class(class_id).await?;
+    ctx.store
+        .membership()
+        .require_role(class.organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let class = ctx
+        .store
+        .class()
+        .cancel_class(class.id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ClassBody { class }))
+}
+
+async fn create_class_type(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<ClassTypeBody<NewClassType>>,
+) -> Result<Json<ClassTypeBody<ClassTypeDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let class_type = ctx
+        .store
+        .class_type()
+        .create_class_type(organization_id, req.class_type, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ClassTypeBody { class_type }))
+}
+
+async fn list_class_types(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<ClassTypesBody<ClassTypeDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let class_types = ctx
+        .store
+        .class_type()
+        .list_class_types(organization_id)
+        .await?;
+
+    Ok(Json(ClassTypesBody { class_types }))
+}
+
+async fn update_class_type(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(class_type_id): Path<Uuid>,
+    Json(req): Json<ClassTypeBody<UpdateClassType>>,
+) -> Result<Json<ClassTypeBody<ClassTypeDTO>>> {
+    let class_type = ctx.store.class_type().get_class_type(class_type_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            class_type.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let class_type = ctx
+        .store
+        .class_type()
+        .update_class_type(class_type_id, req.class_type, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ClassTypeBody { class_type }))
+}
+
+async fn create_location(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<LocationBody<NewLocation>>,
+) -> Result<Json<LocationBody<LocationDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let location = ctx
+        .store
+        .location()
+        .create_location(organization_id, req.location, &auth_account.actor())
+        .await?;
+
+    Ok(Json(LocationBody { location }))
+}
+
+async fn list_locations(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<LocationsBody<LocationDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let locations = ctx.store.location().list_locations(organization_id).await?;
+
+    Ok(Json(LocationsBody { locations }))
+}
@@ -0,0 +1,136 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Error, Result};
+use crate::models::email::{EmailDTO, EmailTemplate, EmailTemplateDTO, EmailTemplateKind};
+use crate::models::membership::Role;
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/email-templates",
+            get(list_email_templates),
+        )
+        .route(
+            "/api/organizations/:organization_id/email-templates/:kind",
+            get(get_email_template)
+                .put(save_email_template)
+                .delete(reset_email_template),
+        )
+        .route(
+            "/api/organizations/:organization_id/emails",
+            get(list_emails),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EmailTemplateBody<T> {
+    email_template: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EmailTemplatesBody<T> {
+    email_templates: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EmailsBody<T> {
+    emails: Vec<T>,
+}
+
+async fn list_email_templates(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<EmailTemplatesBody<EmailTemplateDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let email_templates = ctx.store.email().list_templates(organization_id).await?;
+
+    Ok(Json(EmailTemplatesBody { email_templates }))
+}
+
+async fn get_email_template(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path((organization_id, kind)): Path<(Uuid, EmailTemplateKind)>,
+) -> Result<Json<EmailTemplateBody<EmailTemplateDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let email_template = ctx
+        .store
+        .email()
+        .list_templates(organization_id)
+        .await?
+        .into_iter()
+        .find(|t| t.kind == kind)
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(EmailTemplateBody { email_template }))
+}
+
+/// Owners word emails in their studio's own voice, e.g. `{"email_template": {"subject":
+/// "See you at {{class_name}}!", "body": "..."}}`.
+async fn save_email_template(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path((organization_id, kind)): Path<(Uuid, EmailTemplateKind)>,
+    Json(req): Json<EmailTemplateBody<EmailTemplate>>,
+) -> Result<Json<EmailTemplateBody<EmailTemplateDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let email_template = ctx
+        .store
+        .email()
+        .save_template(organization_id, kind, req.email_template)
+        .await?;
+
+    Ok(Json(EmailTemplateBody { email_template }))
+}
+
+async fn reset_email_template(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path((organization_id, kind)): Path<(Uuid, EmailTemplateKind)>,
+) -> Result<Json<EmailTemplateBody<EmailTemplateDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let email_template = ctx
+        .store
+        .email()
+        .reset_template(organization_id, kind)
+        .await?;
+
+    Ok(Json(EmailTemplateBody { email_template }))
+}
+
+/// What was emailed on the organization's behalf, to look into emails people say they never
+/// got. Notifications to members are listed with each member's.
+async fn list_emails(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<EmailsBody<EmailDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let emails = ctx.store.email().list_emails(organization_id).await?;
+
+    Ok(Json(EmailsBody { emails }))
+}
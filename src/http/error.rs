@@ -12,7 +12,8 @@ use std::collections::HashMap;
 ///
 /// For convenience, this represents both API errors as well as internal recoverable errors,
 /// and maps them to appropriate status codes along with at least a minimally useful error
-/// message in a plain text body, or a JSON body in the case of `UnprocessableEntity`.
+/// message in a plain text body, or a JSON body in the case of `Forbidden` and
+/// `UnprocessableEntity`.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     /// Return `401 Unauthorized`
@@ -20,8 +21,14 @@ pub enum Error {
     Unauthorized,
 
     /// Return `403 Forbidden`
+    ///
+    /// This serializes to JSON with an `upgrade_hint` when the organization's platform plan
+    /// is what stands in the way, e.g. it is at its tier's member limit, which the frontend
+    /// can show the studio's owner.
     #[error("user may not perform that action")]
-    Forbidden,
+    Forbidden {
+        upgrade_hint: Option<Cow<'static, str>>,
+    },
 
     /// Return `404 Not Found`
     #[error("request path not found")]
@@ -102,7 +109,7 @@ impl Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
-            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Forbidden { .. } => StatusCode::FORBIDDEN,
             Self::NotFound => StatusCode::NOT_FOUND,
             Self::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             Self::Sqlx(_) | Self::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -126,6 +133,20 @@ impl IntoResponse for Error {
 
                 return (StatusCode::UNPROCESSABLE_ENTITY, Json(Errors { errors })).into_response();
             }
+            Self::Forbidden { ref upgrade_hint } => {
+                #[derive(serde::Serialize)]
+                struct Forbidden<'a> {
+                    error: String,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    upgrade_hint: Option<&'a str>,
+                }
+
+                let body = Forbidden {
+                    error: self.to_string(),
+                    upgrade_hint: upgrade_hint.as_deref(),
+                };
+                return (self.status_code(), Json(body)).into_response();
+            }
             Self::Unauthorized => {
                 return (
                     self.status_code(),
@@ -0,0 +1,35 @@
+use crate::http::extractor::InternalConsumer;
+use crate::http::{ApiContext, Result};
+use crate::models::event::{EventDTO, EventQuery};
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/api/events", get(list_events))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EventsBody<T> {
+    events: Vec<T>,
+    /// Pass as `after` to read the next page. Stays put once the log is read to its end, so
+    /// consumers poll with it until new events come in.
+    next_cursor: i64,
+}
+
+/// The event log for internal consumers, e.g. `GET /api/events?after=1200&limit=500`,
+/// optionally narrowed down with `organization_id`.
+async fn list_events(
+    _consumer: InternalConsumer,
+    ctx: State<ApiContext>,
+    Query(query): Query<EventQuery>,
+) -> Result<Json<EventsBody<EventDTO>>> {
+    let after = query.after;
+    let events = ctx.store.event().list_events(query).await?;
+    let next_cursor = events.last().map_or(after, |event| event.sequence);
+
+    Ok(Json(EventsBody {
+        events,
+        next_cursor,
+    }))
+}
@@ -0,0 +1,121 @@
+use crate::http::{ApiContext, Error};
+use crate::models::audit::Actor;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::{AUTHORIZATION, USER_AGENT};
+use axum::http::request::Parts;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const SCHEME_PREFIX: &str = "Token ";
+
+/// Add this as a parameter to a handler function to require the account to be logged in.
+///
+/// Parses the account session id from the header `Authorization: Token <account session id>`,
+/// which is the `account_session_id` returned by `POST /api/login`.
+///
+/// The session must exist, be active and not have expired.
+pub struct AuthAccount {
+    pub account_id: Uuid,
+    pub account_session_id: Uuid,
+    /// Where the request came from, for the audit log.
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AuthAccount {
+    /// The account as the author of a change, for the audit log.
+    pub fn actor(&self) -> Actor {
+        Actor {
+            account_id: self.account_id,
+            ip: self.ip.clone(),
+            user_agent: self.user_agent.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<ApiContext> for AuthAccount {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &ApiContext,
+    ) -> Result<Self, Self::Rejection> {
+        let token = token(parts)?;
+
+        let account_session_id = Uuid::parse_str(token).map_err(|_| Error::Unauthorized)?;
+
+        let account_session = ctx
+            .store
+            .account_session()
+            .get_account_session(account_session_id)
+            .await?
+            .ok_or(Error::Unauthorized)?;
+
+        if account_session.active == 0 || account_session.expires_at <= OffsetDateTime::now_utc() {
+            return Err(Error::Unauthorized);
+        }
+
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Self {
+            account_id: account_session.account_id,
+            account_session_id: account_session.id,
+            ip,
+            user_agent,
+        })
+    }
+}
+
+/// Add this as a parameter to a handler function to only let internal consumers, e.g.
+/// analytics, through.
+///
+/// Requires the header `Authorization: Token <key>` with `Config::events_api_key` as the key.
+pub struct InternalConsumer;
+
+#[async_trait]
+impl FromRequestParts<ApiContext> for InternalConsumer {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &ApiContext,
+    ) -> Result<Self, Self::Rejection> {
+        let key = ctx
+            .config
+            .events_api_key
+            .as_deref()
+            .ok_or(Error::Unauthorized)?;
+        let token = token(parts)?;
+
+        // Comparing digests rather than the keys themselves doesn't give away how much of a
+        // guessed key was right by how long the comparison took.
+        if Sha256::digest(token) != Sha256::digest(key) {
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(Self)
+    }
+}
+
+fn token(parts: &Parts) -> Result<&str, Error> {
+    parts
+        .headers
+        .get(AUTHORIZATION)
+        .ok_or(Error::Unauthorized)?
+        .to_str()
+        .map_err(|_| Error::Unauthorized)?
+        .strip_prefix(SCHEME_PREFIX)
+        .ok_or(Error::Unauthorized)
+}
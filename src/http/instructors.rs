@@ -0,0 +1,177 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Error, Result};
+use crate::models::instructor::{
+    AvailabilityDTO, InstructorDTO, NewAvailability, NewInstructor, UpdateInstructor,
+};
+use crate::models::membership::Role;
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/instructors",
+            get(list_instructors).post(create_instructor),
+        )
+        .route(
+            "/api/instructors/:instructor_id",
+            get(get_instructor).patch(update_instructor),
+        )
+        .route(
+            "/api/instructors/:instructor_id/availability",
+            get(list_availability).put(set_availability),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct InstructorBody<T> {
+    instructor: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct InstructorsBody<T> {
+    instructors: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AvailabilityBody<T> {
+    availability: Vec<T>,
+}
+
+/// Checks the account is the instructor themselves or an owner of their organization.
+async fn require_self_or_owner(
+    ctx: &ApiContext,
+    instructor: &InstructorDTO,
+    account_id: Uuid,
+) -> Result<()> {
+    let membership = ctx
+        .store
+        .membership()
+        .require_role(instructor.organization_id, account_id, Role::Member)
+        .await?;
+
+    if membership.id != instructor.membership_id && membership.role < Role::Owner {
+        return Err(Error::Forbidden { upgrade_hint: None });
+    }
+
+    Ok(())
+}
+
+async fn create_instructor(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<InstructorBody<NewInstructor>>,
+) -> Result<Json<InstructorBody<InstructorDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let instructor = ctx
+        .store
+        .instructor()
+        .create_instructor(organization_id, req.instructor, &auth_account.actor())
+        .await?;
+
+    Ok(Json(InstructorBody { instructor }))
+}
+
+async fn list_instructors(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<InstructorsBody<InstructorDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let instructors = ctx
+        .store
+        .instructor()
+        .list_instructors(organization_id)
+        .await?;
+
+    Ok(Json(InstructorsBody { instructors }))
+}
+
+async fn get_instructor(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(instructor_id): Path<Uuid>,
+) -> Result<Json<InstructorBody<InstructorDTO>>> {
+    let instructor = ctx.store.instructor().get_instructor(instructor_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            instructor.organization_id,
+            auth_account.account_id,
+            Role::Member,
+        )
+        .await?;
+
+    Ok(Json(InstructorBody { instructor }))
+}
+
+async fn update_instructor(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(instructor_id): Path<Uuid>,
+    Json(req): Json<InstructorBody<UpdateInstructor>>,
+) -> Result<Json<InstructorBody<InstructorDTO>>> {
+    let instructor = ctx.store.instructor().get_instructor(instructor_id).await?;
+    require_self_or_owner(&ctx, &instructor, auth_account.account_id).await?;
+
+    let instructor = ctx
+        .store
+        .instructor()
+        .update_instructor(instructor_id, req.instructor, &auth_account.actor())
+        .await?;
+
+    Ok(Json(InstructorBody { instructor }))
+}
+
+async fn list_availability(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(instructor_id): Path<Uuid>,
+) -> Result<Json<AvailabilityBody<AvailabilityDTO>>> {
+    let instructor = ctx.store.instructor().get_instructor(instructor_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            instructor.organization_id,
+            auth_account.account_id,
+            Role::Member,
+        )
+        .await?;
+
+    let availability = ctx
+        .store
+        .instructor()
+        .list_availability(instructor_id)
+        .await?;
+
+    Ok(Json(AvailabilityBody { availability }))
+}
+
+async fn set_availability(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(instructor_id): Path<Uuid>,
+    Json(req): Json<AvailabilityBody<NewAvailability>>,
+) -> Result<Json<AvailabilityBody<AvailabilityDTO>>> {
+    let instructor = ctx.store.instructor().get_instructor(instructor_id).await?;
+    require_self_or_owner(&ctx, &instructor, auth_account.account_id).await?;
+
+    let availability = ctx
+        .store
+        .instructor()
+        .set_availability(instructor_id, req.availability, &auth_account.actor())
+        .await?;
+
+    Ok(Json(AvailabilityBody { availability }))
+}
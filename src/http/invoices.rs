@@ -0,0 +1,268 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::credit_note::{CreditNoteDTO, NewCreditNote};
+use crate::models::invoice::{InvoiceDTO, InvoiceWithLinesDTO};
+use crate::models::membership::{Permission, Role};
+use crate::models::payment_profile::{NewPaymentMethod, PaymentProfileDTO};
+use crate::payments::{NewCustomer, NewRefund};
+use axum::extract::{Path, State};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/invoices",
+            get(list_invoices),
+        )
+        .route(
+            "/api/memberships/:membership_id/invoices",
+            get(list_member_invoices),
+        )
+        .route("/api/invoices/:invoice_id", get(get_invoice))
+        .route(
+            "/api/memberships/:membership_id/payment-method",
+            put(save_payment_method),
+        )
+        .route("/api/invoices/:invoice_id/refunds", post(refund_invoice))
+        .route(
+            "/api/organizations/:organization_id/credit-notes",
+            get(list_credit_notes),
+        )
+        .route("/api/credit-notes/:credit_note_id", get(get_credit_note))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct InvoiceBody<T> {
+    invoice: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct InvoicesBody<T> {
+    invoices: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RefundBody<T> {
+    refund: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CreditNoteBody<T> {
+    credit_note: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CreditNotesBody<T> {
+    credit_notes: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PaymentMethodBody<T> {
+    payment_method: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PaymentProfileBody<T> {
+    payment_profile: T,
+}
+
+async fn list_invoices(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<InvoicesBody<InvoiceDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let invoices = ctx.store.invoice().list_invoices(organization_id).await?;
+
+    Ok(Json(InvoicesBody { invoices }))
+}
+
+async fn list_member_invoices(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<InvoicesBody<InvoiceDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let invoices = ctx
+        .store
+        .invoice()
+        .list_member_invoices(membership_id)
+        .await?;
+
+    Ok(Json(InvoicesBody { invoices }))
+}
+
+async fn get_invoice(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(invoice_id): Path<Uuid>,
+) -> Result<Json<InvoiceBody<InvoiceWithLinesDTO>>> {
+    let invoice = ctx.store.invoice().get_invoice(invoice_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(
+            invoice.invoice.organization_id,
+            auth_account.account_id,
+            invoice.invoice.membership_id,
+        )
+        .await?;
+
+    Ok(Json(InvoiceBody { invoice }))
+}
+
+/// Saves the card or other payment method renewals are charged to, registering the member
+/// with the payment provider the first time.
+async fn save_payment_method(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+    Json(req): Json<PaymentMethodBody<NewPaymentMethod>>,
+) -> Result<Json<PaymentProfileBody<PaymentProfileDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let customer_id = match ctx
+        .store
+        .payment_profile()
+        .get_payment_profile(member.id)
+        .await?
+    {
+        Some(profile) => profile.customer_id,
+        None => {
+            let account = ctx.store.account().get_account(member.account_id).await?;
+            ctx.payments
+                .create_customer(NewCustomer {
+                    membership_id: member.id,
+                    name: account.name,
+                    email: account.email,
+                })
+                .await?
+                .id
+        }
+    };
+    let payment_method = ctx
+        .payments
+        .attach_payment_method(&customer_id, &req.payment_method.token)
+        .await?;
+
+    let payment_profile = ctx
+        .store
+        .payment_profile()
+        .save_payment_profile(member.id, customer_id, Some(payment_method.id))
+        .await?;
+
+    Ok(Json(PaymentProfileBody { payment_profile }))
+}
+
+/// Refunds part or all of a paid invoice through the payment provider and issues a credit
+/// note for it. Invoices paid outside the provider only get the credit note, the money is
+/// handed back by whoever refunds it.
+async fn refund_invoice(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(invoice_id): Path<Uuid>,
+    Json(req): Json<RefundBody<NewCreditNote>>,
+) -> Result<Json<CreditNoteBody<CreditNoteDTO>>> {
+    let invoice = ctx.store.invoice().get_invoice(invoice_id).await?;
+    let staff = ctx
+        .store
+        .membership()
+        .require_permission(
+            invoice.invoice.organization_id,
+            auth_account.account_id,
+            Permission::BillingRefund,
+        )
+        .await?;
+
+    let mut new_credit_note = req.refund;
+    let quote = ctx
+        .store
+        .credit_note()
+        .quote_refund(invoice_id, &new_credit_note)
+        .await?;
+    let refund_id = match quote.invoice.charge_id {
+        Some(charge_id) => {
+            let refund = ctx
+                .payments
+                .refund(NewRefund {
+                    charge_id,
+                    amount: Some(quote.amount),
+                    idempotency_key: quote.idempotency_key,
+                })
+                .await?;
+            // A retried refund returns the one first made, whose amount is what was refunded.
+            new_credit_note.amount = Some(refund.amount);
+            Some(refund.id)
+        }
+        None => None,
+    };
+
+    let credit_note = ctx
+        .store
+        .credit_note()
+        .issue_credit_note(
+            invoice_id,
+            staff.id,
+            new_credit_note,
+            refund_id,
+            &auth_account.actor(),
+        )
+        .await?;
+
+    Ok(Json(CreditNoteBody { credit_note }))
+}
+
+async fn list_credit_notes(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<CreditNotesBody<CreditNoteDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let credit_notes = ctx
+        .store
+        .credit_note()
+        .list_credit_notes(organization_id)
+        .await?;
+
+    Ok(Json(CreditNotesBody { credit_notes }))
+}
+
+async fn get_credit_note(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(credit_note_id): Path<Uuid>,
+) -> Result<Json<CreditNoteBody<CreditNoteDTO>>> {
+    let credit_note = ctx
+        .store
+        .credit_note()
+        .get_credit_note(credit_note_id)
+        .await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(
+            credit_note.organization_id,
+            auth_account.account_id,
+            credit_note.membership_id,
+        )
+        .await?;
+
+    Ok(Json(CreditNoteBody { credit_note }))
+}
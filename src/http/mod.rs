@@ -1,7 +1,24 @@
 mod error;
 
 pub mod accounts;
+pub mod audit_log;
+pub mod bookings;
+pub mod classes;
+pub mod emails;
+pub mod events;
+pub mod extractor;
 pub mod health;
+pub mod instructors;
+pub mod invoices;
+pub mod notifications;
+pub mod organizations;
+pub mod plans;
+pub mod platform;
+pub mod sales;
+pub mod subscriptions;
+pub mod wallets;
+pub mod webhook_endpoints;
+pub mod webhooks;
 
 pub mod server;
 pub use server::serve;
@@ -0,0 +1,99 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Error, Result};
+use crate::models::notification::{
+    NotificationDTO, NotificationPreferencesDTO, UpdateNotificationPreferences,
+};
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/memberships/:membership_id/notification-preferences",
+            get(get_notification_preferences).put(update_notification_preferences),
+        )
+        .route(
+            "/api/memberships/:membership_id/notifications",
+            get(list_notifications),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct NotificationPreferencesBody<T> {
+    notification_preferences: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct NotificationsBody<T> {
+    notifications: Vec<T>,
+}
+
+async fn get_notification_preferences(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<NotificationPreferencesBody<NotificationPreferencesDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let notification_preferences = ctx
+        .store
+        .notification()
+        .get_preferences(membership_id)
+        .await?;
+
+    Ok(Json(NotificationPreferencesBody {
+        notification_preferences,
+    }))
+}
+
+/// Only members choose how they are reached, e.g. `{"notification_preferences": {"phone":
+/// "+15550100", "quiet_hours": {"start": "22:00:00.0", "end": "07:00:00.0"}, "events":
+/// [{"event": "class_cancelled", "channels": ["email", "sms"]}]}}`.
+async fn update_notification_preferences(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+    Json(req): Json<NotificationPreferencesBody<UpdateNotificationPreferences>>,
+) -> Result<Json<NotificationPreferencesBody<NotificationPreferencesDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    if member.account_id != auth_account.account_id {
+        return Err(Error::Forbidden { upgrade_hint: None });
+    }
+
+    let notification_preferences = ctx
+        .store
+        .notification()
+        .update_preferences(membership_id, req.notification_preferences)
+        .await?;
+
+    Ok(Json(NotificationPreferencesBody {
+        notification_preferences,
+    }))
+}
+
+/// What the member was told, and on which channels, for when they say they never heard.
+async fn list_notifications(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<NotificationsBody<NotificationDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let notifications = ctx
+        .store
+        .notification()
+        .list_notifications(membership_id)
+        .await?;
+
+    Ok(Json(NotificationsBody { notifications }))
+}
@@ -0,0 +1,157 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::membership::{MembershipDTO, NewMembership, Role};
+use crate::models::organization::{NewOrganization, OrganizationDTO, UpdateOrganization};
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route("/api/organizations", post(create_organization))
+        .route(
+            "/api/organizations/:organization_id",
+            get(get_organization).patch(update_organization),
+        )
+        .route(
+            "/api/organizations/:organization_id/memberships",
+            post(create_membership),
+        )
+        .route(
+            "/api/memberships/:membership_id/check-in-token",
+            get(get_check_in_token).post(rotate_check_in_token),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct OrganizationBody<T> {
+    organization: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MembershipBody<T> {
+    membership: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CheckInTokenBody {
+    check_in_token: String,
+}
+
+async fn create_organization(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Json(req): Json<OrganizationBody<NewOrganization>>,
+) -> Result<Json<OrganizationBody<OrganizationDTO>>> {
+    let organization = ctx
+        .store
+        .organization()
+        .create_organization(auth_account.account_id, req.organization)
+        .await?;
+
+    Ok(Json(OrganizationBody { organization }))
+}
+
+async fn get_organization(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<OrganizationBody<OrganizationDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let organization = ctx
+        .store
+        .organization()
+        .get_organization(organization_id)
+        .await?;
+
+    Ok(Json(OrganizationBody { organization }))
+}
+
+async fn update_organization(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<OrganizationBody<UpdateOrganization>>,
+) -> Result<Json<OrganizationBody<OrganizationDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let organization = ctx
+        .store
+        .organization()
+        .update_organization(organization_id, req.organization, &auth_account.actor())
+        .await?;
+
+    Ok(Json(OrganizationBody { organization }))
+}
+
+async fn create_membership(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<MembershipBody<NewMembership>>,
+) -> Result<Json<MembershipBody<MembershipDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let membership = ctx
+        .store
+        .membership()
+        .create_membership(organization_id, req.membership, &auth_account.actor())
+        .await?;
+
+    Ok(Json(MembershipBody { membership }))
+}
+
+async fn get_check_in_token(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<CheckInTokenBody>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let check_in_token = ctx
+        .store
+        .membership()
+        .get_check_in_token(membership_id)
+        .await?;
+
+    Ok(Json(CheckInTokenBody { check_in_token }))
+}
+
+async fn rotate_check_in_token(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<CheckInTokenBody>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    // Staff rotating a member's token for them are audited.
+    let actor = auth_account.actor();
+    let staff = (member.account_id != auth_account.account_id).then_some(&actor);
+
+    let check_in_token = ctx
+        .store
+        .membership()
+        .rotate_check_in_token(membership_id, staff)
+        .await?;
+
+    Ok(Json(CheckInTokenBody { check_in_token }))
+}
@@ -0,0 +1,271 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::membership::Role;
+use crate::models::plan::{NewPlan, PlanDTO, UpdatePlan};
+use crate::models::promo_code::{NewPromoCode, PromoCodeDTO};
+use crate::models::tax_rate::{NewTaxRate, TaxRateDTO};
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/plans",
+            get(list_plans).post(create_plan),
+        )
+        .route(
+            "/api/plans/:plan_id",
+            get(get_plan).patch(update_plan).delete(archive_plan),
+        )
+        .route(
+            "/api/organizations/:organization_id/tax-rates",
+            get(list_tax_rates).post(create_tax_rate),
+        )
+        .route(
+            "/api/organizations/:organization_id/promo-codes",
+            get(list_promo_codes).post(create_promo_code),
+        )
+        .route(
+            "/api/promo-codes/:promo_code_id",
+            get(get_promo_code).delete(archive_promo_code),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PlanBody<T> {
+    plan: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PlansBody<T> {
+    plans: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TaxRateBody<T> {
+    tax_rate: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TaxRatesBody<T> {
+    tax_rates: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PromoCodeBody<T> {
+    promo_code: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PromoCodesBody<T> {
+    promo_codes: Vec<T>,
+}
+
+async fn create_plan(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<PlanBody<NewPlan>>,
+) -> Result<Json<PlanBody<PlanDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let plan = ctx
+        .store
+        .plan()
+        .create_plan(organization_id, req.plan, &auth_account.actor())
+        .await?;
+
+    Ok(Json(PlanBody { plan }))
+}
+
+async fn list_plans(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<PlansBody<PlanDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let plans = ctx.store.plan().list_plans(organization_id).await?;
+
+    Ok(Json(PlansBody { plans }))
+}
+
+async fn get_plan(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(plan_id): Path<Uuid>,
+) -> Result<Json<PlanBody<PlanDTO>>> {
+    let plan = ctx.store.plan().get_plan(plan_id).await?;
+    ctx.store
+        .membership()
+        .require_role(plan.organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    Ok(Json(PlanBody { plan }))
+}
+
+async fn update_plan(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(plan_id): Path<Uuid>,
+    Json(req): Json<PlanBody<UpdatePlan>>,
+) -> Result<Json<PlanBody<PlanDTO>>> {
+    let plan = ctx.store.plan().get_plan(plan_id).await?;
+    ctx.store
+        .membership()
+        .require_role(plan.organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let plan = ctx
+        .store
+        .plan()
+        .update_plan(plan_id, req.plan, &auth_account.actor())
+        .await?;
+
+    Ok(Json(PlanBody { plan }))
+}
+
+async fn archive_plan(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(plan_id): Path<Uuid>,
+) -> Result<Json<PlanBody<PlanDTO>>> {
+    let plan = ctx.store.plan().get_plan(plan_id).await?;
+    ctx.store
+        .membership()
+        .require_role(plan.organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let plan = ctx
+        .store
+        .plan()
+        .archive_plan(plan_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(PlanBody { plan }))
+}
+
+async fn create_tax_rate(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<TaxRateBody<NewTaxRate>>,
+) -> Result<Json<TaxRateBody<TaxRateDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let tax_rate = ctx
+        .store
+        .tax_rate()
+        .create_tax_rate(organization_id, req.tax_rate, &auth_account.actor())
+        .await?;
+
+    Ok(Json(TaxRateBody { tax_rate }))
+}
+
+async fn list_tax_rates(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<TaxRatesBody<TaxRateDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Member)
+        .await?;
+
+    let tax_rates = ctx.store.tax_rate().list_tax_rates(organization_id).await?;
+
+    Ok(Json(TaxRatesBody { tax_rates }))
+}
+
+async fn create_promo_code(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<PromoCodeBody<NewPromoCode>>,
+) -> Result<Json<PromoCodeBody<PromoCodeDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let promo_code = ctx
+        .store
+        .promo_code()
+        .create_promo_code(organization_id, req.promo_code, &auth_account.actor())
+        .await?;
+
+    Ok(Json(PromoCodeBody { promo_code }))
+}
+
+/// Staff only, members are given codes rather than browsing them.
+async fn list_promo_codes(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<PromoCodesBody<PromoCodeDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let promo_codes = ctx
+        .store
+        .promo_code()
+        .list_promo_codes(organization_id)
+        .await?;
+
+    Ok(Json(PromoCodesBody { promo_codes }))
+}
+
+async fn get_promo_code(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(promo_code_id): Path<Uuid>,
+) -> Result<Json<PromoCodeBody<PromoCodeDTO>>> {
+    let promo_code = ctx.store.promo_code().get_promo_code(promo_code_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            promo_code.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    Ok(Json(PromoCodeBody { promo_code }))
+}
+
+async fn archive_promo_code(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(promo_code_id): Path<Uuid>,
+) -> Result<Json<PromoCodeBody<PromoCodeDTO>>> {
+    let promo_code = ctx.store.promo_code().get_promo_code(promo_code_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            promo_code.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let promo_code = ctx
+        .store
+        .promo_code()
+        .archive_promo_code(promo_code_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(PromoCodeBody { promo_code }))
+}
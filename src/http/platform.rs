@@ -0,0 +1,115 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::membership::Role;
+use crate::models::platform_plan::PlatformPlanDTO;
+use crate::models::platform_subscription::{
+    ChangePlatformPlan, PlatformSubscriptionWithUsageDTO, PlatformUsageDTO,
+};
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route("/api/platform-plans", get(list_platform_plans))
+        .route(
+            "/api/organizations/:organization_id/platform-subscription",
+            get(get_platform_subscription).put(change_platform_plan),
+        )
+        .route(
+            "/api/organizations/:organization_id/platform-usage",
+            get(list_platform_usage),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PlatformPlansBody<T> {
+    platform_plans: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PlatformSubscriptionBody<T> {
+    platform_subscription: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PlatformUsageBody<T> {
+    platform_usage: Vec<T>,
+}
+
+/// Open to any account, e.g. to compare tiers before creating an organization.
+async fn list_platform_plans(
+    _auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+) -> Result<Json<PlatformPlansBody<PlatformPlanDTO>>> {
+    let platform_plans = ctx.store.platform_plan().list_platform_plans().await?;
+
+    Ok(Json(PlatformPlansBody { platform_plans }))
+}
+
+async fn get_platform_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<PlatformSubscriptionBody<PlatformSubscriptionWithUsageDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let platform_subscription = ctx
+        .store
+        .platform_subscription()
+        .get_platform_subscription(organization_id)
+        .await?;
+
+    Ok(Json(PlatformSubscriptionBody {
+        platform_subscription,
+    }))
+}
+
+async fn change_platform_plan(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<PlatformSubscriptionBody<ChangePlatformPlan>>,
+) -> Result<Json<PlatformSubscriptionBody<PlatformSubscriptionWithUsageDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let platform_subscription = ctx
+        .store
+        .platform_subscription()
+        .change_platform_plan(
+            organization_id,
+            req.platform_subscription,
+            &auth_account.actor(),
+        )
+        .await?;
+
+    Ok(Json(PlatformSubscriptionBody {
+        platform_subscription,
+    }))
+}
+
+async fn list_platform_usage(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<PlatformUsageBody<PlatformUsageDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let platform_usage = ctx
+        .store
+        .platform_subscription()
+        .list_platform_usage(organization_id)
+        .await?;
+
+    Ok(Json(PlatformUsageBody { platform_usage }))
+}
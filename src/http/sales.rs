@@ -0,0 +1,392 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Error, Result};
+use crate::models::dunning::PaymentAttempt;
+use crate::models::invoice::InvoiceStatus;
+use crate::models::membership::Role;
+use crate::models::product::{NewProduct, ProductDTO, StockAdjustment, UpdateProduct};
+use crate::models::sale::{
+    CashDrawerCountDTO, CashDrawerReportDTO, NewCashDrawerCount, NewSale, SaleDTO,
+    SalePaymentMethod, SaleWithInvoiceDTO,
+};
+use crate::payments::NewCharge;
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use time::Date;
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/products",
+            get(list_products).post(create_product),
+        )
+        .route(
+            "/api/products/:product_id",
+            get(get_product)
+                .patch(update_product)
+                .delete(archive_product),
+        )
+        .route("/api/products/:product_id/stock", post(adjust_stock))
+        .route(
+            "/api/organizations/:organization_id/checkout",
+            post(checkout),
+        )
+        .route(
+            "/api/organizations/:organization_id/sales/:business_date",
+            get(list_sales),
+        )
+        .route("/api/sales/:sale_id/void", post(void_sale))
+        .route(
+            "/api/organizations/:organization_id/cash-drawer/:business_date",
+            get(get_cash_drawer_report),
+        )
+        .route(
+            "/api/organizations/:organization_id/cash-drawer-counts",
+            post(count_cash_drawer),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ProductBody<T> {
+    product: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ProductsBody<T> {
+    products: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StockAdjustmentBody<T> {
+    stock_adjustment: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SaleBody<T> {
+    sale: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SalesBody<T> {
+    sales: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CashDrawerReportBody<T> {
+    cash_drawer_report: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CashDrawerCountBody<T> {
+    cash_drawer_count: T,
+}
+
+async fn create_product(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<ProductBody<NewProduct>>,
+) -> Result<Json<ProductBody<ProductDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let product = ctx
+        .store
+        .product()
+        .create_product(organization_id, req.product, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ProductBody { product }))
+}
+
+async fn list_products(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<ProductsBody<ProductDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let products = ctx.store.product().list_products(organization_id).await?;
+
+    Ok(Json(ProductsBody { products }))
+}
+
+async fn get_product(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(product_id): Path<Uuid>,
+) -> Result<Json<ProductBody<ProductDTO>>> {
+    let product = ctx.store.product().get_product(product_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            product.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    Ok(Json(ProductBody { product }))
+}
+
+async fn update_product(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(product_id): Path<Uuid>,
+    Json(req): Json<ProductBody<UpdateProduct>>,
+) -> Result<Json<ProductBody<ProductDTO>>> {
+    let product = ctx.store.product().get_product(product_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            product.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let product = ctx
+        .store
+        .product()
+        .update_product(product_id, req.product, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ProductBody { product }))
+}
+
+async fn archive_product(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(product_id): Path<Uuid>,
+) -> Result<Json<ProductBody<ProductDTO>>> {
+    let product = ctx.store.product().get_product(product_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            product.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let product = ctx
+        .store
+        .product()
+        .archive_product(product_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ProductBody { product }))
+}
+
+/// Staff count deliveries in and write off what was lost or broken.
+async fn adjust_stock(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(product_id): Path<Uuid>,
+    Json(req): Json<StockAdjustmentBody<StockAdjustment>>,
+) -> Result<Json<ProductBody<ProductDTO>>> {
+    let product = ctx.store.product().get_product(product_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            product.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    let product = ctx
+        .store
+        .product()
+        .adjust_stock(product_id, req.stock_adjustment, &auth_account.actor())
+        .await?;
+
+    Ok(Json(ProductBody { product }))
+}
+
+/// Rings up a sale at the front desk. Card sales are charged to the member's saved card
+/// there and then, and nothing is sold if it is declined.
+async fn checkout(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<SaleBody<NewSale>>,
+) -> Result<Json<SaleBody<SaleWithInvoiceDTO>>> {
+    let staff = ctx
+        .store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let card = match req.sale.payment_method {
+        SalePaymentMethod::Card => {
+            let profile = ctx
+                .store
+                .payment_profile()
+                .get_payment_profile(req.sale.membership_id)
+                .await?;
+            match profile.and_then(|p| Some((p.customer_id, p.payment_method_id?))) {
+                Some(card) => Some(card),
+                None => {
+                    return Err(Error::unprocessable_entity([(
+                        "payment_method",
+                        "the member has no card on file",
+                    )]))
+                }
+            }
+        }
+        SalePaymentMethod::Cash | SalePaymentMethod::Account | SalePaymentMethod::Wallet => None,
+    };
+
+    let sale = ctx
+        .store
+        .sale()
+        .create_sale(organization_id, staff.id, req.sale, &auth_account.actor())
+        .await?;
+
+    let sale = match card {
+        Some((customer_id, payment_method_id)) if sale.invoice.status == InvoiceStatus::Open => {
+            charge_sale(&ctx, sale, customer_id, payment_method_id).await?
+        }
+        _ => sale,
+    };
+
+    Ok(Json(SaleBody { sale }))
+}
+
+/// Charges the card for the sale, voiding the sale if the charge fails or the provider
+/// can't be reached.
+async fn charge_sale(
+    ctx: &ApiContext,
+    sale: SaleWithInvoiceDTO,
+    customer_id: String,
+    payment_method_id: String,
+) -> Result<SaleWithInvoiceDTO> {
+    let invoice = &sale.invoice;
+    let charge = ctx
+        .payments
+        .charge(NewCharge {
+            invoice_id: invoice.id,
+            customer_id,
+            payment_method_id: Some(payment_method_id),
+            amount: invoice.total_amount,
+            currency: invoice.currency.clone(),
+            description: format!("Invoice #{}", invoice.number),
+            idempotency_key: format!("sale-{}", sale.sale.id),
+        })
+        .await;
+
+    let attempt = match PaymentAttempt::from_charge(charge) {
+        Ok(PaymentAttempt::Failed { message, .. }) => {
+            ctx.store.sale().void_sale(sale.sale.id, None).await?;
+            return Err(Error::unprocessable_entity([("payment_method", message)]));
+        }
+        Ok(attempt) => attempt,
+        Err(e) => {
+            ctx.store.sale().void_sale(sale.sale.id, None).await?;
+            return Err(e);
+        }
+    };
+
+    let outcome = ctx
+        .store
+        .dunning()
+        .record_payment_attempt(invoice.id, attempt, time::OffsetDateTime::now_utc())
+        .await?;
+
+    Ok(SaleWithInvoiceDTO {
+        sale: sale.sale,
+        invoice: outcome.invoice,
+    })
+}
+
+/// Days are in UTC, e.g. `2026-10-19`.
+async fn list_sales(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path((organization_id, business_date)): Path<(Uuid, Date)>,
+) -> Result<Json<SalesBody<SaleDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let sales = ctx
+        .store
+        .sale()
+        .list_sales(organization_id, business_date)
+        .await?;
+
+    Ok(Json(SalesBody { sales }))
+}
+
+async fn void_sale(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(sale_id): Path<Uuid>,
+) -> Result<Json<SaleBody<SaleWithInvoiceDTO>>> {
+    let sale = ctx.store.sale().get_sale(sale_id).await?;
+    ctx.store
+        .membership()
+        .require_role(sale.organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let sale = ctx
+        .store
+        .sale()
+        .void_sale(sale_id, Some(&auth_account.actor()))
+        .await?;
+
+    Ok(Json(SaleBody { sale }))
+}
+
+async fn get_cash_drawer_report(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path((organization_id, business_date)): Path<(Uuid, Date)>,
+) -> Result<Json<CashDrawerReportBody<CashDrawerReportDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let cash_drawer_report = ctx
+        .store
+        .sale()
+        .get_cash_drawer_report(organization_id, business_date)
+        .await?;
+
+    Ok(Json(CashDrawerReportBody { cash_drawer_report }))
+}
+
+async fn count_cash_drawer(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<CashDrawerCountBody<NewCashDrawerCount>>,
+) -> Result<Json<CashDrawerCountBody<CashDrawerCountDTO>>> {
+    let staff = ctx
+        .store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let cash_drawer_count = ctx
+        .store
+        .sale()
+        .count_cash_drawer(
+            organization_id,
+            staff.id,
+            req.cash_drawer_count,
+            &auth_account.actor(),
+        )
+        .await?;
+
+    Ok(Json(CashDrawerCountBody { cash_drawer_count }))
+}
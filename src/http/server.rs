@@ -1,34 +1,103 @@
 use crate::config::Config;
 use crate::http::accounts;
+use crate::http::audit_log;
+use crate::http::bookings;
+use crate::http::classes;
+use crate::http::emails;
+use crate::http::events;
 use crate::http::health;
+use crate::http::instructors;
+use crate::http::invoices;
+use crate::http::notifications;
+use crate::http::organizations;
+use crate::http::plans;
+use crate::http::platform;
+use crate::http::sales;
+use crate::http::subscriptions;
+use crate::http::wallets;
+use crate::http::webhook_endpoints;
+use crate::http::webhooks;
 use crate::http::ApiContext;
+use crate::integrations::WebhookClient;
+use crate::jobs;
+use crate::mail;
 use crate::models::DynStore;
 use crate::models::Store;
+use crate::notify;
+use crate::payments;
 use anyhow::Context;
 use axum::Router;
 use sqlx::SqlitePool;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::sync::watch;
 use tracing::info;
 
 pub async fn serve(config: Config, db: SqlitePool) -> anyhow::Result<()> {
     let port = config.port;
 
+    let payments = payments::from_config(&config)?;
+    let mailer = mail::from_config(&config)?;
+    let notifiers = notify::from_config(&config, mailer.clone())?;
     let api_context = ApiContext {
         config: Arc::new(config),
         store: Arc::new(Store::new(db.clone())) as DynStore,
+        payments,
+        mailer,
+        notifiers,
+        webhooks: WebhookClient::new(),
     };
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let workers = jobs::spawn(api_context.clone(), shutdown_rx);
     let app = api_router(api_context);
 
     // Port is configured in .env
     let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
     info!("addr {}", addr);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    let served = axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
-        .context("error running HTTP server")
+        .context("error running HTTP server");
+
+    // Requests in flight have been answered, now let the job workers finish what they are
+    // running.
+    info!("waiting for job workers to finish");
+    shutdown_tx.send_replace(true);
+    if let Err(e) = workers.await {
+        tracing::error!("job workers panicked: {e:?}");
+    }
+
+    served
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM, e.g. from `docker stop`.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("shutting down");
 }
 
 fn api_router(api_context: ApiContext) -> Router {
@@ -36,5 +105,21 @@ fn api_router(api_context: ApiContext) -> Router {
     Router::new()
         .merge(accounts::router())
         .merge(health::router())
+        .merge(organizations::router())
+        .merge(classes::router())
+        .merge(bookings::router())
+        .merge(instructors::router())
+        .merge(plans::router())
+        .merge(subscriptions::router())
+        .merge(invoices::router())
+        .merge(webhooks::router())
+        .merge(platform::router())
+        .merge(sales::router())
+        .merge(wallets::router())
+        .merge(emails::router())
+        .merge(notifications::router())
+        .merge(webhook_endpoints::router())
+        .merge(events::router())
+        .merge(audit_log::router())
         .with_state(api_context)
 }
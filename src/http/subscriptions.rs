@@ -0,0 +1,362 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Error, Result};
+use crate::models::credit::{CreditBalanceDTO, CreditEntryDTO, NewClassPack};
+use crate::models::membership::Role;
+use crate::models::subscription::{NewFreeze, NewSubscription, SubscriptionDTO};
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/subscriptions",
+            post(create_subscription),
+        )
+        .route(
+            "/api/memberships/:membership_id/subscriptions",
+            get(list_member_subscriptions),
+        )
+        .route("/api/subscriptions/:subscription_id", get(get_subscription))
+        .route(
+            "/api/subscriptions/:subscription_id/cancel",
+            post(cancel_subscription),
+        )
+        .route(
+            "/api/subscriptions/:subscription_id/pause",
+            post(pause_subscription),
+        )
+        .route(
+            "/api/subscriptions/:subscription_id/resume",
+            post(resume_subscription),
+        )
+        .route(
+            "/api/subscriptions/:subscription_id/freeze",
+            post(freeze_subscription).delete(unfreeze_subscription),
+        )
+        .route(
+            "/api/organizations/:organization_id/class-packs",
+            post(purchase_class_pack),
+        )
+        .route("/api/memberships/:membership_id/credits", get(get_credits))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SubscriptionBody<T> {
+    subscription: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SubscriptionsBody<T> {
+    subscriptions: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FreezeBody<T> {
+    freeze: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClassPackBody<T> {
+    class_pack: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CreditEntryBody<T> {
+    credit_entry: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CreditsBody<T> {
+    credits: T,
+}
+
+/// Finds who a purchase is for: the account's own membership, or as staff, `membership_id`.
+///
+/// Also returns whether it's staff buying for someone else, which is audited.
+async fn require_purchaser(
+    ctx: &ApiContext,
+    organization_id: Uuid,
+    account_id: Uuid,
+    membership_id: Option<Uuid>,
+) -> Result<(Uuid, bool)> {
+    let membership = ctx
+        .store
+        .membership()
+        .require_role(organization_id, account_id, Role::Member)
+        .await?;
+
+    match membership_id {
+        Some(membership_id) if membership_id != membership.id => {
+            let member = ctx.store.membership().get_membership(membership_id).await?;
+            if membership.role < Role::Staff || member.organization_id != organization_id {
+                return Err(Error::Forbidden { upgrade_hint: None });
+            }
+            Ok((member.id, true))
+        }
+        _ => Ok((membership.id, false)),
+    }
+}
+
+async fn create_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<SubscriptionBody<NewSubscription>>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let (membership_id, by_staff) = require_purchaser(
+        &ctx,
+        organization_id,
+        auth_account.account_id,
+        req.subscription.membership_id,
+    )
+    .await?;
+
+    let actor = auth_account.actor();
+    let subscription = ctx
+        .store
+        .subscription()
+        .create_subscription(
+            membership_id,
+            req.subscription.plan_id,
+            req.subscription.promo_code.as_deref(),
+            by_staff.then_some(&actor),
+        )
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn list_member_subscriptions(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<SubscriptionsBody<SubscriptionDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let subscriptions = ctx
+        .store
+        .subscription()
+        .list_member_subscriptions(membership_id)
+        .await?;
+
+    Ok(Json(SubscriptionsBody { subscriptions }))
+}
+
+async fn get_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(subscription_id): Path<Uuid>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let subscription = ctx
+        .store
+        .subscription()
+        .get_subscription(subscription_id)
+        .await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(
+            subscription.organization_id,
+            auth_account.account_id,
+            subscription.membership_id,
+        )
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn cancel_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(subscription_id): Path<Uuid>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let subscription = ctx
+        .store
+        .subscription()
+        .get_subscription(subscription_id)
+        .await?;
+    let membership = ctx
+        .store
+        .membership()
+        .require_self_or_staff(
+            subscription.organization_id,
+            auth_account.account_id,
+            subscription.membership_id,
+        )
+        .await?;
+
+    // Staff cancelling for the member are audited.
+    let actor = auth_account.actor();
+    let staff = (membership.id != subscription.membership_id).then_some(&actor);
+
+    let subscription = ctx
+        .store
+        .subscription()
+        .cancel_subscription(subscription_id, staff)
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn pause_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(subscription_id): Path<Uuid>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let subscription = ctx
+        .store
+        .subscription()
+        .get_subscription(subscription_id)
+        .await?;
+    ctx.store
+        .membership()
+        .require_role(
+            subscription.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    let subscription = ctx
+        .store
+        .subscription()
+        .pause_subscription(subscription_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn resume_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(subscription_id): Path<Uuid>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let subscription = ctx
+        .store
+        .subscription()
+        .get_subscription(subscription_id)
+        .await?;
+    ctx.store
+        .membership()
+        .require_role(
+            subscription.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    let subscription = ctx
+        .store
+        .subscription()
+        .resume_subscription(subscription_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn freeze_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(subscription_id): Path<Uuid>,
+    Json(req): Json<FreezeBody<NewFreeze>>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let subscription = ctx
+        .store
+        .subscription()
+        .get_subscription(subscription_id)
+        .await?;
+    ctx.store
+        .membership()
+        .require_role(
+            subscription.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    let subscription = ctx
+        .store
+        .subscription()
+        .freeze_subscription(subscription_id, req.freeze, &auth_account.actor())
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn unfreeze_subscription(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(subscription_id): Path<Uuid>,
+) -> Result<Json<SubscriptionBody<SubscriptionDTO>>> {
+    let subscription = ctx
+        .store
+        .subscription()
+        .get_subscription(subscription_id)
+        .await?;
+    ctx.store
+        .membership()
+        .require_role(
+            subscription.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    let subscription = ctx
+        .store
+        .subscription()
+        .unfreeze_subscription(subscription_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(SubscriptionBody { subscription }))
+}
+
+async fn purchase_class_pack(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<ClassPackBody<NewClassPack>>,
+) -> Result<Json<CreditEntryBody<CreditEntryDTO>>> {
+    let (membership_id, by_staff) = require_purchaser(
+        &ctx,
+        organization_id,
+        auth_account.account_id,
+        req.class_pack.membership_id,
+    )
+    .await?;
+
+    let actor = auth_account.actor();
+    let credit_entry = ctx
+        .store
+        .credit()
+        .purchase_class_pack(
+            membership_id,
+            req.class_pack.plan_id,
+            req.class_pack.promo_code.as_deref(),
+            by_staff.then_some(&actor),
+        )
+        .await?;
+
+    Ok(Json(CreditEntryBody { credit_entry }))
+}
+
+async fn get_credits(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<CreditsBody<CreditBalanceDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let credits = ctx.store.credit().get_balance(membership_id).await?;
+
+    Ok(Json(CreditsBody { credits }))
+}
@@ -0,0 +1,170 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::gift_card::{GiftCardDTO, GiftCardRedemption, NewGiftCard};
+use crate::models::membership::Role;
+use crate::models::wallet::{LedgerDTO, WalletDTO};
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/gift-cards",
+            get(list_gift_cards).post(issue_gift_card),
+        )
+        .route("/api/gift-cards/:gift_card_id", get(get_gift_card))
+        .route("/api/memberships/:membership_id/wallet", get(get_wallet))
+        .route(
+            "/api/memberships/:membership_id/wallet/redemptions",
+            post(redeem_gift_card),
+        )
+        .route(
+            "/api/organizations/:organization_id/ledger",
+            get(get_ledger),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GiftCardBody<T> {
+    gift_card: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GiftCardsBody<T> {
+    gift_cards: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RedemptionBody<T> {
+    redemption: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WalletBody<T> {
+    wallet: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LedgerBody<T> {
+    ledger: T,
+}
+
+/// Gift cards are sold at the front desk, the code on the issued card is what the buyer
+/// gets.
+async fn issue_gift_card(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<GiftCardBody<NewGiftCard>>,
+) -> Result<Json<GiftCardBody<GiftCardDTO>>> {
+    let staff = ctx
+        .store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let gift_card = ctx
+        .store
+        .gift_card()
+        .issue_gift_card(
+            organization_id,
+            staff.id,
+            req.gift_card,
+            &auth_account.actor(),
+        )
+        .await?;
+
+    Ok(Json(GiftCardBody { gift_card }))
+}
+
+async fn list_gift_cards(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<GiftCardsBody<GiftCardDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Staff)
+        .await?;
+
+    let gift_cards = ctx
+        .store
+        .gift_card()
+        .list_gift_cards(organization_id)
+        .await?;
+
+    Ok(Json(GiftCardsBody { gift_cards }))
+}
+
+async fn get_gift_card(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(gift_card_id): Path<Uuid>,
+) -> Result<Json<GiftCardBody<GiftCardDTO>>> {
+    let gift_card = ctx.store.gift_card().get_gift_card(gift_card_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            gift_card.organization_id,
+            auth_account.account_id,
+            Role::Staff,
+        )
+        .await?;
+
+    Ok(Json(GiftCardBody { gift_card }))
+}
+
+async fn get_wallet(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+) -> Result<Json<WalletBody<WalletDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    let wallet = ctx.store.wallet().get_wallet(membership_id).await?;
+
+    Ok(Json(WalletBody { wallet }))
+}
+
+/// Members redeem gift cards themselves, or staff do it for them at the front desk.
+async fn redeem_gift_card(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(membership_id): Path<Uuid>,
+    Json(req): Json<RedemptionBody<GiftCardRedemption>>,
+) -> Result<Json<WalletBody<WalletDTO>>> {
+    let member = ctx.store.membership().get_membership(membership_id).await?;
+    ctx.store
+        .membership()
+        .require_self_or_staff(member.organization_id, auth_account.account_id, member.id)
+        .await?;
+
+    ctx.store
+        .gift_card()
+        .redeem_gift_card(membership_id, req.redemption)
+        .await?;
+    let wallet = ctx.store.wallet().get_wallet(membership_id).await?;
+
+    Ok(Json(WalletBody { wallet }))
+}
+
+async fn get_ledger(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<LedgerBody<LedgerDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let ledger = ctx.store.wallet().get_ledger(organization_id).await?;
+
+    Ok(Json(LedgerBody { ledger }))
+}
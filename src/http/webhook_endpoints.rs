@@ -0,0 +1,227 @@
+use crate::http::extractor::AuthAccount;
+use crate::http::{ApiContext, Result};
+use crate::models::membership::Role;
+use crate::models::webhook::{
+    NewWebhookEndpoint, UpdateWebhookEndpoint, WebhookDeliveryDTO, WebhookEndpointDTO,
+};
+use axum::extract::{Path, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use uuid::Uuid;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/organizations/:organization_id/webhook-endpoints",
+            get(list_webhook_endpoints).post(create_webhook_endpoint),
+        )
+        .route(
+            "/api/webhook-endpoints/:endpoint_id",
+            get(get_webhook_endpoint)
+                .patch(update_webhook_endpoint)
+                .delete(archive_webhook_endpoint),
+        )
+        .route(
+            "/api/webhook-endpoints/:endpoint_id/deliveries",
+            get(list_webhook_deliveries),
+        )
+        .route(
+            "/api/webhook-deliveries/:delivery_id",
+            get(get_webhook_delivery),
+        )
+        .route(
+            "/api/webhook-deliveries/:delivery_id/replay",
+            post(replay_webhook_delivery),
+        )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WebhookEndpointBody<T> {
+    webhook_endpoint: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WebhookEndpointsBody<T> {
+    webhook_endpoints: Vec<T>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WebhookDeliveryBody<T> {
+    webhook_delivery: T,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WebhookDeliveriesBody<T> {
+    webhook_deliveries: Vec<T>,
+}
+
+/// Owners only, like everything about webhooks, as endpoints carry their signing secret.
+/// E.g. `{"webhook_endpoint": {"url": "http://crm.example/hooks", "events":
+/// ["booking.created", "booking.cancelled"]}}`.
+async fn create_webhook_endpoint(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<WebhookEndpointBody<NewWebhookEndpoint>>,
+) -> Result<Json<WebhookEndpointBody<WebhookEndpointDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let webhook_endpoint = ctx
+        .store
+        .webhook()
+        .create_endpoint(organization_id, req.webhook_endpoint, &auth_account.actor())
+        .await?;
+
+    Ok(Json(WebhookEndpointBody { webhook_endpoint }))
+}
+
+async fn list_webhook_endpoints(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<Json<WebhookEndpointsBody<WebhookEndpointDTO>>> {
+    ctx.store
+        .membership()
+        .require_role(organization_id, auth_account.account_id, Role::Owner)
+        .await?;
+
+    let webhook_endpoints = ctx.store.webhook().list_endpoints(organization_id).await?;
+
+    Ok(Json(WebhookEndpointsBody { webhook_endpoints }))
+}
+
+async fn get_webhook_endpoint(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(endpoint_id): Path<Uuid>,
+) -> Result<Json<WebhookEndpointBody<WebhookEndpointDTO>>> {
+    let webhook_endpoint = ctx.store.webhook().get_endpoint(endpoint_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            webhook_endpoint.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    Ok(Json(WebhookEndpointBody { webhook_endpoint }))
+}
+
+async fn update_webhook_endpoint(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(endpoint_id): Path<Uuid>,
+    Json(req): Json<WebhookEndpointBody<UpdateWebhookEndpoint>>,
+) -> Result<Json<WebhookEndpointBody<WebhookEndpointDTO>>> {
+    let webhook_endpoint = ctx.store.webhook().get_endpoint(endpoint_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            webhook_endpoint.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let webhook_endpoint = ctx
+        .store
+        .webhook()
+        .update_endpoint(endpoint_id, req.webhook_endpoint, &auth_account.actor())
+        .await?;
+
+    Ok(Json(WebhookEndpointBody { webhook_endpoint }))
+}
+
+/// Deliveries still waiting to be posted to the endpoint are called off.
+async fn archive_webhook_endpoint(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(endpoint_id): Path<Uuid>,
+) -> Result<Json<WebhookEndpointBody<WebhookEndpointDTO>>> {
+    let webhook_endpoint = ctx.store.webhook().get_endpoint(endpoint_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            webhook_endpoint.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let webhook_endpoint = ctx
+        .store
+        .webhook()
+        .archive_endpoint(endpoint_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(WebhookEndpointBody { webhook_endpoint }))
+}
+
+/// The delivery log, for when a studio's tool says it never got an event.
+async fn list_webhook_deliveries(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(endpoint_id): Path<Uuid>,
+) -> Result<Json<WebhookDeliveriesBody<WebhookDeliveryDTO>>> {
+    let webhook_endpoint = ctx.store.webhook().get_endpoint(endpoint_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            webhook_endpoint.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let webhook_deliveries = ctx.store.webhook().list_deliveries(endpoint_id).await?;
+
+    Ok(Json(WebhookDeliveriesBody { webhook_deliveries }))
+}
+
+async fn get_webhook_delivery(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(delivery_id): Path<Uuid>,
+) -> Result<Json<WebhookDeliveryBody<WebhookDeliveryDTO>>> {
+    let webhook_delivery = ctx.store.webhook().get_delivery(delivery_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            webhook_delivery.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    Ok(Json(WebhookDeliveryBody { webhook_delivery }))
+}
+
+/// Posts the delivery's event again, whether or not it was delivered, as a new delivery
+/// with `replay_of` set.
+async fn replay_webhook_delivery(
+    auth_account: AuthAccount,
+    ctx: State<ApiContext>,
+    Path(delivery_id): Path<Uuid>,
+) -> Result<Json<WebhookDeliveryBody<WebhookDeliveryDTO>>> {
+    let webhook_delivery = ctx.store.webhook().get_delivery(delivery_id).await?;
+    ctx.store
+        .membership()
+        .require_role(
+            webhook_delivery.organization_id,
+            auth_account.account_id,
+            Role::Owner,
+        )
+        .await?;
+
+    let webhook_delivery = ctx
+        .store
+        .webhook()
+        .replay_delivery(delivery_id, &auth_account.actor())
+        .await?;
+
+    Ok(Json(WebhookDeliveryBody { webhook_delivery }))
+}
@@ -0,0 +1,58 @@
+use crate::http::{ApiContext, Error, Result};
+use crate::models::payment_event::PaymentEventDTO;
+use crate::payments::webhook::{self, WebhookProvider};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::routing::post;
+use axum::{Json, Router};
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route(
+        "/api/webhooks/payments/:provider",
+        post(receive_payment_event),
+    )
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PaymentEventBody<T> {
+    payment_event: T,
+}
+
+/// Receives an event from a payment provider, signed with `Config::payment_webhook_secret`.
+///
+/// Only answers with success once the event's changes are committed, so the provider keeps
+/// redelivering it until then. Redelivered events are acknowledged without being applied again.
+async fn receive_payment_event(
+    ctx: State<ApiContext>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<PaymentEventBody<PaymentEventDTO>>> {
+    let provider = WebhookProvider::from_path(&provider).ok_or(Error::NotFound)?;
+    let secret = ctx
+        .config
+        .payment_webhook_secret
+        .as_deref()
+        .ok_or(Error::Unauthorized)?;
+
+    let signature = headers
+        .get(provider.signature_header())
+        .and_then(|h| h.to_str().ok())
+        .ok_or(Error::Unauthorized)?;
+    if !webhook::verify_signature(signature, &body, secret, time::OffsetDateTime::now_utc()) {
+        return Err(Error::Unauthorized);
+    }
+
+    let event = webhook::parse_event(&body)
+        .ok_or_else(|| Error::unprocessable_entity([("event", "is not a payment event")]))?;
+    let payload = String::from_utf8_lossy(&body);
+
+    let payment_event = ctx
+        .store
+        .payment_event()
+        .process_event(provider, event, &payload)
+        .await?;
+
+    Ok(Json(PaymentEventBody { payment_event }))
+}
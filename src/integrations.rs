@@ -0,0 +1,82 @@
+//! Posting organizations' events to the webhook endpoints of the tools they integrate with.
+//!
+//! Deliveries aren't posted from requests but written to the database first, in the same
+//! transaction as the change they report, see `models::webhook`. A job posts them.
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::notify::PublicConnector;
+
+/// The header carrying the signature, `t=<unix time>,v1=<hex HMAC-SHA256 of "t.body">`, the
+/// same scheme payment providers sign their webhooks with.
+pub const SIGNATURE_HEADER: &str = "rustfit-signature";
+
+/// Gives up on an endpoint that stops answering, the delivery is retried later.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Posts to endpoints over https, on public addresses only, see
+/// `notify::public_client`.
+#[derive(Clone)]
+pub struct WebhookClient {
+    client: Client<PublicConnector>,
+}
+
+impl WebhookClient {
+    pub fn new() -> Self {
+        Self {
+            client: crate::notify::public_client(),
+        }
+    }
+
+    /// Posts `body` as JSON to `url`, signed with `secret`. The status the endpoint answers
+    /// with is returned, an error means it didn't answer at all. What it answered is dropped
+    /// unread, it could be anything.
+    pub async fn post(
+        &self,
+        url: &str,
+        secret: &str,
+        body: Vec<u8>,
+        now: OffsetDateTime,
+    ) -> anyhow::Result<StatusCode> {
+        let uri = crate::notify::parse_public_url(url)?;
+        let signature = sign(secret, &body, now.unix_timestamp());
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri.clone())
+            .header("content-type", "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .body(Body::from(body))
+            .context("building webhook request")?;
+
+        let response = tokio::time::timeout(TIMEOUT, self.client.request(request))
+            .await
+            .with_context(|| format!("timed out posting to {uri}"))?
+            .with_context(|| format!("posting to {uri}"))?;
+
+        Ok(response.status())
+    }
+}
+
+impl Default for WebhookClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Signs `body` as sent at `timestamp`, so endpoints can check it came from us and is recent.
+pub fn sign(secret: &str, body: &[u8], timestamp: i64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!(
+        "t={timestamp},v1={}",
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
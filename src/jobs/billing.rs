@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+
+use crate::http::{ApiContext, Result};
+use crate::models::dunning::PaymentAttempt;
+use crate::models::invoice::InvoiceDTO;
+use crate::models::job::{JobDTO, JobKind, JobPayload};
+use crate::payments::NewCharge;
+
+use super::queue::JobHandler;
+
+/// Renews, charges and meters, see `run`.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(super) struct Billing;
+
+impl JobPayload for Billing {
+    const KIND: JobKind = JobKind::Billing;
+}
+
+#[async_trait]
+impl JobHandler for Billing {
+    fn every(ctx: &ApiContext) -> Option<Duration> {
+        Some(super::interval(ctx))
+    }
+
+    async fn run(self, ctx: &ApiContext, _job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        run(ctx, now).await
+    }
+}
+
+/// Starts and ends freezes, renews subscriptions whose period ended, charges invoices whose
+/// payment is due, expires class credits and meters organizations' platform usage. One
+/// subscription or invoice failing doesn't hold up the rest.
+async fn run(ctx: &ApiContext, now: OffsetDateTime) -> Result<()> {
+    // Freezes first, so a subscription resuming today has its renewal pushed back before
+    // it would be renewed.
+    for subscription in ctx.store.subscription().list_due_freezes(now).await? {
+        if let Err(e) = ctx
+            .store
+            .subscription()
+            .apply_freeze(subscription.id, now)
+            .await
+        {
+            tracing::error!(subscription_id = %subscription.id, "failed to apply freeze: {e:?}");
+        }
+    }
+
+    for subscription in ctx.store.subscription().list_due_subscriptions(now).await? {
+        if let Err(e) = ctx
+            .store
+            .subscription()
+            .roll_over_subscription(subscription.id, now)
+            .await
+        {
+            tracing::error!(subscription_id = %subscription.id, "failed to roll over subscription: {e:?}");
+        }
+    }
+
+    for invoice in ctx.store.dunning().list_collectable_invoices(now).await? {
+        if let Err(e) = collect(ctx, &invoice, now).await {
+            tracing::error!(invoice_id = %invoice.id, "failed to collect invoice: {e:?}");
+        }
+    }
+
+    let expired = ctx.store.credit().expire_credits(now).await?;
+    if expired > 0 {
+        tracing::info!("expired credits on {expired} class packs");
+    }
+
+    ctx.store.platform_subscription().meter_usage(now).await?;
+
+    Ok(())
+}
+
+/// Pays the invoice out of the member's wallet if it holds enough, or else charges their
+/// saved payment method. When the provider can't be reached the attempt isn't counted and
+/// is made again on the next run.
+async fn collect(ctx: &ApiContext, invoice: &InvoiceDTO, now: OffsetDateTime) -> Result<()> {
+    if ctx
+        .store
+        .wallet()
+        .pay_invoice(invoice.id, now)
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
+
+    let profile = ctx
+        .store
+        .payment_profile()
+        .get_payment_profile(invoice.membership_id)
+        .await?;
+
+    let attempt = match profile.and_then(|p| Some((p.customer_id, p.payment_method_id?))) {
+        Some((customer_id, payment_method_id)) => {
+            let charge = ctx
+                .payments
+                .charge(NewCharge {
+                    invoice_id: invoice.id,
+                    customer_id,
+                    payment_method_id: Some(payment_method_id),
+                    amount: invoice.total_amount,
+                    currency: invoice.currency.clone(),
+                    description: format!("Invoice #{}", invoice.number),
+                    idempotency_key: format!(
+                        "invoice-{}-attempt-{}",
+                        invoice.id,
+                        invoice.payment_attempts + 1
+                    ),
+                })
+                .await;
+
+            PaymentAttempt::from_charge(charge)?
+        }
+        None => PaymentAttempt::Failed {
+            charge_id: None,
+            message: "no payment method on file".to_string(),
+        },
+    };
+
+    ctx.store
+        .dunning()
+        .record_payment_attempt(invoice.id, attempt, now)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! Work that runs in the background rather than in response to a request, from a queue of
+//! jobs stored in the database.
+
+use time::{Duration, OffsetDateTime};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::http::ApiContext;
+use crate::models::job::JobKind;
+
+mod billing;
+mod no_shows;
+mod notifications;
+mod outbox;
+mod queue;
+mod reminders;
+mod sessions;
+mod webhooks;
+
+/// Jobs that keep running again for as long as the server is up.
+const RECURRING: [JobKind; 5] = [
+    JobKind::Billing,
+    JobKind::EmailOutbox,
+    JobKind::NotificationDelivery,
+    JobKind::SessionReaping,
+    JobKind::NoShowMarking,
+];
+
+/// Schedules the recurring jobs to run right away, and starts `job_workers` workers on the
+/// queue. They stop once `shutdown` is set, after finishing the jobs they are running.
+pub fn spawn(ctx: ApiContext, shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let now = OffsetDateTime::now_utc();
+        for kind in RECURRING {
+            if let Err(e) = ctx.store.job().schedule_recurring_job(kind, now).await {
+                tracing::error!(%kind, "failed to schedule recurring job: {e:?}");
+            }
+        }
+
+        let workers: Vec<_> = (0..ctx.config.job_workers.max(1))
+            .map(|_| tokio::spawn(queue::work(ctx.clone(), shutdown.clone())))
+            .collect();
+        for worker in workers {
+            if let Err(e) = worker.await {
+                tracing::error!("job worker panicked: {e:?}");
+            }
+        }
+    })
+}
+
+/// How often the recurring jobs that keep billing and delivery going run.
+fn interval(ctx: &ApiContext) -> Duration {
+    Duration::seconds(ctx.config.job_interval_seconds as i64)
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+
+use crate::http::{ApiContext, Result};
+use crate::models::job::{JobDTO, JobKind, JobPayload};
+
+use super::queue::JobHandler;
+
+/// Members who booked a class but never checked in are marked as no-shows once it ends,
+/// getting the organization's no-show penalty.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(super) struct NoShowMarking;
+
+impl JobPayload for NoShowMarking {
+    const KIND: JobKind = JobKind::NoShowMarking;
+}
+
+#[async_trait]
+impl JobHandler for NoShowMarking {
+    fn every(ctx: &ApiContext) -> Option<Duration> {
+        Some(super::interval(ctx))
+    }
+
+    async fn run(self, ctx: &ApiContext, _job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        for booking in ctx.store.booking().list_unattended_bookings(now).await? {
+            if let Err(e) = ctx.store.booking().mark_no_show(booking.id, None).await {
+                tracing::error!(booking_id = %booking.id, "failed to mark no-show: {e:?}");
+            }
+        }
+
+        Ok(())
+    }
+}
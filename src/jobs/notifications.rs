@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+
+use crate::http::{ApiContext, Result};
+use crate::models::job::{JobDTO, JobKind, JobPayload};
+use crate::models::notification::NotificationChannelKind;
+use crate::notify::{DeliveryError, Notification};
+
+use super::queue::JobHandler;
+
+/// Delivers the notifications due, see `run`.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(super) struct NotificationDelivery;
+
+impl JobPayload for NotificationDelivery {
+    const KIND: JobKind = JobKind::NotificationDelivery;
+}
+
+#[async_trait]
+impl JobHandler for NotificationDelivery {
+    fn every(ctx: &ApiContext) -> Option<Duration> {
+        Some(super::interval(ctx))
+    }
+
+    async fn run(self, ctx: &ApiContext, _job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        run(ctx, now).await
+    }
+}
+
+/// Hands the notifications due to their channels. One notification failing doesn't hold up
+/// the rest, it is retried later unless its channel rejected it for good.
+async fn run(ctx: &ApiContext, now: OffsetDateTime) -> Result<()> {
+    for notification in ctx.store.notification().list_due_notifications(now).await? {
+        let channel = match notification.channel {
+            NotificationChannelKind::Email => &ctx.notifiers.email,
+            NotificationChannelKind::Sms => &ctx.notifiers.sms,
+            NotificationChannelKind::Webhook => &ctx.notifiers.webhook,
+        };
+        let delivery = Notification {
+            id: notification.id,
+            event: notification.event.to_string(),
+            organization_id: notification.organization_id,
+            membership_id: notification.membership_id,
+            recipient: notification.recipient,
+            from_name: notification.from_name,
+            subject: notification.subject,
+            body: notification.body,
+            data: notification.data.0.into(),
+            created_at: notification.inserted_at,
+        };
+
+        let recorded = match channel.deliver(&delivery).await {
+            Ok(()) => {
+                ctx.store
+                    .notification()
+                    .record_sent(notification.id, now)
+                    .await
+            }
+            Err(e) => {
+                tracing::warn!(
+                    notification_id = %notification.id,
+                    "failed to deliver notification: {e:?}"
+                );
+                let (error, permanent) = match e {
+                    DeliveryError::Rejected(message) => (message, true),
+                    DeliveryError::Unavailable(e) => (format!("{e:#}"), false),
+                };
+                ctx.store
+                    .notification()
+                    .record_failure(notification.id, &error, permanent, now)
+                    .await
+            }
+        };
+        if let Err(e) = recorded {
+            tracing::error!(
+                notification_id = %notification.id,
+                "failed to record notification delivery: {e:?}"
+            );
+        }
+    }
+
+    Ok(())
+}
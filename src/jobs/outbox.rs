@@ -0,0 +1,174 @@
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+
+use crate::http::{ApiContext, Result};
+use crate::mail::{MailError, Message};
+use crate::models::job::{JobDTO, JobKind, JobPayload};
+
+use super::queue::JobHandler;
+
+/// Sends the emails due in the outbox, see `run`.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(super) struct EmailOutbox;
+
+impl JobPayload for EmailOutbox {
+    const KIND: JobKind = JobKind::EmailOutbox;
+}
+
+#[async_trait]
+impl JobHandler for EmailOutbox {
+    fn every(ctx: &ApiContext) -> Option<Duration> {
+        Some(super::interval(ctx))
+    }
+
+    async fn run(self, ctx: &ApiContext, _job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        run(ctx, now).await
+    }
+}
+
+/// Hands the emails due in the outbox to the mailer. One email failing doesn't hold up the
+/// rest, it is retried later unless the mail server rejected it for good.
+async fn run(ctx: &ApiContext, now: OffsetDateTime) -> Result<()> {
+    for email in ctx.store.email().list_due_emails(now).await? {
+        let message = Message {
+            id: email.id,
+            from_name: email.from_name,
+            from_address: ctx.config.mail_from.clone(),
+            to_address: email.to_address,
+            subject: email.subject,
+            body: email.body,
+            date: email.inserted_at,
+        };
+
+        let recorded = match ctx.mailer.send(&message).await {
+            Ok(()) => ctx.store.email().record_sent(email.id, now).await,
+            Err(e) => {
+                tracing::warn!(email_id = %email.id, "failed to send email: {e:?}");
+                let (error, permanent) = match e {
+                    MailError::Rejected(message) => (message, true),
+                    MailError::Unavailable(e) => (format!("{e:#}"), false),
+                };
+                ctx.store
+                    .email()
+                    .record_failure(email.id, &error, permanent, now)
+                    .await
+            }
+        };
+        if let Err(e) = recorded {
+            tracing::error!(email_id = %email.id, "failed to record email delivery: {e:?}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::integrations::WebhookClient;
+    use crate::mail::{DynMailer, FileMailer};
+    use crate::models::account::NewAccount;
+    use crate::models::email::{self, EmailStatus, EmailTemplateKind, NewEmail};
+    use crate::models::organization::NewOrganization;
+    use crate::models::{DynStore, Store};
+    use crate::payments::FakeGateway;
+
+    /// An email is retried after the mailer fails, and marked sent once it goes through.
+    #[tokio::test]
+    async fn retries_until_sent() {
+        let pool = crate::models::test_pool().await;
+
+        let mail_dir =
+            std::env::temp_dir().join(format!("rustfit-outbox-{}", uuid::Uuid::new_v4()));
+        let mailer = Arc::new(FileMailer::new(mail_dir.to_str().unwrap()).unwrap()) as DynMailer;
+        let config = Config {
+            mail_from: "no-reply@rustfit.localhost".to_string(),
+            ..Config::default()
+        };
+        let ctx = ApiContext {
+            notifiers: crate::notify::from_config(&config, mailer.clone()).unwrap(),
+            config: Arc::new(config),
+            store: Arc::new(Store::new(pool.clone())) as DynStore,
+            payments: Arc::new(FakeGateway::new()),
+            mailer,
+            webhooks: WebhookClient::new(),
+        };
+
+        let account = ctx
+            .store
+            .account()
+            .create_account(NewAccount {
+                name: "Ada".to_string(),
+                email: "ada@example.com".to_string(),
+                password: "password".to_string(),
+            })
+            .await
+            .unwrap();
+        let organization = ctx
+            .store
+            .organization()
+            .create_organization(
+                account.id,
+                NewOrganization {
+                    name: "Studio".to_string(),
+                    waitlist_cutoff_minutes: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let now = OffsetDateTime::now_utc();
+        let queued = email::enqueue(
+            &mut pool.acquire().await.unwrap(),
+            NewEmail {
+                organization_id: organization.id,
+                to_address: "ada@example.com".to_string(),
+                template: EmailTemplateKind::MembershipInvitation,
+                variables: vec![
+                    ("member_name", "Ada".to_string()),
+                    ("role", "member".to_string()),
+                ],
+            },
+            now,
+        )
+        .await
+        .unwrap();
+
+        // The mail directory going away makes writing the message fail.
+        std::fs::remove_dir_all(&mail_dir).unwrap();
+        run(&ctx, now).await.unwrap();
+
+        let emails = ctx
+            .store
+            .email()
+            .list_emails(organization.id)
+            .await
+            .unwrap();
+        let failed = emails.iter().find(|e| e.id == queued.id).unwrap();
+        assert_eq!(failed.status, EmailStatus::Pending);
+        assert_eq!(failed.attempts, 1);
+        assert!(failed.last_error.is_some());
+        let retry_at = failed.next_attempt_at.unwrap();
+        assert!(retry_at > now);
+
+        std::fs::create_dir_all(&mail_dir).unwrap();
+        run(&ctx, retry_at).await.unwrap();
+
+        let emails = ctx
+            .store
+            .email()
+            .list_emails(organization.id)
+            .await
+            .unwrap();
+        let sent = emails.iter().find(|e| e.id == queued.id).unwrap();
+        assert_eq!(sent.status, EmailStatus::Sent);
+        assert_eq!(sent.attempts, 2);
+        assert_eq!(sent.sent_at, Some(retry_at));
+        assert!(mail_dir.join(format!("{}.eml", queued.id)).exists());
+
+        std::fs::remove_dir_all(&mail_dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::Duration as StdDuration;
+
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::watch;
+
+use crate::http::{ApiContext, Error, Result};
+use crate::models::job::{JobDTO, JobKind, JobPayload, JobStatus};
+use crate::models::reminder::ClassReminder;
+use crate::models::webhook::WebhookDelivery;
+
+use super::billing::Billing;
+use super::no_shows::NoShowMarking;
+use super::notifications::NotificationDelivery;
+use super::outbox::EmailOutbox;
+use super::sessions::SessionReaping;
+
+/// How long an idle worker waits before looking for due jobs again.
+const POLL_INTERVAL: StdDuration = StdDuration::from_secs(1);
+
+/// Runs the jobs of the kind its payload is for.
+#[async_trait]
+pub(super) trait JobHandler: JobPayload + Sync {
+    /// Recurring jobs are run again this long after each run, instead of being done. A
+    /// failed run is retried then too, they are never dead-lettered.
+    fn every(_ctx: &ApiContext) -> Option<Duration> {
+        None
+    }
+
+    /// Does the job's work. The job is marked done afterwards, unless the handler did so
+    /// itself with `job::complete`.
+    async fn run(self, ctx: &ApiContext, job: &JobDTO, now: OffsetDateTime) -> Result<()>;
+}
+
+/// Claims and runs due jobs one at a time until `shutdown` is set. A job that is running
+/// then is finished first.
+pub(super) async fn work(ctx: ApiContext, mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        let now = OffsetDateTime::now_utc();
+        match ctx.store.job().claim_next_job(now).await {
+            Ok(Some(job)) => {
+                dispatch(&ctx, &job, now).await;
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("failed to claim job: {e:?}"),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            changed = shutdown.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn dispatch(ctx: &ApiContext, job: &JobDTO, now: OffsetDateTime) {
+    match job.kind {
+        JobKind::ClassReminder => run_job::<ClassReminder>(ctx, job, now).await,
+        JobKind::Billing => run_job::<Billing>(ctx, job, now).await,
+        JobKind::EmailOutbox => run_job::<EmailOutbox>(ctx, job, now).await,
+        JobKind::NotificationDelivery => run_job::<NotificationDelivery>(ctx, job, now).await,
+        JobKind::SessionReaping => run_job::<SessionReaping>(ctx, job, now).await,
+        JobKind::NoShowMarking => run_job::<NoShowMarking>(ctx, job, now).await,
+        JobKind::WebhookDelivery => run_job::<WebhookDelivery>(ctx, job, now).await,
+    }
+}
+
+/// Runs a claimed job with its handler and records how it went. One job failing doesn't
+/// hold up the rest, it is retried later with backoff.
+async fn run_job<H: JobHandler>(ctx: &ApiContext, job: &JobDTO, now: OffsetDateTime) {
+    let result = match serde_json::from_value::<H>(job.payload.0.clone()) {
+        Ok(payload) => payload.run(ctx, job, now).await,
+        Err(e) => {
+            // Trying again won't make the payload any more readable.
+            let error = format!("invalid payload: {e}");
+            tracing::error!(job_id = %job.id, kind = %job.kind, "{error}");
+            if let Err(e) = ctx
+                .store
+                .job()
+                .record_failure(job.id, &error, true, now)
+                .await
+            {
+                tracing::error!(job_id = %job.id, "failed to record job failure: {e:?}");
+            }
+            return;
+        }
+    };
+    if let Err(e) = &result {
+        tracing::warn!(job_id = %job.id, kind = %job.kind, "job failed: {e:?}");
+    }
+
+    let recorded = match (H::every(ctx), result) {
+        (Some(every), result) => {
+            let error = result.err().map(|e| describe(&e));
+            ctx.store
+                .job()
+                .reschedule_job(job.id, now + every, error.as_deref(), now)
+                .await
+        }
+        (None, Ok(())) => ctx.store.job().finish_job(job.id, now).await,
+        (None, Err(e)) => ctx
+            .store
+            .job()
+            .record_failure(job.id, &describe(&e), false, now)
+            .await
+            .map(|job| {
+                if job.status == JobStatus::Dead {
+                    tracing::error!(
+                        job_id = %job.id,
+                        kind = %job.kind,
+                        "job dead-lettered after {} attempts",
+                        job.attempts
+                    );
+                }
+            }),
+    };
+    if let Err(e) = recorded {
+        tracing::error!(job_id = %job.id, "failed to record job run: {e:?}");
+    }
+}
+
+/// What went wrong, for the job's `last_error`. Unlike responses, it shows internal errors.
+fn describe(error: &Error) -> String {
+    match error {
+        Error::Sqlx(e) => e.to_string(),
+        Error::Anyhow(e) => format!("{e:#}"),
+        e => e.to_string(),
+    }
+}
@@ -0,0 +1,18 @@
+use async_trait::async_trait;
+use time::OffsetDateTime;
+
+use crate::http::{ApiContext, Result};
+use crate::models::job::JobDTO;
+use crate::models::reminder::ClassReminder;
+
+use super::queue::JobHandler;
+
+#[async_trait]
+impl JobHandler for ClassReminder {
+    async fn run(self, ctx: &ApiContext, job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        ctx.store
+            .reminder()
+            .send_class_reminder(job.id, self, now)
+            .await
+    }
+}
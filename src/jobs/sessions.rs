@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+use time::{Duration, OffsetDateTime};
+
+use crate::http::{ApiContext, Result};
+use crate::models::job::{JobDTO, JobKind, JobPayload};
+
+use super::queue::JobHandler;
+
+/// Sessions are only looked at when they are used, so the stale ones pile up without this.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(super) struct SessionReaping;
+
+impl JobPayload for SessionReaping {
+    const KIND: JobKind = JobKind::SessionReaping;
+}
+
+#[async_trait]
+impl JobHandler for SessionReaping {
+    fn every(_ctx: &ApiContext) -> Option<Duration> {
+        Some(Duration::hours(1))
+    }
+
+    async fn run(self, ctx: &ApiContext, _job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        let deleted = ctx
+            .store
+            .account_session()
+            .delete_stale_account_sessions(now)
+            .await?;
+        if deleted > 0 {
+            tracing::info!("deleted {deleted} stale account sessions");
+        }
+
+        Ok(())
+    }
+}
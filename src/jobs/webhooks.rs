@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+use time::OffsetDateTime;
+
+use crate::http::{ApiContext, Result};
+use crate::models::job::{JobDTO, MAX_ATTEMPTS};
+use crate::models::webhook::{DeliveryAttempt, WebhookDelivery, WebhookDeliveryStatus};
+
+use super::queue::JobHandler;
+
+/// Posts a delivery to its endpoint. Anything but a 2xx answer fails the job, so the queue
+/// retries it with backoff until it runs out of attempts.
+#[async_trait]
+impl JobHandler for WebhookDelivery {
+    async fn run(self, ctx: &ApiContext, job: &JobDTO, now: OffsetDateTime) -> Result<()> {
+        let delivery = ctx.store.webhook().get_delivery(self.delivery_id).await?;
+        if delivery.status != WebhookDeliveryStatus::Pending {
+            return Ok(());
+        }
+        let endpoint = ctx
+            .store
+            .webhook()
+            .get_endpoint(delivery.endpoint_id)
+            .await?;
+
+        // Archiving an endpoint calls off what was still to be posted to it.
+        if endpoint.archived_at.is_some() {
+            let attempt = DeliveryAttempt {
+                response_status: None,
+                error: Some("endpoint was archived".to_string()),
+            };
+            ctx.store
+                .webhook()
+                .record_delivery_attempt(delivery.id, attempt, true, now)
+                .await?;
+            return Ok(());
+        }
+
+        let body = serde_json::to_vec(&delivery.payload.0)
+            .map_err(|e| anyhow::anyhow!("failed to encode delivery {}: {e}", delivery.id))?;
+        let attempt = match ctx
+            .webhooks
+            .post(&endpoint.url, &endpoint.secret, body, now)
+            .await
+        {
+            Ok(status) => DeliveryAttempt {
+                response_status: Some(status.as_u16()),
+                error: (!status.is_success()).then(|| format!("endpoint responded {status}")),
+            },
+            Err(e) => DeliveryAttempt {
+                response_status: None,
+                error: Some(format!("{e:#}")),
+            },
+        };
+
+        let error = attempt.error.clone();
+        ctx.store
+            .webhook()
+            .record_delivery_attempt(delivery.id, attempt, job.attempts >= MAX_ATTEMPTS, now)
+            .await?;
+
+        match error {
+            Some(error) => Err(anyhow::anyhow!(error).into()),
+            None => Ok(()),
+        }
+    }
+}
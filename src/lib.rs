@@ -1,3 +1,8 @@
 pub mod config;
 pub mod http;
+pub mod integrations;
+pub mod jobs;
+pub mod mail;
 pub mod models;
+pub mod notify;
+pub mod payments;
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use async_trait::async_trait;
+
+use super::{Mailer, Message, Result};
+
+/// Logs each message instead of sending it, for development.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: &Message) -> Result<()> {
+        tracing::info!(
+            to = %message.to_address,
+            subject = %message.subject,
+            "email not sent, the log mailer is in use:\n{}",
+            message.body
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes each message to `<dir>/<id>.eml` instead of sending it, for development. The
+/// files open in any mail client.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: &str) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create mail directory {dir:?}"))?;
+
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: &Message) -> Result<()> {
+        let path = self.dir.join(format!("{}.eml", message.id));
+        tokio::fs::write(&path, message.to_rfc5322())
+            .await
+            .with_context(|| format!("failed to write {path:?}"))?;
+
+        Ok(())
+    }
+}
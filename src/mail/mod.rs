@@ -0,0 +1,135 @@
+//! Sending email to members and staff.
+//!
+//! Like payments, handlers and jobs only ever see `DynMailer`, and which one is used is
+//! decided once, from `Config`, when the server starts. Email isn't sent from requests but
+//! written to the outbox first, see `models::email`.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::config::{Config, MailerKind};
+
+mod file;
+mod smtp;
+
+pub use file::{FileMailer, LogMailer};
+pub use smtp::SmtpMailer;
+
+pub type DynMailer = Arc<dyn Mailer + Send + Sync>;
+
+#[async_trait]
+pub trait Mailer {
+    async fn send(&self, message: &Message) -> Result<()>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    /// The server refused the message for good, e.g. an address that doesn't exist.
+    /// Sending it again won't help.
+    #[error("mail server rejected the message: {0}")]
+    Rejected(String),
+
+    /// The server couldn't be reached or asked us to try again later.
+    #[error("mail server unavailable")]
+    Unavailable(#[from] anyhow::Error),
+}
+
+pub type Result<T, E = MailError> = std::result::Result<T, E>;
+
+/// A plain text email.
+pub struct Message {
+    /// Sent as the `Message-ID`, so a message sent twice after a retry reads as one.
+    pub id: Uuid,
+    /// Shown as the sender, with the address from `Config::mail_from`.
+    pub from_name: String,
+    pub from_address: String,
+    pub to_address: String,
+    pub subject: String,
+    pub body: String,
+    /// When the message was written, sent as its `Date`.
+    pub date: OffsetDateTime,
+}
+
+impl Message {
+    /// The message as sent over the wire, with CRLF line endings.
+    pub fn to_rfc5322(&self) -> String {
+        let date = self
+            .date
+            .format(&Rfc2822)
+            .unwrap_or_else(|_| self.date.to_string());
+        let domain = self
+            .from_address
+            .rsplit_once('@')
+            .map_or("localhost", |(_, domain)| domain);
+
+        let mut message = String::new();
+        for (name, value) in [
+            ("Date", date),
+            (
+                "From",
+                format!(
+                    "{} <{}>",
+                    encode_header(&self.from_name),
+                    header_value(&self.from_address)
+                ),
+            ),
+            ("To", header_value(&self.to_address)),
+            ("Subject", encode_header(&self.subject)),
+            ("Message-ID", format!("<{}@{}>", self.id, domain)),
+            ("MIME-Version", "1.0".to_string()),
+            ("Content-Type", "text/plain; charset=utf-8".to_string()),
+            ("Content-Transfer-Encoding", "8bit".to_string()),
+        ] {
+            message.push_str(&format!("{name}: {value}\r\n"));
+        }
+        message.push_str("\r\n");
+        for line in self.body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+
+        message
+    }
+}
+
+/// Drops line breaks, which would let a value start headers of its own.
+fn header_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// Encodes a header value that isn't plain ASCII as an RFC 2047 encoded word.
+fn encode_header(value: &str) -> String {
+    let value = header_value(value);
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return value;
+    }
+
+    let mut encoded = String::from("=?utf-8?q?");
+    for byte in value.bytes() {
+        match byte {
+            b' ' => encoded.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'*' | b'+' | b'-' | b'/' => {
+                encoded.push(char::from(byte))
+            }
+            _ => encoded.push_str(&format!("={byte:02X}")),
+        }
+    }
+    encoded.push_str("?=");
+
+    encoded
+}
+
+/// The mailer `config` asks for.
+pub fn from_config(config: &Config) -> anyhow::Result<DynMailer> {
+    Ok(match config.mailer {
+        MailerKind::Log => Arc::new(LogMailer) as DynMailer,
+        MailerKind::File => Arc::new(FileMailer::new(&config.mail_dir)?) as DynMailer,
+        MailerKind::Smtp => {
+            Arc::new(SmtpMailer::new(&config.smtp_host, config.smtp_port)) as DynMailer
+        }
+    })
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{header_value, MailError, Mailer, Message, Result};
+
+/// Gives up on a server that stops answering, the message is retried later.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Hands messages to an SMTP server, e.g. Mailpit in development.
+///
+/// Only plain SMTP without authentication is supported, reach a provider through a local
+/// relay that handles TLS and credentials.
+pub struct SmtpMailer {
+    host: String,
+    port: u16,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    async fn deliver(&self, message: &Message) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("failed to connect to {}:{}", self.host, self.port))?;
+        let mut conn = Connection {
+            stream: BufReader::new(stream),
+        };
+
+        let from = header_value(&message.from_address);
+        let to = header_value(&message.to_address);
+        let domain = from.rsplit_once('@').map_or("localhost", |(_, d)| d);
+
+        conn.reply(220).await?;
+        conn.command(&format!("EHLO {domain}"), 250).await?;
+        conn.command(&format!("MAIL FROM:<{from}>"), 250).await?;
+        conn.command(&format!("RCPT TO:<{to}>"), 250).await?;
+        conn.command("DATA", 354).await?;
+
+        let mut data = String::new();
+        for line in message.to_rfc5322().split_terminator("\r\n") {
+            // A line of its own starting with a dot would otherwise end the message early.
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        conn.write(&data).await?;
+        conn.reply(250).await?;
+
+        // The message was accepted, a server hanging up without saying goodbye is fine.
+        let _ = conn.command("QUIT", 221).await;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: &Message) -> Result<()> {
+        tokio::time::timeout(TIMEOUT, self.deliver(message))
+            .await
+            .context("timed out talking to the mail server")?
+    }
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn write(&mut self, data: &str) -> Result<()> {
+        self.stream
+            .get_mut()
+            .write_all(data.as_bytes())
+            .await
+            .context("failed to write to the mail server")?;
+
+        Ok(())
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String> {
+        self.write(&format!("{command}\r\n")).await?;
+        self.reply(expected).await
+    }
+
+    /// Reads the server's reply, whose lines up to the last are marked by a dash after the
+    /// code, e.g. `250-SIZE` then `250 OK`. Permanent failures (5xx) reject the message,
+    /// anything else unexpected is worth trying again.
+    async fn reply(&mut self, expected: u16) -> Result<String> {
+        let mut text = Vec::new();
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .await
+                .context("failed to read from the mail server")?;
+            if read == 0 {
+                return Err(anyhow::anyhow!("the mail server closed the connection").into());
+            }
+
+            let line = line.trim_end();
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .with_context(|| format!("unexpected reply from the mail server: {line:?}"))?;
+            text.push(line.get(4..).unwrap_or_default().to_string());
+
+            if line.get(3..4) == Some("-") {
+                continue;
+            }
+
+            let text = text.join(" ");
+            return match code {
+                // 251 is "user not local, will forward".
+                code if code == expected || (expected == 250 && code == 251) => Ok(text),
+                500..=599 => Err(MailError::Rejected(format!("{code} {text}"))),
+                _ => Err(
+                    anyhow::anyhow!("unexpected reply from the mail server: {code} {text}").into(),
+                ),
+            };
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use clap::Parser;
 use rustfit::config::Config;
 use rustfit::http;
-use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use std::str::FromStr;
 use tracing::info;
 
 #[tokio::main]
@@ -10,16 +11,17 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let config = Config::parse();
-    let db = SqlitePoolOptions::new()
-        .connect(&config.database_url)
-        .await?;
+    // Write-ahead logging lets requests read while the job workers write.
+    let options =
+        SqliteConnectOptions::from_str(&config.database_url)?.journal_mode(SqliteJournalMode::Wal);
+    let db = SqlitePoolOptions::new().connect_with(options).await?;
 
     info!("connected to db");
     sqlx::migrate!().run(&db).await?;
     info!("ran migrations");
 
     http::serve(config, db).await?;
-    info!("serving application");
+    info!("shut down");
 
     Ok(())
 }
@@ -10,6 +10,10 @@ use time::OffsetDateTime;
 use uuid::Uuid;
 
 use super::account_session;
+use super::event::{self, EventType};
+
+/// How long a session created by logging in stays valid.
+const SESSION_LENGTH: time::Duration = time::Duration::weeks(2);
 
 #[derive(serde::Deserialize)]
 pub struct NewAccount {
@@ -91,6 +95,10 @@ pub trait AccountCtrlTrait {
     ) -> Result<AccountWithAccountSessionDTO>;
 
     async fn get_account_by_email(&self, email: String) -> Result<AccountWithPasswordHashDTO>;
+
+    async fn find_account_by_email(&self, email: String) -> Result<Option<AccountDTO>>;
+
+    async fn get_account(&self, id: Uuid) -> Result<AccountDTO>;
 }
 
 #[async_trait]
@@ -99,6 +107,7 @@ impl AccountCtrlTrait for AccountController {
         let id = uuid::Uuid::new_v4();
         let password_hash = Account::hash_password(new_account.password.clone()).await?;
         let inserted_at = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
 
         let account = sqlx::query_as!(
             AccountDTO,
@@ -118,9 +127,20 @@ impl AccountCtrlTrait for AccountController {
             inserted_at,
             inserted_at
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            None,
+            EventType::AccountCreated,
+            &account,
+            inserted_at,
+        )
         .await?;
 
+        tx.commit().await?;
+
         Ok(account)
     }
 
@@ -134,7 +154,7 @@ impl AccountCtrlTrait for AccountController {
 
         let account_session_create = account_session::AccountSessionCreate {
             account_id: account.id,
-            expires_at: time::OffsetDateTime::now_utc(),
+            expires_at: time::OffsetDateTime::now_utc() + SESSION_LENGTH,
         };
 
         let account_session = &self
@@ -165,6 +185,37 @@ impl AccountCtrlTrait for AccountController {
 
         Ok(account)
     }
+
+    async fn find_account_by_email(&self, email: String) -> Result<Option<AccountDTO>> {
+        let account = sqlx::query_as!(
+            AccountDTO,
+            r#"select
+                id as "id: Uuid", name, email,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from accounts
+            where email = $1"#,
+            email
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(account)
+    }
+
+    async fn get_account(&self, id: Uuid) -> Result<AccountDTO> {
+        sqlx::query_as!(
+            AccountDTO,
+            r#"select
+                id as "id: Uuid", name, email,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from accounts
+            where id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
 }
 
 impl Account {
@@ -1,23 +1,13 @@
 use std::sync::Arc;
 
-use crate::http::{Error, Result};
-use anyhow::Context;
-use argon2::{password_hash::SaltString, Argon2, PasswordHash};
+use crate::http::Result;
 use async_trait::async_trait;
 
 use sqlx::SqlitePool;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
-struct AccountSession {
-    id: Uuid,
-    account_id: Uuid,
-    expires_at: OffsetDateTime,
-    active: i64,
-    inserted_at: OffsetDateTime,
-    updated_at: OffsetDateTime,
-}
+use super::event::{self, EventType};
 
 #[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
 pub struct AccountSessionDTO {
@@ -29,7 +19,9 @@ pub struct AccountSessionDTO {
     pub updated_at: OffsetDateTime,
 }
 
-#[derive(serde::Deserialize)]
+/// Also the data of the `session.opened` event, which leaves out the session's id as it is
+/// the account's token.
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct AccountSessionCreate {
     pub account_id: Uuid,
     pub expires_at: OffsetDateTime,
@@ -58,6 +50,11 @@ pub trait AccountSessionCtrlTrait {
         &self,
         account_session_create: AccountSessionCreate,
     ) -> Result<AccountSessionDTO>;
+
+    async fn get_account_session(&self, id: Uuid) -> Result<Option<AccountSessionDTO>>;
+
+    /// Deletes the sessions that expired or were logged out of, returning how many.
+    async fn delete_stale_account_sessions(&self, now: OffsetDateTime) -> Result<u64>;
 }
 
 #[async_trait]
@@ -67,10 +64,12 @@ impl AccountSessionCtrlTrait for AccountSessionController {
         account_session_create: AccountSessionCreate,
     ) -> Result<AccountSessionDTO> {
         let id = uuid::Uuid::new_v4();
-        let expires_at = time::OffsetDateTime::now_utc();
         let inserted_at = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
 
-        let account_session = sqlx::query_as!(AccountSessionDTO, r#"
+        let account_session = sqlx::query_as!(
+            AccountSessionDTO,
+            r#"
             insert into "account_sessions" (
                 id, account_id,
                 expires_at, active,
@@ -85,14 +84,55 @@ impl AccountSessionCtrlTrait for AccountSessionController {
                 updated_at as "updated_at: OffsetDateTime""#,
             id,
             account_session_create.account_id,
-            expires_at,
+            account_session_create.expires_at,
             1,
             inserted_at,
             inserted_at
-            )
-            .fetch_one(&self.pool)
-            .await?;
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            None,
+            EventType::SessionOpened,
+            &account_session_create,
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(account_session)
+    }
+
+    async fn get_account_session(&self, id: Uuid) -> Result<Option<AccountSessionDTO>> {
+        let account_session = sqlx::query_as!(
+            AccountSessionDTO,
+            r#"select
+                id as "id: Uuid", account_id as "account_id: Uuid",
+                expires_at as "expires_at: OffsetDateTime", active,
+                inserted_at as "inserted_at: OffsetDateTime",
+                updated_at as "updated_at: OffsetDateTime"
+            from account_sessions
+            where id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
 
         Ok(account_session)
     }
+
+    async fn delete_stale_account_sessions(&self, now: OffsetDateTime) -> Result<u64> {
+        let deleted = sqlx::query!(
+            "delete from account_sessions where active = 0 or expires_at <= $1",
+            now
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(deleted)
+    }
 }
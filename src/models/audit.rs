@@ -0,0 +1,425 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use serde_json::{Map, Value};
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// How many entries a page of the audit log holds when the owner doesn't say.
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// The most entries a page of the audit log holds.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Fields whose values are never written to the audit log, only that they changed.
+const REDACTED_FIELDS: &[&str] = &["secret", "check_in_token"];
+
+/// Who made a change, as the audit log remembers them.
+#[derive(Clone, Debug)]
+pub struct Actor {
+    pub account_id: Uuid,
+    /// The address the request came from, as seen by the server. That's the proxy's when
+    /// running behind one.
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Something staff or an owner did in their organization. Members acting on their own
+/// bookings, subscriptions and so on aren't audited, only staff doing it for them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+pub enum AuditAction {
+    /// For a member, by staff.
+    #[serde(rename = "booking.cancelled")]
+    #[sqlx(rename = "booking.cancelled")]
+    BookingCancelled,
+    #[serde(rename = "booking.checked_in")]
+    #[sqlx(rename = "booking.checked_in")]
+    BookingCheckedIn,
+    #[serde(rename = "booking.no_show")]
+    #[sqlx(rename = "booking.no_show")]
+    BookingNoShow,
+    #[serde(rename = "booking.removed")]
+    #[sqlx(rename = "booking.removed")]
+    BookingRemoved,
+    /// A member taken off a waitlist by staff.
+    #[serde(rename = "waitlist_entry.removed")]
+    #[sqlx(rename = "waitlist_entry.removed")]
+    WaitlistEntryRemoved,
+    #[serde(rename = "class.created")]
+    #[sqlx(rename = "class.created")]
+    ClassCreated,
+    #[serde(rename = "class.cancelled")]
+    #[sqlx(rename = "class.cancelled")]
+    ClassCancelled,
+    #[serde(rename = "class_type.created")]
+    #[sqlx(rename = "class_type.created")]
+    ClassTypeCreated,
+    #[serde(rename = "class_type.updated")]
+    #[sqlx(rename = "class_type.updated")]
+    ClassTypeUpdated,
+    #[serde(rename = "location.created")]
+    #[sqlx(rename = "location.created")]
+    LocationCreated,
+    #[serde(rename = "instructor.created")]
+    #[sqlx(rename = "instructor.created")]
+    InstructorCreated,
+    #[serde(rename = "instructor.updated")]
+    #[sqlx(rename = "instructor.updated")]
+    InstructorUpdated,
+    #[serde(rename = "instructor.availability_updated")]
+    #[sqlx(rename = "instructor.availability_updated")]
+    InstructorAvailabilityUpdated,
+    #[serde(rename = "organization.updated")]
+    #[sqlx(rename = "organization.updated")]
+    OrganizationUpdated,
+    #[serde(rename = "membership.created")]
+    #[sqlx(rename = "membership.created")]
+    MembershipCreated,
+    /// For a member, by staff.
+    #[serde(rename = "membership.check_in_token_rotated")]
+    #[sqlx(rename = "membership.check_in_token_rotated")]
+    CheckInTokenRotated,
+    #[serde(rename = "plan.created")]
+    #[sqlx(rename = "plan.created")]
+    PlanCreated,
+    #[serde(rename = "plan.updated")]
+    #[sqlx(rename = "plan.updated")]
+    PlanUpdated,
+    #[serde(rename = "plan.archived")]
+    #[sqlx(rename = "plan.archived")]
+    PlanArchived,
+    #[serde(rename = "tax_rate.created")]
+    #[sqlx(rename = "tax_rate.created")]
+    TaxRateCreated,
+    #[serde(rename = "promo_code.created")]
+    #[sqlx(rename = "promo_code.created")]
+    PromoCodeCreated,
+    #[serde(rename = "promo_code.archived")]
+    #[sqlx(rename = "promo_code.archived")]
+    PromoCodeArchived,
+    /// For a member, by staff.
+    #[serde(rename = "subscription.created")]
+    #[sqlx(rename = "subscription.created")]
+    SubscriptionCreated,
+    /// For a member, by staff.
+    #[serde(rename = "subscription.cancelled")]
+    #[sqlx(rename = "subscription.cancelled")]
+    SubscriptionCancelled,
+    #[serde(rename = "subscription.paused")]
+    #[sqlx(rename = "subscription.paused")]
+    SubscriptionPaused,
+    #[serde(rename = "subscription.resumed")]
+    #[sqlx(rename = "subscription.resumed")]
+    SubscriptionResumed,
+    #[serde(rename = "subscription.freeze_scheduled")]
+    #[sqlx(rename = "subscription.freeze_scheduled")]
+    SubscriptionFreezeScheduled,
+    #[serde(rename = "subscription.freeze_cleared")]
+    #[sqlx(rename = "subscription.freeze_cleared")]
+    SubscriptionFreezeCleared,
+    /// For a member, by staff. The target is the credit entry granting the pack.
+    #[serde(rename = "class_pack.purchased")]
+    #[sqlx(rename = "class_pack.purchased")]
+    ClassPackPurchased,
+    /// A refund or credit against an invoice.
+    #[serde(rename = "credit_note.issued")]
+    #[sqlx(rename = "credit_note.issued")]
+    CreditNoteIssued,
+    #[serde(rename = "product.created")]
+    #[sqlx(rename = "product.created")]
+    ProductCreated,
+    #[serde(rename = "product.updated")]
+    #[sqlx(rename = "product.updated")]
+    ProductUpdated,
+    #[serde(rename = "product.archived")]
+    #[sqlx(rename = "product.archived")]
+    ProductArchived,
+    #[serde(rename = "product.stock_adjusted")]
+    #[sqlx(rename = "product.stock_adjusted")]
+    ProductStockAdjusted,
+    #[serde(rename = "sale.created")]
+    #[sqlx(rename = "sale.created")]
+    SaleCreated,
+    #[serde(rename = "sale.voided")]
+    #[sqlx(rename = "sale.voided")]
+    SaleVoided,
+    /// The target is the count.
+    #[serde(rename = "cash_drawer.counted")]
+    #[sqlx(rename = "cash_drawer.counted")]
+    CashDrawerCounted,
+    #[serde(rename = "gift_card.issued")]
+    #[sqlx(rename = "gift_card.issued")]
+    GiftCardIssued,
+    #[serde(rename = "webhook_endpoint.created")]
+    #[sqlx(rename = "webhook_endpoint.created")]
+    WebhookEndpointCreated,
+    #[serde(rename = "webhook_endpoint.updated")]
+    #[sqlx(rename = "webhook_endpoint.updated")]
+    WebhookEndpointUpdated,
+    #[serde(rename = "webhook_endpoint.archived")]
+    #[sqlx(rename = "webhook_endpoint.archived")]
+    WebhookEndpointArchived,
+    #[serde(rename = "webhook_delivery.replayed")]
+    #[sqlx(rename = "webhook_delivery.replayed")]
+    WebhookDeliveryReplayed,
+    /// The target id is the organization's, its Rustfit subscription has none of its own.
+    #[serde(rename = "platform_subscription.changed")]
+    #[sqlx(rename = "platform_subscription.changed")]
+    PlatformPlanChanged,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::BookingCancelled => "booking.cancelled",
+            Self::BookingCheckedIn => "booking.checked_in",
+            Self::BookingNoShow => "booking.no_show",
+            Self::BookingRemoved => "booking.removed",
+            Self::WaitlistEntryRemoved => "waitlist_entry.removed",
+            Self::ClassCreated => "class.created",
+            Self::ClassCancelled => "class.cancelled",
+            Self::ClassTypeCreated => "class_type.created",
+            Self::ClassTypeUpdated => "class_type.updated",
+            Self::LocationCreated => "location.created",
+            Self::InstructorCreated => "instructor.created",
+            Self::InstructorUpdated => "instructor.updated",
+            Self::InstructorAvailabilityUpdated => "instructor.availability_updated",
+            Self::OrganizationUpdated => "organization.updated",
+            Self::MembershipCreated => "membership.created",
+            Self::CheckInTokenRotated => "membership.check_in_token_rotated",
+            Self::PlanCreated => "plan.created",
+            Self::PlanUpdated => "plan.updated",
+            Self::PlanArchived => "plan.archived",
+            Self::TaxRateCreated => "tax_rate.created",
+            Self::PromoCodeCreated => "promo_code.created",
+            Self::PromoCodeArchived => "promo_code.archived",
+            Self::SubscriptionCreated => "subscription.created",
+            Self::SubscriptionCancelled => "subscription.cancelled",
+            Self::SubscriptionPaused => "subscription.paused",
+            Self::SubscriptionResumed => "subscription.resumed",
+            Self::SubscriptionFreezeScheduled => "subscription.freeze_scheduled",
+            Self::SubscriptionFreezeCleared => "subscription.freeze_cleared",
+            Self::ClassPackPurchased => "class_pack.purchased",
+            Self::CreditNoteIssued => "credit_note.issued",
+            Self::ProductCreated => "product.created",
+            Self::ProductUpdated => "product.updated",
+            Self::ProductArchived => "product.archived",
+            Self::ProductStockAdjusted => "product.stock_adjusted",
+            Self::SaleCreated => "sale.created",
+            Self::SaleVoided => "sale.voided",
+            Self::CashDrawerCounted => "cash_drawer.counted",
+            Self::GiftCardIssued => "gift_card.issued",
+            Self::WebhookEndpointCreated => "webhook_endpoint.created",
+            Self::WebhookEndpointUpdated => "webhook_endpoint.updated",
+            Self::WebhookEndpointArchived => "webhook_endpoint.archived",
+            Self::WebhookDeliveryReplayed => "webhook_delivery.replayed",
+            Self::PlatformPlanChanged => "platform_subscription.changed",
+        }
+    }
+
+    /// What kind of thing the action was taken on, e.g. `booking` for `booking.removed`.
+    pub fn target_type(self) -> &'static str {
+        self.as_str()
+            .split_once('.')
+            .map_or(self.as_str(), |(target_type, _)| target_type)
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An entry in the audit log. Entries are recorded in the transaction that made the change
+/// and are never changed or removed afterwards, the database refuses to.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct AuditLogEntryDTO {
+    /// Where the entry is in the log. Pass the last one read as `before` to read the
+    /// entries before it.
+    pub sequence: i64,
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub actor_account_id: Uuid,
+    pub actor_name: String,
+    pub action: AuditAction,
+    pub target_type: String,
+    pub target_id: Uuid,
+    /// The fields that changed, each as `{"before": .., "after": ..}`. Created things have
+    /// `null` before, see `diff`.
+    pub changes: Json<Value>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub inserted_at: OffsetDateTime,
+}
+
+/// A page of an organization's audit log, newest first, narrowed down by any of the filters.
+#[derive(serde::Deserialize)]
+pub struct AuditLogQuery {
+    /// Reads the entries before this `sequence`, from the newest if left out.
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+    pub actor_account_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub target_type: Option<String>,
+    pub target_id: Option<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct AuditController {
+    pool: SqlitePool,
+}
+
+impl AuditController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynAuditCtrl = Arc<dyn AuditCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait AuditCtrlTrait {
+    async fn list_entries(
+        &self,
+        organization_id: Uuid,
+        query: AuditLogQuery,
+    ) -> Result<Vec<AuditLogEntryDTO>>;
+}
+
+#[async_trait]
+impl AuditCtrlTrait for AuditController {
+    async fn list_entries(
+        &self,
+        organization_id: Uuid,
+        query: AuditLogQuery,
+    ) -> Result<Vec<AuditLogEntryDTO>> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let entries = sqlx::query_as!(
+            AuditLogEntryDTO,
+            r#"select
+                e.sequence as "sequence!", e.id as "id: Uuid", e.organization_id as "organization_id: Uuid",
+                e.actor_account_id as "actor_account_id: Uuid", a.name as actor_name,
+                e.action as "action: AuditAction", e.target_type,
+                e.target_id as "target_id: Uuid", e.changes as "changes: Json<Value>",
+                e.ip, e.user_agent, e.inserted_at as "inserted_at: OffsetDateTime"
+            from audit_log_entries e
+            join accounts a on a.id = e.actor_account_id
+            where e.organization_id = $1
+                and ($2 is null or e.sequence < $2)
+                and ($3 is null or e.actor_account_id = $3)
+                and ($4 is null or e.action = $4)
+                and ($5 is null or e.target_type = $5)
+                and ($6 is null or e.target_id = $6)
+            order by e.sequence desc
+            limit $7"#,
+            organization_id,
+            query.before,
+            query.actor_account_id,
+            query.action,
+            query.target_type,
+            query.target_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+/// Writes down that `actor` took `action` on the target, in the transaction making the
+/// change. `before` is `None` for things being created, `after` for things going away.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn record<T: serde::Serialize>(
+    conn: &mut SqliteConnection,
+    actor: &Actor,
+    organization_id: Uuid,
+    action: AuditAction,
+    target_id: Uuid,
+    before: Option<&T>,
+    after: Option<&T>,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let encode = |value: Option<&T>| {
+        value
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| Error::Anyhow(anyhow::anyhow!("failed to encode {action}: {e}")))
+    };
+    let changes = Json(diff(encode(before)?, encode(after)?));
+    let id = uuid::Uuid::new_v4();
+    let target_type = action.target_type();
+
+    sqlx::query!(
+        r#"insert into "audit_log_entries" (
+            id, organization_id, actor_account_id, action, target_type, target_id,
+            changes, ip, user_agent, inserted_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6,
+            $7, $8, $9, $10
+        )"#,
+        id,
+        organization_id,
+        actor.account_id,
+        action,
+        target_type,
+        target_id,
+        changes,
+        actor.ip,
+        actor.user_agent,
+        now
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// The top-level fields that differ between `before` and `after`, as
+/// `{"field": {"before": .., "after": ..}}`. A missing side counts as every field being
+/// `null`. Values that aren't objects are compared whole, under the field `value`.
+fn diff(before: Option<Value>, after: Option<Value>) -> Value {
+    let fields = |value: Option<Value>| match value {
+        Some(Value::Object(fields)) => fields,
+        Some(value) => Map::from_iter([("value".to_string(), value)]),
+        None => Map::new(),
+    };
+    let before = fields(before);
+    let after = fields(after);
+
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        if changes.contains_key(key) {
+            continue;
+        }
+        let old = before.get(key).unwrap_or(&Value::Null);
+        let new = after.get(key).unwrap_or(&Value::Null);
+        if old == new {
+            continue;
+        }
+
+        let (old, new) = if REDACTED_FIELDS.contains(&key.as_str()) {
+            let redacted = |value: &Value| match value {
+                Value::Null => Value::Null,
+                _ => Value::from("[redacted]"),
+            };
+            (redacted(old), redacted(new))
+        } else {
+            (old.clone(), new.clone())
+        };
+        changes.insert(
+            key.clone(),
+            serde_json::json!({ "before": old, "after": new }),
+        );
+    }
+
+    Value::Object(changes)
+}
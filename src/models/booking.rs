@@ -0,0 +1,1041 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::class::{self, ClassDTO};
+use super::class_type;
+use super::credit;
+use super::email;
+use super::event::{self, EventType};
+use super::membership;
+use super::notification::{self, NewNotification, NotificationEvent};
+use super::organization;
+use super::penalty::{self, PenaltyKind, PenaltyReason};
+use super::plan::PlanKind;
+use super::reminder;
+use super::subscription;
+use super::waitlist;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum BookingStatus {
+    Booked,
+    CheckedIn,
+    Cancelled,
+    NoShow,
+    /// Taken off the class roster by staff.
+    Removed,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct BookingDTO {
+    pub id: Uuid,
+    pub class_id: Uuid,
+    pub membership_id: Uuid,
+    pub status: BookingStatus,
+    pub cancelled_at: Option<OffsetDateTime>,
+    pub checked_in_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// What lets a member book a class, found by `check_can_book` and spent by `use_entitlement`.
+pub(crate) enum Entitlement {
+    /// The organization lets members book without a plan.
+    NotRequired,
+    /// Covered by an unlimited plan or the weekly allowance of a limited one.
+    Subscription,
+    /// Paid with a class credit from this grant.
+    Credit { grant_id: Uuid },
+}
+
+/// Members can be checked in from this long before their class starts until it ends.
+const CHECK_IN_OPENS_MINUTES: i64 = 60;
+
+/// Bookings for classes that ended longer ago than this aren't marked as no-shows
+/// automatically, so bookings from before they were aren't all penalized at once.
+const NO_SHOW_LOOKBACK: Duration = Duration::days(1);
+
+/// A booking on a class roster, with enough about the member for front desk staff.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct RosterEntryDTO {
+    pub booking_id: Uuid,
+    pub membership_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub status: BookingStatus,
+    pub checked_in_at: Option<OffsetDateTime>,
+}
+
+#[derive(Clone)]
+pub struct BookingController {
+    pool: SqlitePool,
+}
+
+impl BookingController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynBookingCtrl = Arc<dyn BookingCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait BookingCtrlTrait {
+    async fn create_booking(&self, class_id: Uuid, membership_id: Uuid) -> Result<BookingDTO>;
+
+    /// Cancels the booking and, unless the class starts within the organization's
+    /// waitlist cut-off, promotes the first eligible waitlisted member into the freed spot.
+    ///
+    /// Members cancelling their own booking inside the organization's cancellation window
+    /// get its late cancel penalty. `staff` cancelling on a member's behalf skip it, and
+    /// are audited.
+    async fn cancel_booking(&self, id: Uuid, staff: Option<&Actor>) -> Result<BookingDTO>;
+
+    /// Marks a booking for a class that has started as not attended and records the
+    /// organization's no-show penalty. Marked by `staff` it is audited, otherwise the class
+    /// ended without the member checking in.
+    async fn mark_no_show(&self, id: Uuid, staff: Option<&Actor>) -> Result<BookingDTO>;
+
+    /// Bookings still awaiting check-in to classes that ended, and weren't cancelled, by
+    /// `now`.
+    async fn list_unattended_bookings(&self, now: OffsetDateTime) -> Result<Vec<BookingDTO>>;
+
+    async fn check_in_booking(&self, id: Uuid, actor: &Actor) -> Result<BookingDTO>;
+
+    /// Checks in the member holding `check_in_token`, as scanned from their QR code
+    /// by a front desk device, to their booking for the class.
+    async fn check_in_member(
+        &self,
+        class_id: Uuid,
+        check_in_token: String,
+        actor: &Actor,
+    ) -> Result<BookingDTO>;
+
+    /// Takes the booking off the class roster, freeing the spot for the waitlist.
+    async fn remove_booking(&self, id: Uuid, actor: &Actor) -> Result<BookingDTO>;
+
+    /// Lists everyone who booked the class except those who cancelled.
+    async fn list_roster(&self, class_id: Uuid) -> Result<Vec<RosterEntryDTO>>;
+
+    async fn get_booking(&self, id: Uuid) -> Result<BookingDTO>;
+    async fn list_bookings(&self, class_id: Uuid) -> Result<Vec<BookingDTO>>;
+    async fn list_member_bookings(&self, membership_id: Uuid) -> Result<Vec<BookingDTO>>;
+}
+
+#[async_trait]
+impl BookingCtrlTrait for BookingController {
+    async fn create_booking(&self, class_id: Uuid, membership_id: Uuid) -> Result<BookingDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let class = class::get_class(&mut tx, class_id).await?;
+        let entitlement = check_can_book(&mut tx, &class, membership_id, now).await?;
+
+        if count_booked(&mut tx, class.id).await? >= class.capacity {
+            return Err(Error::unprocessable_entity([(
+                "class",
+                "is full, join the waitlist instead",
+            )]));
+        }
+
+        let booking = insert_booking(&mut tx, class.id, membership_id, now).await?;
+        use_entitlement(&mut tx, &class, &booking, entitlement, now).await?;
+        reminder::schedule_class_reminder(&mut tx, &class, &booking, now).await?;
+        event::record(
+            &mut tx,
+            Some(class.organization_id),
+            EventType::BookingCreated,
+            &booking,
+            now,
+        )
+        .await?;
+
+        notification::notify(
+            &mut tx,
+            NewNotification {
+                membership_id,
+                event: NotificationEvent::BookingConfirmed,
+                variables: vec![
+                    ("class_name", class.name.clone()),
+                    ("starts_at", email::format_time(class.starts_at)),
+                ],
+            },
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(booking)
+    }
+
+    async fn cancel_booking(&self, id: Uuid, staff: Option<&Actor>) -> Result<BookingDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_booking(&mut tx, id).await?;
+        if before.status != BookingStatus::Booked {
+            return Err(Error::unprocessable_entity([("booking", "is not active")]));
+        }
+
+        let booking = update_status(&mut tx, id, BookingStatus::Cancelled, now).await?;
+
+        let class = class::get_class(&mut tx, booking.class_id).await?;
+        let organization = organization::get_organization(&mut tx, class.organization_id).await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                organization.id,
+                AuditAction::BookingCancelled,
+                booking.id,
+                Some(&before),
+                Some(&booking),
+                now,
+            )
+            .await?;
+        }
+
+        let late = now
+            .checked_add(time::Duration::minutes(
+                organization.cancellation_window_minutes,
+            ))
+            .is_none_or(|deadline| deadline > class.starts_at);
+        let penalty = if staff.is_none() && late {
+            penalty::apply_penalty(
+                &mut tx,
+                &organization,
+                booking.membership_id,
+                booking.id,
+                PenaltyReason::LateCancel,
+                now,
+            )
+            .await?
+        } else {
+            None
+        };
+
+        if !matches!(penalty, Some(ref p) if p.kind == PenaltyKind::ForfeitCredit) {
+            credit::refund_booking(&mut tx, booking.id, now).await?;
+        }
+
+        waitlist::promote_next(&mut tx, &class, now).await?;
+
+        tx.commit().await?;
+
+        Ok(booking)
+    }
+
+    async fn mark_no_show(&self, id: Uuid, staff: Option<&Actor>) -> Result<BookingDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_booking(&mut tx, id).await?;
+        if before.status != BookingStatus::Booked {
+            return Err(Error::unprocessable_entity([("booking", "is not active")]));
+        }
+
+        let class = class::get_class(&mut tx, before.class_id).await?;
+        if class.starts_at > now {
+            return Err(Error::unprocessable_entity([(
+                "class",
+                "has not started yet",
+            )]));
+        }
+
+        let booking = update_status(&mut tx, id, BookingStatus::NoShow, now).await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                class.organization_id,
+                AuditAction::BookingNoShow,
+                booking.id,
+                Some(&before),
+                Some(&booking),
+                now,
+            )
+            .await?;
+        }
+
+        let organization = organization::get_organization(&mut tx, class.organization_id).await?;
+        penalty::apply_penalty(
+            &mut tx,
+            &organization,
+            booking.membership_id,
+            booking.id,
+            PenaltyReason::NoShow,
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(booking)
+    }
+
+    async fn check_in_booking(&self, id: Uuid, actor: &Actor) -> Result<BookingDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let booking = check_in(&mut tx, id, actor, now).await?;
+
+        tx.commit().await?;
+
+        Ok(booking)
+    }
+
+    async fn check_in_member(
+        &self,
+        class_id: Uuid,
+        check_in_token: String,
+        actor: &Actor,
+    ) -> Result<BookingDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let class = class::get_class(&mut tx, class_id).await?;
+        let membership =
+            membership::find_by_check_in_token(&mut tx, class.organization_id, &check_in_token)
+                .await?
+                .ok_or_else(|| {
+                    Error::unprocessable_entity([("check_in_token", "does not match a member")])
+                })?;
+
+        let booking = find_active_booking(&mut tx, class.id, membership.id)
+            .await?
+            .ok_or_else(|| {
+                Error::unprocessable_entity([("class", "is not booked by this member")])
+            })?;
+
+        let booking = check_in(&mut tx, booking.id, actor, now).await?;
+
+        tx.commit().await?;
+
+        Ok(booking)
+    }
+
+    async fn remove_booking(&self, id: Uuid, actor: &Actor) -> Result<BookingDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_booking(&mut tx, id).await?;
+        if !matches!(
+            before.status,
+            BookingStatus::Booked | BookingStatus::CheckedIn
+        ) {
+            return Err(Error::unprocessable_entity([("booking", "is not active")]));
+        }
+
+        let booking = update_status(&mut tx, id, BookingStatus::Removed, now).await?;
+        credit::refund_booking(&mut tx, booking.id, now).await?;
+
+        let class = class::get_class(&mut tx, booking.class_id).await?;
+        audit::record(
+            &mut tx,
+            actor,
+            class.organization_id,
+            AuditAction::BookingRemoved,
+            booking.id,
+            Some(&before),
+            Some(&booking),
+            now,
+        )
+        .await?;
+        waitlist::promote_next(&mut tx, &class, now).await?;
+
+        tx.commit().await?;
+
+        Ok(booking)
+    }
+
+    async fn list_roster(&self, class_id: Uuid) -> Result<Vec<RosterEntryDTO>> {
+        let roster = sqlx::query_as!(
+            RosterEntryDTO,
+            r#"select
+                bookings.id as "booking_id: Uuid", memberships.id as "membership_id: Uuid",
+                accounts.name, accounts.email, bookings.status as "status: BookingStatus",
+                bookings.checked_in_at as "checked_in_at: OffsetDateTime"
+            from bookings
+            inner join memberships on memberships.id = bookings.membership_id
+            inner join accounts on accounts.id = memberships.account_id
+            where bookings.class_id = $1 and bookings.status != $2
+            order by accounts.name"#,
+            class_id,
+            BookingStatus::Cancelled
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(roster)
+    }
+
+    async fn get_booking(&self, id: Uuid) -> Result<BookingDTO> {
+        get_booking(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn list_bookings(&self, class_id: Uuid) -> Result<Vec<BookingDTO>> {
+        let bookings = sqlx::query_as!(
+            BookingDTO,
+            r#"select
+                id as "id: Uuid", class_id as "class_id: Uuid",
+                membership_id as "membership_id: Uuid", status as "status: BookingStatus",
+                cancelled_at as "cancelled_at: OffsetDateTime", checked_in_at as "checked_in_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from bookings
+            where class_id = $1
+            order by inserted_at"#,
+            class_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bookings)
+    }
+
+    async fn list_member_bookings(&self, membership_id: Uuid) -> Result<Vec<BookingDTO>> {
+        let bookings = sqlx::query_as!(
+            BookingDTO,
+            r#"select
+                id as "id: Uuid", class_id as "class_id: Uuid",
+                membership_id as "membership_id: Uuid", status as "status: BookingStatus",
+                cancelled_at as "cancelled_at: OffsetDateTime", checked_in_at as "checked_in_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from bookings
+            where membership_id = $1
+            order by inserted_at desc"#,
+            membership_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bookings)
+    }
+
+    async fn list_unattended_bookings(&self, now: OffsetDateTime) -> Result<Vec<BookingDTO>> {
+        let ended_after = now - NO_SHOW_LOOKBACK;
+        let bookings = sqlx::query_as!(
+            BookingDTO,
+            r#"select
+                bookings.id as "id: Uuid", bookings.class_id as "class_id: Uuid",
+                bookings.membership_id as "membership_id: Uuid", bookings.status as "status: BookingStatus",
+                bookings.cancelled_at as "cancelled_at: OffsetDateTime",
+                bookings.checked_in_at as "checked_in_at: OffsetDateTime",
+                bookings.inserted_at as "inserted_at: OffsetDateTime",
+                bookings.updated_at as "updated_at: OffsetDateTime"
+            from bookings
+            join classes on classes.id = bookings.class_id
+            where bookings.status = $1
+                and classes.cancelled_at is null
+                and classes.ends_at <= $2 and classes.ends_at > $3
+            order by classes.ends_at"#,
+            BookingStatus::Booked,
+            now,
+            ended_after
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bookings)
+    }
+}
+
+/// Rules a member must pass to hold a spot in a class, shared by direct bookings and
+/// waitlist promotions. Capacity is checked separately by the caller.
+///
+/// Besides the class being bookable at all, this enforces penalty booking blocks, freezes, the
+/// class type's booking window, the organization's limit of bookings per day and, when
+/// the organization requires it, that the member has a plan or credits to book with.
+/// The entitlement found must be spent with `use_entitlement` in the same transaction.
+pub(crate) async fn check_can_book(
+    conn: &mut SqliteConnection,
+    class: &ClassDTO,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Entitlement> {
+    if class.cancelled_at.is_some() {
+        return Err(Error::unprocessable_entity([(
+            "class",
+            "has been cancelled",
+        )]));
+    }
+    if class.starts_at <= now {
+        return Err(Error::unprocessable_entity([(
+            "class",
+            "has already started",
+        )]));
+    }
+    if find_active_booking(conn, class.id, membership_id)
+        .await?
+        .is_some()
+    {
+        return Err(Error::unprocessable_entity([(
+            "class",
+            "is already booked",
+        )]));
+    }
+    if let Some(blocked_until) = penalty::blocked_until(conn, membership_id, now).await? {
+        return Err(Error::unprocessable_entity([(
+            "membership",
+            format!("is blocked from booking until {blocked_until}"),
+        )]));
+    }
+    if let Some(frozen_until) =
+        subscription::frozen_until(conn, membership_id, class.starts_at).await?
+    {
+        return Err(Error::unprocessable_entity([(
+            "membership",
+            format!("is frozen until {frozen_until}"),
+        )]));
+    }
+    if subscription::has_past_due(conn, membership_id).await? {
+        return Err(Error::unprocessable_entity([(
+            "membership",
+            "has an unpaid renewal, update your payment method to book",
+        )]));
+    }
+
+    if let Some(class_type_id) = class.class_type_id {
+        class_type::get_class_type(conn, class_type_id)
+            .await?
+            .check_booking_window(class.starts_at, now)?;
+    }
+
+    let organization = organization::get_organization(conn, class.organization_id).await?;
+    if let Some(max_bookings_per_day) = organization.max_bookings_per_day {
+        let day = class.starts_at.to_offset(UtcOffset::UTC).date();
+        let booked_that_day = sqlx::query_scalar!(
+            r#"select classes.starts_at as "starts_at: OffsetDateTime"
+            from bookings
+            inner join classes on classes.id = bookings.class_id
+            where bookings.membership_id = $1 and bookings.status in ($2, $3)
+                and classes.cancelled_at is null"#,
+            membership_id,
+            BookingStatus::Booked,
+            BookingStatus::CheckedIn
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .filter(|starts_at| starts_at.to_offset(UtcOffset::UTC).date() == day)
+        .count();
+
+        if booked_that_day as i64 >= max_bookings_per_day {
+            return Err(Error::unprocessable_entity([(
+                "booking",
+                format!("is over the limit of {max_bookings_per_day} per member per day"),
+            )]));
+        }
+    }
+
+    if !organization.booking_requires_entitlement {
+        return Ok(Entitlement::NotRequired);
+    }
+
+    find_entitlement(conn, class, membership_id, now).await
+}
+
+/// Picks how the member pays for the class: an unlimited plan first, then the allowance
+/// of a weekly limit plan, then a class credit.
+async fn find_entitlement(
+    conn: &mut SqliteConnection,
+    class: &ClassDTO,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Entitlement> {
+    let covering = subscription::list_covering(conn, membership_id, class.starts_at).await?;
+
+    if covering
+        .iter()
+        .any(|(_, plan)| plan.kind == PlanKind::Unlimited)
+    {
+        return Ok(Entitlement::Subscription);
+    }
+
+    let classes_per_week = covering
+        .iter()
+        .filter_map(|(_, plan)| plan.classes_per_week)
+        .max();
+    if let Some(classes_per_week) = classes_per_week {
+        if count_subscription_bookings_in_week(conn, membership_id, class.starts_at).await?
+            < classes_per_week
+        {
+            return Ok(Entitlement::Subscription);
+        }
+    }
+
+    match credit::find_spendable_grant(conn, membership_id, now).await? {
+        Some(grant_id) => Ok(Entitlement::Credit { grant_id }),
+        None if classes_per_week.is_some() => Err(Error::unprocessable_entity([(
+            "booking",
+            "is over the weekly limit of your plan and you have no class credits",
+        )])),
+        None => Err(Error::unprocessable_entity([(
+            "booking",
+            "needs a membership plan or class credits",
+        )])),
+    }
+}
+
+/// Counts the member's bookings not paid with credits for classes in the same week (Monday
+/// to Sunday, UTC) as `starts_at`. No-shows count, they used up the spot.
+async fn count_subscription_bookings_in_week(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    starts_at: OffsetDateTime,
+) -> Result<i64> {
+    let week_of = |at: OffsetDateTime| {
+        let date = at.to_offset(UtcOffset::UTC).date();
+        date - Duration::days(i64::from(date.weekday().number_days_from_monday()))
+    };
+    let week = week_of(starts_at);
+
+    let booked_in_week = sqlx::query_scalar!(
+        r#"select classes.starts_at as "starts_at: OffsetDateTime"
+        from bookings
+        inner join classes on classes.id = bookings.class_id
+        where bookings.membership_id = $1 and bookings.status in ($2, $3, $4)
+            and classes.cancelled_at is null
+            and not exists (
+                select 1 from credit_entries
+                where credit_entries.booking_id = bookings.id and credit_entries.kind = $5
+            )"#,
+        membership_id,
+        BookingStatus::Booked,
+        BookingStatus::CheckedIn,
+        BookingStatus::NoShow,
+        credit::CreditEntryKind::Debit
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .filter(|starts_at| week_of(*starts_at) == week)
+    .count();
+
+    Ok(booked_in_week as i64)
+}
+
+/// Spends what `check_can_book` found the member booking with.
+pub(crate) async fn use_entitlement(
+    conn: &mut SqliteConnection,
+    class: &ClassDTO,
+    booking: &BookingDTO,
+    entitlement: Entitlement,
+    now: OffsetDateTime,
+) -> Result<()> {
+    if let Entitlement::Credit { grant_id } = entitlement {
+        credit::debit(
+            conn,
+            class.organization_id,
+            booking.membership_id,
+            grant_id,
+            booking.id,
+            now,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Counts the bookings holding a spot in the class, whether or not they checked in yet.
+pub(crate) async fn count_booked(conn: &mut SqliteConnection, class_id: Uuid) -> Result<i64> {
+    let booked = sqlx::query_scalar!(
+        r#"select count(*) as "count!: i64" from bookings
+        where class_id = $1 and status in ($2, $3)"#,
+        class_id,
+        BookingStatus::Booked,
+        BookingStatus::CheckedIn
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(booked)
+}
+
+pub(crate) async fn find_active_booking(
+    conn: &mut SqliteConnection,
+    class_id: Uuid,
+    membership_id: Uuid,
+) -> Result<Option<BookingDTO>> {
+    let booking = sqlx::query_as!(
+        BookingDTO,
+        r#"select
+            id as "id: Uuid", class_id as "class_id: Uuid",
+            membership_id as "membership_id: Uuid", status as "status: BookingStatus",
+            cancelled_at as "cancelled_at: OffsetDateTime", checked_in_at as "checked_in_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from bookings
+        where class_id = $1 and membership_id = $2 and status in ($3, $4)"#,
+        class_id,
+        membership_id,
+        BookingStatus::Booked,
+        BookingStatus::CheckedIn
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(booking)
+}
+
+pub(crate) async fn insert_booking(
+    conn: &mut SqliteConnection,
+    class_id: Uuid,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<BookingDTO> {
+    let id = uuid::Uuid::new_v4();
+
+    let booking = sqlx::query_as!(
+        BookingDTO,
+        r#"insert into "bookings" (
+            id, class_id, membership_id, status,
+            inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4,
+            $5, $6
+        ) returning
+            id as "id: Uuid", class_id as "class_id: Uuid",
+            membership_id as "membership_id: Uuid", status as "status: BookingStatus",
+            cancelled_at as "cancelled_at: OffsetDateTime", checked_in_at as "checked_in_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+        id,
+        class_id,
+        membership_id,
+        BookingStatus::Booked,
+        now,
+        now
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(booking)
+}
+
+pub(crate) async fn get_booking(conn: &mut SqliteConnection, id: Uuid) -> Result<BookingDTO> {
+    sqlx::query_as!(
+        BookingDTO,
+        r#"select
+            id as "id: Uuid", class_id as "class_id: Uuid",
+            membership_id as "membership_id: Uuid", status as "status: BookingStatus",
+            cancelled_at as "cancelled_at: OffsetDateTime", checked_in_at as "checked_in_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from bookings
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Cancels the bookings of a class that was called off, giving back any credits they used,
+/// and lets the members know. Members who already checked in, which opens before the class
+/// starts, are included.
+pub(crate) async fn cancel_class_bookings(
+    conn: &mut SqliteConnection,
+    class: &ClassDTO,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let booking_ids = sqlx::query_scalar!(
+        r#"select id as "id: Uuid" from bookings
+        where class_id = $1 and status in ($2, $3)"#,
+        class.id,
+        BookingStatus::Booked,
+        BookingStatus::CheckedIn
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for booking_id in booking_ids {
+        let booking = update_status(conn, booking_id, BookingStatus::Cancelled, now).await?;
+        credit::refund_booking(conn, booking.id, now).await?;
+
+        notification::notify(
+            conn,
+            NewNotification {
+                membership_id: booking.membership_id,
+                event: NotificationEvent::ClassCancelled,
+                variables: vec![
+                    ("class_name", class.name.clone()),
+                    ("starts_at", email::format_time(class.starts_at)),
+                ],
+            },
+            now,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Sets the booking's status, stamping `cancelled_at` or `checked_in_at` when it
+/// moves into those states, and records the change in the event log. A booking that is
+/// cancelled or removed isn't reminded of.
+async fn update_status(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    status: BookingStatus,
+    now: OffsetDateTime,
+) -> Result<BookingDTO> {
+    let cancelled_at = (status == BookingStatus::Cancelled).then_some(now);
+    let checked_in_at = (status == BookingStatus::CheckedIn).then_some(now);
+
+    let booking = sqlx::query_as!(
+        BookingDTO,
+        r#"update bookings
+        set status = $1,
+            cancelled_at = coalesce($2, cancelled_at),
+            checked_in_at = coalesce($3, checked_in_at),
+            updated_at = $4
+        where id = $5
+        returning
+            id as "id: Uuid", class_id as "class_id: Uuid",
+            membership_id as "membership_id: Uuid", status as "status: BookingStatus",
+            cancelled_at as "cancelled_at: OffsetDateTime", checked_in_at as "checked_in_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+        status,
+        cancelled_at,
+        checked_in_at,
+        now,
+        id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if matches!(status, BookingStatus::Cancelled | BookingStatus::Removed) {
+        reminder::cancel_class_reminder(conn, booking.id, now).await?;
+    }
+    let event = match status {
+        BookingStatus::Booked => None,
+        BookingStatus::CheckedIn => Some(EventType::BookingCheckedIn),
+        BookingStatus::Cancelled => Some(EventType::BookingCancelled),
+        BookingStatus::NoShow => Some(EventType::BookingNoShow),
+        BookingStatus::Removed => Some(EventType::BookingRemoved),
+    };
+    if let Some(event) = event {
+        let class = class::get_class(conn, booking.class_id).await?;
+        event::record(conn, Some(class.organization_id), event, &booking, now).await?;
+    }
+
+    Ok(booking)
+}
+
+async fn check_in(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    actor: &Actor,
+    now: OffsetDateTime,
+) -> Result<BookingDTO> {
+    let booking = get_booking(conn, id).await?;
+    if booking.status != BookingStatus::Booked {
+        return Err(Error::unprocessable_entity([(
+            "booking",
+            "is not awaiting check-in",
+        )]));
+    }
+
+    let class = class::get_class(conn, booking.class_id).await?;
+    if class.cancelled_at.is_some() {
+        return Err(Error::unprocessable_entity([(
+            "class",
+            "has been cancelled",
+        )]));
+    }
+    if now + time::Duration::minutes(CHECK_IN_OPENS_MINUTES) < class.starts_at {
+        return Err(Error::unprocessable_entity([(
+            "class",
+            "is not open for check-in yet",
+        )]));
+    }
+    if now > class.ends_at {
+        return Err(Error::unprocessable_entity([(
+            "class",
+            "has already ended",
+        )]));
+    }
+
+    let checked_in = update_status(conn, id, BookingStatus::CheckedIn, now).await?;
+    audit::record(
+        conn,
+        actor,
+        class.organization_id,
+        AuditAction::BookingCheckedIn,
+        checked_in.id,
+        Some(&booking),
+        Some(&checked_in),
+        now,
+    )
+    .await?;
+
+    Ok(checked_in)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::account::NewAccount;
+    use crate::models::class::NewClass;
+    use crate::models::membership::Role;
+    use crate::models::organization::{NewOrganization, UpdateOrganization};
+    use crate::models::plan::NewPlan;
+    use crate::models::{Store, StoreTrait};
+
+    /// Checked-in members of a class called off before it starts get their credit back and
+    /// are told, like everyone still waiting to check in.
+    #[tokio::test]
+    async fn cancelling_class_refunds_checked_in_booking() {
+        let store = Store::new(super::super::test_pool().await);
+
+        let account = store
+            .account()
+            .create_account(NewAccount {
+                name: "Ada".to_string(),
+                email: "ada@example.com".to_string(),
+                password: "password".to_string(),
+            })
+            .await
+            .unwrap();
+        let actor = Actor {
+            account_id: account.id,
+            ip: None,
+            user_agent: None,
+        };
+        let organization = store
+            .organization()
+            .create_organization(
+                account.id,
+                NewOrganization {
+                    name: "Studio".to_string(),
+                    waitlist_cutoff_minutes: None,
+                },
+            )
+            .await
+            .unwrap();
+        store
+            .organization()
+            .update_organization(
+                organization.id,
+                UpdateOrganization {
+                    name: None,
+                    currency: None,
+                    waitlist_cutoff_minutes: None,
+                    cancellation_window_minutes: None,
+                    late_cancel_penalty: None,
+                    no_show_penalty: None,
+                    penalty_fee_amount: None,
+                    penalty_block_days: None,
+                    max_bookings_per_day: None,
+                    instructor_travel_gap_minutes: None,
+                    booking_requires_entitlement: Some(true),
+                    dunning_final_action: None,
+                    billing_refund_role: None,
+                    class_reminder_hours: None,
+                },
+                &actor,
+            )
+            .await
+            .unwrap();
+        let membership = store
+            .membership()
+            .require_role(organization.id, account.id, Role::Member)
+            .await
+            .unwrap();
+
+        let plan = store
+            .plan()
+            .create_plan(
+                organization.id,
+                NewPlan {
+                    name: "Five classes".to_string(),
+                    description: None,
+                    kind: PlanKind::ClassPack,
+                    price_amount: 0,
+                    currency: None,
+                    billing_interval: None,
+                    trial_days: None,
+                    classes_per_week: None,
+                    class_count: Some(5),
+                    validity_days: Some(30),
+                    tax_rate_id: None,
+                },
+                &actor,
+            )
+            .await
+            .unwrap();
+        store
+            .credit()
+            .purchase_class_pack(membership.id, plan.id, None, None)
+            .await
+            .unwrap();
+
+        // Within the check-in window.
+        let starts_at = OffsetDateTime::now_utc() + Duration::minutes(30);
+        let class = store
+            .class()
+            .create_class(
+                organization.id,
+                NewClass {
+                    name: "Vinyasa".to_string(),
+                    class_type_id: None,
+                    location_id: None,
+                    instructor_id: None,
+                    starts_at,
+                    ends_at: starts_at + Duration::hours(1),
+                    capacity: 10,
+                },
+                &actor,
+            )
+            .await
+            .unwrap();
+
+        let booking = store
+            .booking()
+            .create_booking(class.id, membership.id)
+            .await
+            .unwrap();
+        store
+            .booking()
+            .check_in_booking(booking.id, &actor)
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .credit()
+                .get_balance(membership.id)
+                .await
+                .unwrap()
+                .balance,
+            4
+        );
+
+        store.class().cancel_class(class.id, &actor).await.unwrap();
+
+        let booking = store.booking().get_booking(booking.id).await.unwrap();
+        assert_eq!(booking.status, BookingStatus::Cancelled);
+        assert_eq!(
+            store
+                .credit()
+                .get_balance(membership.id)
+                .await
+                .unwrap()
+                .balance,
+            5
+        );
+        let notifications = store
+            .notification()
+            .list_notifications(membership.id)
+            .await
+            .unwrap();
+        assert!(notifications
+            .iter()
+            .any(|n| n.event == NotificationEvent::ClassCancelled));
+    }
+}
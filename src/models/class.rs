@@ -0,0 +1,331 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::{booking, class_type, instructor, location, organization};
+
+#[derive(serde::Deserialize)]
+pub struct NewClass {
+    pub name: String,
+    pub class_type_id: Option<Uuid>,
+    pub location_id: Option<Uuid>,
+    pub instructor_id: Option<Uuid>,
+    pub starts_at: OffsetDateTime,
+    pub ends_at: OffsetDateTime,
+    pub capacity: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ClassDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub class_type_id: Option<Uuid>,
+    pub location_id: Option<Uuid>,
+    pub instructor_id: Option<Uuid>,
+    pub name: String,
+    pub starts_at: OffsetDateTime,
+    pub ends_at: OffsetDateTime,
+    pub capacity: i64,
+    pub cancelled_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl NewClass {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(("name", "can't be blank"));
+        }
+        if self.ends_at <= self.starts_at {
+            errors.push(("ends_at", "must be after starts_at"));
+        }
+        if self.capacity < 1 {
+            errors.push(("capacity", "must be at least 1"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassController {
+    pool: SqlitePool,
+}
+
+impl ClassController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynClassCtrl = Arc<dyn ClassCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait ClassCtrlTrait {
+    async fn create_class(
+        &self,
+        organization_id: Uuid,
+        new_class: NewClass,
+        actor: &Actor,
+    ) -> Result<ClassDTO>;
+    async fn list_classes(&self, organization_id: Uuid) -> Result<Vec<ClassDTO>>;
+    async fn get_class(&self, id: Uuid) -> Result<ClassDTO>;
+
+    /// Calls off a class that hasn't started. Its bookings are cancelled, giving back any
+    /// credits they used, and the members booked are notified.
+    async fn cancel_class(&self, id: Uuid, actor: &Actor) -> Result<ClassDTO>;
+}
+
+#[async_trait]
+impl ClassCtrlTrait for ClassController {
+    async fn create_class(
+        &self,
+        organization_id: Uuid,
+        new_class: NewClass,
+        actor: &Actor,
+    ) -> Result<ClassDTO> {
+        new_class.validate()?;
+        // Stored in UTC, so they compare correctly as text.
+        let new_class = NewClass {
+            starts_at: new_class.starts_at.to_offset(UtcOffset::UTC),
+            ends_at: new_class.ends_at.to_offset(UtcOffset::UTC),
+            ..new_class
+        };
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        if let Some(class_type_id) = new_class.class_type_id {
+            check_belongs_to_organization(
+                class_type::get_class_type(&mut tx, class_type_id)
+                    .await
+                    .map(|class_type| class_type.organization_id),
+                organization_id,
+                "class_type_id",
+                "does not match a class type of this organization",
+            )?;
+        }
+
+        if let Some(location_id) = new_class.location_id {
+            check_belongs_to_organization(
+                location::get_location(&mut tx, location_id)
+                    .await
+                    .map(|location| location.organization_id),
+                organization_id,
+                "location_id",
+                "does not match a location of this organization",
+            )?;
+        }
+
+        if let Some(instructor_id) = new_class.instructor_id {
+            check_belongs_to_organization(
+                instructor::get_instructor(&mut tx, instructor_id)
+                    .await
+                    .map(|instructor| instructor.organization_id),
+                organization_id,
+                "instructor_id",
+                "does not match an instructor of this organization",
+            )?;
+
+            let organization = organization::get_organization(&mut tx, organization_id).await?;
+            instructor::check_schedule(
+                &mut tx,
+                &organization,
+                instructor_id,
+                new_class.location_id,
+                new_class.starts_at,
+                new_class.ends_at,
+            )
+            .await?;
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let class = sqlx::query_as!(
+            ClassDTO,
+            r#"insert into "classes" (
+                id, organization_id, class_type_id, location_id, instructor_id, name,
+                starts_at, ends_at, capacity,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6,
+                $7, $8, $9,
+                $10, $11
+            ) returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                class_type_id as "class_type_id: Uuid",
+                location_id as "location_id: Uuid", instructor_id as "instructor_id: Uuid", name,
+                starts_at as "starts_at: OffsetDateTime", ends_at as "ends_at: OffsetDateTime",
+                capacity, cancelled_at as "cancelled_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            id,
+            organization_id,
+            new_class.class_type_id,
+            new_class.location_id,
+            new_class.instructor_id,
+            new_class.name,
+            new_class.starts_at,
+            new_class.ends_at,
+            new_class.capacity,
+            inserted_at,
+            inserted_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::ClassCreated,
+            &class,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::ClassCreated,
+            class.id,
+            None,
+            Some(&class),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(class)
+    }
+
+    async fn list_classes(&self, organization_id: Uuid) -> Result<Vec<ClassDTO>> {
+        let classes = sqlx::query_as!(
+            ClassDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                class_type_id as "class_type_id: Uuid",
+                location_id as "location_id: Uuid", instructor_id as "instructor_id: Uuid", name,
+                starts_at as "starts_at: OffsetDateTime", ends_at as "ends_at: OffsetDateTime",
+                capacity, cancelled_at as "cancelled_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from classes
+            where organization_id = $1
+            order by starts_at"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(classes)
+    }
+
+    async fn get_class(&self, id: Uuid) -> Result<ClassDTO> {
+        get_class(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn cancel_class(&self, id: Uuid, actor: &Actor) -> Result<ClassDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_class(&mut tx, id).await?;
+        if before.starts_at <= now {
+            return Err(Error::unprocessable_entity([(
+                "class",
+                "has already started",
+            )]));
+        }
+
+        // Only one of two concurrent requests gets to cancel the class and its bookings.
+        let class = sqlx::query_as!(
+            ClassDTO,
+            r#"update classes
+            set cancelled_at = $1, updated_at = $2
+            where id = $3 and cancelled_at is null
+            returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                class_type_id as "class_type_id: Uuid",
+                location_id as "location_id: Uuid", instructor_id as "instructor_id: Uuid", name,
+                starts_at as "starts_at: OffsetDateTime", ends_at as "ends_at: OffsetDateTime",
+                capacity, cancelled_at as "cancelled_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            now,
+            now,
+            before.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::unprocessable_entity([("class", "is already cancelled")]))?;
+
+        booking::cancel_class_bookings(&mut tx, &class, now).await?;
+        event::record(
+            &mut tx,
+            Some(class.organization_id),
+            EventType::ClassCancelled,
+            &class,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            class.organization_id,
+            AuditAction::ClassCancelled,
+            class.id,
+            Some(&before),
+            Some(&class),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(class)
+    }
+}
+
+/// Errors on `field` unless `found`, the organization of something looked up by the id
+/// given for it, is `organization_id`.
+fn check_belongs_to_organization(
+    found: Result<Uuid>,
+    organization_id: Uuid,
+    field: &'static str,
+    message: &'static str,
+) -> Result<()> {
+    match found {
+        Ok(found) if found == organization_id => Ok(()),
+        Ok(_) | Err(Error::NotFound) => Err(Error::unprocessable_entity([(field, message)])),
+        Err(e) => Err(e),
+    }
+}
+
+/// Fetches a class on an existing connection, so it can be read inside a transaction.
+pub(crate) async fn get_class(conn: &mut SqliteConnection, id: Uuid) -> Result<ClassDTO> {
+    sqlx::query_as!(
+        ClassDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            class_type_id as "class_type_id: Uuid",
+            location_id as "location_id: Uuid", instructor_id as "instructor_id: Uuid", name,
+            starts_at as "starts_at: OffsetDateTime", ends_at as "ends_at: OffsetDateTime",
+            capacity, cancelled_at as "cancelled_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from classes
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
@@ -0,0 +1,324 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+
+/// A kind of class, e.g. "Spinning", carrying the rules for when it can be booked.
+#[derive(serde::Deserialize)]
+pub struct NewClassType {
+    pub name: String,
+    /// Leave out to allow booking as soon as the class is scheduled.
+    pub booking_opens_minutes_before: Option<i64>,
+    pub booking_closes_minutes_before: Option<i64>,
+}
+
+/// Fields left out keep their current value, `booking_opens_minutes_before: null`
+/// removes the opening rule.
+#[derive(serde::Deserialize)]
+pub struct UpdateClassType {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub booking_opens_minutes_before: Option<Option<i64>>,
+    pub booking_closes_minutes_before: Option<i64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ClassTypeDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub booking_opens_minutes_before: Option<i64>,
+    pub booking_closes_minutes_before: i64,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl ClassTypeDTO {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(("name", "can't be blank"));
+        }
+        if self.booking_closes_minutes_before < 0 {
+            errors.push(("booking_closes_minutes_before", "must not be negative"));
+        }
+        if let Some(opens) = self.booking_opens_minutes_before {
+            if opens <= self.booking_closes_minutes_before {
+                errors.push((
+                    "booking_opens_minutes_before",
+                    "must be greater than booking_closes_minutes_before",
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+
+    /// Checks `now` falls inside the booking window of a class of this type starting at
+    /// `starts_at`, explaining which rule blocked the booking otherwise.
+    pub(crate) fn check_booking_window(
+        &self,
+        starts_at: OffsetDateTime,
+        now: OffsetDateTime,
+    ) -> Result<()> {
+        let minutes_to_start = (starts_at - now).whole_minutes();
+
+        if let Some(opens) = self.booking_opens_minutes_before {
+            if minutes_to_start > opens {
+                return Err(Error::unprocessable_entity([(
+                    "booking",
+                    format!(
+                        "{} classes open for booking {} before they start",
+                        self.name,
+                        describe_minutes(opens)
+                    ),
+                )]));
+            }
+        }
+
+        if minutes_to_start < self.booking_closes_minutes_before {
+            return Err(Error::unprocessable_entity([(
+                "booking",
+                format!(
+                    "{} classes close for booking {} before they start",
+                    self.name,
+                    describe_minutes(self.booking_closes_minutes_before)
+                ),
+            )]));
+        }
+
+        Ok(())
+    }
+}
+
+/// Renders a duration in the largest whole unit, e.g. "2 days" or "90 minutes".
+pub(crate) fn describe_minutes(minutes: i64) -> String {
+    let (amount, unit) = if minutes >= 24 * 60 && minutes % (24 * 60) == 0 {
+        (minutes / (24 * 60), "day")
+    } else if minutes >= 60 && minutes % 60 == 0 {
+        (minutes / 60, "hour")
+    } else {
+        (minutes, "minute")
+    };
+
+    if amount == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{amount} {unit}s")
+    }
+}
+
+#[derive(Clone)]
+pub struct ClassTypeController {
+    pool: SqlitePool,
+}
+
+impl ClassTypeController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynClassTypeCtrl = Arc<dyn ClassTypeCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait ClassTypeCtrlTrait {
+    async fn create_class_type(
+        &self,
+        organization_id: Uuid,
+        new_class_type: NewClassType,
+        actor: &Actor,
+    ) -> Result<ClassTypeDTO>;
+    async fn update_class_type(
+        &self,
+        id: Uuid,
+        update_class_type: UpdateClassType,
+        actor: &Actor,
+    ) -> Result<ClassTypeDTO>;
+    async fn list_class_types(&self, organization_id: Uuid) -> Result<Vec<ClassTypeDTO>>;
+    async fn get_class_type(&self, id: Uuid) -> Result<ClassTypeDTO>;
+}
+
+#[async_trait]
+impl ClassTypeCtrlTrait for ClassTypeController {
+    async fn create_class_type(
+        &self,
+        organization_id: Uuid,
+        new_class_type: NewClassType,
+        actor: &Actor,
+    ) -> Result<ClassTypeDTO> {
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let class_type = ClassTypeDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            name: new_class_type.name,
+            booking_opens_minutes_before: new_class_type.booking_opens_minutes_before,
+            booking_closes_minutes_before: new_class_type
+                .booking_closes_minutes_before
+                .unwrap_or(0),
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        class_type.validate()?;
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        sqlx::query!(
+            r#"insert into "class_types" (
+                id, organization_id, name,
+                booking_opens_minutes_before, booking_closes_minutes_before,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3,
+                $4, $5,
+                $6, $7
+            )"#,
+            class_type.id,
+            class_type.organization_id,
+            class_type.name,
+            class_type.booking_opens_minutes_before,
+            class_type.booking_closes_minutes_before,
+            class_type.inserted_at,
+            class_type.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::ClassTypeCreated,
+            &class_type,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::ClassTypeCreated,
+            class_type.id,
+            None,
+            Some(&class_type),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(class_type)
+    }
+
+    async fn update_class_type(
+        &self,
+        id: Uuid,
+        update_class_type: UpdateClassType,
+        actor: &Actor,
+    ) -> Result<ClassTypeDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_class_type(&mut tx, id).await?;
+        let class_type = ClassTypeDTO {
+            name: update_class_type
+                .name
+                .unwrap_or_else(|| current.name.clone()),
+            booking_opens_minutes_before: update_class_type
+                .booking_opens_minutes_before
+                .unwrap_or(current.booking_opens_minutes_before),
+            booking_closes_minutes_before: update_class_type
+                .booking_closes_minutes_before
+                .unwrap_or(current.booking_closes_minutes_before),
+            updated_at: time::OffsetDateTime::now_utc(),
+            ..current
+        };
+        class_type.validate()?;
+
+        sqlx::query!(
+            r#"update class_types set
+                name = $1, booking_opens_minutes_before = $2, booking_closes_minutes_before = $3,
+                updated_at = $4
+            where id = $5"#,
+            class_type.name,
+            class_type.booking_opens_minutes_before,
+            class_type.booking_closes_minutes_before,
+            class_type.updated_at,
+            class_type.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(class_type.organization_id),
+            EventType::ClassTypeUpdated,
+            &class_type,
+            class_type.updated_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            class_type.organization_id,
+            AuditAction::ClassTypeUpdated,
+            class_type.id,
+            Some(&current),
+            Some(&class_type),
+            class_type.updated_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(class_type)
+    }
+
+    async fn list_class_types(&self, organization_id: Uuid) -> Result<Vec<ClassTypeDTO>> {
+        let class_types = sqlx::query_as!(
+            ClassTypeDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid", name,
+                booking_opens_minutes_before, booking_closes_minutes_before,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from class_types
+            where organization_id = $1
+            order by name"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(class_types)
+    }
+
+    async fn get_class_type(&self, id: Uuid) -> Result<ClassTypeDTO> {
+        get_class_type(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+pub(crate) async fn get_class_type(conn: &mut SqliteConnection, id: Uuid) -> Result<ClassTypeDTO> {
+    sqlx::query_as!(
+        ClassTypeDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid", name,
+            booking_opens_minutes_before, booking_closes_minutes_before,
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from class_types
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
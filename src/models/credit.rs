@@ -0,0 +1,579 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::invoice::{self, NewInvoice, NewInvoiceLine};
+use super::plan::{self, PlanKind};
+use super::promo_code;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum CreditEntryKind {
+    /// Credits bought with a class pack, usable until `expires_at`.
+    Grant,
+    /// A credit spent on a booking.
+    Debit,
+    /// A debit given back, e.g. when the booking was cancelled in time.
+    Refund,
+    /// Credits left on a grant when it expired.
+    Expiry,
+    /// Credits left on a grant taken back, e.g. when the sale of a drop-in pass was voided.
+    Revocation,
+}
+
+/// Buys a class pack. Staff can buy one for another member by passing `membership_id`.
+#[derive(serde::Deserialize)]
+pub struct NewClassPack {
+    pub plan_id: Uuid,
+    pub membership_id: Option<Uuid>,
+    pub promo_code: Option<String>,
+}
+
+/// A line of the credits ledger. Entries are never changed once written, balances are
+/// always summed from them.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct CreditEntryDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub kind: CreditEntryKind,
+    /// Positive for grants and refunds, negative for debits and expiries.
+    pub amount: i64,
+    /// The grant a debit, refund or expiry draws from.
+    pub grant_id: Option<Uuid>,
+    pub plan_id: Option<Uuid>,
+    pub booking_id: Option<Uuid>,
+    /// The invoice a grant was bought on.
+    pub invoice_id: Option<Uuid>,
+    pub expires_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CreditBalanceDTO {
+    /// Credits that can still be spent.
+    pub balance: i64,
+    pub entries: Vec<CreditEntryDTO>,
+}
+
+/// Credits to add to the member's ledger.
+pub(crate) struct NewGrant {
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub amount: i64,
+    pub plan_id: Option<Uuid>,
+    pub invoice_id: Option<Uuid>,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+/// A grant with what is left of it after its debits, refunds and expiries.
+struct GrantRemaining {
+    id: Uuid,
+    expires_at: Option<OffsetDateTime>,
+    remaining: i64,
+}
+
+#[derive(Clone)]
+pub struct CreditController {
+    pool: SqlitePool,
+}
+
+impl CreditController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynCreditCtrl = Arc<dyn CreditCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait CreditCtrlTrait {
+    /// Grants the member the class pack's credits, discounted by the promo code if given.
+    async fn purchase_class_pack(
+        &self,
+        membership_id: Uuid,
+        plan_id: Uuid,
+        promo_code: Option<&str>,
+        staff: Option<&Actor>,
+    ) -> Result<CreditEntryDTO>;
+    async fn get_balance(&self, membership_id: Uuid) -> Result<CreditBalanceDTO>;
+
+    /// Writes expiry entries for the credits left on grants that expired by `now`,
+    /// returning how many grants were expired.
+    async fn expire_credits(&self, now: OffsetDateTime) -> Result<u64>;
+}
+
+#[async_trait]
+impl CreditCtrlTrait for CreditController {
+    async fn purchase_class_pack(
+        &self,
+        membership_id: Uuid,
+        plan_id: Uuid,
+        promo_code: Option<&str>,
+        staff: Option<&Actor>,
+    ) -> Result<CreditEntryDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let plan = plan::get_plan(&mut tx, plan_id).await?;
+        let membership_organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            membership_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        if plan.organization_id != membership_organization_id || plan.archived_at.is_some() {
+            return Err(Error::unprocessable_entity([(
+                "plan_id",
+                "does not match a plan on sale at this organization",
+            )]));
+        }
+        let (class_count, validity_days) = match (plan.kind, plan.class_count, plan.validity_days) {
+            (PlanKind::ClassPack, Some(class_count), Some(validity_days)) => {
+                (class_count, validity_days)
+            }
+            _ => {
+                return Err(Error::unprocessable_entity([(
+                    "plan_id",
+                    "is not a class pack",
+                )]))
+            }
+        };
+
+        let now = time::OffsetDateTime::now_utc();
+        let expires_at = now
+            .checked_add(Duration::days(validity_days))
+            .ok_or_else(|| {
+                Error::unprocessable_entity([("plan_id", "is valid for too long to sell")])
+            })?;
+        let promo_code = match promo_code {
+            Some(code) => {
+                Some(promo_code::find_redeemable(&mut tx, code, &plan, membership_id, now).await?)
+            }
+            None => None,
+        };
+
+        let mut line = NewInvoiceLine::for_plan(&mut tx, &plan, plan.name.clone()).await?;
+        if let Some(promo_code) = &promo_code {
+            line = line.with_promo_code(promo_code);
+        }
+        let invoice = invoice::create_invoice(
+            &mut tx,
+            NewInvoice {
+                organization_id: plan.organization_id,
+                membership_id,
+                subscription_id: None,
+                currency: plan.currency.clone(),
+                period_start: None,
+                period_end: None,
+                next_payment_attempt_at: None,
+                lines: vec![line],
+            },
+            now,
+        )
+        .await?;
+        let entry = grant(
+            &mut tx,
+            NewGrant {
+                organization_id: plan.organization_id,
+                membership_id,
+                amount: class_count,
+                plan_id: Some(plan.id),
+                invoice_id: Some(invoice.id),
+                expires_at: Some(expires_at),
+            },
+            now,
+        )
+        .await?;
+        event::record(
+            &mut tx,
+            Some(plan.organization_id),
+            EventType::ClassPackPurchased,
+            &entry,
+            now,
+        )
+        .await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                plan.organization_id,
+                AuditAction::ClassPackPurchased,
+                entry.id,
+                None,
+                Some(&entry),
+                now,
+            )
+            .await?;
+        }
+        if let Some(promo_code) = &promo_code {
+            promo_code::redeem(
+                &mut tx,
+                promo_code,
+                membership_id,
+                None,
+                Some(invoice.id),
+                now,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(entry)
+    }
+
+    async fn get_balance(&self, membership_id: Uuid) -> Result<CreditBalanceDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut conn = self.pool.acquire().await?;
+
+        let balance = balance(&mut conn, membership_id, now).await?;
+        let entries = sqlx::query_as!(
+            CreditEntryDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", kind as "kind: CreditEntryKind", amount,
+                grant_id as "grant_id: Uuid", plan_id as "plan_id: Uuid",
+                booking_id as "booking_id: Uuid", invoice_id as "invoice_id: Uuid",
+                expires_at as "expires_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime"
+            from credit_entries
+            where membership_id = $1
+            order by inserted_at desc"#,
+            membership_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(CreditBalanceDTO { balance, entries })
+    }
+
+    async fn expire_credits(&self, now: OffsetDateTime) -> Result<u64> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let grants = sqlx::query!(
+            r#"select
+                g.id as "id: Uuid", g.organization_id as "organization_id: Uuid",
+                g.membership_id as "membership_id: Uuid",
+                g.expires_at as "expires_at: OffsetDateTime",
+                g.amount + coalesce(
+                    (select sum(e.amount) from credit_entries e where e.grant_id = g.id), 0
+                ) as "remaining!: i64"
+            from credit_entries g
+            where g.kind = $1"#,
+            CreditEntryKind::Grant
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut expired = 0;
+        for grant in grants {
+            if grant.remaining <= 0 || !matches!(grant.expires_at, Some(at) if at <= now) {
+                continue;
+            }
+
+            let entry = CreditEntryDTO {
+                id: uuid::Uuid::new_v4(),
+                organization_id: grant.organization_id,
+                membership_id: grant.membership_id,
+                kind: CreditEntryKind::Expiry,
+                amount: -grant.remaining,
+                grant_id: Some(grant.id),
+                plan_id: None,
+                booking_id: None,
+                invoice_id: None,
+                expires_at: None,
+                inserted_at: now,
+            };
+            insert_entry(&mut tx, &entry).await?;
+            event::record(
+                &mut tx,
+                Some(entry.organization_id),
+                EventType::CreditsExpired,
+                &entry,
+                now,
+            )
+            .await?;
+            expired += 1;
+        }
+
+        tx.commit().await?;
+
+        Ok(expired)
+    }
+}
+
+async fn insert_entry(conn: &mut SqliteConnection, entry: &CreditEntryDTO) -> Result<()> {
+    sqlx::query!(
+        r#"insert into "credit_entries" (
+            id, organization_id, membership_id, kind, amount,
+            grant_id, plan_id, booking_id, invoice_id, expires_at,
+            inserted_at
+        ) VALUES (
+            $1, $2, $3, $4, $5,
+            $6, $7, $8, $9, $10,
+            $11
+        )"#,
+        entry.id,
+        entry.organization_id,
+        entry.membership_id,
+        entry.kind,
+        entry.amount,
+        entry.grant_id,
+        entry.plan_id,
+        entry.booking_id,
+        entry.invoice_id,
+        entry.expires_at,
+        entry.inserted_at
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// The member's unexpired grants with credits left, soonest to expire first.
+async fn spendable_grants(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Vec<GrantRemaining>> {
+    let mut grants = sqlx::query_as!(
+        GrantRemaining,
+        r#"select
+            g.id as "id: Uuid", g.expires_at as "expires_at: OffsetDateTime",
+            g.amount + coalesce(
+                (select sum(e.amount) from credit_entries e where e.grant_id = g.id), 0
+            ) as "remaining!: i64"
+        from credit_entries g
+        where g.membership_id = $1 and g.kind = $2"#,
+        membership_id,
+        CreditEntryKind::Grant
+    )
+    .fetch_all(conn)
+    .await?;
+
+    grants.retain(|g| g.remaining > 0 && g.expires_at.is_none_or(|at| at > now));
+    grants.sort_by_key(|g| g.expires_at);
+
+    Ok(grants)
+}
+
+/// The member's spendable credits, summed from the ledger.
+pub(crate) async fn balance(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<i64> {
+    let grants = spendable_grants(conn, membership_id, now).await?;
+
+    Ok(grants.iter().map(|g| g.remaining).sum())
+}
+
+/// The grant a booking made `now` would spend a credit from, if the member has any left.
+pub(crate) async fn find_spendable_grant(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Option<Uuid>> {
+    let grants = spendable_grants(conn, membership_id, now).await?;
+
+    Ok(grants.first().map(|g| g.id))
+}
+
+pub(crate) async fn grant(
+    conn: &mut SqliteConnection,
+    new_grant: NewGrant,
+    now: OffsetDateTime,
+) -> Result<CreditEntryDTO> {
+    let entry = CreditEntryDTO {
+        id: uuid::Uuid::new_v4(),
+        organization_id: new_grant.organization_id,
+        membership_id: new_grant.membership_id,
+        kind: CreditEntryKind::Grant,
+        amount: new_grant.amount,
+        grant_id: None,
+        plan_id: new_grant.plan_id,
+        booking_id: None,
+        invoice_id: new_grant.invoice_id,
+        expires_at: new_grant.expires_at,
+        inserted_at: now,
+    };
+    insert_entry(conn, &entry).await?;
+
+    Ok(entry)
+}
+
+/// Takes back the credits left on the grants bought on the invoice. Credits already spent
+/// stay spent.
+pub(crate) async fn revoke_invoice_grants(
+    conn: &mut SqliteConnection,
+    invoice_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Vec<CreditEntryDTO>> {
+    let grants = sqlx::query!(
+        r#"select
+            g.id as "id: Uuid", g.organization_id as "organization_id: Uuid",
+            g.membership_id as "membership_id: Uuid",
+            g.amount + coalesce(
+                (select sum(e.amount) from credit_entries e where e.grant_id = g.id), 0
+            ) as "remaining!: i64"
+        from credit_entries g
+        where g.invoice_id = $1 and g.kind = $2"#,
+        invoice_id,
+        CreditEntryKind::Grant
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut revocations = Vec::new();
+    for grant in grants.into_iter().filter(|g| g.remaining > 0) {
+        let entry = CreditEntryDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id: grant.organization_id,
+            membership_id: grant.membership_id,
+            kind: CreditEntryKind::Revocation,
+            amount: -grant.remaining,
+            grant_id: Some(grant.id),
+            plan_id: None,
+            booking_id: None,
+            invoice_id: None,
+            expires_at: None,
+            inserted_at: now,
+        };
+        insert_entry(conn, &entry).await?;
+        revocations.push(entry);
+    }
+
+    Ok(revocations)
+}
+
+/// Gives the member back the class credits they spent from grants bought on the invoice,
+/// as a new grant lasting as long as those did. Returns `None` if none were spent.
+pub(crate) async fn restore_invoice_credits(
+    conn: &mut SqliteConnection,
+    invoice_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Option<CreditEntryDTO>> {
+    let grants = sqlx::query!(
+        r#"select
+            g.organization_id as "organization_id: Uuid", g.membership_id as "membership_id: Uuid",
+            g.expires_at as "expires_at: OffsetDateTime",
+            g.inserted_at as "inserted_at: OffsetDateTime",
+            coalesce(
+                (select sum(e.amount) from credit_entries e
+                where e.grant_id = g.id and e.kind in ($2, $3)), 0
+            ) as "spent!: i64"
+        from credit_entries g
+        where g.invoice_id = $1 and g.kind = $4"#,
+        invoice_id,
+        CreditEntryKind::Debit,
+        CreditEntryKind::Refund,
+        CreditEntryKind::Grant
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let spent: i64 = grants.iter().map(|g| -g.spent).sum();
+    let Some(first) = grants.first().filter(|_| spent > 0) else {
+        return Ok(None);
+    };
+    let validity = grants
+        .iter()
+        .filter_map(|g| Some(g.expires_at? - g.inserted_at))
+        .max();
+
+    let entry = grant(
+        conn,
+        NewGrant {
+            organization_id: first.organization_id,
+            membership_id: first.membership_id,
+            amount: spent,
+            plan_id: None,
+            invoice_id: Some(invoice_id),
+            expires_at: validity.map(|validity| now + validity),
+        },
+        now,
+    )
+    .await?;
+
+    Ok(Some(entry))
+}
+
+/// Spends a credit from `grant_id` on the booking.
+pub(crate) async fn debit(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    membership_id: Uuid,
+    grant_id: Uuid,
+    booking_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<CreditEntryDTO> {
+    let entry = CreditEntryDTO {
+        id: uuid::Uuid::new_v4(),
+        organization_id,
+        membership_id,
+        kind: CreditEntryKind::Debit,
+        amount: -1,
+        grant_id: Some(grant_id),
+        plan_id: None,
+        booking_id: Some(booking_id),
+        invoice_id: None,
+        expires_at: None,
+        inserted_at: now,
+    };
+    insert_entry(conn, &entry).await?;
+
+    Ok(entry)
+}
+
+/// Gives back the credits spent on the booking to the grants they came from. Does nothing
+/// for bookings not paid with credits or already refunded.
+pub(crate) async fn refund_booking(
+    conn: &mut SqliteConnection,
+    booking_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Vec<CreditEntryDTO>> {
+    let spent = sqlx::query!(
+        r#"select
+            organization_id as "organization_id: Uuid", membership_id as "membership_id: Uuid",
+            grant_id as "grant_id!: Uuid", sum(amount) as "amount!: i64"
+        from credit_entries
+        where booking_id = $1 and kind in ($2, $3)
+        group by organization_id, membership_id, grant_id"#,
+        booking_id,
+        CreditEntryKind::Debit,
+        CreditEntryKind::Refund
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut refunds = Vec::new();
+    for spent in spent.into_iter().filter(|s| s.amount < 0) {
+        let entry = CreditEntryDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id: spent.organization_id,
+            membership_id: spent.membership_id,
+            kind: CreditEntryKind::Refund,
+            amount: -spent.amount,
+            grant_id: Some(spent.grant_id),
+            plan_id: None,
+            booking_id: Some(booking_id),
+            invoice_id: None,
+            expires_at: None,
+            inserted_at: now,
+        };
+        insert_entry(conn, &entry).await?;
+        refunds.push(entry);
+    }
+
+    Ok(refunds)
+}
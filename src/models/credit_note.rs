@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::credit;
+use super::event::{self, EventType};
+use super::invoice::{self, InvoiceDTO, InvoiceStatus};
+use super::wallet;
+
+/// Refunds a paid invoice.
+#[derive(serde::Deserialize)]
+pub struct NewCreditNote {
+    /// In minor units of the invoice's currency, leave out to refund what is left of it.
+    pub amount: Option<i64>,
+    pub reason: Option<String>,
+    /// Also give the member back the class credits they spent from what the invoice bought,
+    /// e.g. when the classes fell through.
+    #[serde(default)]
+    pub restore_credits: bool,
+}
+
+/// A refund of part or all of an invoice. Credit notes are numbered per organization
+/// apart from invoices, and never change once issued.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct CreditNoteDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub invoice_id: Uuid,
+    /// Sequential per organization, without gaps.
+    pub number: i64,
+    /// Refunded, in minor units of `currency`.
+    pub amount: i64,
+    pub currency: String,
+    pub reason: String,
+    /// The provider's refund, `None` for invoices paid outside the provider. Those paid out
+    /// of the member's wallet are refunded back into it, others, e.g. paid in cash, by hand.
+    pub refund_id: Option<String>,
+    /// Class credits given back to the member.
+    pub credits_restored: i64,
+    pub issued_by_membership_id: Uuid,
+    pub inserted_at: OffsetDateTime,
+}
+
+/// What to refund of an invoice, worked out before asking the provider for the refund.
+pub struct RefundQuote {
+    pub invoice: InvoiceDTO,
+    pub amount: i64,
+    /// Sending the refund again with this key doesn't refund twice.
+    pub idempotency_key: String,
+}
+
+#[derive(Clone)]
+pub struct CreditNoteController {
+    pool: SqlitePool,
+}
+
+impl CreditNoteController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynCreditNoteCtrl = Arc<dyn CreditNoteCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait CreditNoteCtrlTrait {
+    /// Checks the invoice can be refunded by `new_credit_note`, and by how much.
+    async fn quote_refund(
+        &self,
+        invoice_id: Uuid,
+        new_credit_note: &NewCreditNote,
+    ) -> Result<RefundQuote>;
+
+    /// Issues the credit note for a refund made, `refund_id` being the provider's, or
+    /// refunds the invoice into the member's wallet if it was paid out of it. Issuing it
+    /// again for the same refund returns the credit note already issued.
+    async fn issue_credit_note(
+        &self,
+        invoice_id: Uuid,
+        issued_by_membership_id: Uuid,
+        new_credit_note: NewCreditNote,
+        refund_id: Option<String>,
+        actor: &Actor,
+    ) -> Result<CreditNoteDTO>;
+
+    async fn list_credit_notes(&self, organization_id: Uuid) -> Result<Vec<CreditNoteDTO>>;
+    async fn get_credit_note(&self, id: Uuid) -> Result<CreditNoteDTO>;
+}
+
+#[async_trait]
+impl CreditNoteCtrlTrait for CreditNoteController {
+    async fn quote_refund(
+        &self,
+        invoice_id: Uuid,
+        new_credit_note: &NewCreditNote,
+    ) -> Result<RefundQuote> {
+        let mut conn = self.pool.acquire().await?;
+
+        let invoice = invoice::get_invoice(&mut conn, invoice_id).await?;
+        let credit_notes = list_invoice_credit_notes(&mut conn, invoice_id).await?;
+        let amount = refund_amount(&invoice, &credit_notes, new_credit_note)?;
+
+        Ok(RefundQuote {
+            idempotency_key: format!("invoice-{}-refund-{}", invoice.id, credit_notes.len() + 1),
+            invoice,
+            amount,
+        })
+    }
+
+    async fn issue_credit_note(
+        &self,
+        invoice_id: Uuid,
+        issued_by_membership_id: Uuid,
+        new_credit_note: NewCreditNote,
+        refund_id: Option<String>,
+        actor: &Actor,
+    ) -> Result<CreditNoteDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let invoice = invoice::get_invoice(&mut tx, invoice_id).await?;
+        let mut credit_notes = list_invoice_credit_notes(&mut tx, invoice_id).await?;
+        if let Some(issued) = credit_notes
+            .iter()
+            .position(|c| refund_id.is_some() && c.refund_id == refund_id)
+        {
+            return Ok(credit_notes.swap_remove(issued));
+        }
+        let amount = refund_amount(&invoice, &credit_notes, &new_credit_note)?;
+
+        let now = time::OffsetDateTime::now_utc();
+        let credits_restored = if new_credit_note.restore_credits {
+            if credit_notes.iter().any(|c| c.credits_restored > 0) {
+                return Err(Error::unprocessable_entity([(
+                    "restore_credits",
+                    "the invoice's credits have already been restored",
+                )]));
+            }
+            credit::restore_invoice_credits(&mut tx, invoice.id, now)
+                .await?
+                .map_or(0, |grant| grant.amount)
+        } else {
+            0
+        };
+
+        // Like invoice numbers, bumping the counter takes SQLite's write lock so credit notes
+        // issued at the same time don't race for a number.
+        let number = sqlx::query_scalar!(
+            r#"update organizations set last_credit_note_number = last_credit_note_number + 1
+            where id = $1
+            returning last_credit_note_number"#,
+            invoice.organization_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        let credit_note = CreditNoteDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id: invoice.organization_id,
+            membership_id: invoice.membership_id,
+            invoice_id: invoice.id,
+            number,
+            amount,
+            currency: invoice.currency,
+            reason: new_credit_note.reason.unwrap_or_default(),
+            refund_id,
+            credits_restored,
+            issued_by_membership_id,
+            inserted_at: now,
+        };
+
+        sqlx::query!(
+            r#"insert into "credit_notes" (
+                id, organization_id, membership_id, invoice_id, number,
+                amount, currency, reason, refund_id, credits_restored,
+                issued_by_membership_id, inserted_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7, $8, $9, $10,
+                $11, $12
+            )"#,
+            credit_note.id,
+            credit_note.organization_id,
+            credit_note.membership_id,
+            credit_note.invoice_id,
+            credit_note.number,
+            credit_note.amount,
+            credit_note.currency,
+            credit_note.reason,
+            credit_note.refund_id,
+            credit_note.credits_restored,
+            credit_note.issued_by_membership_id,
+            credit_note.inserted_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        wallet::refund_to_wallet(&mut tx, &credit_note, now).await?;
+
+        event::record(
+            &mut tx,
+            Some(credit_note.organization_id),
+            EventType::CreditNoteIssued,
+            &credit_note,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            credit_note.organization_id,
+            AuditAction::CreditNoteIssued,
+            credit_note.id,
+            None,
+            Some(&credit_note),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(credit_note)
+    }
+
+    async fn list_credit_notes(&self, organization_id: Uuid) -> Result<Vec<CreditNoteDTO>> {
+        let credit_notes = sqlx::query_as!(
+            CreditNoteDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", invoice_id as "invoice_id: Uuid",
+                number, amount, currency, reason, refund_id, credits_restored,
+                issued_by_membership_id as "issued_by_membership_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime"
+            from credit_notes
+            where organization_id = $1
+            order by number desc"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(credit_notes)
+    }
+
+    async fn get_credit_note(&self, id: Uuid) -> Result<CreditNoteDTO> {
+        sqlx::query_as!(
+            CreditNoteDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", invoice_id as "invoice_id: Uuid",
+                number, amount, currency, reason, refund_id, credits_restored,
+                issued_by_membership_id as "issued_by_membership_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime"
+            from credit_notes
+            where id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+}
+
+/// How much `new_credit_note` refunds of the invoice, after the credit notes already issued.
+fn refund_amount(
+    invoice: &InvoiceDTO,
+    credit_notes: &[CreditNoteDTO],
+    new_credit_note: &NewCreditNote,
+) -> Result<i64> {
+    if invoice.status != InvoiceStatus::Paid {
+        return Err(Error::unprocessable_entity([(
+            "invoice",
+            "must be paid to be refunded",
+        )]));
+    }
+
+    let refundable = invoice.total_amount - credit_notes.iter().map(|c| c.amount).sum::<i64>();
+    let amount = new_credit_note.amount.unwrap_or(refundable);
+    if refundable <= 0 {
+        return Err(Error::unprocessable_entity([(
+            "invoice",
+            "has already been refunded in full",
+        )]));
+    }
+    if !(1..=refundable).contains(&amount) {
+        return Err(Error::unprocessable_entity([(
+            "amount",
+            format!("must be between 1 and the {refundable} left to refund"),
+        )]));
+    }
+
+    Ok(amount)
+}
+
+pub(crate) async fn list_invoice_credit_notes(
+    conn: &mut SqliteConnection,
+    invoice_id: Uuid,
+) -> Result<Vec<CreditNoteDTO>> {
+    let credit_notes = sqlx::query_as!(
+        CreditNoteDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", invoice_id as "invoice_id: Uuid",
+            number, amount, currency, reason, refund_id, credits_restored,
+            issued_by_membership_id as "issued_by_membership_id: Uuid",
+            inserted_at as "inserted_at: OffsetDateTime"
+        from credit_notes
+        where invoice_id = $1
+        order by number"#,
+        invoice_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(credit_notes)
+}
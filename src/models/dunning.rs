@@ -0,0 +1,338 @@
+use std::sync::Arc;
+
+use crate::http::Result;
+use crate::payments::{self, Charge, ChargeStatus, PaymentError};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::email;
+use super::invoice::{self, InvoiceDTO, InvoiceStatus};
+use super::notification::{self, NewNotification, NotificationEvent};
+use super::organization;
+use super::subscription::{self, SubscriptionStatus};
+
+/// Days after an invoice is issued on which a failed payment is retried.
+pub const RETRY_DAYS: [i64; 3] = [1, 3, 7];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum DunningFinalAction {
+    /// End the subscription straight away.
+    Cancel,
+    /// Freeze the subscription until staff resume it.
+    Pause,
+}
+
+/// The result of trying to charge an invoice.
+pub enum PaymentAttempt {
+    Succeeded {
+        charge_id: Option<String>,
+    },
+    /// Waiting on the provider, which reports the outcome by webhook.
+    Pending {
+        charge_id: String,
+    },
+    Failed {
+        charge_id: Option<String>,
+        message: String,
+    },
+}
+
+impl PaymentAttempt {
+    /// What asking the provider for `charge` amounted to. Errors reaching the provider are
+    /// passed on, the attempt wasn't made and can be retried with the same idempotency key.
+    pub(crate) fn from_charge(charge: payments::Result<Charge>) -> Result<Self> {
+        let attempt = match charge {
+            Ok(charge) => match charge.status {
+                ChargeStatus::Succeeded => PaymentAttempt::Succeeded {
+                    charge_id: Some(charge.id),
+                },
+                ChargeStatus::Pending => PaymentAttempt::Pending {
+                    charge_id: charge.id,
+                },
+                ChargeStatus::Failed => PaymentAttempt::Failed {
+                    charge_id: Some(charge.id).filter(|id| !id.is_empty()),
+                    message: charge
+                        .failure_message
+                        .unwrap_or_else(|| "payment failed".to_string()),
+                },
+            },
+            Err(PaymentError::Rejected(message)) => PaymentAttempt::Failed {
+                charge_id: None,
+                message,
+            },
+            Err(e @ PaymentError::Unavailable(_)) => return Err(e.into()),
+        };
+
+        Ok(attempt)
+    }
+}
+
+/// Where an invoice got to in dunning after a payment attempt.
+pub enum DunningStep {
+    Paid,
+    Pending,
+    /// The payment failed and is retried at `next_attempt_at`.
+    Retrying {
+        next_attempt_at: OffsetDateTime,
+    },
+    /// The last retry failed and the organization's final action was taken.
+    GaveUp {
+        action: DunningFinalAction,
+    },
+    /// The attempt changed nothing, e.g. the invoice was already settled.
+    Unchanged,
+}
+
+pub struct DunningOutcome {
+    pub invoice: InvoiceDTO,
+    pub step: DunningStep,
+}
+
+#[derive(Clone)]
+pub struct DunningController {
+    pool: SqlitePool,
+}
+
+impl DunningController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynDunningCtrl = Arc<dyn DunningCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait DunningCtrlTrait {
+    /// Open invoices whose next payment attempt is due by `now`.
+    async fn list_collectable_invoices(&self, now: OffsetDateTime) -> Result<Vec<InvoiceDTO>>;
+
+    /// Records an attempt to charge the invoice, scheduling a retry or giving up if it failed.
+    async fn record_payment_attempt(
+        &self,
+        invoice_id: Uuid,
+        attempt: PaymentAttempt,
+        now: OffsetDateTime,
+    ) -> Result<DunningOutcome>;
+}
+
+#[async_trait]
+impl DunningCtrlTrait for DunningController {
+    async fn list_collectable_invoices(&self, now: OffsetDateTime) -> Result<Vec<InvoiceDTO>> {
+        let mut conn = self.pool.acquire().await?;
+
+        let mut invoices = invoice::list_with_payment_attempts(&mut conn).await?;
+        invoices.retain(|i| i.next_payment_attempt_at.is_some_and(|at| at <= now));
+
+        Ok(invoices)
+    }
+
+    async fn record_payment_attempt(
+        &self,
+        invoice_id: Uuid,
+        attempt: PaymentAttempt,
+        now: OffsetDateTime,
+    ) -> Result<DunningOutcome> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let invoice = invoice::get_invoice(&mut tx, invoice_id).await?;
+        let outcome = match attempt {
+            PaymentAttempt::Succeeded { charge_id } => {
+                payment_succeeded(&mut tx, invoice, charge_id.as_deref(), now).await?
+            }
+            PaymentAttempt::Pending { charge_id } => {
+                let invoice = invoice::await_payment(&mut tx, invoice.id, &charge_id, now).await?;
+                DunningOutcome {
+                    invoice,
+                    step: DunningStep::Pending,
+                }
+            }
+            PaymentAttempt::Failed { charge_id, message } => {
+                payment_failed(&mut tx, invoice, charge_id.as_deref(), &message, now).await?
+            }
+        };
+
+        tx.commit().await?;
+
+        Ok(outcome)
+    }
+}
+
+/// Marks the invoice paid and brings its subscription back from `PastDue`.
+pub(crate) async fn payment_succeeded(
+    conn: &mut SqliteConnection,
+    invoice: InvoiceDTO,
+    charge_id: Option<&str>,
+    now: OffsetDateTime,
+) -> Result<DunningOutcome> {
+    if invoice.status != InvoiceStatus::Open {
+        if invoice.status == InvoiceStatus::Void {
+            tracing::warn!(
+                invoice_id = %invoice.id,
+                charge_id = charge_id.unwrap_or_default(),
+                "payment received for a void invoice, it needs refunding"
+            );
+        }
+        return Ok(DunningOutcome {
+            invoice,
+            step: DunningStep::Unchanged,
+        });
+    }
+
+    let invoice = invoice::mark_paid(conn, invoice.id, charge_id, now).await?;
+
+    if let Some(subscription_id) = invoice.subscription_id {
+        let subscription = subscription::get_subscription(conn, subscription_id).await?;
+        if subscription.status == SubscriptionStatus::PastDue {
+            subscription::transition(conn, subscription, SubscriptionStatus::Active, now).await?;
+        }
+    }
+
+    Ok(DunningOutcome {
+        invoice,
+        step: DunningStep::Paid,
+    })
+}
+
+/// Counts a failed payment of the invoice. Its subscription goes `PastDue` and the payment
+/// is retried on the `RETRY_DAYS`, after which the organization's `dunning_final_action` is
+/// taken. A failure already recorded for the same charge isn't counted twice, since both
+/// the charge and the provider's webhook report it.
+pub(crate) async fn payment_failed(
+    conn: &mut SqliteConnection,
+    invoice: InvoiceDTO,
+    charge_id: Option<&str>,
+    message: &str,
+    now: OffsetDateTime,
+) -> Result<DunningOutcome> {
+    let already_recorded = charge_id.is_some()
+        && invoice.charge_id.as_deref() == charge_id
+        && invoice.payment_error.is_some();
+    if invoice.status != InvoiceStatus::Open || already_recorded {
+        return Ok(DunningOutcome {
+            invoice,
+            step: DunningStep::Unchanged,
+        });
+    }
+
+    let payment_attempts = invoice.payment_attempts + 1;
+    let next_attempt_at = usize::try_from(payment_attempts - 1)
+        .ok()
+        .and_then(|i| RETRY_DAYS.get(i))
+        .map(|days| (invoice.issued_at + Duration::days(*days)).max(now));
+
+    let invoice = invoice::record_payment_failure(
+        conn,
+        invoice.id,
+        charge_id,
+        message,
+        payment_attempts,
+        next_attempt_at,
+        now,
+    )
+    .await?;
+
+    let Some(subscription_id) = invoice.subscription_id else {
+        let step = match next_attempt_at {
+            Some(next_attempt_at) => DunningStep::Retrying { next_attempt_at },
+            None => DunningStep::Unchanged,
+        };
+        let outcome = DunningOutcome { invoice, step };
+        notify(conn, &outcome, now).await?;
+        return Ok(outcome);
+    };
+
+    let mut subscription = subscription::get_subscription(conn, subscription_id).await?;
+    if matches!(
+        subscription.status,
+        SubscriptionStatus::Trialing | SubscriptionStatus::Active
+    ) {
+        subscription =
+            subscription::transition(conn, subscription, SubscriptionStatus::PastDue, now).await?;
+    }
+
+    let step = match next_attempt_at {
+        Some(next_attempt_at) => DunningStep::Retrying { next_attempt_at },
+        None if subscription.status == SubscriptionStatus::PastDue => {
+            let organization =
+                organization::get_organization(conn, subscription.organization_id).await?;
+            let action = organization.dunning_final_action;
+            match action {
+                DunningFinalAction::Cancel => {
+                    let subscription = subscription::transition(
+                        conn,
+                        subscription,
+                        SubscriptionStatus::Cancelled,
+                        now,
+                    )
+                    .await?;
+                    subscription::transition(conn, subscription, SubscriptionStatus::Expired, now)
+                        .await?;
+                }
+                DunningFinalAction::Pause => {
+                    subscription::transition(conn, subscription, SubscriptionStatus::Paused, now)
+                        .await?;
+                }
+            }
+            DunningStep::GaveUp { action }
+        }
+        None => DunningStep::Unchanged,
+    };
+
+    let outcome = DunningOutcome { invoice, step };
+    notify(conn, &outcome, now).await?;
+
+    Ok(outcome)
+}
+
+/// Lets the member know their payment failed, and what happens next.
+async fn notify(
+    conn: &mut SqliteConnection,
+    outcome: &DunningOutcome,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let invoice = &outcome.invoice;
+    let next_step = match &outcome.step {
+        DunningStep::Retrying { next_attempt_at } => format!(
+            "We'll try again on {}, please make sure your payment method is up to date.",
+            email::format_time(*next_attempt_at)
+        ),
+        DunningStep::GaveUp { action } => match action {
+            DunningFinalAction::Cancel => "Your subscription has been cancelled.".to_string(),
+            DunningFinalAction::Pause => {
+                "Your subscription has been paused until the invoice is settled.".to_string()
+            }
+        },
+        DunningStep::Paid | DunningStep::Pending | DunningStep::Unchanged => return Ok(()),
+    };
+
+    notification::notify(
+        conn,
+        NewNotification {
+            membership_id: invoice.membership_id,
+            event: NotificationEvent::PaymentFailed,
+            variables: vec![
+                ("invoice_number", invoice.number.to_string()),
+                (
+                    "amount",
+                    email::format_amount(invoice.total_amount, &invoice.currency),
+                ),
+                (
+                    "reason",
+                    invoice
+                        .payment_error
+                        .clone()
+                        .unwrap_or_else(|| "payment failed".to_string()),
+                ),
+                ("next_step", next_step),
+            ],
+        },
+        now,
+    )
+    .await?;
+
+    Ok(())
+}
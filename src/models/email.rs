@@ -0,0 +1,668 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::event::{self, EventType};
+use super::organization;
+
+/// Sending an email is given up on after this many attempts.
+pub const MAX_ATTEMPTS: i64 = 8;
+
+/// The outbox hands this many emails to the mailer per run, the rest wait for the next.
+const SEND_BATCH_SIZE: i64 = 100;
+
+/// Staff see this many of the latest emails sent on the organization's behalf.
+const LIST_LIMIT: i64 = 100;
+
+/// Every template can use the organization's name.
+const ORGANIZATION_NAME: &str = "organization_name";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum EmailTemplateKind {
+    /// Sent when staff add someone to the organization.
+    MembershipInvitation,
+    /// Sent when a member books a class.
+    BookingConfirmation,
+    /// Sent to everyone booked into a class staff cancelled.
+    ClassCancelled,
+    /// Sent when a spot opens up and a waitlisted member is booked into it.
+    WaitlistPromoted,
+    /// Sent when paying an invoice failed.
+    PaymentFailed,
+    /// Sent the organization's `class_reminder_hours` before a class the member booked.
+    ClassReminder,
+}
+
+impl EmailTemplateKind {
+    const ALL: [Self; 6] = [
+        Self::MembershipInvitation,
+        Self::BookingConfirmation,
+        Self::ClassCancelled,
+        Self::WaitlistPromoted,
+        Self::PaymentFailed,
+        Self::ClassReminder,
+    ];
+
+    /// The variables its templates can use, besides `organization_name`.
+    fn variables(self) -> &'static [&'static str] {
+        match self {
+            Self::MembershipInvitation => &["member_name", "role"],
+            Self::BookingConfirmation
+            | Self::ClassCancelled
+            | Self::WaitlistPromoted
+            | Self::ClassReminder => &["member_name", "class_name", "starts_at"],
+            Self::PaymentFailed => &[
+                "member_name",
+                "invoice_number",
+                "amount",
+                "reason",
+                "next_step",
+            ],
+        }
+    }
+
+    /// The subject and body used until the organization writes its own.
+    fn default_template(self) -> (&'static str, &'static str) {
+        match self {
+            Self::MembershipInvitation => (
+                "You've been added to {{organization_name}}",
+                "Hi {{member_name}},\n\n\
+                {{organization_name}} added you as {{role}}. Log in to book classes and \
+                manage your membership.\n\n\
+                See you soon,\n\
+                {{organization_name}}\n",
+            ),
+            Self::BookingConfirmation => (
+                "You're booked for {{class_name}}",
+                "Hi {{member_name}},\n\n\
+                You're booked for {{class_name}} on {{starts_at}}.\n\n\
+                Can't make it? Cancel your booking so someone on the waitlist can have \
+                your spot.\n\n\
+                {{organization_name}}\n",
+            ),
+            Self::ClassCancelled => (
+                "{{class_name}} on {{starts_at}} is cancelled",
+                "Hi {{member_name}},\n\n\
+                We're sorry, {{class_name}} on {{starts_at}} had to be cancelled. Your \
+                booking was cancelled with it and any class credit it used was given back.\n\n\
+                {{organization_name}}\n",
+            ),
+            Self::WaitlistPromoted => (
+                "A spot opened up in {{class_name}}",
+                "Hi {{member_name}},\n\n\
+                Good news, a spot opened up and you're now booked for {{class_name}} on \
+                {{starts_at}}.\n\n\
+                Can't make it after all? Cancel your booking so the next person on the \
+                waitlist can have it.\n\n\
+                {{organization_name}}\n",
+            ),
+            Self::PaymentFailed => (
+                "Your payment of invoice #{{invoice_number}} failed",
+                "Hi {{member_name}},\n\n\
+                We couldn't collect {{amount}} for invoice #{{invoice_number}}: {{reason}}.\n\n\
+                {{next_step}}\n\n\
+                {{organization_name}}\n",
+            ),
+            Self::ClassReminder => (
+                "Reminder: {{class_name}} on {{starts_at}}",
+                "Hi {{member_name}},\n\n\
+                Just a reminder that you're booked for {{class_name}} on {{starts_at}}.\n\n\
+                Can't make it? Cancel your booking so someone on the waitlist can have \
+                your spot.\n\n\
+                {{organization_name}}\n",
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum EmailStatus {
+    /// Waiting to be sent, or to be retried at `next_attempt_at`.
+    Pending,
+    Sent,
+    /// Rejected by the mail server, or every attempt failed.
+    Failed,
+}
+
+/// An organization's own wording of an email. Variables are written `{{name}}`.
+#[derive(serde::Deserialize)]
+pub struct EmailTemplate {
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct EmailTemplateDTO {
+    pub organization_id: Uuid,
+    pub kind: EmailTemplateKind,
+    pub subject: String,
+    pub body: String,
+    /// What the subject and body can use.
+    pub variables: Vec<String>,
+    /// `false` while the default template is used.
+    pub customized: bool,
+}
+
+/// An email in the outbox. It is written in the same transaction as whatever it is about,
+/// so it is sent even if the server restarts before it gets to it.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct EmailDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub template: EmailTemplateKind,
+    pub to_address: String,
+    /// The organization's name when the email was written.
+    pub from_name: String,
+    pub subject: String,
+    pub body: String,
+    pub status: EmailStatus,
+    pub attempts: i64,
+    pub next_attempt_at: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+    pub sent_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// An email to write to the outbox, rendered from the organization's template.
+pub(crate) struct NewEmail {
+    pub organization_id: Uuid,
+    pub to_address: String,
+    pub template: EmailTemplateKind,
+    /// Values for the template's variables, `organization_name` is filled in.
+    pub variables: Vec<(&'static str, String)>,
+}
+
+/// An email rendered from the organization's template, ready to send.
+pub(crate) struct RenderedEmail {
+    /// The organization's name.
+    pub from_name: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Who a member's email goes to.
+pub(crate) struct Recipient {
+    pub organization_id: Uuid,
+    pub email: String,
+    pub name: String,
+}
+
+#[derive(Clone)]
+pub struct EmailController {
+    pool: SqlitePool,
+}
+
+impl EmailController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynEmailCtrl = Arc<dyn EmailCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait EmailCtrlTrait {
+    /// Every kind of email, in the organization's wording where it has its own.
+    async fn list_templates(&self, organization_id: Uuid) -> Result<Vec<EmailTemplateDTO>>;
+    async fn save_template(
+        &self,
+        organization_id: Uuid,
+        kind: EmailTemplateKind,
+        template: EmailTemplate,
+    ) -> Result<EmailTemplateDTO>;
+    /// Goes back to the default wording.
+    async fn reset_template(
+        &self,
+        organization_id: Uuid,
+        kind: EmailTemplateKind,
+    ) -> Result<EmailTemplateDTO>;
+
+    /// The latest emails sent, or to be sent, on the organization's behalf.
+    async fn list_emails(&self, organization_id: Uuid) -> Result<Vec<EmailDTO>>;
+
+    /// Pending emails whose next attempt is due by `now`, oldest first.
+    async fn list_due_emails(&self, now: OffsetDateTime) -> Result<Vec<EmailDTO>>;
+    async fn record_sent(&self, id: Uuid, now: OffsetDateTime) -> Result<EmailDTO>;
+    /// Schedules another attempt with backoff, or gives up if the failure is `permanent` or
+    /// the email is out of attempts.
+    async fn record_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        permanent: bool,
+        now: OffsetDateTime,
+    ) -> Result<EmailDTO>;
+}
+
+#[async_trait]
+impl EmailCtrlTrait for EmailController {
+    async fn list_templates(&self, organization_id: Uuid) -> Result<Vec<EmailTemplateDTO>> {
+        let mut conn = self.pool.acquire().await?;
+
+        let mut templates = Vec::with_capacity(EmailTemplateKind::ALL.len());
+        for kind in EmailTemplateKind::ALL {
+            templates.push(get_template(&mut conn, organization_id, kind).await?);
+        }
+
+        Ok(templates)
+    }
+
+    async fn save_template(
+        &self,
+        organization_id: Uuid,
+        kind: EmailTemplateKind,
+        template: EmailTemplate,
+    ) -> Result<EmailTemplateDTO> {
+        let mut errors = Vec::new();
+        if template.subject.trim().is_empty() {
+            errors.push(("subject", "can't be blank".to_string()));
+        }
+        if template.body.trim().is_empty() {
+            errors.push(("body", "can't be blank".to_string()));
+        }
+        for (field, text) in [("subject", &template.subject), ("body", &template.body)] {
+            for name in template_variables(text) {
+                if name != ORGANIZATION_NAME && !kind.variables().contains(&name) {
+                    errors.push((field, format!("uses unknown variable {{{{{name}}}}}")));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(Error::unprocessable_entity(errors));
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        sqlx::query!(
+            r#"insert into "email_templates" (
+                id, organization_id, kind, subject, body, inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7
+            )
+            on conflict (organization_id, kind) do update set
+                subject = excluded.subject, body = excluded.body, updated_at = excluded.updated_at"#,
+            id,
+            organization_id,
+            kind,
+            template.subject,
+            template.body,
+            now,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let template = get_template(&mut tx, organization_id, kind).await?;
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::EmailTemplateUpdated,
+            &template,
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(template)
+    }
+
+    async fn reset_template(
+        &self,
+        organization_id: Uuid,
+        kind: EmailTemplateKind,
+    ) -> Result<EmailTemplateDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        sqlx::query!(
+            "delete from email_templates where organization_id = $1 and kind = $2",
+            organization_id,
+            kind
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let template = get_template(&mut tx, organization_id, kind).await?;
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::EmailTemplateReset,
+            &template,
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(template)
+    }
+
+    async fn list_emails(&self, organization_id: Uuid) -> Result<Vec<EmailDTO>> {
+        let emails = sqlx::query_as!(
+            EmailDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                template as "template: EmailTemplateKind", to_address, from_name, subject, body,
+                status as "status: EmailStatus", attempts,
+                next_attempt_at as "next_attempt_at: OffsetDateTime", last_error,
+                sent_at as "sent_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from emails
+            where organization_id = $1
+            order by inserted_at desc
+            limit $2"#,
+            organization_id,
+            LIST_LIMIT
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(emails)
+    }
+
+    async fn list_due_emails(&self, now: OffsetDateTime) -> Result<Vec<EmailDTO>> {
+        let emails = sqlx::query_as!(
+            EmailDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                template as "template: EmailTemplateKind", to_address, from_name, subject, body,
+                status as "status: EmailStatus", attempts,
+                next_attempt_at as "next_attempt_at: OffsetDateTime", last_error,
+                sent_at as "sent_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from emails
+            where status = $1 and next_attempt_at <= $2
+            order by next_attempt_at
+            limit $3"#,
+            EmailStatus::Pending,
+            now,
+            SEND_BATCH_SIZE
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(emails)
+    }
+
+    async fn record_sent(&self, id: Uuid, now: OffsetDateTime) -> Result<EmailDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_email(&mut tx, id).await?;
+        let email = EmailDTO {
+            status: EmailStatus::Sent,
+            attempts: current.attempts + 1,
+            next_attempt_at: None,
+            last_error: None,
+            sent_at: Some(now),
+            updated_at: now,
+            ..current
+        };
+        update_delivery(&mut tx, &email).await?;
+
+        tx.commit().await?;
+
+        Ok(email)
+    }
+
+    async fn record_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        permanent: bool,
+        now: OffsetDateTime,
+    ) -> Result<EmailDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_email(&mut tx, id).await?;
+        let attempts = current.attempts + 1;
+        let give_up = permanent || attempts >= MAX_ATTEMPTS;
+        let email = EmailDTO {
+            status: if give_up {
+                EmailStatus::Failed
+            } else {
+                EmailStatus::Pending
+            },
+            attempts,
+            // 1, 2, 4, ... minutes after each failed attempt.
+            next_attempt_at: (!give_up).then(|| now + Duration::minutes(1 << (attempts - 1))),
+            last_error: Some(error.to_string()),
+            updated_at: now,
+            ..current
+        };
+        update_delivery(&mut tx, &email).await?;
+
+        tx.commit().await?;
+
+        Ok(email)
+    }
+}
+
+async fn get_template(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    kind: EmailTemplateKind,
+) -> Result<EmailTemplateDTO> {
+    let custom = sqlx::query!(
+        "select subject, body from email_templates where organization_id = $1 and kind = $2",
+        organization_id,
+        kind
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    let (default_subject, default_body) = kind.default_template();
+    let mut variables = vec![ORGANIZATION_NAME.to_string()];
+    variables.extend(kind.variables().iter().map(|v| v.to_string()));
+
+    Ok(EmailTemplateDTO {
+        organization_id,
+        kind,
+        customized: custom.is_some(),
+        subject: custom
+            .as_ref()
+            .map_or(default_subject.to_string(), |c| c.subject.clone()),
+        body: custom.map_or(default_body.to_string(), |c| c.body),
+        variables,
+    })
+}
+
+async fn get_email(conn: &mut SqliteConnection, id: Uuid) -> Result<EmailDTO> {
+    sqlx::query_as!(
+        EmailDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            template as "template: EmailTemplateKind", to_address, from_name, subject, body,
+            status as "status: EmailStatus", attempts,
+            next_attempt_at as "next_attempt_at: OffsetDateTime", last_error,
+            sent_at as "sent_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from emails
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+async fn update_delivery(conn: &mut SqliteConnection, email: &EmailDTO) -> Result<()> {
+    sqlx::query!(
+        r#"update emails set
+            status = $1, attempts = $2, next_attempt_at = $3, last_error = $4, sent_at = $5,
+            updated_at = $6
+        where id = $7"#,
+        email.status,
+        email.attempts,
+        email.next_attempt_at,
+        email.last_error,
+        email.sent_at,
+        email.updated_at,
+        email.id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes an email to the outbox, to be sent by the outbox job.
+pub(crate) async fn enqueue(
+    conn: &mut SqliteConnection,
+    new_email: NewEmail,
+    now: OffsetDateTime,
+) -> Result<EmailDTO> {
+    let rendered = render_template(
+        conn,
+        new_email.organization_id,
+        new_email.template,
+        new_email.variables,
+    )
+    .await?;
+
+    let email = EmailDTO {
+        id: uuid::Uuid::new_v4(),
+        organization_id: new_email.organization_id,
+        template: new_email.template,
+        to_address: new_email.to_address,
+        from_name: rendered.from_name,
+        subject: rendered.subject,
+        body: rendered.body,
+        status: EmailStatus::Pending,
+        attempts: 0,
+        next_attempt_at: Some(now),
+        last_error: None,
+        sent_at: None,
+        inserted_at: now,
+        updated_at: now,
+    };
+
+    sqlx::query!(
+        r#"insert into "emails" (
+            id, organization_id, template, to_address, from_name, subject, body,
+            status, attempts, next_attempt_at,
+            inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7,
+            $8, $9, $10,
+            $11, $12
+        )"#,
+        email.id,
+        email.organization_id,
+        email.template,
+        email.to_address,
+        email.from_name,
+        email.subject,
+        email.body,
+        email.status,
+        email.attempts,
+        email.next_attempt_at,
+        email.inserted_at,
+        email.updated_at
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(email)
+}
+
+/// Renders the organization's template of `kind`, `organization_name` is filled in.
+pub(crate) async fn render_template(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    kind: EmailTemplateKind,
+    mut variables: Vec<(&'static str, String)>,
+) -> Result<RenderedEmail> {
+    let organization = organization::get_organization(conn, organization_id).await?;
+    let template = get_template(conn, organization.id, kind).await?;
+
+    variables.push((ORGANIZATION_NAME, organization.name.clone()));
+
+    Ok(RenderedEmail {
+        from_name: organization.name,
+        subject: render(&template.subject, &variables),
+        body: render(&template.body, &variables),
+    })
+}
+
+pub(crate) async fn recipient(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+) -> Result<Recipient> {
+    sqlx::query_as!(
+        Recipient,
+        r#"select m.organization_id as "organization_id: Uuid", a.email, a.name
+        from memberships m
+        join accounts a on a.id = m.account_id
+        where m.id = $1"#,
+        membership_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Formats a time for an email, e.g. `2026-12-01 09:00 UTC`.
+pub(crate) fn format_time(at: OffsetDateTime) -> String {
+    let at = at.to_offset(time::UtcOffset::UTC);
+    format!("{} {:02}:{:02} UTC", at.date(), at.hour(), at.minute())
+}
+
+/// Formats an amount in minor units for an email, e.g. `12.50 USD`.
+pub(crate) fn format_amount(amount: i64, currency: &str) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let amount = amount.unsigned_abs();
+    format!("{sign}{}.{:02} {currency}", amount / 100, amount % 100)
+}
+
+/// Fills in the template's `{{name}}` variables. Unknown ones are left as they are.
+fn render(template: &str, variables: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some((before, name, after)) = next_variable(rest) {
+        rendered.push_str(before);
+        match variables.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => rendered.push_str(value),
+            None => rendered.push_str(&rest[before.len()..rest.len() - after.len()]),
+        }
+        rest = after;
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+fn template_variables(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some((_, name, after)) = next_variable(rest) {
+        names.push(name);
+        rest = after;
+    }
+
+    names
+}
+
+/// Splits `text` around its first `{{name}}` into what comes before, the name and what
+/// comes after.
+fn next_variable(text: &str) -> Option<(&str, &str, &str)> {
+    let start = text.find("{{")?;
+    let end = start + text[start..].find("}}")?;
+
+    Some((
+        &text[..start],
+        text[start + 2..end].trim(),
+        &text[end + 2..],
+    ))
+}
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use crate::http::Result;
+use async_trait::async_trait;
+
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::webhook;
+
+/// How many events a page of the feed holds when the consumer doesn't say.
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// The most events a page of the feed holds.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Something that changed. Every change the controllers make is recorded as one of these,
+/// except bookkeeping: of jobs, of platform usage being metered and of email,
+/// notifications and webhooks being sent. Changes to webhook endpoints aren't recorded
+/// either, they would carry the endpoints' signing secrets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+pub enum EventType {
+    #[serde(rename = "account.created")]
+    #[sqlx(rename = "account.created")]
+    AccountCreated,
+    /// An account logged in. The session's id is the account's token, so it's left out.
+    #[serde(rename = "session.opened")]
+    #[sqlx(rename = "session.opened")]
+    SessionOpened,
+    #[serde(rename = "organization.created")]
+    #[sqlx(rename = "organization.created")]
+    OrganizationCreated,
+    #[serde(rename = "organization.updated")]
+    #[sqlx(rename = "organization.updated")]
+    OrganizationUpdated,
+    /// A membership with the member role.
+    #[serde(rename = "member.created")]
+    #[sqlx(rename = "member.created")]
+    MemberCreated,
+    /// A membership with the owner or staff role, including the owner of a new organization.
+    #[serde(rename = "staff.created")]
+    #[sqlx(rename = "staff.created")]
+    StaffCreated,
+    /// The token is left out.
+    #[serde(rename = "member.check_in_token_rotated")]
+    #[sqlx(rename = "member.check_in_token_rotated")]
+    CheckInTokenRotated,
+    #[serde(rename = "location.created")]
+    #[sqlx(rename = "location.created")]
+    LocationCreated,
+    #[serde(rename = "class_type.created")]
+    #[sqlx(rename = "class_type.created")]
+    ClassTypeCreated,
+    #[serde(rename = "class_type.updated")]
+    #[sqlx(rename = "class_type.updated")]
+    ClassTypeUpdated,
+    #[serde(rename = "instructor.created")]
+    #[sqlx(rename = "instructor.created")]
+    InstructorCreated,
+    #[serde(rename = "instructor.updated")]
+    #[sqlx(rename = "instructor.updated")]
+    InstructorUpdated,
+    #[serde(rename = "instructor.availability_updated")]
+    #[sqlx(rename = "instructor.availability_updated")]
+    InstructorAvailabilityUpdated,
+    #[serde(rename = "class.created")]
+    #[sqlx(rename = "class.created")]
+    ClassCreated,
+    #[serde(rename = "class.cancelled")]
+    #[sqlx(rename = "class.cancelled")]
+    ClassCancelled,
+    /// Also when a member is promoted off the waitlist.
+    #[serde(rename = "booking.created")]
+    #[sqlx(rename = "booking.created")]
+    BookingCreated,
+    /// By the member or staff, or because the class was cancelled.
+    #[serde(rename = "booking.cancelled")]
+    #[sqlx(rename = "booking.cancelled")]
+    BookingCancelled,
+    #[serde(rename = "booking.checked_in")]
+    #[sqlx(rename = "booking.checked_in")]
+    BookingCheckedIn,
+    #[serde(rename = "booking.no_show")]
+    #[sqlx(rename = "booking.no_show")]
+    BookingNoShow,
+    /// Taken off the class by staff.
+    #[serde(rename = "booking.removed")]
+    #[sqlx(rename = "booking.removed")]
+    BookingRemoved,
+    #[serde(rename = "waitlist.joined")]
+    #[sqlx(rename = "waitlist.joined")]
+    WaitlistJoined,
+    /// By the member. Being promoted is a `booking.created`.
+    #[serde(rename = "waitlist.left")]
+    #[sqlx(rename = "waitlist.left")]
+    WaitlistLeft,
+    /// For a late cancellation or a no-show.
+    #[serde(rename = "penalty.applied")]
+    #[sqlx(rename = "penalty.applied")]
+    PenaltyApplied,
+    #[serde(rename = "plan.created")]
+    #[sqlx(rename = "plan.created")]
+    PlanCreated,
+    #[serde(rename = "plan.updated")]
+    #[sqlx(rename = "plan.updated")]
+    PlanUpdated,
+    #[serde(rename = "plan.archived")]
+    #[sqlx(rename = "plan.archived")]
+    PlanArchived,
+    #[serde(rename = "subscription.created")]
+    #[sqlx(rename = "subscription.created")]
+    SubscriptionCreated,
+    /// A new billing period started.
+    #[serde(rename = "subscription.renewed")]
+    #[sqlx(rename = "subscription.renewed")]
+    SubscriptionRenewed,
+    /// Out of its trial, paid up after being past due, or resumed.
+    #[serde(rename = "subscription.activated")]
+    #[sqlx(rename = "subscription.activated")]
+    SubscriptionActivated,
+    #[serde(rename = "subscription.past_due")]
+    #[sqlx(rename = "subscription.past_due")]
+    SubscriptionPastDue,
+    /// Also when a freeze starts.
+    #[serde(rename = "subscription.paused")]
+    #[sqlx(rename = "subscription.paused")]
+    SubscriptionPaused,
+    #[serde(rename = "subscription.cancelled")]
+    #[sqlx(rename = "subscription.cancelled")]
+    SubscriptionCancelled,
+    #[serde(rename = "subscription.expired")]
+    #[sqlx(rename = "subscription.expired")]
+    SubscriptionExpired,
+    #[serde(rename = "subscription.freeze_scheduled")]
+    #[sqlx(rename = "subscription.freeze_scheduled")]
+    SubscriptionFreezeScheduled,
+    /// A freeze was called off before it started, or dropped.
+    #[serde(rename = "subscription.freeze_cleared")]
+    #[sqlx(rename = "subscription.freeze_cleared")]
+    SubscriptionFreezeCleared,
+    #[serde(rename = "class_pack.purchased")]
+    #[sqlx(rename = "class_pack.purchased")]
+    ClassPackPurchased,
+    /// The credits left on a grant ran out.
+    #[serde(rename = "credits.expired")]
+    #[sqlx(rename = "credits.expired")]
+    CreditsExpired,
+    #[serde(rename = "tax_rate.created")]
+    #[sqlx(rename = "tax_rate.created")]
+    TaxRateCreated,
+    #[serde(rename = "invoice.created")]
+    #[sqlx(rename = "invoice.created")]
+    InvoiceCreated,
+    #[serde(rename = "invoice.paid")]
+    #[sqlx(rename = "invoice.paid")]
+    InvoicePaid,
+    #[serde(rename = "invoice.payment_failed")]
+    #[sqlx(rename = "invoice.payment_failed")]
+    InvoicePaymentFailed,
+    #[serde(rename = "invoice.voided")]
+    #[sqlx(rename = "invoice.voided")]
+    InvoiceVoided,
+    #[serde(rename = "credit_note.issued")]
+    #[sqlx(rename = "credit_note.issued")]
+    CreditNoteIssued,
+    #[serde(rename = "payment_profile.saved")]
+    #[sqlx(rename = "payment_profile.saved")]
+    PaymentProfileSaved,
+    #[serde(rename = "promo_code.created")]
+    #[sqlx(rename = "promo_code.created")]
+    PromoCodeCreated,
+    #[serde(rename = "promo_code.archived")]
+    #[sqlx(rename = "promo_code.archived")]
+    PromoCodeArchived,
+    #[serde(rename = "promo_code.redeemed")]
+    #[sqlx(rename = "promo_code.redeemed")]
+    PromoCodeRedeemed,
+    #[serde(rename = "product.created")]
+    #[sqlx(rename = "product.created")]
+    ProductCreated,
+    #[serde(rename = "product.updated")]
+    #[sqlx(rename = "product.updated")]
+    ProductUpdated,
+    #[serde(rename = "product.archived")]
+    #[sqlx(rename = "product.archived")]
+    ProductArchived,
+    /// Restocked or corrected by staff. Stock sold or put back is part of `sale.*`.
+    #[serde(rename = "product.stock_adjusted")]
+    #[sqlx(rename = "product.stock_adjusted")]
+    ProductStockAdjusted,
+    #[serde(rename = "sale.created")]
+    #[sqlx(rename = "sale.created")]
+    SaleCreated,
+    #[serde(rename = "sale.voided")]
+    #[sqlx(rename = "sale.voided")]
+    SaleVoided,
+    #[serde(rename = "cash_drawer.counted")]
+    #[sqlx(rename = "cash_drawer.counted")]
+    CashDrawerCounted,
+    #[serde(rename = "gift_card.issued")]
+    #[sqlx(rename = "gift_card.issued")]
+    GiftCardIssued,
+    #[serde(rename = "gift_card.redeemed")]
+    #[sqlx(rename = "gift_card.redeemed")]
+    GiftCardRedeemed,
+    #[serde(rename = "email_template.updated")]
+    #[sqlx(rename = "email_template.updated")]
+    EmailTemplateUpdated,
+    #[serde(rename = "email_template.reset")]
+    #[sqlx(rename = "email_template.reset")]
+    EmailTemplateReset,
+    #[serde(rename = "notification_preferences.updated")]
+    #[sqlx(rename = "notification_preferences.updated")]
+    NotificationPreferencesUpdated,
+    #[serde(rename = "platform_subscription.changed")]
+    #[sqlx(rename = "platform_subscription.changed")]
+    PlatformSubscriptionChanged,
+}
+
+impl EventType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AccountCreated => "account.created",
+            Self::SessionOpened => "session.opened",
+            Self::OrganizationCreated => "organization.created",
+            Self::OrganizationUpdated => "organization.updated",
+            Self::MemberCreated => "member.created",
+            Self::StaffCreated => "staff.created",
+            Self::CheckInTokenRotated => "member.check_in_token_rotated",
+            Self::LocationCreated => "location.created",
+            Self::ClassTypeCreated => "class_type.created",
+            Self::ClassTypeUpdated => "class_type.updated",
+            Self::InstructorCreated => "instructor.created",
+            Self::InstructorUpdated => "instructor.updated",
+            Self::InstructorAvailabilityUpdated => "instructor.availability_updated",
+            Self::ClassCreated => "class.created",
+            Self::ClassCancelled => "class.cancelled",
+            Self::BookingCreated => "booking.created",
+            Self::BookingCancelled => "booking.cancelled",
+            Self::BookingCheckedIn => "booking.checked_in",
+            Self::BookingNoShow => "booking.no_show",
+            Self::BookingRemoved => "booking.removed",
+            Self::WaitlistJoined => "waitlist.joined",
+            Self::WaitlistLeft => "waitlist.left",
+            Self::PenaltyApplied => "penalty.applied",
+            Self::PlanCreated => "plan.created",
+            Self::PlanUpdated => "plan.updated",
+            Self::PlanArchived => "plan.archived",
+            Self::SubscriptionCreated => "subscription.created",
+            Self::SubscriptionRenewed => "subscription.renewed",
+            Self::SubscriptionActivated => "subscription.activated",
+            Self::SubscriptionPastDue => "subscription.past_due",
+            Self::SubscriptionPaused => "subscription.paused",
+            Self::SubscriptionCancelled => "subscription.cancelled",
+            Self::SubscriptionExpired => "subscription.expired",
+            Self::SubscriptionFreezeScheduled => "subscription.freeze_scheduled",
+            Self::SubscriptionFreezeCleared => "subscription.freeze_cleared",
+            Self::ClassPackPurchased => "class_pack.purchased",
+            Self::CreditsExpired => "credits.expired",
+            Self::TaxRateCreated => "tax_rate.created",
+            Self::InvoiceCreated => "invoice.created",
+            Self::InvoicePaid => "invoice.paid",
+            Self::InvoicePaymentFailed => "invoice.payment_failed",
+            Self::InvoiceVoided => "invoice.voided",
+            Self::CreditNoteIssued => "credit_note.issued",
+            Self::PaymentProfileSaved => "payment_profile.saved",
+            Self::PromoCodeCreated => "promo_code.created",
+            Self::PromoCodeArchived => "promo_code.archived",
+            Self::PromoCodeRedeemed => "promo_code.redeemed",
+            Self::ProductCreated => "product.created",
+            Self::ProductUpdated => "product.updated",
+            Self::ProductArchived => "product.archived",
+            Self::ProductStockAdjusted => "product.stock_adjusted",
+            Self::SaleCreated => "sale.created",
+            Self::SaleVoided => "sale.voided",
+            Self::CashDrawerCounted => "cash_drawer.counted",
+            Self::GiftCardIssued => "gift_card.issued",
+            Self::GiftCardRedeemed => "gift_card.redeemed",
+            Self::EmailTemplateUpdated => "email_template.updated",
+            Self::EmailTemplateReset => "email_template.reset",
+            Self::NotificationPreferencesUpdated => "notification_preferences.updated",
+            Self::PlatformSubscriptionChanged => "platform_subscription.changed",
+        }
+    }
+
+    /// Whether events of this type happen in an organization, as opposed to an account,
+    /// and so can be posted to its webhook endpoints.
+    pub fn is_organization_event(self) -> bool {
+        !matches!(self, Self::AccountCreated | Self::SessionOpened)
+    }
+}
+
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An entry in the event log. Events are recorded in the transaction that made the change,
+/// so the log has every change that was committed and none that wasn't.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct EventDTO {
+    /// Where the event is in the log, increasing in the order events were committed. Pass
+    /// the last one read as `after` to read on from there.
+    pub sequence: i64,
+    pub id: Uuid,
+    /// `None` for events of an account, see `EventType::is_organization_event`.
+    pub organization_id: Option<Uuid>,
+    #[serde(rename = "type")]
+    pub event: EventType,
+    /// What changed, usually the record as it is after the change.
+    pub data: Json<serde_json::Value>,
+    pub inserted_at: OffsetDateTime,
+}
+
+/// A page of the event log.
+#[derive(serde::Deserialize)]
+pub struct EventQuery {
+    /// Reads the events after this `sequence`, from the start of the log if left out.
+    #[serde(default)]
+    pub after: i64,
+    pub limit: Option<i64>,
+    /// Only the events of this organization.
+    pub organization_id: Option<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct EventController {
+    pool: SqlitePool,
+}
+
+impl EventController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynEventCtrl = Arc<dyn EventCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait EventCtrlTrait {
+    /// The events after the query's cursor, oldest first.
+    async fn list_events(&self, query: EventQuery) -> Result<Vec<EventDTO>>;
+}
+
+#[async_trait]
+impl EventCtrlTrait for EventController {
+    async fn list_events(&self, query: EventQuery) -> Result<Vec<EventDTO>> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let events = sqlx::query_as!(
+            EventDTO,
+            r#"select
+                sequence, id as "id: Uuid", organization_id as "organization_id: Uuid",
+                type as "event: EventType", data as "data: Json<serde_json::Value>",
+                inserted_at as "inserted_at: OffsetDateTime"
+            from events
+            where sequence > $1 and ($2 is null or organization_id = $2)
+            order by sequence
+            limit $3"#,
+            query.after,
+            query.organization_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(events)
+    }
+}
+
+/// Appends an event to the log, in the transaction making the change, and queues its
+/// delivery to the webhook endpoints of its organization that subscribed to it.
+pub(crate) async fn record<T: serde::Serialize>(
+    conn: &mut SqliteConnection,
+    organization_id: Option<Uuid>,
+    event: EventType,
+    data: &T,
+    now: OffsetDateTime,
+) -> Result<EventDTO> {
+    let id = uuid::Uuid::new_v4();
+    let data = serde_json::to_value(data)
+        .map(Json)
+        .map_err(|e| anyhow::anyhow!("failed to encode {event}: {e}"))?;
+
+    let event = sqlx::query_as!(
+        EventDTO,
+        r#"insert into "events" (
+            id, organization_id, type, data, inserted_at
+        ) VALUES (
+            $1, $2, $3, $4, $5
+        ) returning
+            sequence, id as "id: Uuid", organization_id as "organization_id: Uuid",
+            type as "event: EventType", data as "data: Json<serde_json::Value>",
+            inserted_at as "inserted_at: OffsetDateTime""#,
+        id,
+        organization_id,
+        event,
+        data,
+        now
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    webhook::publish(conn, &event).await?;
+
+    Ok(event)
+}
@@ -0,0 +1,398 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use rand::Rng;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::organization::{self, is_currency_code};
+use super::wallet::{self, LedgerAccountKind, LedgerTransactionKind, NewLedgerTransaction};
+
+/// Letters and digits that can't be mistaken for one another when read off a card.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 16;
+
+#[derive(serde::Deserialize)]
+pub struct NewGiftCard {
+    /// In minor units of `currency`.
+    pub amount: i64,
+    /// Leave out to use the organization's currency.
+    pub currency: Option<String>,
+    /// Leave out for a card that doesn't expire.
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+/// Redeems a gift card into the member's wallet.
+#[derive(serde::Deserialize)]
+pub struct GiftCardRedemption {
+    /// Matched ignoring case, spaces and dashes.
+    pub code: String,
+}
+
+/// A prepaid card sold by the organization. Its balance is redeemed into a member's wallet
+/// all at once.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct GiftCardDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    /// Generated, e.g. `K7QM-2XPA-9RTD-H4NW`.
+    pub code: String,
+    /// In minor units of `currency`.
+    pub initial_amount: i64,
+    /// What is left to redeem, summed from the ledger.
+    pub balance: i64,
+    pub currency: String,
+    pub expires_at: Option<OffsetDateTime>,
+    pub issued_by_membership_id: Uuid,
+    pub redeemed_by_membership_id: Option<Uuid>,
+    pub redeemed_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct GiftCardController {
+    pool: SqlitePool,
+}
+
+impl GiftCardController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynGiftCardCtrl = Arc<dyn GiftCardCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait GiftCardCtrlTrait {
+    /// Issues a card with a new code, for an amount taken outside of the app, e.g. at the
+    /// front desk.
+    async fn issue_gift_card(
+        &self,
+        organization_id: Uuid,
+        issued_by_membership_id: Uuid,
+        new_gift_card: NewGiftCard,
+        actor: &Actor,
+    ) -> Result<GiftCardDTO>;
+
+    /// Moves the card's balance into the member's wallet. A card can only be redeemed once.
+    async fn redeem_gift_card(
+        &self,
+        membership_id: Uuid,
+        redemption: GiftCardRedemption,
+    ) -> Result<GiftCardDTO>;
+
+    async fn list_gift_cards(&self, organization_id: Uuid) -> Result<Vec<GiftCardDTO>>;
+    async fn get_gift_card(&self, id: Uuid) -> Result<GiftCardDTO>;
+}
+
+#[async_trait]
+impl GiftCardCtrlTrait for GiftCardController {
+    async fn issue_gift_card(
+        &self,
+        organization_id: Uuid,
+        issued_by_membership_id: Uuid,
+        new_gift_card: NewGiftCard,
+        actor: &Actor,
+    ) -> Result<GiftCardDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let organization = organization::get_organization(&mut tx, organization_id).await?;
+        let currency = new_gift_card.currency.unwrap_or(organization.currency);
+        let now = time::OffsetDateTime::now_utc();
+        // Stored in UTC like every other time, whatever offset it was given with.
+        let expires_at = new_gift_card
+            .expires_at
+            .map(|at| at.to_offset(UtcOffset::UTC));
+
+        let mut errors = Vec::new();
+        if new_gift_card.amount < 1 {
+            errors.push(("amount", "must be at least 1"));
+        }
+        if !is_currency_code(&currency) {
+            errors.push(("currency", "must be a three letter ISO 4217 code"));
+        }
+        if expires_at.is_some_and(|at| at <= now) {
+            errors.push(("expires_at", "must be in the future"));
+        }
+        if !errors.is_empty() {
+            return Err(Error::unprocessable_entity(errors));
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let code = generate_code();
+        let account = wallet::ledger_account(
+            &mut tx,
+            organization_id,
+            LedgerAccountKind::GiftCard,
+            id,
+            &currency,
+            now,
+        )
+        .await?;
+        let issued = wallet::ledger_account(
+            &mut tx,
+            organization_id,
+            LedgerAccountKind::GiftCardsIssued,
+            organization_id,
+            &currency,
+            now,
+        )
+        .await?;
+
+        sqlx::query!(
+            r#"insert into "gift_cards" (
+                id, organization_id, code, initial_amount, currency, ledger_account_id,
+                expires_at, issued_by_membership_id,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6,
+                $7, $8,
+                $9, $10
+            )"#,
+            id,
+            organization_id,
+            code,
+            new_gift_card.amount,
+            currency,
+            account,
+            expires_at,
+            issued_by_membership_id,
+            now,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        wallet::post(
+            &mut tx,
+            NewLedgerTransaction {
+                organization_id,
+                kind: LedgerTransactionKind::GiftCardIssue,
+                currency,
+                membership_id: None,
+                gift_card_id: Some(id),
+                invoice_id: None,
+                credit_note_id: None,
+                entries: vec![
+                    (account, new_gift_card.amount),
+                    (issued, -new_gift_card.amount),
+                ],
+            },
+            now,
+        )
+        .await?;
+
+        let gift_card = get_gift_card(&mut tx, id).await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::GiftCardIssued,
+            &gift_card,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::GiftCardIssued,
+            gift_card.id,
+            None,
+            Some(&gift_card),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(gift_card)
+    }
+
+    async fn redeem_gift_card(
+        &self,
+        membership_id: Uuid,
+        redemption: GiftCardRedemption,
+    ) -> Result<GiftCardDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            membership_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        let code = normalize_code(&redemption.code);
+        let gift_card = sqlx::query_scalar!(
+            r#"select id as "id: Uuid" from gift_cards where organization_id = $1 and code = $2"#,
+            organization_id,
+            code
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(gift_card_id) = gift_card else {
+            return Err(Error::unprocessable_entity([(
+                "code",
+                "does not match a gift card of this organization",
+            )]));
+        };
+        let gift_card = get_gift_card(&mut tx, gift_card_id).await?;
+
+        let now = time::OffsetDateTime::now_utc();
+        if gift_card.expires_at.is_some_and(|at| at <= now) {
+            return Err(Error::unprocessable_entity([("code", "has expired")]));
+        }
+
+        // Like stock, the check and the update are one statement so a card can't be redeemed
+        // twice at the same time.
+        let redeemed = sqlx::query!(
+            r#"update gift_cards set redeemed_by_membership_id = $1, redeemed_at = $2, updated_at = $2
+            where id = $3 and redeemed_at is null"#,
+            membership_id,
+            now,
+            gift_card.id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if redeemed == 0 {
+            return Err(Error::unprocessable_entity([(
+                "code",
+                "has already been redeemed",
+            )]));
+        }
+
+        let account = sqlx::query_scalar!(
+            r#"select ledger_account_id as "ledger_account_id: Uuid" from gift_cards where id = $1"#,
+            gift_card.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let wallet = wallet::ledger_account(
+            &mut tx,
+            organization_id,
+            LedgerAccountKind::Wallet,
+            membership_id,
+            &gift_card.currency,
+            now,
+        )
+        .await?;
+
+        wallet::post(
+            &mut tx,
+            NewLedgerTransaction {
+                organization_id,
+                kind: LedgerTransactionKind::GiftCardRedemption,
+                currency: gift_card.currency.clone(),
+                membership_id: Some(membership_id),
+                gift_card_id: Some(gift_card.id),
+                invoice_id: None,
+                credit_note_id: None,
+                entries: vec![(account, -gift_card.balance), (wallet, gift_card.balance)],
+            },
+            now,
+        )
+        .await?;
+
+        let gift_card = get_gift_card(&mut tx, gift_card.id).await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::GiftCardRedeemed,
+            &gift_card,
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(gift_card)
+    }
+
+    async fn list_gift_cards(&self, organization_id: Uuid) -> Result<Vec<GiftCardDTO>> {
+        let gift_cards = sqlx::query_as!(
+            GiftCardDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                code, initial_amount,
+                (select coalesce(sum(amount), 0) from ledger_entries
+                    where account_id = gift_cards.ledger_account_id) as "balance!: i64",
+                currency, expires_at as "expires_at: OffsetDateTime",
+                issued_by_membership_id as "issued_by_membership_id: Uuid",
+                redeemed_by_membership_id as "redeemed_by_membership_id: Uuid",
+                redeemed_at as "redeemed_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from gift_cards
+            where organization_id = $1
+            order by inserted_at desc"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(gift_cards)
+    }
+
+    async fn get_gift_card(&self, id: Uuid) -> Result<GiftCardDTO> {
+        get_gift_card(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+async fn get_gift_card(conn: &mut SqliteConnection, id: Uuid) -> Result<GiftCardDTO> {
+    sqlx::query_as!(
+        GiftCardDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            code, initial_amount,
+            (select coalesce(sum(amount), 0) from ledger_entries
+                where account_id = gift_cards.ledger_account_id) as "balance!: i64",
+            currency, expires_at as "expires_at: OffsetDateTime",
+            issued_by_membership_id as "issued_by_membership_id: Uuid",
+            redeemed_by_membership_id as "redeemed_by_membership_id: Uuid",
+            redeemed_at as "redeemed_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from gift_cards
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: Vec<char> = (0..CODE_LENGTH)
+        .map(|_| char::from(CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())]))
+        .collect();
+
+    group_code(&chars)
+}
+
+/// Puts a code as typed in into the form it is stored in.
+fn normalize_code(code: &str) -> String {
+    let chars: Vec<char> = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    group_code(&chars)
+}
+
+/// Splits the code into groups of four with dashes, for reading it out.
+fn group_code(chars: &[char]) -> String {
+    chars
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,534 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::class_type::describe_minutes;
+use super::event::{self, EventType};
+use super::organization::OrganizationDTO;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// Makes a member of the organization an instructor classes can be assigned to.
+#[derive(serde::Deserialize)]
+pub struct NewInstructor {
+    pub membership_id: Uuid,
+    pub bio: Option<String>,
+    pub specialities: Option<Vec<String>>,
+}
+
+/// Fields left out keep their current value.
+#[derive(serde::Deserialize)]
+pub struct UpdateInstructor {
+    pub bio: Option<String>,
+    pub specialities: Option<Vec<String>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct InstructorDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub bio: String,
+    pub specialities: Json<Vec<String>>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// A weekly block of time an instructor can teach in, in UTC.
+#[derive(serde::Deserialize)]
+pub struct NewAvailability {
+    /// Counting from Monday = 1 to Sunday = 7.
+    pub weekday: i64,
+    /// Minutes since midnight.
+    pub start_minute: i64,
+    /// Minutes since midnight, up to 1440 for a block running until the end of the day.
+    pub end_minute: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct AvailabilityDTO {
+    pub id: Uuid,
+    pub instructor_id: Uuid,
+    pub weekday: i64,
+    pub start_minute: i64,
+    pub end_minute: i64,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl InstructorDTO {
+    fn validate(&self) -> Result<()> {
+        if self.specialities.iter().any(|s| s.trim().is_empty()) {
+            return Err(Error::unprocessable_entity([(
+                "specialities",
+                "can't contain blank entries",
+            )]));
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_availability(blocks: &[NewAvailability]) -> Result<()> {
+    let mut errors = Vec::new();
+
+    for block in blocks {
+        if !(1..=7).contains(&block.weekday) {
+            errors.push(("weekday", "must be between 1 (Monday) and 7 (Sunday)"));
+        }
+        if block.start_minute < 0 || block.end_minute > MINUTES_PER_DAY {
+            errors.push(("availability", "must fall within a single day"));
+        }
+        if block.end_minute <= block.start_minute {
+            errors.push(("end_minute", "must be after start_minute"));
+        }
+    }
+
+    let overlapping = blocks.iter().enumerate().any(|(i, a)| {
+        blocks[i + 1..].iter().any(|b| {
+            a.weekday == b.weekday && a.start_minute < b.end_minute && b.start_minute < a.end_minute
+        })
+    });
+    if overlapping {
+        errors.push(("availability", "can't contain overlapping blocks"));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::unprocessable_entity(errors))
+    }
+}
+
+#[derive(Clone)]
+pub struct InstructorController {
+    pool: SqlitePool,
+}
+
+impl InstructorController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynInstructorCtrl = Arc<dyn InstructorCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait InstructorCtrlTrait {
+    async fn create_instructor(
+        &self,
+        organization_id: Uuid,
+        new_instructor: NewInstructor,
+        actor: &Actor,
+    ) -> Result<InstructorDTO>;
+    async fn update_instructor(
+        &self,
+        id: Uuid,
+        update_instructor: UpdateInstructor,
+        actor: &Actor,
+    ) -> Result<InstructorDTO>;
+    async fn list_instructors(&self, organization_id: Uuid) -> Result<Vec<InstructorDTO>>;
+    async fn get_instructor(&self, id: Uuid) -> Result<InstructorDTO>;
+
+    /// Replaces the instructor's weekly availability with `blocks`.
+    async fn set_availability(
+        &self,
+        instructor_id: Uuid,
+        blocks: Vec<NewAvailability>,
+        actor: &Actor,
+    ) -> Result<Vec<AvailabilityDTO>>;
+    async fn list_availability(&self, instructor_id: Uuid) -> Result<Vec<AvailabilityDTO>>;
+}
+
+#[async_trait]
+impl InstructorCtrlTrait for InstructorController {
+    async fn create_instructor(
+        &self,
+        organization_id: Uuid,
+        new_instructor: NewInstructor,
+        actor: &Actor,
+    ) -> Result<InstructorDTO> {
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let instructor = InstructorDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            membership_id: new_instructor.membership_id,
+            bio: new_instructor.bio.unwrap_or_default(),
+            specialities: Json(new_instructor.specialities.unwrap_or_default()),
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        instructor.validate()?;
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        let membership_organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            instructor.membership_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if membership_organization_id != Some(organization_id) {
+            return Err(Error::unprocessable_entity([(
+                "membership_id",
+                "does not match a member of this organization",
+            )]));
+        }
+
+        let existing = sqlx::query_scalar!(
+            r#"select id as "id: Uuid" from instructors where membership_id = $1"#,
+            instructor.membership_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if existing.is_some() {
+            return Err(Error::unprocessable_entity([(
+                "membership_id",
+                "is already an instructor",
+            )]));
+        }
+
+        sqlx::query!(
+            r#"insert into "instructors" (
+                id, organization_id, membership_id, bio, specialities,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7
+            )"#,
+            instructor.id,
+            instructor.organization_id,
+            instructor.membership_id,
+            instructor.bio,
+            instructor.specialities,
+            instructor.inserted_at,
+            instructor.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::InstructorCreated,
+            &instructor,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::InstructorCreated,
+            instructor.id,
+            None,
+            Some(&instructor),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(instructor)
+    }
+
+    async fn update_instructor(
+        &self,
+        id: Uuid,
+        update_instructor: UpdateInstructor,
+        actor: &Actor,
+    ) -> Result<InstructorDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_instructor(&mut tx, id).await?;
+        let instructor = InstructorDTO {
+            bio: update_instructor.bio.unwrap_or_else(|| current.bio.clone()),
+            specialities: update_instructor
+                .specialities
+                .map(Json)
+                .unwrap_or_else(|| current.specialities.clone()),
+            updated_at: time::OffsetDateTime::now_utc(),
+            ..current
+        };
+        instructor.validate()?;
+
+        sqlx::query!(
+            r#"update instructors set
+                bio = $1, specialities = $2, updated_at = $3
+            where id = $4"#,
+            instructor.bio,
+            instructor.specialities,
+            instructor.updated_at,
+            instructor.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(instructor.organization_id),
+            EventType::InstructorUpdated,
+            &instructor,
+            instructor.updated_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            instructor.organization_id,
+            AuditAction::InstructorUpdated,
+            instructor.id,
+            Some(&current),
+            Some(&instructor),
+            instructor.updated_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(instructor)
+    }
+
+    async fn list_instructors(&self, organization_id: Uuid) -> Result<Vec<InstructorDTO>> {
+        let instructors = sqlx::query_as!(
+            InstructorDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", bio,
+                specialities as "specialities: Json<Vec<String>>",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from instructors
+            where organization_id = $1
+            order by inserted_at"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(instructors)
+    }
+
+    async fn get_instructor(&self, id: Uuid) -> Result<InstructorDTO> {
+        get_instructor(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn set_availability(
+        &self,
+        instructor_id: Uuid,
+        blocks: Vec<NewAvailability>,
+        actor: &Actor,
+    ) -> Result<Vec<AvailabilityDTO>> {
+        validate_availability(&blocks)?;
+
+        let mut tx = super::begin(&self.pool).await?;
+        let instructor = get_instructor(&mut tx, instructor_id).await?;
+        let before = list_availability(&mut tx, instructor_id).await?;
+
+        sqlx::query!(
+            "delete from instructor_availability where instructor_id = $1",
+            instructor_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let inserted_at = time::OffsetDateTime::now_utc();
+        for block in blocks {
+            let id = uuid::Uuid::new_v4();
+            sqlx::query!(
+                r#"insert into "instructor_availability" (
+                    id, instructor_id, weekday, start_minute, end_minute,
+                    inserted_at, updated_at
+                ) VALUES (
+                    $1, $2, $3, $4, $5,
+                    $6, $7
+                )"#,
+                id,
+                instructor_id,
+                block.weekday,
+                block.start_minute,
+                block.end_minute,
+                inserted_at,
+                inserted_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let availability = list_availability(&mut tx, instructor_id).await?;
+
+        event::record(
+            &mut tx,
+            Some(instructor.organization_id),
+            EventType::InstructorAvailabilityUpdated,
+            &serde_json::json!({
+                "instructor_id": instructor_id,
+                "availability": availability,
+            }),
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            instructor.organization_id,
+            AuditAction::InstructorAvailabilityUpdated,
+            instructor.id,
+            Some(&serde_json::json!({ "availability": before })),
+            Some(&serde_json::json!({ "availability": availability })),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(availability)
+    }
+
+    async fn list_availability(&self, instructor_id: Uuid) -> Result<Vec<AvailabilityDTO>> {
+        list_availability(&mut *self.pool.acquire().await?, instructor_id).await
+    }
+}
+
+pub(crate) async fn get_instructor(conn: &mut SqliteConnection, id: Uuid) -> Result<InstructorDTO> {
+    sqlx::query_as!(
+        InstructorDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", bio,
+            specialities as "specialities: Json<Vec<String>>",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from instructors
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+async fn list_availability(
+    conn: &mut SqliteConnection,
+    instructor_id: Uuid,
+) -> Result<Vec<AvailabilityDTO>> {
+    let availability = sqlx::query_as!(
+        AvailabilityDTO,
+        r#"select
+            id as "id: Uuid", instructor_id as "instructor_id: Uuid",
+            weekday, start_minute, end_minute,
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from instructor_availability
+        where instructor_id = $1
+        order by weekday, start_minute"#,
+        instructor_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(availability)
+}
+
+struct ScheduledClass {
+    name: String,
+    location_id: Option<Uuid>,
+    starts_at: OffsetDateTime,
+    ends_at: OffsetDateTime,
+}
+
+/// Checks the instructor can teach a class at `location_id` from `starts_at` to `ends_at`:
+/// it must fit one of their availability blocks (when they have any), must not overlap
+/// another of their classes, and must leave the organization's travel gap to their classes
+/// at other locations.
+pub(crate) async fn check_schedule(
+    conn: &mut SqliteConnection,
+    organization: &OrganizationDTO,
+    instructor_id: Uuid,
+    location_id: Option<Uuid>,
+    starts_at: OffsetDateTime,
+    ends_at: OffsetDateTime,
+) -> Result<()> {
+    // Class times are stored in UTC, so they compare as text.
+    let starts_at = starts_at.to_offset(UtcOffset::UTC);
+    let ends_at = ends_at.to_offset(UtcOffset::UTC);
+
+    let availability = list_availability(&mut *conn, instructor_id).await?;
+    if !availability.is_empty() {
+        let weekday = i64::from(starts_at.weekday().number_from_monday());
+        let start_minute = i64::from(starts_at.hour()) * 60 + i64::from(starts_at.minute());
+        let end_minute = start_minute + (ends_at - starts_at).whole_minutes();
+
+        let available = availability.iter().any(|block| {
+            block.weekday == weekday
+                && block.start_minute <= start_minute
+                && end_minute <= block.end_minute
+        });
+        if !available {
+            return Err(Error::unprocessable_entity([(
+                "instructor_id",
+                "is not available at that time",
+            )]));
+        }
+    }
+
+    let travel_gap = Duration::minutes(organization.instructor_travel_gap_minutes);
+    let (Some(window_starts_at), Some(window_ends_at)) = (
+        starts_at.checked_sub(travel_gap),
+        ends_at.checked_add(travel_gap),
+    ) else {
+        return Err(Error::unprocessable_entity([(
+            "starts_at",
+            "is out of range",
+        )]));
+    };
+    let classes = sqlx::query_as!(
+        ScheduledClass,
+        r#"select
+            name, location_id as "location_id: Uuid",
+            starts_at as "starts_at: OffsetDateTime", ends_at as "ends_at: OffsetDateTime"
+        from classes
+        where instructor_id = $1 and cancelled_at is null
+            and starts_at < $2 and ends_at > $3"#,
+        instructor_id,
+        window_ends_at,
+        window_starts_at
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for class in classes {
+        if class.starts_at < ends_at && starts_at < class.ends_at {
+            return Err(Error::unprocessable_entity([(
+                "instructor_id",
+                format!("is already teaching {} at that time", class.name),
+            )]));
+        }
+
+        let other_location = matches!(
+            (location_id, class.location_id),
+            (Some(a), Some(b)) if a != b
+        );
+        let gap = if class.ends_at <= starts_at {
+            starts_at - class.ends_at
+        } else {
+            class.starts_at - ends_at
+        };
+        if other_location && gap < travel_gap {
+            return Err(Error::unprocessable_entity([(
+                "location_id",
+                format!(
+                    "leaves the instructor less than {} to travel to or from {}",
+                    describe_minutes(organization.instructor_travel_gap_minutes),
+                    class.name
+                ),
+            )]));
+        }
+    }
+
+    Ok(())
+}
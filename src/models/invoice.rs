@@ -0,0 +1,705 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::credit_note::{self, CreditNoteDTO};
+use super::event::{self, EventType};
+use super::plan::PlanDTO;
+use super::promo_code::{self, PromoCodeDTO};
+use super::subscription::SubscriptionDTO;
+use super::tax_rate;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    /// Issued and waiting to be paid.
+    Open,
+    Paid,
+    /// Cancelled after being issued, it keeps its number.
+    Void,
+}
+
+/// Amounts are in minor units of `currency`. `total_amount` is the subtotal less
+/// discounts plus tax, summed from the lines.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct InvoiceDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub subscription_id: Option<Uuid>,
+    /// Sequential per organization, without gaps.
+    pub number: i64,
+    pub status: InvoiceStatus,
+    pub currency: String,
+    pub subtotal_amount: i64,
+    pub discount_amount: i64,
+    pub tax_amount: i64,
+    pub total_amount: i64,
+    /// The subscription period billed, for renewals.
+    pub period_start: Option<OffsetDateTime>,
+    pub period_end: Option<OffsetDateTime>,
+    pub issued_at: OffsetDateTime,
+    pub paid_at: Option<OffsetDateTime>,
+    pub voided_at: Option<OffsetDateTime>,
+    /// The provider's charge from the latest attempt to pay the invoice.
+    pub charge_id: Option<String>,
+    /// Why the latest attempt to pay the invoice failed.
+    pub payment_error: Option<String>,
+    /// Failed attempts to charge the member for the invoice.
+    pub payment_attempts: i64,
+    /// When the member is next charged for the invoice, `None` if they aren't charged
+    /// automatically or we're waiting to hear back from the payment provider.
+    pub next_payment_attempt_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct InvoiceLineDTO {
+    pub id: Uuid,
+    pub invoice_id: Uuid,
+    pub position: i64,
+    pub description: String,
+    pub plan_id: Option<Uuid>,
+    /// The product sold at the front desk.
+    pub product_id: Option<Uuid>,
+    pub quantity: i64,
+    pub unit_amount: i64,
+    /// `quantity` times `unit_amount`.
+    pub subtotal_amount: i64,
+    pub discount_amount: i64,
+    /// The promo code the discount came from.
+    pub promo_code_id: Option<Uuid>,
+    pub tax_rate_id: Option<Uuid>,
+    /// The tax rate when the invoice was issued, in hundredths of a percent.
+    pub tax_rate_basis_points: i64,
+    pub tax_amount: i64,
+    pub total_amount: i64,
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize)]
+pub struct InvoiceWithLinesDTO {
+    #[serde(flatten)]
+    pub invoice: InvoiceDTO,
+    pub lines: Vec<InvoiceLineDTO>,
+    /// Refunds of the invoice.
+    pub credit_notes: Vec<CreditNoteDTO>,
+}
+
+/// A line to put on a new invoice, its amounts are worked out by `create_invoice`.
+pub(crate) struct NewInvoiceLine {
+    pub description: String,
+    pub plan_id: Option<Uuid>,
+    pub product_id: Option<Uuid>,
+    pub quantity: i64,
+    pub unit_amount: i64,
+    pub discount_amount: i64,
+    pub promo_code_id: Option<Uuid>,
+    pub tax_rate_id: Option<Uuid>,
+    pub tax_rate_basis_points: i64,
+}
+
+impl NewInvoiceLine {
+    /// One of the plan at its current price and tax rate.
+    pub(crate) async fn for_plan(
+        conn: &mut SqliteConnection,
+        plan: &PlanDTO,
+        description: String,
+    ) -> Result<Self> {
+        let tax_rate_basis_points = match plan.tax_rate_id {
+            Some(tax_rate_id) => {
+                tax_rate::get_tax_rate(conn, tax_rate_id)
+                    .await?
+                    .rate_basis_points
+            }
+            None => 0,
+        };
+
+        Ok(NewInvoiceLine {
+            description,
+            plan_id: Some(plan.id),
+            product_id: None,
+            quantity: 1,
+            unit_amount: plan.price_amount,
+            discount_amount: 0,
+            promo_code_id: None,
+            tax_rate_id: plan.tax_rate_id,
+            tax_rate_basis_points,
+        })
+    }
+
+    /// Takes the promo code's discount off the line.
+    pub(crate) fn with_promo_code(self, promo_code: &PromoCodeDTO) -> Self {
+        NewInvoiceLine {
+            discount_amount: promo_code.discount_on(self.quantity * self.unit_amount),
+            promo_code_id: Some(promo_code.id),
+            ..self
+        }
+    }
+}
+
+pub(crate) struct NewInvoice {
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub subscription_id: Option<Uuid>,
+    pub currency: String,
+    pub period_start: Option<OffsetDateTime>,
+    pub period_end: Option<OffsetDateTime>,
+    /// Set to charge the member's saved payment method for the invoice from then on.
+    pub next_payment_attempt_at: Option<OffsetDateTime>,
+    pub lines: Vec<NewInvoiceLine>,
+}
+
+/// Tax on `amount` at `basis_points`, rounded half up to the minor unit.
+fn tax_on(amount: i64, basis_points: i64) -> i64 {
+    (amount * basis_points + 5_000).div_euclid(10_000)
+}
+
+#[derive(Clone)]
+pub struct InvoiceController {
+    pool: SqlitePool,
+}
+
+impl InvoiceController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynInvoiceCtrl = Arc<dyn InvoiceCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait InvoiceCtrlTrait {
+    async fn get_invoice(&self, id: Uuid) -> Result<InvoiceWithLinesDTO>;
+    async fn list_member_invoices(&self, membership_id: Uuid) -> Result<Vec<InvoiceDTO>>;
+    async fn list_invoices(&self, organization_id: Uuid) -> Result<Vec<InvoiceDTO>>;
+}
+
+#[async_trait]
+impl InvoiceCtrlTrait for InvoiceController {
+    async fn get_invoice(&self, id: Uuid) -> Result<InvoiceWithLinesDTO> {
+        let mut conn = self.pool.acquire().await?;
+
+        let invoice = get_invoice(&mut conn, id).await?;
+        let lines = sqlx::query_as!(
+            InvoiceLineDTO,
+            r#"select
+                id as "id: Uuid", invoice_id as "invoice_id: Uuid", position, description,
+                plan_id as "plan_id: Uuid", product_id as "product_id: Uuid",
+                quantity, unit_amount, subtotal_amount,
+                discount_amount, promo_code_id as "promo_code_id: Uuid",
+                tax_rate_id as "tax_rate_id: Uuid", tax_rate_basis_points,
+                tax_amount, total_amount, inserted_at as "inserted_at: OffsetDateTime"
+            from invoice_lines
+            where invoice_id = $1
+            order by position"#,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        let credit_notes = credit_note::list_invoice_credit_notes(&mut conn, id).await?;
+
+        Ok(InvoiceWithLinesDTO {
+            invoice,
+            lines,
+            credit_notes,
+        })
+    }
+
+    async fn list_member_invoices(&self, membership_id: Uuid) -> Result<Vec<InvoiceDTO>> {
+        let invoices = sqlx::query_as!(
+            InvoiceDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", subscription_id as "subscription_id: Uuid",
+                number, status as "status: InvoiceStatus", currency,
+                subtotal_amount, discount_amount, tax_amount, total_amount,
+                period_start as "period_start: OffsetDateTime", period_end as "period_end: OffsetDateTime",
+                issued_at as "issued_at: OffsetDateTime", paid_at as "paid_at: OffsetDateTime",
+                voided_at as "voided_at: OffsetDateTime", charge_id, payment_error,
+            payment_attempts, next_payment_attempt_at as "next_payment_attempt_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from invoices
+            where membership_id = $1
+            order by number desc"#,
+            membership_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invoices)
+    }
+
+    async fn list_invoices(&self, organization_id: Uuid) -> Result<Vec<InvoiceDTO>> {
+        let invoices = sqlx::query_as!(
+            InvoiceDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", subscription_id as "subscription_id: Uuid",
+                number, status as "status: InvoiceStatus", currency,
+                subtotal_amount, discount_amount, tax_amount, total_amount,
+                period_start as "period_start: OffsetDateTime", period_end as "period_end: OffsetDateTime",
+                issued_at as "issued_at: OffsetDateTime", paid_at as "paid_at: OffsetDateTime",
+                voided_at as "voided_at: OffsetDateTime", charge_id, payment_error,
+            payment_attempts, next_payment_attempt_at as "next_payment_attempt_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from invoices
+            where organization_id = $1
+            order by number desc"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invoices)
+    }
+}
+
+/// Issues an invoice with the organization's next number. Run it in the transaction that
+/// makes the sale, so a rolled back sale doesn't leave a gap in the numbering.
+pub(crate) async fn create_invoice(
+    conn: &mut SqliteConnection,
+    new_invoice: NewInvoice,
+    now: OffsetDateTime,
+) -> Result<InvoiceDTO> {
+    let mut lines = Vec::with_capacity(new_invoice.lines.len());
+    for (position, line) in new_invoice.lines.into_iter().enumerate() {
+        if line.quantity < 1 || line.unit_amount < 0 {
+            return Err(Error::unprocessable_entity([(
+                "lines",
+                "must have a positive quantity and a price that isn't negative",
+            )]));
+        }
+
+        let subtotal_amount = line.quantity * line.unit_amount;
+        if !(0..=subtotal_amount).contains(&line.discount_amount) {
+            return Err(Error::unprocessable_entity([(
+                "discount_amount",
+                "can't be more than the line's subtotal",
+            )]));
+        }
+        let tax_amount = tax_on(
+            subtotal_amount - line.discount_amount,
+            line.tax_rate_basis_points,
+        );
+
+        lines.push(InvoiceLineDTO {
+            id: uuid::Uuid::new_v4(),
+            invoice_id: Uuid::nil(),
+            position: position as i64,
+            description: line.description,
+            plan_id: line.plan_id,
+            product_id: line.product_id,
+            quantity: line.quantity,
+            unit_amount: line.unit_amount,
+            subtotal_amount,
+            discount_amount: line.discount_amount,
+            promo_code_id: line.promo_code_id,
+            tax_rate_id: line.tax_rate_id,
+            tax_rate_basis_points: line.tax_rate_basis_points,
+            tax_amount,
+            total_amount: subtotal_amount - line.discount_amount + tax_amount,
+            inserted_at: now,
+        });
+    }
+
+    // Bumping the counter takes SQLite's write lock, so concurrent invoices queue up here
+    // instead of racing for the same number.
+    let number = sqlx::query_scalar!(
+        r#"update organizations set last_invoice_number = last_invoice_number + 1
+        where id = $1
+        returning last_invoice_number"#,
+        new_invoice.organization_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let total_amount = lines.iter().map(|l| l.total_amount).sum();
+    let (status, paid_at) = if total_amount == 0 {
+        (InvoiceStatus::Paid, Some(now))
+    } else {
+        (InvoiceStatus::Open, None)
+    };
+
+    let invoice = InvoiceDTO {
+        id: uuid::Uuid::new_v4(),
+        organization_id: new_invoice.organization_id,
+        membership_id: new_invoice.membership_id,
+        subscription_id: new_invoice.subscription_id,
+        number,
+        status,
+        currency: new_invoice.currency,
+        subtotal_amount: lines.iter().map(|l| l.subtotal_amount).sum(),
+        discount_amount: lines.iter().map(|l| l.discount_amount).sum(),
+        tax_amount: lines.iter().map(|l| l.tax_amount).sum(),
+        total_amount,
+        period_start: new_invoice.period_start,
+        period_end: new_invoice.period_end,
+        issued_at: now,
+        paid_at,
+        voided_at: None,
+        charge_id: None,
+        payment_error: None,
+        payment_attempts: 0,
+        next_payment_attempt_at: new_invoice
+            .next_payment_attempt_at
+            .filter(|_| status == InvoiceStatus::Open),
+        inserted_at: now,
+        updated_at: now,
+    };
+
+    sqlx::query!(
+        r#"insert into "invoices" (
+            id, organization_id, membership_id, subscription_id, number, status, currency,
+            subtotal_amount, discount_amount, tax_amount, total_amount,
+            period_start, period_end, issued_at, paid_at, next_payment_attempt_at,
+            inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7,
+            $8, $9, $10, $11,
+            $12, $13, $14, $15, $16,
+            $17, $18
+        )"#,
+        invoice.id,
+        invoice.organization_id,
+        invoice.membership_id,
+        invoice.subscription_id,
+        invoice.number,
+        invoice.status,
+        invoice.currency,
+        invoice.subtotal_amount,
+        invoice.discount_amount,
+        invoice.tax_amount,
+        invoice.total_amount,
+        invoice.period_start,
+        invoice.period_end,
+        invoice.issued_at,
+        invoice.paid_at,
+        invoice.next_payment_attempt_at,
+        invoice.inserted_at,
+        invoice.updated_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    for line in lines {
+        sqlx::query!(
+            r#"insert into "invoice_lines" (
+                id, invoice_id, position, description, plan_id, product_id,
+                quantity, unit_amount, subtotal_amount, discount_amount, promo_code_id,
+                tax_rate_id, tax_rate_basis_points, tax_amount, total_amount,
+                inserted_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6,
+                $7, $8, $9, $10, $11,
+                $12, $13, $14, $15,
+                $16
+            )"#,
+            line.id,
+            invoice.id,
+            line.position,
+            line.description,
+            line.plan_id,
+            line.product_id,
+            line.quantity,
+            line.unit_amount,
+            line.subtotal_amount,
+            line.discount_amount,
+            line.promo_code_id,
+            line.tax_rate_id,
+            line.tax_rate_basis_points,
+            line.tax_amount,
+            line.total_amount,
+            line.inserted_at
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    event::record(
+        &mut *conn,
+        Some(invoice.organization_id),
+        EventType::InvoiceCreated,
+        &invoice,
+        now,
+    )
+    .await?;
+    if invoice.status == InvoiceStatus::Paid {
+        event::record(
+            conn,
+            Some(invoice.organization_id),
+            EventType::InvoicePaid,
+            &invoice,
+            now,
+        )
+        .await?;
+    }
+
+    Ok(invoice)
+}
+
+/// Invoices the subscription's current period, or returns the invoice already issued for
+/// it, so renewals can be retried without billing twice.
+pub(crate) async fn invoice_subscription_period(
+    conn: &mut SqliteConnection,
+    subscription: &SubscriptionDTO,
+    plan: &PlanDTO,
+    now: OffsetDateTime,
+) -> Result<InvoiceDTO> {
+    let existing = sqlx::query_as!(
+        InvoiceDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", subscription_id as "subscription_id: Uuid",
+            number, status as "status: InvoiceStatus", currency,
+            subtotal_amount, discount_amount, tax_amount, total_amount,
+            period_start as "period_start: OffsetDateTime", period_end as "period_end: OffsetDateTime",
+            issued_at as "issued_at: OffsetDateTime", paid_at as "paid_at: OffsetDateTime",
+            voided_at as "voided_at: OffsetDateTime", charge_id, payment_error,
+            payment_attempts, next_payment_attempt_at as "next_payment_attempt_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from invoices
+        where subscription_id = $1 and period_start = $2"#,
+        subscription.id,
+        subscription.current_period_start
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(invoice) = existing {
+        return Ok(invoice);
+    }
+
+    let description = format!(
+        "{} ({} to {})",
+        plan.name,
+        subscription.current_period_start.date(),
+        subscription.current_period_end.date()
+    );
+    let mut line = NewInvoiceLine::for_plan(conn, plan, description).await?;
+    if let Some(promo_code_id) = subscription.promo_code_id {
+        let promo_code = promo_code::get_promo_code(conn, promo_code_id).await?;
+        if promo_code.applies_to_period(subscription.periods_billed) {
+            line = line.with_promo_code(&promo_code);
+        }
+    }
+
+    create_invoice(
+        conn,
+        NewInvoice {
+            organization_id: subscription.organization_id,
+            membership_id: subscription.membership_id,
+            subscription_id: Some(subscription.id),
+            currency: plan.currency.clone(),
+            period_start: Some(subscription.current_period_start),
+            period_end: Some(subscription.current_period_end),
+            next_payment_attempt_at: Some(now),
+            lines: vec![line],
+        },
+        now,
+    )
+    .await
+}
+
+pub(crate) async fn get_invoice(conn: &mut SqliteConnection, id: Uuid) -> Result<InvoiceDTO> {
+    sqlx::query_as!(
+        InvoiceDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", subscription_id as "subscription_id: Uuid",
+            number, status as "status: InvoiceStatus", currency,
+            subtotal_amount, discount_amount, tax_amount, total_amount,
+            period_start as "period_start: OffsetDateTime", period_end as "period_end: OffsetDateTime",
+            issued_at as "issued_at: OffsetDateTime", paid_at as "paid_at: OffsetDateTime",
+            voided_at as "voided_at: OffsetDateTime", charge_id, payment_error,
+            payment_attempts, next_payment_attempt_at as "next_payment_attempt_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from invoices
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Marks an open invoice paid by `charge_id`. Invoices already paid or voided are returned
+/// as they are.
+pub(crate) async fn mark_paid(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    charge_id: Option<&str>,
+    now: OffsetDateTime,
+) -> Result<InvoiceDTO> {
+    let paid = sqlx::query_as!(
+        InvoiceDTO,
+        r#"update invoices
+        set status = $1, paid_at = $2, charge_id = coalesce($3, charge_id),
+            payment_error = null, next_payment_attempt_at = null, updated_at = $2
+        where id = $4 and status = $5
+        returning
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", subscription_id as "subscription_id: Uuid",
+            number, status as "status: InvoiceStatus", currency,
+            subtotal_amount, discount_amount, tax_amount, total_amount,
+            period_start as "period_start: OffsetDateTime", period_end as "period_end: OffsetDateTime",
+            issued_at as "issued_at: OffsetDateTime", paid_at as "paid_at: OffsetDateTime",
+            voided_at as "voided_at: OffsetDateTime", charge_id, payment_error,
+            payment_attempts, next_payment_attempt_at as "next_payment_attempt_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+        InvoiceStatus::Paid,
+        now,
+        charge_id,
+        id,
+        InvoiceStatus::Open
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match paid {
+        Some(invoice) => {
+            event::record(
+                conn,
+                Some(invoice.organization_id),
+                EventType::InvoicePaid,
+                &invoice,
+                now,
+            )
+            .await?;
+            Ok(invoice)
+        }
+        None => get_invoice(conn, id).await,
+    }
+}
+
+/// Cancels an open invoice, it keeps its number. Invoices already paid or voided are
+/// returned as they are.
+pub(crate) async fn void_invoice(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    now: OffsetDateTime,
+) -> Result<InvoiceDTO> {
+    let voided = sqlx::query!(
+        r#"update invoices set status = $1, voided_at = $2, next_payment_attempt_at = null, updated_at = $2
+        where id = $3 and status = $4"#,
+        InvoiceStatus::Void,
+        now,
+        id,
+        InvoiceStatus::Open
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected()
+        > 0;
+
+    let invoice = get_invoice(conn, id).await?;
+    if voided {
+        event::record(
+            conn,
+            Some(invoice.organization_id),
+            EventType::InvoiceVoided,
+            &invoice,
+            now,
+        )
+        .await?;
+    }
+
+    Ok(invoice)
+}
+
+/// Records a failed payment of an open invoice, with when to try again if at all.
+pub(crate) async fn record_payment_failure(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    charge_id: Option<&str>,
+    payment_error: &str,
+    payment_attempts: i64,
+    next_payment_attempt_at: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+) -> Result<InvoiceDTO> {
+    let failed = sqlx::query!(
+        r#"update invoices set
+            charge_id = coalesce($1, charge_id), payment_error = $2, payment_attempts = $3,
+            next_payment_attempt_at = $4, updated_at = $5
+        where id = $6 and status = $7"#,
+        charge_id,
+        payment_error,
+        payment_attempts,
+        next_payment_attempt_at,
+        now,
+        id,
+        InvoiceStatus::Open
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected()
+        > 0;
+
+    let invoice = get_invoice(conn, id).await?;
+    if failed {
+        event::record(
+            conn,
+            Some(invoice.organization_id),
+            EventType::InvoicePaymentFailed,
+            &invoice,
+            now,
+        )
+        .await?;
+    }
+
+    Ok(invoice)
+}
+
+/// Stops charging an open invoice while the provider processes `charge_id`, its webhook
+/// then settles the invoice.
+pub(crate) async fn await_payment(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    charge_id: &str,
+    now: OffsetDateTime,
+) -> Result<InvoiceDTO> {
+    sqlx::query!(
+        r#"update invoices set charge_id = $1, next_payment_attempt_at = null, updated_at = $2
+        where id = $3 and status = $4"#,
+        charge_id,
+        now,
+        id,
+        InvoiceStatus::Open
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    get_invoice(conn, id).await
+}
+
+/// Open invoices the member is charged for automatically, whenever that's due.
+pub(crate) async fn list_with_payment_attempts(
+    conn: &mut SqliteConnection,
+) -> Result<Vec<InvoiceDTO>> {
+    let invoices = sqlx::query_as!(
+        InvoiceDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", subscription_id as "subscription_id: Uuid",
+            number, status as "status: InvoiceStatus", currency,
+            subtotal_amount, discount_amount, tax_amount, total_amount,
+            period_start as "period_start: OffsetDateTime", period_end as "period_end: OffsetDateTime",
+            issued_at as "issued_at: OffsetDateTime", paid_at as "paid_at: OffsetDateTime",
+            voided_at as "voided_at: OffsetDateTime", charge_id, payment_error,
+            payment_attempts, next_payment_attempt_at as "next_payment_attempt_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from invoices
+        where status = $1 and next_payment_attempt_at is not null"#,
+        InvoiceStatus::Open
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(invoices)
+}
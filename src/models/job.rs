@@ -0,0 +1,392 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+/// A job is dead-lettered after this many attempts.
+pub const MAX_ATTEMPTS: i64 = 8;
+
+/// A worker's claim on a job. A job still running after this long is taken to have been
+/// abandoned, e.g. by a server that crashed, and is claimed again.
+const LEASE: Duration = Duration::minutes(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Reminds a member of a class they booked, see `reminder`.
+    ClassReminder,
+    /// Renewals, payment retries and the rest of billing, recurring.
+    Billing,
+    /// Sends the emails due in the outbox, recurring.
+    EmailOutbox,
+    /// Delivers the notifications due, recurring.
+    NotificationDelivery,
+    /// Deletes expired and logged out account sessions, recurring.
+    SessionReaping,
+    /// Marks bookings for classes that ended without a check-in as no-shows, recurring.
+    NoShowMarking,
+    /// Posts an event to an organization's webhook endpoint, see `webhook`.
+    WebhookDelivery,
+}
+
+impl std::fmt::Display for JobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::ClassReminder => "class_reminder",
+            Self::Billing => "billing",
+            Self::EmailOutbox => "email_outbox",
+            Self::NotificationDelivery => "notification_delivery",
+            Self::SessionReaping => "session_reaping",
+            Self::NoShowMarking => "no_show_marking",
+            Self::WebhookDelivery => "webhook_delivery",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for `run_at`, also after a failed attempt.
+    Pending,
+    /// Claimed by a worker until `locked_until`.
+    Running,
+    Done,
+    /// Called off before it ran, e.g. the reminder of a booking that was cancelled.
+    Cancelled,
+    /// Every attempt failed, or the job can't be run at all. Dead jobs are kept for
+    /// inspection and never run again.
+    Dead,
+}
+
+/// What a job of a kind is given to run, stored as its `payload`.
+pub trait JobPayload: serde::Serialize + serde::de::DeserializeOwned + Send {
+    const KIND: JobKind;
+}
+
+/// Work scheduled to run in the background at `run_at`. Jobs are stored, so they survive
+/// restarts of the server.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct JobDTO {
+    pub id: Uuid,
+    pub kind: JobKind,
+    /// Identifies what the job is for, e.g. `class_reminder:<booking id>`, so it is only
+    /// scheduled once and can be cancelled.
+    pub key: Option<String>,
+    pub payload: Json<serde_json::Value>,
+    pub status: JobStatus,
+    pub run_at: OffsetDateTime,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub locked_until: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct JobController {
+    pool: SqlitePool,
+}
+
+impl JobController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynJobCtrl = Arc<dyn JobCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait JobCtrlTrait {
+    /// Schedules a job that keeps running again, keyed by its kind so there is only ever
+    /// one. It is due right away, also when it was waiting for a later run. Recurring jobs
+    /// have no payload.
+    async fn schedule_recurring_job(&self, kind: JobKind, now: OffsetDateTime) -> Result<()>;
+
+    /// Claims the job that has been due the longest for a worker, `None` if no job is due.
+    /// A running job whose claim ran out is claimed again.
+    async fn claim_next_job(&self, now: OffsetDateTime) -> Result<Option<JobDTO>>;
+
+    /// Marks a claimed job done, unless its handler already did.
+    async fn finish_job(&self, id: Uuid, now: OffsetDateTime) -> Result<()>;
+
+    /// Puts a recurring job back in the queue to run again at `run_at`, recording the error
+    /// if this run failed.
+    async fn reschedule_job(
+        &self,
+        id: Uuid,
+        run_at: OffsetDateTime,
+        error: Option<&str>,
+        now: OffsetDateTime,
+    ) -> Result<()>;
+
+    /// Schedules another attempt with backoff, or dead-letters the job once it is out of
+    /// attempts or the failure is `permanent`.
+    async fn record_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        permanent: bool,
+        now: OffsetDateTime,
+    ) -> Result<JobDTO>;
+}
+
+#[async_trait]
+impl JobCtrlTrait for JobController {
+    async fn schedule_recurring_job(&self, kind: JobKind, now: OffsetDateTime) -> Result<()> {
+        let id = uuid::Uuid::new_v4();
+        let key = kind.to_string();
+        let payload = Json(serde_json::Value::Null);
+        sqlx::query!(
+            r#"insert into "jobs" (
+                id, kind, key, payload, status, run_at,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6,
+                $7, $8
+            )
+            on conflict (key) do update set run_at = excluded.run_at, updated_at = excluded.updated_at
+            where jobs.status = $9"#,
+            id,
+            kind,
+            key,
+            payload,
+            JobStatus::Pending,
+            now,
+            now,
+            now,
+            JobStatus::Pending
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn claim_next_job(&self, now: OffsetDateTime) -> Result<Option<JobDTO>> {
+        let locked_until = now + LEASE;
+        let job = sqlx::query_as!(
+            JobDTO,
+            r#"update jobs
+            set status = $1, locked_until = $2, attempts = attempts + 1, updated_at = $3
+            where id = (
+                select id from jobs
+                where (status = $4 and run_at <= $5) or (status = $6 and locked_until <= $7)
+                order by run_at
+                limit 1
+            )
+            returning
+                id as "id: Uuid", kind as "kind: JobKind", key,
+                payload as "payload: Json<serde_json::Value>", status as "status: JobStatus",
+                run_at as "run_at: OffsetDateTime", attempts, last_error,
+                locked_until as "locked_until: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            JobStatus::Running,
+            locked_until,
+            now,
+            JobStatus::Pending,
+            now,
+            JobStatus::Running,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    async fn finish_job(&self, id: Uuid, now: OffsetDateTime) -> Result<()> {
+        complete(&mut *self.pool.acquire().await?, id, now).await?;
+
+        Ok(())
+    }
+
+    async fn reschedule_job(
+        &self,
+        id: Uuid,
+        run_at: OffsetDateTime,
+        error: Option<&str>,
+        now: OffsetDateTime,
+    ) -> Result<()> {
+        // A recurring job counts its failures in a row, and never runs out of attempts.
+        let failed = error.is_some();
+        sqlx::query!(
+            r#"update jobs set
+                status = $1, run_at = $2, locked_until = null, last_error = $3,
+                attempts = case when $4 then attempts else 0 end, updated_at = $5
+            where id = $6 and status = $7"#,
+            JobStatus::Pending,
+            run_at,
+            error,
+            failed,
+            now,
+            id,
+            JobStatus::Running
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        permanent: bool,
+        now: OffsetDateTime,
+    ) -> Result<JobDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_job(&mut tx, id).await?;
+        let dead = permanent || current.attempts >= MAX_ATTEMPTS;
+        let job = JobDTO {
+            status: if dead {
+                JobStatus::Dead
+            } else {
+                JobStatus::Pending
+            },
+            // 1, 2, 4, ... minutes after each failed attempt.
+            run_at: if dead {
+                current.run_at
+            } else {
+                now + Duration::minutes(1 << (current.attempts - 1))
+            },
+            last_error: Some(error.to_string()),
+            locked_until: None,
+            updated_at: now,
+            ..current
+        };
+
+        sqlx::query!(
+            r#"update jobs set
+                status = $1, run_at = $2, last_error = $3, locked_until = null, updated_at = $4
+            where id = $5 and status = $6"#,
+            job.status,
+            job.run_at,
+            job.last_error,
+            job.updated_at,
+            job.id,
+            JobStatus::Running
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(job)
+    }
+}
+
+async fn get_job(conn: &mut SqliteConnection, id: Uuid) -> Result<JobDTO> {
+    sqlx::query_as!(
+        JobDTO,
+        r#"select
+        id as "id: Uuid", kind as "kind: JobKind", key,
+        payload as "payload: Json<serde_json::Value>", status as "status: JobStatus",
+        run_at as "run_at: OffsetDateTime", attempts, last_error,
+        locked_until as "locked_until: OffsetDateTime",
+        inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from jobs
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+fn encode<P: JobPayload>(payload: &P) -> Result<Json<serde_json::Value>> {
+    let payload = serde_json::to_value(payload)
+        .map_err(|e| anyhow::anyhow!("failed to encode {} job: {e}", P::KIND))?;
+
+    Ok(Json(payload))
+}
+
+/// Schedules a job to run at `run_at`. A job already scheduled under `key` is kept as it is.
+pub(crate) async fn schedule<P: JobPayload>(
+    conn: &mut SqliteConnection,
+    key: &str,
+    payload: &P,
+    run_at: OffsetDateTime,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let id = uuid::Uuid::new_v4();
+    let payload = encode(payload)?;
+    sqlx::query!(
+        r#"insert into "jobs" (
+            id, kind, key, payload, status, run_at,
+            inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6,
+            $7, $8
+        )
+        on conflict (key) do nothing"#,
+        id,
+        P::KIND,
+        key,
+        payload,
+        JobStatus::Pending,
+        run_at,
+        now,
+        now
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Calls off the job scheduled under `key`, unless it already ran.
+pub(crate) async fn cancel(
+    conn: &mut SqliteConnection,
+    key: &str,
+    now: OffsetDateTime,
+) -> Result<()> {
+    sqlx::query!(
+        "update jobs set status = $1, updated_at = $2 where key = $3 and status = $4",
+        JobStatus::Cancelled,
+        now,
+        key,
+        JobStatus::Pending
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks a claimed job done. A handler may do so inside the transaction it does its work
+/// in, so the work and the job's completion commit together and a job that is claimed again
+/// after its worker died isn't done twice. `None` means it was already done or dead-lettered.
+pub(crate) async fn complete(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Option<JobDTO>> {
+    let job = sqlx::query_as!(
+        JobDTO,
+        r#"update jobs
+        set status = $1, locked_until = null, last_error = null, updated_at = $2
+        where id = $3 and status = $4
+        returning
+            id as "id: Uuid", kind as "kind: JobKind", key,
+            payload as "payload: Json<serde_json::Value>", status as "status: JobStatus",
+            run_at as "run_at: OffsetDateTime", attempts, last_error,
+            locked_until as "locked_until: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+        JobStatus::Done,
+        now,
+        id,
+        JobStatus::Running
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(job)
+}
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::platform_plan::PlatformLimit;
+use super::platform_subscription;
+
+/// A place an organization holds classes at, e.g. one of its studios.
+#[derive(serde::Deserialize)]
+pub struct NewLocation {
+    pub name: String,
+    pub address: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct LocationDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub address: Option<String>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl NewLocation {
+    fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(Error::unprocessable_entity([("name", "can't be blank")]));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct LocationController {
+    pool: SqlitePool,
+}
+
+impl LocationController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynLocationCtrl = Arc<dyn LocationCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait LocationCtrlTrait {
+    async fn create_location(
+        &self,
+        organization_id: Uuid,
+        new_location: NewLocation,
+        actor: &Actor,
+    ) -> Result<LocationDTO>;
+    async fn list_locations(&self, organization_id: Uuid) -> Result<Vec<LocationDTO>>;
+    async fn get_location(&self, id: Uuid) -> Result<LocationDTO>;
+}
+
+#[async_trait]
+impl LocationCtrlTrait for LocationController {
+    async fn create_location(
+        &self,
+        organization_id: Uuid,
+        new_location: NewLocation,
+        actor: &Actor,
+    ) -> Result<LocationDTO> {
+        new_location.validate()?;
+        platform_subscription::require_capacity(
+            &mut *self.pool.acquire().await?,
+            organization_id,
+            PlatformLimit::Locations,
+        )
+        .await?;
+
+        let id = uuid::Uuid::new_v4();
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        let location = sqlx::query_as!(
+            LocationDTO,
+            r#"insert into "locations" (
+                id, organization_id, name, address,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4,
+                $5, $6
+            ) returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid", name, address,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            id,
+            organization_id,
+            new_location.name,
+            new_location.address,
+            inserted_at,
+            inserted_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::LocationCreated,
+            &location,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::LocationCreated,
+            location.id,
+            None,
+            Some(&location),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(location)
+    }
+
+    async fn list_locations(&self, organization_id: Uuid) -> Result<Vec<LocationDTO>> {
+        let locations = sqlx::query_as!(
+            LocationDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid", name, address,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from locations
+            where organization_id = $1
+            order by name"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(locations)
+    }
+
+    async fn get_location(&self, id: Uuid) -> Result<LocationDTO> {
+        get_location(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+pub(crate) async fn get_location(conn: &mut SqliteConnection, id: Uuid) -> Result<LocationDTO> {
+    sqlx::query_as!(
+        LocationDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid", name, address,
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from locations
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
@@ -0,0 +1,434 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::account;
+use super::audit::{self, Actor, AuditAction};
+use super::email::{self, EmailTemplateKind, NewEmail};
+use super::event::{self, EventType};
+use super::organization;
+use super::platform_plan::PlatformLimit;
+use super::platform_subscription;
+
+const CHECK_IN_TOKEN_LENGTH: usize = 32;
+
+/// The role an account has within an organization.
+///
+/// Variants are ordered from least to most privileged, so `role >= Role::Staff`
+/// reads as "staff or above".
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Role {
+    Member,
+    Staff,
+    Owner,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Member => "member",
+            Role::Staff => "staff",
+            Role::Owner => "owner",
+        })
+    }
+}
+
+/// Actions an organization chooses the least privileged role allowed to take, rather
+/// than them being tied to a fixed role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// `billing.refund`: refunding invoices and issuing credit notes.
+    BillingRefund,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewMembership {
+    pub email: String,
+    pub role: Role,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct MembershipDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub account_id: Uuid,
+    pub role: Role,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct MembershipController {
+    pool: SqlitePool,
+    dyn_account_ctrl: account::DynAccountCtrl,
+}
+
+impl MembershipController {
+    pub fn new(pool: SqlitePool, dyn_account_ctrl: account::DynAccountCtrl) -> Self {
+        Self {
+            pool,
+            dyn_account_ctrl,
+        }
+    }
+}
+
+pub type DynMembershipCtrl = Arc<dyn MembershipCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait MembershipCtrlTrait {
+    /// Adds the account with `new_membership.email` to the organization and emails them
+    /// to say so.
+    async fn create_membership(
+        &self,
+        organization_id: Uuid,
+        new_membership: NewMembership,
+        actor: &Actor,
+    ) -> Result<MembershipDTO>;
+
+    async fn get_membership(&self, id: Uuid) -> Result<MembershipDTO>;
+
+    async fn find_membership(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+    ) -> Result<Option<MembershipDTO>>;
+
+    /// The token encoded in the member's QR code, scanned by front desk devices to check in.
+    async fn get_check_in_token(&self, id: Uuid) -> Result<String>;
+
+    /// Replaces the member's check-in token, e.g. when their QR code has leaked. Audited when
+    /// `staff` do it for the member.
+    async fn rotate_check_in_token(&self, id: Uuid, staff: Option<&Actor>) -> Result<String>;
+
+    /// Returns the account's membership if it holds at least `role` in the organization,
+    /// otherwise `Error::Forbidden`.
+    async fn require_role(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+        role: Role,
+    ) -> Result<MembershipDTO>;
+
+    /// Returns the account's membership if its role is one the organization lets use
+    /// `permission`, otherwise `Error::Forbidden`.
+    async fn require_permission(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+        permission: Permission,
+    ) -> Result<MembershipDTO>;
+
+    /// Returns the account's membership if it is `membership_id` itself, or staff and above
+    /// in the organization, otherwise `Error::Forbidden`. Members act on what is their own,
+    /// e.g. bookings and invoices, staff on anyone's.
+    async fn require_self_or_staff(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+        membership_id: Uuid,
+    ) -> Result<MembershipDTO>;
+}
+
+#[async_trait]
+impl MembershipCtrlTrait for MembershipController {
+    async fn create_membership(
+        &self,
+        organization_id: Uuid,
+        new_membership: NewMembership,
+        actor: &Actor,
+    ) -> Result<MembershipDTO> {
+        let account = self
+            .dyn_account_ctrl
+            .find_account_by_email(new_membership.email)
+            .await?
+            .ok_or_else(|| Error::unprocessable_entity([("email", "account not found")]))?;
+
+        if self
+            .find_membership(organization_id, account.id)
+            .await?
+            .is_some()
+        {
+            return Err(Error::unprocessable_entity([(
+                "email",
+                "account already has a role in this organization",
+            )]));
+        }
+
+        if new_membership.role == Role::Member {
+            platform_subscription::require_capacity(
+                &mut *self.pool.acquire().await?,
+                organization_id,
+                PlatformLimit::ActiveMembers,
+            )
+            .await?;
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let check_in_token = generate_check_in_token();
+        let inserted_at = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let membership = sqlx::query_as!(
+            MembershipDTO,
+            r#"insert into "memberships" (
+                id, organization_id, account_id, role, check_in_token,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7
+            ) returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                account_id as "account_id: Uuid", role as "role: Role",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            id,
+            organization_id,
+            account.id,
+            new_membership.role,
+            check_in_token,
+            inserted_at,
+            inserted_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let event = match membership.role {
+            Role::Member => EventType::MemberCreated,
+            Role::Owner | Role::Staff => EventType::StaffCreated,
+        };
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            event,
+            &membership,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::MembershipCreated,
+            membership.id,
+            None,
+            Some(&membership),
+            inserted_at,
+        )
+        .await?;
+
+        email::enqueue(
+            &mut tx,
+            NewEmail {
+                organization_id,
+                to_address: account.email,
+                template: EmailTemplateKind::MembershipInvitation,
+                variables: vec![
+                    ("member_name", account.name),
+                    ("role", membership.role.to_string()),
+                ],
+            },
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(membership)
+    }
+
+    async fn get_membership(&self, id: Uuid) -> Result<MembershipDTO> {
+        sqlx::query_as!(
+            MembershipDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                account_id as "account_id: Uuid", role as "role: Role",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from memberships
+            where id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+
+    async fn find_membership(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+    ) -> Result<Option<MembershipDTO>> {
+        let membership = sqlx::query_as!(
+            MembershipDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                account_id as "account_id: Uuid", role as "role: Role",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from memberships
+            where organization_id = $1 and account_id = $2"#,
+            organization_id,
+            account_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(membership)
+    }
+
+    async fn get_check_in_token(&self, id: Uuid) -> Result<String> {
+        sqlx::query_scalar!(
+            r#"select check_in_token as "check_in_token!" from memberships where id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+
+    async fn rotate_check_in_token(&self, id: Uuid, staff: Option<&Actor>) -> Result<String> {
+        let check_in_token = generate_check_in_token();
+        let updated_at = time::OffsetDateTime::now_utc();
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        let previous = sqlx::query_scalar!(
+            r#"select check_in_token as "check_in_token!" from memberships where id = $1"#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        let membership = sqlx::query_as!(
+            MembershipDTO,
+            r#"update memberships set check_in_token = $1, updated_at = $2 where id = $3
+            returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                account_id as "account_id: Uuid", role as "role: Role",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            check_in_token,
+            updated_at,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        event::record(
+            &mut tx,
+            Some(membership.organization_id),
+            EventType::CheckInTokenRotated,
+            &membership,
+            updated_at,
+        )
+        .await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                membership.organization_id,
+                AuditAction::CheckInTokenRotated,
+                membership.id,
+                Some(&serde_json::json!({ "check_in_token": previous })),
+                Some(&serde_json::json!({ "check_in_token": check_in_token })),
+                updated_at,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(check_in_token)
+    }
+
+    async fn require_role(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+        role: Role,
+    ) -> Result<MembershipDTO> {
+        match self.find_membership(organization_id, account_id).await? {
+            Some(membership) if membership.role >= role => Ok(membership),
+            _ => Err(Error::Forbidden { upgrade_hint: None }),
+        }
+    }
+
+    async fn require_permission(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+        permission: Permission,
+    ) -> Result<MembershipDTO> {
+        let membership = self
+            .require_role(organization_id, account_id, Role::Member)
+            .await?;
+        let organization =
+            organization::get_organization(&mut *self.pool.acquire().await?, organization_id)
+                .await?;
+
+        if membership.role < organization.role_for(permission) {
+            return Err(Error::Forbidden { upgrade_hint: None });
+        }
+
+        Ok(membership)
+    }
+
+    async fn require_self_or_staff(
+        &self,
+        organization_id: Uuid,
+        account_id: Uuid,
+        membership_id: Uuid,
+    ) -> Result<MembershipDTO> {
+        let membership = self
+            .require_role(organization_id, account_id, Role::Member)
+            .await?;
+
+        if membership.id != membership_id && membership.role < Role::Staff {
+            return Err(Error::Forbidden { upgrade_hint: None });
+        }
+
+        Ok(membership)
+    }
+}
+
+pub(crate) fn generate_check_in_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), CHECK_IN_TOKEN_LENGTH)
+}
+
+pub(crate) async fn find_by_check_in_token(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    check_in_token: &str,
+) -> Result<Option<MembershipDTO>> {
+    let membership = sqlx::query_as!(
+        MembershipDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            account_id as "account_id: Uuid", role as "role: Role",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from memberships
+        where organization_id = $1 and check_in_token = $2"#,
+        organization_id,
+        check_in_token
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(membership)
+}
@@ -1,8 +1,40 @@
-use sqlx::SqlitePool;
+use sqlx::{Sqlite, SqlitePool, Transaction};
 use std::sync::Arc;
 
 pub mod account;
 pub mod account_session;
+pub mod audit;
+pub mod booking;
+pub mod class;
+pub mod class_type;
+pub mod credit;
+pub mod credit_note;
+pub mod dunning;
+pub mod email;
+pub mod event;
+pub mod gift_card;
+pub mod instructor;
+pub mod invoice;
+pub mod job;
+pub mod location;
+pub mod membership;
+pub mod notification;
+pub mod organization;
+pub mod payment_event;
+pub mod payment_profile;
+pub mod penalty;
+pub mod plan;
+pub mod platform_plan;
+pub mod platform_subscription;
+pub mod product;
+pub mod promo_code;
+pub mod reminder;
+pub mod sale;
+pub mod subscription;
+pub mod tax_rate;
+pub mod waitlist;
+pub mod wallet;
+pub mod webhook;
 
 pub type DynStore = Arc<dyn StoreTrait + Send + Sync>;
 
@@ -14,6 +46,76 @@ pub struct Store {
 pub trait StoreTrait {
     fn account(&self) -> account::DynAccountCtrl;
     fn account_session(&self) -> account_session::DynAccountSessionCtrl;
+    fn organization(&self) -> organization::DynOrganizationCtrl;
+    fn membership(&self) -> membership::DynMembershipCtrl;
+    fn class(&self) -> class::DynClassCtrl;
+    fn booking(&self) -> booking::DynBookingCtrl;
+    fn waitlist(&self) -> waitlist::DynWaitlistCtrl;
+    fn penalty(&self) -> penalty::DynPenaltyCtrl;
+    fn class_type(&self) -> class_type::DynClassTypeCtrl;
+    fn location(&self) -> location::DynLocationCtrl;
+    fn instructor(&self) -> instructor::DynInstructorCtrl;
+    fn plan(&self) -> plan::DynPlanCtrl;
+    fn subscription(&self) -> subscription::DynSubscriptionCtrl;
+    fn credit(&self) -> credit::DynCreditCtrl;
+    fn tax_rate(&self) -> tax_rate::DynTaxRateCtrl;
+    fn invoice(&self) -> invoice::DynInvoiceCtrl;
+    fn payment_event(&self) -> payment_event::DynPaymentEventCtrl;
+    fn payment_profile(&self) -> payment_profile::DynPaymentProfileCtrl;
+    fn dunning(&self) -> dunning::DynDunningCtrl;
+    fn promo_code(&self) -> promo_code::DynPromoCodeCtrl;
+    fn platform_plan(&self) -> platform_plan::DynPlatformPlanCtrl;
+    fn platform_subscription(&self) -> platform_subscription::DynPlatformSubscriptionCtrl;
+    fn product(&self) -> product::DynProductCtrl;
+    fn sale(&self) -> sale::DynSaleCtrl;
+    fn credit_note(&self) -> credit_note::DynCreditNoteCtrl;
+    fn gift_card(&self) -> gift_card::DynGiftCardCtrl;
+    fn wallet(&self) -> wallet::DynWalletCtrl;
+    fn email(&self) -> email::DynEmailCtrl;
+    fn notification(&self) -> notification::DynNotificationCtrl;
+    fn job(&self) -> job::DynJobCtrl;
+    fn reminder(&self) -> reminder::DynReminderCtrl;
+    fn webhook(&self) -> webhook::DynWebhookCtrl;
+    fn event(&self) -> event::DynEventCtrl;
+    fn audit(&self) -> audit::DynAuditCtrl;
+}
+
+/// Deserializes a field that may be missing, `null` or set, for updates where leaving a
+/// field out keeps its value but `null` clears it. Use with `#[serde(default)]`.
+pub(crate) fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// Starts a transaction that holds SQLite's write lock from the start, like `BEGIN
+/// IMMEDIATE`, which sqlx doesn't offer. Otherwise a transaction that reads before it
+/// writes fails with "database is locked", rather than waiting its turn, when a request or
+/// job worker writes in between.
+pub(crate) async fn begin(pool: &SqlitePool) -> crate::http::Result<Transaction<'static, Sqlite>> {
+    let mut tx = pool.begin().await?;
+    // Writing nothing still takes the lock.
+    sqlx::query("update _sqlx_migrations set version = version where 0")
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(tx)
+}
+
+/// A database of its own for a test, in memory and migrated.
+#[cfg(test)]
+pub(crate) async fn test_pool() -> SqlitePool {
+    // One connection, every connection to `:memory:` opens a database of its own.
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+
+    pool
 }
 
 impl Store {
@@ -35,4 +137,151 @@ impl StoreTrait for Store {
             self.pool.clone(),
         )) as account_session::DynAccountSessionCtrl
     }
+
+    fn organization(&self) -> organization::DynOrganizationCtrl {
+        Arc::new(organization::OrganizationController::new(self.pool.clone()))
+            as organization::DynOrganizationCtrl
+    }
+
+    fn membership(&self) -> membership::DynMembershipCtrl {
+        Arc::new(membership::MembershipController::new(
+            self.pool.clone(),
+            self.account(),
+        )) as membership::DynMembershipCtrl
+    }
+
+    fn class(&self) -> class::DynClassCtrl {
+        Arc::new(class::ClassController::new(self.pool.clone())) as class::DynClassCtrl
+    }
+
+    fn booking(&self) -> booking::DynBookingCtrl {
+        Arc::new(booking::BookingController::new(self.pool.clone())) as booking::DynBookingCtrl
+    }
+
+    fn waitlist(&self) -> waitlist::DynWaitlistCtrl {
+        Arc::new(waitlist::WaitlistController::new(self.pool.clone())) as waitlist::DynWaitlistCtrl
+    }
+
+    fn penalty(&self) -> penalty::DynPenaltyCtrl {
+        Arc::new(penalty::PenaltyController::new(self.pool.clone())) as penalty::DynPenaltyCtrl
+    }
+
+    fn class_type(&self) -> class_type::DynClassTypeCtrl {
+        Arc::new(class_type::ClassTypeController::new(self.pool.clone()))
+            as class_type::DynClassTypeCtrl
+    }
+
+    fn location(&self) -> location::DynLocationCtrl {
+        Arc::new(location::LocationController::new(self.pool.clone())) as location::DynLocationCtrl
+    }
+
+    fn instructor(&self) -> instructor::DynInstructorCtrl {
+        Arc::new(instructor::InstructorController::new(self.pool.clone()))
+            as instructor::DynInstructorCtrl
+    }
+
+    fn plan(&self) -> plan::DynPlanCtrl {
+        Arc::new(plan::PlanController::new(self.pool.clone())) as plan::DynPlanCtrl
+    }
+
+    fn subscription(&self) -> subscription::DynSubscriptionCtrl {
+        Arc::new(subscription::SubscriptionController::new(self.pool.clone()))
+            as subscription::DynSubscriptionCtrl
+    }
+
+    fn credit(&self) -> credit::DynCreditCtrl {
+        Arc::new(credit::CreditController::new(self.pool.clone())) as credit::DynCreditCtrl
+    }
+
+    fn tax_rate(&self) -> tax_rate::DynTaxRateCtrl {
+        Arc::new(tax_rate::TaxRateController::new(self.pool.clone())) as tax_rate::DynTaxRateCtrl
+    }
+
+    fn invoice(&self) -> invoice::DynInvoiceCtrl {
+        Arc::new(invoice::InvoiceController::new(self.pool.clone())) as invoice::DynInvoiceCtrl
+    }
+
+    fn payment_event(&self) -> payment_event::DynPaymentEventCtrl {
+        Arc::new(payment_event::PaymentEventController::new(
+            self.pool.clone(),
+        )) as payment_event::DynPaymentEventCtrl
+    }
+
+    fn payment_profile(&self) -> payment_profile::DynPaymentProfileCtrl {
+        Arc::new(payment_profile::PaymentProfileController::new(
+            self.pool.clone(),
+        )) as payment_profile::DynPaymentProfileCtrl
+    }
+
+    fn dunning(&self) -> dunning::DynDunningCtrl {
+        Arc::new(dunning::DunningController::new(self.pool.clone())) as dunning::DynDunningCtrl
+    }
+
+    fn promo_code(&self) -> promo_code::DynPromoCodeCtrl {
+        Arc::new(promo_code::PromoCodeController::new(self.pool.clone()))
+            as promo_code::DynPromoCodeCtrl
+    }
+
+    fn platform_plan(&self) -> platform_plan::DynPlatformPlanCtrl {
+        Arc::new(platform_plan::PlatformPlanController::new(
+            self.pool.clone(),
+        )) as platform_plan::DynPlatformPlanCtrl
+    }
+
+    fn platform_subscription(&self) -> platform_subscription::DynPlatformSubscriptionCtrl {
+        Arc::new(platform_subscription::PlatformSubscriptionController::new(
+            self.pool.clone(),
+        )) as platform_subscription::DynPlatformSubscriptionCtrl
+    }
+
+    fn product(&self) -> product::DynProductCtrl {
+        Arc::new(product::ProductController::new(self.pool.clone())) as product::DynProductCtrl
+    }
+
+    fn sale(&self) -> sale::DynSaleCtrl {
+        Arc::new(sale::SaleController::new(self.pool.clone())) as sale::DynSaleCtrl
+    }
+
+    fn credit_note(&self) -> credit_note::DynCreditNoteCtrl {
+        Arc::new(credit_note::CreditNoteController::new(self.pool.clone()))
+            as credit_note::DynCreditNoteCtrl
+    }
+
+    fn gift_card(&self) -> gift_card::DynGiftCardCtrl {
+        Arc::new(gift_card::GiftCardController::new(self.pool.clone()))
+            as gift_card::DynGiftCardCtrl
+    }
+
+    fn wallet(&self) -> wallet::DynWalletCtrl {
+        Arc::new(wallet::WalletController::new(self.pool.clone())) as wallet::DynWalletCtrl
+    }
+
+    fn email(&self) -> email::DynEmailCtrl {
+        Arc::new(email::EmailController::new(self.pool.clone())) as email::DynEmailCtrl
+    }
+
+    fn notification(&self) -> notification::DynNotificationCtrl {
+        Arc::new(notification::NotificationController::new(self.pool.clone()))
+            as notification::DynNotificationCtrl
+    }
+
+    fn job(&self) -> job::DynJobCtrl {
+        Arc::new(job::JobController::new(self.pool.clone())) as job::DynJobCtrl
+    }
+
+    fn reminder(&self) -> reminder::DynReminderCtrl {
+        Arc::new(reminder::ReminderController::new(self.pool.clone())) as reminder::DynReminderCtrl
+    }
+
+    fn webhook(&self) -> webhook::DynWebhookCtrl {
+        Arc::new(webhook::WebhookController::new(self.pool.clone())) as webhook::DynWebhookCtrl
+    }
+
+    fn event(&self) -> event::DynEventCtrl {
+        Arc::new(event::EventController::new(self.pool.clone())) as event::DynEventCtrl
+    }
+
+    fn audit(&self) -> audit::DynAuditCtrl {
+        Arc::new(audit::AuditController::new(self.pool.clone())) as audit::DynAuditCtrl
+    }
 }
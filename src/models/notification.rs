@@ -0,0 +1,700 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime, Time, UtcOffset};
+use uuid::Uuid;
+
+use super::email::{self, EmailTemplateKind};
+use super::event::{self, EventType};
+
+/// Delivering a notification is given up on after this many attempts.
+pub const MAX_ATTEMPTS: i64 = 8;
+
+/// The delivery job hands this many notifications to their channels per run, the rest wait
+/// for the next.
+const DELIVERY_BATCH_SIZE: i64 = 100;
+
+/// Members see this many of the latest notifications sent to them.
+const LIST_LIMIT: i64 = 100;
+
+/// Something that happened which members are told about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    BookingConfirmed,
+    ClassCancelled,
+    WaitlistPromoted,
+    PaymentFailed,
+    ClassReminder,
+}
+
+impl NotificationEvent {
+    const ALL: [Self; 5] = [
+        Self::BookingConfirmed,
+        Self::ClassCancelled,
+        Self::WaitlistPromoted,
+        Self::PaymentFailed,
+        Self::ClassReminder,
+    ];
+
+    /// The organization's email template the notification is worded by, on every channel.
+    fn email_template(self) -> EmailTemplateKind {
+        match self {
+            Self::BookingConfirmed => EmailTemplateKind::BookingConfirmation,
+            Self::ClassCancelled => EmailTemplateKind::ClassCancelled,
+            Self::WaitlistPromoted => EmailTemplateKind::WaitlistPromoted,
+            Self::PaymentFailed => EmailTemplateKind::PaymentFailed,
+            Self::ClassReminder => EmailTemplateKind::ClassReminder,
+        }
+    }
+}
+
+impl std::fmt::Display for NotificationEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::BookingConfirmed => "booking_confirmed",
+            Self::ClassCancelled => "class_cancelled",
+            Self::WaitlistPromoted => "waitlist_promoted",
+            Self::PaymentFailed => "payment_failed",
+            Self::ClassReminder => "class_reminder",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum NotificationChannelKind {
+    Email,
+    /// A text message to the member's `phone`.
+    Sms,
+    /// A JSON post to the member's `webhook_url`, e.g. a push notification relay.
+    Webhook,
+}
+
+impl NotificationChannelKind {
+    const ALL: [Self; 3] = [Self::Email, Self::Sms, Self::Webhook];
+
+    /// Members get email about everything until they choose otherwise.
+    fn enabled_by_default(self) -> bool {
+        self == Self::Email
+    }
+
+    /// Email waits in the inbox, text messages and pushes buzz the member's phone and are
+    /// held back during their quiet hours.
+    fn respects_quiet_hours(self) -> bool {
+        self != Self::Email
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum NotificationStatus {
+    /// Waiting to be delivered, or to be retried at `next_attempt_at`.
+    Pending,
+    Sent,
+    /// Rejected by the channel, or every attempt failed.
+    Failed,
+}
+
+/// A daily stretch of time, in UTC, in which a member doesn't want to be disturbed. It
+/// runs past midnight when `end` is before `start`, e.g. 22:00 to 07:00.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct QuietHours {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl QuietHours {
+    /// When a notification due at `at` may go out: `at` itself, or the end of the quiet
+    /// hours it falls into.
+    fn next_allowed(&self, at: OffsetDateTime) -> OffsetDateTime {
+        let at = at.to_offset(UtcOffset::UTC);
+        let time = at.time();
+        let quiet = if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        };
+        if !quiet {
+            return at;
+        }
+
+        let end = at.replace_time(self.end);
+        if end > at {
+            end
+        } else {
+            end + Duration::days(1)
+        }
+    }
+}
+
+/// The channels a member is notified on about an event.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct EventChannels {
+    pub event: NotificationEvent,
+    pub channels: Vec<NotificationChannelKind>,
+}
+
+/// Preferences a member can change, fields left out keep their current value.
+#[derive(serde::Deserialize)]
+pub struct UpdateNotificationPreferences {
+    /// `null` removes the phone number.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub phone: Option<Option<String>>,
+    /// `null` removes the webhook.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub webhook_url: Option<Option<String>>,
+    /// `null` turns quiet hours off.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub quiet_hours: Option<Option<QuietHours>>,
+    /// Replaces the channels of the events listed, others keep theirs.
+    pub events: Option<Vec<EventChannels>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct NotificationPreferencesDTO {
+    pub membership_id: Uuid,
+    pub phone: Option<String>,
+    pub webhook_url: Option<String>,
+    pub quiet_hours: Option<QuietHours>,
+    /// Every event, with the channels the member is notified on.
+    pub events: Vec<EventChannels>,
+}
+
+/// A notification on one channel. It is written in the same transaction as whatever it is
+/// about, and delivered by the notifications job.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct NotificationDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub event: NotificationEvent,
+    pub channel: NotificationChannelKind,
+    /// The email address, phone number or URL it went to.
+    pub recipient: String,
+    /// The organization's name when the notification was written.
+    pub from_name: String,
+    pub subject: String,
+    pub body: String,
+    /// The values the organization's template was filled in with.
+    pub data: Json<serde_json::Map<String, serde_json::Value>>,
+    pub status: NotificationStatus,
+    pub attempts: i64,
+    pub next_attempt_at: Option<OffsetDateTime>,
+    pub last_error: Option<String>,
+    pub sent_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// An event to tell a member about, on the channels they chose.
+pub(crate) struct NewNotification {
+    pub membership_id: Uuid,
+    pub event: NotificationEvent,
+    /// Values for the event's template variables, `member_name` and `organization_name`
+    /// are filled in.
+    pub variables: Vec<(&'static str, String)>,
+}
+
+#[derive(Clone)]
+pub struct NotificationController {
+    pool: SqlitePool,
+}
+
+impl NotificationController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynNotificationCtrl = Arc<dyn NotificationCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait NotificationCtrlTrait {
+    async fn get_preferences(&self, membership_id: Uuid) -> Result<NotificationPreferencesDTO>;
+    async fn update_preferences(
+        &self,
+        membership_id: Uuid,
+        update: UpdateNotificationPreferences,
+    ) -> Result<NotificationPreferencesDTO>;
+
+    /// The latest notifications sent, or to be sent, to the member.
+    async fn list_notifications(&self, membership_id: Uuid) -> Result<Vec<NotificationDTO>>;
+
+    /// Pending notifications whose next attempt is due by `now`, oldest first.
+    async fn list_due_notifications(&self, now: OffsetDateTime) -> Result<Vec<NotificationDTO>>;
+    async fn record_sent(&self, id: Uuid, now: OffsetDateTime) -> Result<NotificationDTO>;
+    /// Schedules another attempt with backoff, or gives up if the failure is `permanent` or
+    /// the notification is out of attempts.
+    async fn record_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        permanent: bool,
+        now: OffsetDateTime,
+    ) -> Result<NotificationDTO>;
+}
+
+#[async_trait]
+impl NotificationCtrlTrait for NotificationController {
+    async fn get_preferences(&self, membership_id: Uuid) -> Result<NotificationPreferencesDTO> {
+        get_preferences(&mut *self.pool.acquire().await?, membership_id).await
+    }
+
+    async fn update_preferences(
+        &self,
+        membership_id: Uuid,
+        update: UpdateNotificationPreferences,
+    ) -> Result<NotificationPreferencesDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_preferences(&mut tx, membership_id).await?;
+
+        let mut errors = Vec::new();
+        let phone = match update.phone {
+            Some(Some(phone)) => {
+                let normalized = normalize_phone(&phone);
+                if normalized.is_none() {
+                    errors.push(("phone", "must be a phone number, e.g. +15550100"));
+                }
+                normalized
+            }
+            Some(None) => None,
+            None => current.phone,
+        };
+        let webhook_url = match update.webhook_url {
+            Some(Some(url)) => {
+                if crate::notify::parse_public_url(url.trim()).is_err() {
+                    errors.push(("webhook_url", "must be a public https URL"));
+                }
+                Some(url.trim().to_string())
+            }
+            Some(None) => None,
+            None => current.webhook_url,
+        };
+        let quiet_hours = update.quiet_hours.unwrap_or(current.quiet_hours);
+        if quiet_hours.is_some_and(|q| q.start == q.end) {
+            errors.push((
+                "quiet_hours",
+                "must end at a different time than they start",
+            ));
+        }
+
+        let mut events = current.events;
+        for update in update.events.into_iter().flatten() {
+            if let Some(current) = events.iter_mut().find(|e| e.event == update.event) {
+                current.channels = update.channels;
+            }
+        }
+        let uses = |channel| events.iter().any(|e| e.channels.contains(&channel));
+        if uses(NotificationChannelKind::Sms) && phone.is_none() {
+            errors.push(("events", "need a phone number for sms"));
+        }
+        if uses(NotificationChannelKind::Webhook) && webhook_url.is_none() {
+            errors.push(("events", "need a webhook_url for webhook"));
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::unprocessable_entity(errors));
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let quiet_hours_start = quiet_hours.map(|q| q.start);
+        let quiet_hours_end = quiet_hours.map(|q| q.end);
+        sqlx::query!(
+            r#"insert into "notification_preferences" (
+                membership_id, phone, webhook_url, quiet_hours_start, quiet_hours_end,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7
+            )
+            on conflict (membership_id) do update set
+                phone = excluded.phone, webhook_url = excluded.webhook_url,
+                quiet_hours_start = excluded.quiet_hours_start,
+                quiet_hours_end = excluded.quiet_hours_end, updated_at = excluded.updated_at"#,
+            membership_id,
+            phone,
+            webhook_url,
+            quiet_hours_start,
+            quiet_hours_end,
+            now,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for event_channels in &events {
+            for channel in NotificationChannelKind::ALL {
+                let enabled = event_channels.channels.contains(&channel);
+                sqlx::query!(
+                    r#"insert into "notification_channel_preferences" (
+                        membership_id, event, channel, enabled
+                    ) VALUES (
+                        $1, $2, $3, $4
+                    )
+                    on conflict (membership_id, event, channel) do update set
+                        enabled = excluded.enabled"#,
+                    membership_id,
+                    event_channels.event,
+                    channel,
+                    enabled
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        let preferences = get_preferences(&mut tx, membership_id).await?;
+        let organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            membership_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::NotificationPreferencesUpdated,
+            &preferences,
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(preferences)
+    }
+
+    async fn list_notifications(&self, membership_id: Uuid) -> Result<Vec<NotificationDTO>> {
+        let notifications = sqlx::query_as!(
+            NotificationDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", event as "event: NotificationEvent",
+                channel as "channel: NotificationChannelKind", recipient, from_name, subject,
+                body, data as "data: Json<serde_json::Map<String, serde_json::Value>>",
+                status as "status: NotificationStatus", attempts,
+                next_attempt_at as "next_attempt_at: OffsetDateTime", last_error,
+                sent_at as "sent_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from notifications
+            where membership_id = $1
+            order by inserted_at desc
+            limit $2"#,
+            membership_id,
+            LIST_LIMIT
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(notifications)
+    }
+
+    async fn list_due_notifications(&self, now: OffsetDateTime) -> Result<Vec<NotificationDTO>> {
+        let notifications = sqlx::query_as!(
+            NotificationDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", event as "event: NotificationEvent",
+                channel as "channel: NotificationChannelKind", recipient, from_name, subject,
+                body, data as "data: Json<serde_json::Map<String, serde_json::Value>>",
+                status as "status: NotificationStatus", attempts,
+                next_attempt_at as "next_attempt_at: OffsetDateTime", last_error,
+                sent_at as "sent_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from notifications
+            where status = $1 and next_attempt_at <= $2
+            order by next_attempt_at
+            limit $3"#,
+            NotificationStatus::Pending,
+            now,
+            DELIVERY_BATCH_SIZE
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(notifications)
+    }
+
+    async fn record_sent(&self, id: Uuid, now: OffsetDateTime) -> Result<NotificationDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_notification(&mut tx, id).await?;
+        let notification = NotificationDTO {
+            status: NotificationStatus::Sent,
+            attempts: current.attempts + 1,
+            next_attempt_at: None,
+            last_error: None,
+            sent_at: Some(now),
+            updated_at: now,
+            ..current
+        };
+        update_delivery(&mut tx, &notification).await?;
+
+        tx.commit().await?;
+
+        Ok(notification)
+    }
+
+    async fn record_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        permanent: bool,
+        now: OffsetDateTime,
+    ) -> Result<NotificationDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_notification(&mut tx, id).await?;
+        let attempts = current.attempts + 1;
+        let give_up = permanent || attempts >= MAX_ATTEMPTS;
+        let notification = NotificationDTO {
+            status: if give_up {
+                NotificationStatus::Failed
+            } else {
+                NotificationStatus::Pending
+            },
+            attempts,
+            // 1, 2, 4, ... minutes after each failed attempt.
+            next_attempt_at: (!give_up).then(|| now + Duration::minutes(1 << (attempts - 1))),
+            last_error: Some(error.to_string()),
+            updated_at: now,
+            ..current
+        };
+        update_delivery(&mut tx, &notification).await?;
+
+        tx.commit().await?;
+
+        Ok(notification)
+    }
+}
+
+async fn get_preferences(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+) -> Result<NotificationPreferencesDTO> {
+    let settings = sqlx::query!(
+        r#"select
+            phone, webhook_url,
+            quiet_hours_start as "quiet_hours_start: Time", quiet_hours_end as "quiet_hours_end: Time"
+        from notification_preferences
+        where membership_id = $1"#,
+        membership_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let chosen = sqlx::query!(
+        r#"select
+            event as "event: NotificationEvent", channel as "channel: NotificationChannelKind",
+            enabled
+        from notification_channel_preferences
+        where membership_id = $1"#,
+        membership_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let events = NotificationEvent::ALL
+        .into_iter()
+        .map(|event| EventChannels {
+            event,
+            channels: NotificationChannelKind::ALL
+                .into_iter()
+                .filter(|channel| {
+                    chosen
+                        .iter()
+                        .find(|c| c.event == event && c.channel == *channel)
+                        .map_or(channel.enabled_by_default(), |c| c.enabled)
+                })
+                .collect(),
+        })
+        .collect();
+
+    let (phone, webhook_url, quiet_hours) = match settings {
+        Some(s) => (
+            s.phone,
+            s.webhook_url,
+            s.quiet_hours_start
+                .zip(s.quiet_hours_end)
+                .map(|(start, end)| QuietHours { start, end }),
+        ),
+        None => (None, None, None),
+    };
+
+    Ok(NotificationPreferencesDTO {
+        membership_id,
+        phone,
+        webhook_url,
+        quiet_hours,
+        events,
+    })
+}
+
+async fn get_notification(conn: &mut SqliteConnection, id: Uuid) -> Result<NotificationDTO> {
+    sqlx::query_as!(
+        NotificationDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", event as "event: NotificationEvent",
+            channel as "channel: NotificationChannelKind", recipient, from_name, subject,
+            body, data as "data: Json<serde_json::Map<String, serde_json::Value>>",
+            status as "status: NotificationStatus", attempts,
+            next_attempt_at as "next_attempt_at: OffsetDateTime", last_error,
+            sent_at as "sent_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from notifications
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+async fn update_delivery(
+    conn: &mut SqliteConnection,
+    notification: &NotificationDTO,
+) -> Result<()> {
+    sqlx::query!(
+        r#"update notifications set
+            status = $1, attempts = $2, next_attempt_at = $3, last_error = $4, sent_at = $5,
+            updated_at = $6
+        where id = $7"#,
+        notification.status,
+        notification.attempts,
+        notification.next_attempt_at,
+        notification.last_error,
+        notification.sent_at,
+        notification.updated_at,
+        notification.id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes a notification for each channel the member chose for the event, to be delivered
+/// by the notifications job. Those on channels that respect quiet hours wait for them to end.
+pub(crate) async fn notify(
+    conn: &mut SqliteConnection,
+    new_notification: NewNotification,
+    now: OffsetDateTime,
+) -> Result<Vec<NotificationDTO>> {
+    let member = email::recipient(conn, new_notification.membership_id).await?;
+    let preferences = get_preferences(conn, new_notification.membership_id).await?;
+
+    let mut variables = new_notification.variables;
+    variables.insert(0, ("member_name", member.name));
+    let data: serde_json::Map<String, serde_json::Value> = variables
+        .iter()
+        .map(|(name, value)| (name.to_string(), serde_json::Value::from(value.as_str())))
+        .collect();
+    let rendered = email::render_template(
+        conn,
+        member.organization_id,
+        new_notification.event.email_template(),
+        variables,
+    )
+    .await?;
+
+    let channels = preferences
+        .events
+        .into_iter()
+        .find(|e| e.event == new_notification.event)
+        .map(|e| e.channels)
+        .unwrap_or_default();
+
+    let mut notifications = Vec::with_capacity(channels.len());
+    for channel in channels {
+        let recipient = match channel {
+            NotificationChannelKind::Email => Some(member.email.clone()),
+            NotificationChannelKind::Sms => preferences.phone.clone(),
+            NotificationChannelKind::Webhook => preferences.webhook_url.clone(),
+        };
+        let Some(recipient) = recipient else {
+            continue;
+        };
+
+        let next_attempt_at = match preferences.quiet_hours {
+            Some(quiet_hours) if channel.respects_quiet_hours() => quiet_hours.next_allowed(now),
+            _ => now,
+        };
+
+        let notification = NotificationDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id: member.organization_id,
+            membership_id: new_notification.membership_id,
+            event: new_notification.event,
+            channel,
+            recipient,
+            from_name: rendered.from_name.clone(),
+            subject: rendered.subject.clone(),
+            body: rendered.body.clone(),
+            data: Json(data.clone()),
+            status: NotificationStatus::Pending,
+            attempts: 0,
+            next_attempt_at: Some(next_attempt_at),
+            last_error: None,
+            sent_at: None,
+            inserted_at: now,
+            updated_at: now,
+        };
+
+        sqlx::query!(
+            r#"insert into "notifications" (
+                id, organization_id, membership_id, event, channel, recipient,
+                from_name, subject, body, data,
+                status, attempts, next_attempt_at,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6,
+                $7, $8, $9, $10,
+                $11, $12, $13,
+                $14, $15
+            )"#,
+            notification.id,
+            notification.organization_id,
+            notification.membership_id,
+            notification.event,
+            notification.channel,
+            notification.recipient,
+            notification.from_name,
+            notification.subject,
+            notification.body,
+            notification.data,
+            notification.status,
+            notification.attempts,
+            notification.next_attempt_at,
+            notification.inserted_at,
+            notification.updated_at
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        notifications.push(notification);
+    }
+
+    Ok(notifications)
+}
+
+/// Strips the spaces, dashes and parentheses people write phone numbers with, e.g.
+/// `+1 (555) 010-0` becomes `+15550100`.
+fn normalize_phone(phone: &str) -> Option<String> {
+    let phone: String = phone
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.'))
+        .collect();
+    let digits = phone.strip_prefix('+').unwrap_or(&phone);
+
+    (digits.len() >= 7 && digits.len() <= 15 && digits.chars().all(|c| c.is_ascii_digit()))
+        .then_some(phone)
+}
@@ -0,0 +1,468 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::dunning::DunningFinalAction;
+use super::event::{self, EventType};
+use super::membership::{self, MembershipDTO, Permission, Role};
+use super::penalty::PenaltyKind;
+use super::platform_subscription;
+
+/// Waitlisted members are not promoted once a class is this close to starting,
+/// unless the organization configures its own cut-off.
+const DEFAULT_WAITLIST_CUTOFF_MINUTES: i64 = 60;
+
+/// Waitlists can't stop promoting members further ahead than a week.
+const MAX_WAITLIST_CUTOFF_MINUTES: i64 = 7 * 24 * 60;
+
+/// Cancelling closer than this to the start of a class counts as a late cancel.
+const DEFAULT_CANCELLATION_WINDOW_MINUTES: i64 = 12 * 60;
+
+/// Cancellations can't count as late further ahead than 30 days.
+const MAX_CANCELLATION_WINDOW_MINUTES: i64 = 30 * 24 * 60;
+
+/// Penalties can't block booking for longer than a year.
+const MAX_PENALTY_BLOCK_DAYS: i64 = 365;
+
+const DEFAULT_CURRENCY: &str = "USD";
+
+/// An instructor teaching at two different locations needs this long in between,
+/// unless the organization configures its own gap.
+const DEFAULT_INSTRUCTOR_TRAVEL_GAP_MINUTES: i64 = 30;
+
+/// Instructors can't be required to travel for longer than a day.
+const MAX_INSTRUCTOR_TRAVEL_GAP_MINUTES: i64 = 24 * 60;
+
+/// Members are reminded of the classes they booked this long before they start, unless the
+/// organization configures its own lead time or turns reminders off.
+const DEFAULT_CLASS_REMINDER_HOURS: i64 = 24;
+
+/// Reminders can't be sent further ahead than a week.
+const MAX_CLASS_REMINDER_HOURS: i64 = 7 * 24;
+
+#[derive(serde::Deserialize)]
+pub struct NewOrganization {
+    pub name: String,
+    pub waitlist_cutoff_minutes: Option<i64>,
+}
+
+/// Settings an owner can change, fields left out keep their current value.
+#[derive(serde::Deserialize)]
+pub struct UpdateOrganization {
+    pub name: Option<String>,
+    pub currency: Option<String>,
+    pub waitlist_cutoff_minutes: Option<i64>,
+    pub cancellation_window_minutes: Option<i64>,
+    pub late_cancel_penalty: Option<PenaltyKind>,
+    pub no_show_penalty: Option<PenaltyKind>,
+    pub penalty_fee_amount: Option<i64>,
+    pub penalty_block_days: Option<i64>,
+    /// `null` removes the limit.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub max_bookings_per_day: Option<Option<i64>>,
+    pub instructor_travel_gap_minutes: Option<i64>,
+    pub booking_requires_entitlement: Option<bool>,
+    pub dunning_final_action: Option<DunningFinalAction>,
+    pub billing_refund_role: Option<Role>,
+    /// `null` turns class reminders off.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub class_reminder_hours: Option<Option<i64>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct OrganizationDTO {
+    pub id: Uuid,
+    pub name: String,
+    pub currency: String,
+    pub waitlist_cutoff_minutes: i64,
+    pub cancellation_window_minutes: i64,
+    pub late_cancel_penalty: PenaltyKind,
+    pub no_show_penalty: PenaltyKind,
+    /// In minor units of `currency`, charged by `PenaltyKind::Fee`.
+    pub penalty_fee_amount: i64,
+    /// How long `PenaltyKind::BookingBlock` stops a member from booking.
+    pub penalty_block_days: i64,
+    /// How many classes starting on the same (UTC) day a member may hold bookings for.
+    pub max_bookings_per_day: Option<i64>,
+    /// The minimum time between an instructor's classes at different locations.
+    pub instructor_travel_gap_minutes: i64,
+    /// Whether members need a membership plan or class credits to book.
+    pub booking_requires_entitlement: bool,
+    /// What happens to a subscription once every retry of its renewal payment failed.
+    pub dunning_final_action: DunningFinalAction,
+    /// The least privileged role with `Permission::BillingRefund`.
+    pub billing_refund_role: Role,
+    /// How many hours before a class members who booked it are reminded, if at all.
+    pub class_reminder_hours: Option<i64>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl OrganizationDTO {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(("name", "can't be blank"));
+        }
+        if !is_currency_code(&self.currency) {
+            errors.push(("currency", "must be a three letter ISO 4217 code"));
+        }
+        if !(0..=MAX_WAITLIST_CUTOFF_MINUTES).contains(&self.waitlist_cutoff_minutes) {
+            errors.push(("waitlist_cutoff_minutes", "must be between 0 and 10080"));
+        }
+        if !(0..=MAX_CANCELLATION_WINDOW_MINUTES).contains(&self.cancellation_window_minutes) {
+            errors.push(("cancellation_window_minutes", "must be between 0 and 43200"));
+        }
+        if matches!(self.max_bookings_per_day, Some(max) if max < 1) {
+            errors.push(("max_bookings_per_day", "must be at least 1"));
+        }
+        if !(0..=MAX_INSTRUCTOR_TRAVEL_GAP_MINUTES).contains(&self.instructor_travel_gap_minutes) {
+            errors.push((
+                "instructor_travel_gap_minutes",
+                "must be between 0 and 1440",
+            ));
+        }
+        if self.billing_refund_role < Role::Staff {
+            errors.push(("billing_refund_role", "must be staff or above"));
+        }
+        if matches!(self.class_reminder_hours, Some(hours) if !(1..=MAX_CLASS_REMINDER_HOURS).contains(&hours))
+        {
+            errors.push(("class_reminder_hours", "must be between 1 and 168"));
+        }
+
+        let penalties = [self.late_cancel_penalty, self.no_show_penalty];
+        if penalties.contains(&PenaltyKind::Fee) && self.penalty_fee_amount <= 0 {
+            errors.push(("penalty_fee_amount", "must be positive to charge a fee"));
+        }
+        if penalties.contains(&PenaltyKind::BookingBlock) && self.penalty_block_days <= 0 {
+            errors.push(("penalty_block_days", "must be positive to block bookings"));
+        }
+        if self.penalty_block_days > MAX_PENALTY_BLOCK_DAYS {
+            errors.push(("penalty_block_days", "must be at most 365"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+}
+
+impl OrganizationDTO {
+    /// The least privileged role the organization lets use `permission`.
+    pub(crate) fn role_for(&self, permission: Permission) -> Role {
+        match permission {
+            Permission::BillingRefund => self.billing_refund_role,
+        }
+    }
+}
+
+/// Whether `currency` looks like an ISO 4217 code, e.g. "USD".
+pub(crate) fn is_currency_code(currency: &str) -> bool {
+    currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase())
+}
+
+#[derive(Clone)]
+pub struct OrganizationController {
+    pool: SqlitePool,
+}
+
+impl OrganizationController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynOrganizationCtrl = Arc<dyn OrganizationCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait OrganizationCtrlTrait {
+    /// Creates the organization and makes `owner_account_id` its owner.
+    async fn create_organization(
+        &self,
+        owner_account_id: Uuid,
+        new_organization: NewOrganization,
+    ) -> Result<OrganizationDTO>;
+
+    async fn update_organization(
+        &self,
+        id: Uuid,
+        update_organization: UpdateOrganization,
+        actor: &Actor,
+    ) -> Result<OrganizationDTO>;
+
+    async fn get_organization(&self, id: Uuid) -> Result<OrganizationDTO>;
+}
+
+#[async_trait]
+impl OrganizationCtrlTrait for OrganizationController {
+    async fn create_organization(
+        &self,
+        owner_account_id: Uuid,
+        new_organization: NewOrganization,
+    ) -> Result<OrganizationDTO> {
+        let id = uuid::Uuid::new_v4();
+        let membership_id = uuid::Uuid::new_v4();
+        let owner_role = Role::Owner;
+        let check_in_token = membership::generate_check_in_token();
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let organization = OrganizationDTO {
+            id,
+            name: new_organization.name,
+            currency: DEFAULT_CURRENCY.to_string(),
+            waitlist_cutoff_minutes: new_organization
+                .waitlist_cutoff_minutes
+                .unwrap_or(DEFAULT_WAITLIST_CUTOFF_MINUTES),
+            cancellation_window_minutes: DEFAULT_CANCELLATION_WINDOW_MINUTES,
+            late_cancel_penalty: PenaltyKind::None,
+            no_show_penalty: PenaltyKind::None,
+            penalty_fee_amount: 0,
+            penalty_block_days: 0,
+            max_bookings_per_day: None,
+            instructor_travel_gap_minutes: DEFAULT_INSTRUCTOR_TRAVEL_GAP_MINUTES,
+            booking_requires_entitlement: false,
+            dunning_final_action: DunningFinalAction::Cancel,
+            billing_refund_role: Role::Owner,
+            class_reminder_hours: Some(DEFAULT_CLASS_REMINDER_HOURS),
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        organization.validate()?;
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        sqlx::query!(
+            r#"insert into "organizations" (
+                id, name, currency, waitlist_cutoff_minutes,
+                cancellation_window_minutes, late_cancel_penalty, no_show_penalty,
+                penalty_fee_amount, penalty_block_days, max_bookings_per_day,
+                instructor_travel_gap_minutes, booking_requires_entitlement, dunning_final_action,
+                billing_refund_role, class_reminder_hours, inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4,
+                $5, $6, $7,
+                $8, $9, $10,
+                $11, $12, $13,
+                $14, $15, $16, $17
+            )"#,
+            organization.id,
+            organization.name,
+            organization.currency,
+            organization.waitlist_cutoff_minutes,
+            organization.cancellation_window_minutes,
+            organization.late_cancel_penalty,
+            organization.no_show_penalty,
+            organization.penalty_fee_amount,
+            organization.penalty_block_days,
+            organization.max_bookings_per_day,
+            organization.instructor_travel_gap_minutes,
+            organization.booking_requires_entitlement,
+            organization.dunning_final_action,
+            organization.billing_refund_role,
+            organization.class_reminder_hours,
+            organization.inserted_at,
+            organization.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"insert into "memberships" (
+                id, organization_id, account_id, role, check_in_token,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7
+            )"#,
+            membership_id,
+            id,
+            owner_account_id,
+            owner_role,
+            check_in_token,
+            inserted_at,
+            inserted_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        platform_subscription::start_platform_subscription(&mut tx, id, inserted_at).await?;
+
+        event::record(
+            &mut tx,
+            Some(id),
+            EventType::OrganizationCreated,
+            &organization,
+            inserted_at,
+        )
+        .await?;
+        let owner = MembershipDTO {
+            id: membership_id,
+            organization_id: id,
+            account_id: owner_account_id,
+            role: owner_role,
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        event::record(
+            &mut tx,
+            Some(id),
+            EventType::StaffCreated,
+            &owner,
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(organization)
+    }
+
+    async fn update_organization(
+        &self,
+        id: Uuid,
+        update_organization: UpdateOrganization,
+        actor: &Actor,
+    ) -> Result<OrganizationDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_organization(&mut tx, id).await?;
+        let organization = OrganizationDTO {
+            name: update_organization
+                .name
+                .unwrap_or_else(|| current.name.clone()),
+            currency: update_organization
+                .currency
+                .unwrap_or_else(|| current.currency.clone()),
+            waitlist_cutoff_minutes: update_organization
+                .waitlist_cutoff_minutes
+                .unwrap_or(current.waitlist_cutoff_minutes),
+            cancellation_window_minutes: update_organization
+                .cancellation_window_minutes
+                .unwrap_or(current.cancellation_window_minutes),
+            late_cancel_penalty: update_organization
+                .late_cancel_penalty
+                .unwrap_or(current.late_cancel_penalty),
+            no_show_penalty: update_organization
+                .no_show_penalty
+                .unwrap_or(current.no_show_penalty),
+            penalty_fee_amount: update_organization
+                .penalty_fee_amount
+                .unwrap_or(current.penalty_fee_amount),
+            penalty_block_days: update_organization
+                .penalty_block_days
+                .unwrap_or(current.penalty_block_days),
+            max_bookings_per_day: update_organization
+                .max_bookings_per_day
+                .unwrap_or(current.max_bookings_per_day),
+            instructor_travel_gap_minutes: update_organization
+                .instructor_travel_gap_minutes
+                .unwrap_or(current.instructor_travel_gap_minutes),
+            booking_requires_entitlement: update_organization
+                .booking_requires_entitlement
+                .unwrap_or(current.booking_requires_entitlement),
+            dunning_final_action: update_organization
+                .dunning_final_action
+                .unwrap_or(current.dunning_final_action),
+            billing_refund_role: update_organization
+                .billing_refund_role
+                .unwrap_or(current.billing_refund_role),
+            class_reminder_hours: update_organization
+                .class_reminder_hours
+                .unwrap_or(current.class_reminder_hours),
+            updated_at: time::OffsetDateTime::now_utc(),
+            ..current
+        };
+        organization.validate()?;
+
+        sqlx::query!(
+            r#"update organizations set
+                name = $1, currency = $2, waitlist_cutoff_minutes = $3,
+                cancellation_window_minutes = $4, late_cancel_penalty = $5, no_show_penalty = $6,
+                penalty_fee_amount = $7, penalty_block_days = $8, max_bookings_per_day = $9,
+                instructor_travel_gap_minutes = $10, booking_requires_entitlement = $11,
+                dunning_final_action = $12, billing_refund_role = $13, class_reminder_hours = $14,
+                updated_at = $15
+            where id = $16"#,
+            organization.name,
+            organization.currency,
+            organization.waitlist_cutoff_minutes,
+            organization.cancellation_window_minutes,
+            organization.late_cancel_penalty,
+            organization.no_show_penalty,
+            organization.penalty_fee_amount,
+            organization.penalty_block_days,
+            organization.max_bookings_per_day,
+            organization.instructor_travel_gap_minutes,
+            organization.booking_requires_entitlement,
+            organization.dunning_final_action,
+            organization.billing_refund_role,
+            organization.class_reminder_hours,
+            organization.updated_at,
+            organization.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization.id),
+            EventType::OrganizationUpdated,
+            &organization,
+            organization.updated_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization.id,
+            AuditAction::OrganizationUpdated,
+            organization.id,
+            Some(&current),
+            Some(&organization),
+            organization.updated_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(organization)
+    }
+
+    async fn get_organization(&self, id: Uuid) -> Result<OrganizationDTO> {
+        get_organization(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+/// Fetches an organization on an existing connection, so its settings can be read
+/// inside a transaction.
+pub(crate) async fn get_organization(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+) -> Result<OrganizationDTO> {
+    sqlx::query_as!(
+        OrganizationDTO,
+        r#"select
+            id as "id: Uuid", name, currency, waitlist_cutoff_minutes,
+            cancellation_window_minutes, late_cancel_penalty as "late_cancel_penalty: PenaltyKind",
+            no_show_penalty as "no_show_penalty: PenaltyKind",
+            penalty_fee_amount, penalty_block_days, max_bookings_per_day,
+            instructor_travel_gap_minutes, booking_requires_entitlement,
+            dunning_final_action as "dunning_final_action: DunningFinalAction",
+            billing_refund_role as "billing_refund_role: Role", class_reminder_hours,
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from organizations
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
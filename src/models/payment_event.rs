@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use crate::payments::webhook::{WebhookEvent, WebhookEventKind, WebhookProvider};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::dunning;
+use super::invoice::{self, InvoiceDTO};
+
+/// A webhook event from a payment provider. Each is only ever acted on once.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PaymentEventDTO {
+    pub id: Uuid,
+    pub provider: WebhookProvider,
+    /// The provider's id for the event.
+    pub event_id: String,
+    pub event_type: String,
+    pub kind: WebhookEventKind,
+    pub invoice_id: Option<Uuid>,
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct PaymentEventController {
+    pool: SqlitePool,
+}
+
+impl PaymentEventController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPaymentEventCtrl = Arc<dyn PaymentEventCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PaymentEventCtrlTrait {
+    /// Applies the event to its invoice and subscription and records it, in one transaction.
+    /// An event already recorded is returned without being applied again, since providers
+    /// deliver events at least once.
+    async fn process_event(
+        &self,
+        provider: WebhookProvider,
+        event: WebhookEvent,
+        payload: &str,
+    ) -> Result<PaymentEventDTO>;
+}
+
+#[async_trait]
+impl PaymentEventCtrlTrait for PaymentEventController {
+    async fn process_event(
+        &self,
+        provider: WebhookProvider,
+        event: WebhookEvent,
+        payload: &str,
+    ) -> Result<PaymentEventDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let existing = sqlx::query_as!(
+            PaymentEventDTO,
+            r#"select
+                id as "id: Uuid", provider as "provider: WebhookProvider", event_id, event_type,
+                kind as "kind: WebhookEventKind", invoice_id as "invoice_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime"
+            from payment_events
+            where provider = $1 and event_id = $2"#,
+            provider,
+            event.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(existing) = existing {
+            return Ok(existing);
+        }
+
+        let invoice = match event.invoice_id {
+            Some(invoice_id) => match invoice::get_invoice(&mut tx, invoice_id).await {
+                Ok(invoice) => Some(invoice),
+                Err(Error::NotFound) => {
+                    tracing::warn!(
+                        event_id = %event.id,
+                        %invoice_id,
+                        "payment event for an unknown invoice"
+                    );
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+
+        let invoice_id = invoice.as_ref().map(|i| i.id);
+        if let Some(invoice) = invoice {
+            apply_event(&mut tx, &event, invoice, now).await?;
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let payment_event = sqlx::query_as!(
+            PaymentEventDTO,
+            r#"insert into "payment_events" (
+                id, provider, event_id, event_type, kind, invoice_id, payload,
+                inserted_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7,
+                $8
+            ) returning
+                id as "id: Uuid", provider as "provider: WebhookProvider", event_id, event_type,
+                kind as "kind: WebhookEventKind", invoice_id as "invoice_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime""#,
+            id,
+            provider,
+            event.id,
+            event.event_type,
+            event.kind,
+            invoice_id,
+            payload,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(payment_event)
+    }
+}
+
+/// Pays or fails the invoice through dunning.
+async fn apply_event(
+    conn: &mut SqliteConnection,
+    event: &WebhookEvent,
+    invoice: InvoiceDTO,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let charge_id = event.charge_id.as_deref();
+    match event.kind {
+        WebhookEventKind::PaymentSucceeded => {
+            dunning::payment_succeeded(conn, invoice, charge_id, now).await?;
+        }
+        WebhookEventKind::PaymentFailed => {
+            let message = event.failure_message.as_deref().unwrap_or("payment failed");
+            dunning::payment_failed(conn, invoice, charge_id, message, now).await?;
+        }
+        WebhookEventKind::Other => (),
+    }
+
+    Ok(())
+}
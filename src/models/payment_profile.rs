@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use crate::http::Result;
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::event::{self, EventType};
+use super::invoice::InvoiceStatus;
+
+/// Saves a payment method for the member, from a token made by the provider's client library.
+#[derive(serde::Deserialize)]
+pub struct NewPaymentMethod {
+    pub token: String,
+}
+
+/// Who the member is at the payment provider, and what they are charged with.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PaymentProfileDTO {
+    pub membership_id: Uuid,
+    pub customer_id: String,
+    pub payment_method_id: Option<String>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct PaymentProfileController {
+    pool: SqlitePool,
+}
+
+impl PaymentProfileController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPaymentProfileCtrl = Arc<dyn PaymentProfileCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PaymentProfileCtrlTrait {
+    async fn get_payment_profile(&self, membership_id: Uuid) -> Result<Option<PaymentProfileDTO>>;
+
+    /// Saves the member's customer and payment method. Open invoices still being retried
+    /// are charged again straight away with the new payment method.
+    async fn save_payment_profile(
+        &self,
+        membership_id: Uuid,
+        customer_id: String,
+        payment_method_id: Option<String>,
+    ) -> Result<PaymentProfileDTO>;
+}
+
+#[async_trait]
+impl PaymentProfileCtrlTrait for PaymentProfileController {
+    async fn get_payment_profile(&self, membership_id: Uuid) -> Result<Option<PaymentProfileDTO>> {
+        get_payment_profile(&mut *self.pool.acquire().await?, membership_id).await
+    }
+
+    async fn save_payment_profile(
+        &self,
+        membership_id: Uuid,
+        customer_id: String,
+        payment_method_id: Option<String>,
+    ) -> Result<PaymentProfileDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let profile = sqlx::query_as!(
+            PaymentProfileDTO,
+            r#"insert into "payment_profiles" (
+                membership_id, customer_id, payment_method_id,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3,
+                $4, $5
+            ) on conflict (membership_id) do update set
+                customer_id = excluded.customer_id,
+                payment_method_id = excluded.payment_method_id,
+                updated_at = excluded.updated_at
+            returning
+                membership_id as "membership_id: Uuid", customer_id, payment_method_id,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            membership_id,
+            customer_id,
+            payment_method_id,
+            now,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            membership_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::PaymentProfileSaved,
+            &profile,
+            now,
+        )
+        .await?;
+
+        if profile.payment_method_id.is_some() {
+            sqlx::query!(
+                r#"update invoices set next_payment_attempt_at = $1, updated_at = $1
+                where membership_id = $2 and status = $3 and next_payment_attempt_at is not null"#,
+                now,
+                membership_id,
+                InvoiceStatus::Open
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(profile)
+    }
+}
+
+pub(crate) async fn get_payment_profile(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+) -> Result<Option<PaymentProfileDTO>> {
+    let profile = sqlx::query_as!(
+        PaymentProfileDTO,
+        r#"select
+            membership_id as "membership_id: Uuid", customer_id, payment_method_id,
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from payment_profiles
+        where membership_id = $1"#,
+        membership_id
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(profile)
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use crate::http::Result;
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::event::{self, EventType};
+use super::invoice::{self, NewInvoice, NewInvoiceLine};
+use super::organization::OrganizationDTO;
+
+/// What happens to a member who cancels late or doesn't show up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PenaltyKind {
+    None,
+    /// The class credit used for the booking is not refunded.
+    ForfeitCredit,
+    /// The organization's `penalty_fee_amount` is owed.
+    Fee,
+    /// The member can't book classes for the organization's `penalty_block_days`.
+    BookingBlock,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PenaltyReason {
+    LateCancel,
+    NoShow,
+}
+
+impl PenaltyReason {
+    /// How a fee for it shows on the member's invoice.
+    fn fee_description(self) -> &'static str {
+        match self {
+            Self::LateCancel => "Late cancellation fee",
+            Self::NoShow => "No-show fee",
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PenaltyDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub booking_id: Uuid,
+    pub reason: PenaltyReason,
+    pub kind: PenaltyKind,
+    pub fee_amount: Option<i64>,
+    pub currency: Option<String>,
+    pub blocked_until: Option<OffsetDateTime>,
+    /// The invoice a fee is billed on.
+    pub invoice_id: Option<Uuid>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct PenaltyController {
+    pool: SqlitePool,
+}
+
+impl PenaltyController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPenaltyCtrl = Arc<dyn PenaltyCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PenaltyCtrlTrait {
+    async fn list_penalties(&self, membership_id: Uuid) -> Result<Vec<PenaltyDTO>>;
+}
+
+#[async_trait]
+impl PenaltyCtrlTrait for PenaltyController {
+    async fn list_penalties(&self, membership_id: Uuid) -> Result<Vec<PenaltyDTO>> {
+        let penalties = sqlx::query_as!(
+            PenaltyDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", booking_id as "booking_id: Uuid",
+                reason as "reason: PenaltyReason", kind as "kind: PenaltyKind",
+                fee_amount, currency, blocked_until as "blocked_until: OffsetDateTime",
+                invoice_id as "invoice_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from penalties
+            where membership_id = $1
+            order by inserted_at desc"#,
+            membership_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(penalties)
+    }
+}
+
+/// Records the penalty the organization configured for `reason`, if any. A fee is invoiced
+/// straight away and charged to the member like any other invoice.
+pub(crate) async fn apply_penalty(
+    conn: &mut SqliteConnection,
+    organization: &OrganizationDTO,
+    membership_id: Uuid,
+    booking_id: Uuid,
+    reason: PenaltyReason,
+    now: OffsetDateTime,
+) -> Result<Option<PenaltyDTO>> {
+    let kind = match reason {
+        PenaltyReason::LateCancel => organization.late_cancel_penalty,
+        PenaltyReason::NoShow => organization.no_show_penalty,
+    };
+
+    let (fee_amount, currency, blocked_until) = match kind {
+        PenaltyKind::None => return Ok(None),
+        PenaltyKind::ForfeitCredit => (None, None, None),
+        PenaltyKind::Fee => (
+            Some(organization.penalty_fee_amount),
+            Some(organization.currency.clone()),
+            None,
+        ),
+        PenaltyKind::BookingBlock => (
+            None,
+            None,
+            Some(
+                now.checked_add(time::Duration::days(organization.penalty_block_days))
+                    .ok_or_else(|| anyhow::anyhow!("booking block ends out of range"))?,
+            ),
+        ),
+    };
+
+    let invoice_id = match fee_amount {
+        Some(fee_amount) => {
+            let invoice = invoice::create_invoice(
+                conn,
+                NewInvoice {
+                    organization_id: organization.id,
+                    membership_id,
+                    subscription_id: None,
+                    currency: organization.currency.clone(),
+                    period_start: None,
+                    period_end: None,
+                    next_payment_attempt_at: Some(now),
+                    lines: vec![NewInvoiceLine {
+                        description: reason.fee_description().to_string(),
+                        plan_id: None,
+                        product_id: None,
+                        quantity: 1,
+                        unit_amount: fee_amount,
+                        discount_amount: 0,
+                        promo_code_id: None,
+                        tax_rate_id: None,
+                        tax_rate_basis_points: 0,
+                    }],
+                },
+                now,
+            )
+            .await?;
+            Some(invoice.id)
+        }
+        None => None,
+    };
+
+    let id = uuid::Uuid::new_v4();
+
+    let penalty = sqlx::query_as!(
+        PenaltyDTO,
+        r#"insert into "penalties" (
+            id, organization_id, membership_id, booking_id,
+            reason, kind, fee_amount, currency, blocked_until, invoice_id,
+            inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4,
+            $5, $6, $7, $8, $9, $10,
+            $11, $12
+        ) returning
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", booking_id as "booking_id: Uuid",
+            reason as "reason: PenaltyReason", kind as "kind: PenaltyKind",
+            fee_amount, currency, blocked_until as "blocked_until: OffsetDateTime",
+            invoice_id as "invoice_id: Uuid",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+        id,
+        organization.id,
+        membership_id,
+        booking_id,
+        reason,
+        kind,
+        fee_amount,
+        currency,
+        blocked_until,
+        invoice_id,
+        now,
+        now
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    event::record(
+        conn,
+        Some(organization.id),
+        EventType::PenaltyApplied,
+        &penalty,
+        now,
+    )
+    .await?;
+
+    Ok(Some(penalty))
+}
+
+/// Returns when the member's latest booking block ends, if one is still in effect.
+pub(crate) async fn blocked_until(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<Option<OffsetDateTime>> {
+    let blocks = sqlx::query_scalar!(
+        r#"select blocked_until as "blocked_until!: OffsetDateTime"
+        from penalties
+        where membership_id = $1 and kind = $2 and blocked_until is not null"#,
+        membership_id,
+        PenaltyKind::BookingBlock
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(blocks.into_iter().filter(|until| *until > now).max())
+}
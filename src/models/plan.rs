@@ -0,0 +1,485 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Date, Duration, Month, OffsetDateTime};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::organization::{self, is_currency_code};
+use super::tax_rate;
+
+/// Trials and class packs can't last longer than ten years.
+const MAX_PLAN_DAYS: i64 = 3650;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PlanKind {
+    /// Recurring membership with no limit on bookings.
+    Unlimited,
+    /// Recurring membership allowing `classes_per_week` bookings.
+    WeeklyLimit,
+    /// One-off purchase of `class_count` classes, usable for `validity_days`.
+    ClassPack,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum BillingInterval {
+    Week,
+    Month,
+    Year,
+}
+
+impl BillingInterval {
+    /// The end of the `count`th billing period starting at `anchor`. Months and years keep
+    /// the anchor's day, clamped to the end of shorter months, so a subscription started on
+    /// January 31st renews on February 28th and then March 31st.
+    pub(crate) fn periods_after(self, anchor: OffsetDateTime, count: i64) -> OffsetDateTime {
+        let months = match self {
+            BillingInterval::Week => return anchor + Duration::weeks(count),
+            BillingInterval::Month => count,
+            BillingInterval::Year => count * 12,
+        };
+
+        let total =
+            i64::from(anchor.year()) * 12 + i64::from(u8::from(anchor.month())) - 1 + months;
+        let year = (total.div_euclid(12)) as i32;
+        let month = Month::try_from((total.rem_euclid(12) + 1) as u8).expect("month is in 1..=12");
+        let day = anchor
+            .day()
+            .min(time::util::days_in_year_month(year, month));
+
+        let date = Date::from_calendar_date(year, month, day).expect("day is clamped to the month");
+        anchor.replace_date(date)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewPlan {
+    pub name: String,
+    pub description: Option<String>,
+    pub kind: PlanKind,
+    /// In minor units of `currency`, e.g. cents.
+    pub price_amount: i64,
+    /// Leave out to use the organization's currency.
+    pub currency: Option<String>,
+    pub billing_interval: Option<BillingInterval>,
+    pub trial_days: Option<i64>,
+    pub classes_per_week: Option<i64>,
+    pub class_count: Option<i64>,
+    pub validity_days: Option<i64>,
+    pub tax_rate_id: Option<Uuid>,
+}
+
+/// Fields left out keep their current value. The kind, currency and billing interval
+/// can't change once members may have bought the plan.
+#[derive(serde::Deserialize)]
+pub struct UpdatePlan {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub price_amount: Option<i64>,
+    pub trial_days: Option<i64>,
+    pub classes_per_week: Option<i64>,
+    pub class_count: Option<i64>,
+    pub validity_days: Option<i64>,
+    /// `null` stops charging tax on the plan.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub tax_rate_id: Option<Option<Uuid>>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PlanDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub kind: PlanKind,
+    pub price_amount: i64,
+    pub currency: String,
+    pub billing_interval: Option<BillingInterval>,
+    pub trial_days: i64,
+    pub classes_per_week: Option<i64>,
+    pub class_count: Option<i64>,
+    pub validity_days: Option<i64>,
+    /// Charged on top of `price_amount` when invoiced.
+    pub tax_rate_id: Option<Uuid>,
+    /// Archived plans can't be bought anymore but stay around for existing purchases.
+    pub archived_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl PlanDTO {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(("name", "can't be blank"));
+        }
+        if self.price_amount < 0 {
+            errors.push(("price_amount", "must not be negative"));
+        }
+        if !is_currency_code(&self.currency) {
+            errors.push(("currency", "must be a three letter ISO 4217 code"));
+        }
+        if !(0..=MAX_PLAN_DAYS).contains(&self.trial_days) {
+            errors.push(("trial_days", "must be between 0 and 3650"));
+        }
+
+        match self.kind {
+            PlanKind::Unlimited | PlanKind::WeeklyLimit => {
+                if self.billing_interval.is_none() {
+                    errors.push(("billing_interval", "is required for memberships"));
+                }
+                if self.class_count.is_some() {
+                    errors.push(("class_count", "is only used by class packs"));
+                }
+                if self.validity_days.is_some() {
+                    errors.push(("validity_days", "is only used by class packs"));
+                }
+            }
+            PlanKind::ClassPack => {
+                if self.billing_interval.is_some() {
+                    errors.push(("billing_interval", "is only used by memberships"));
+                }
+                if self.trial_days != 0 {
+                    errors.push(("trial_days", "is only used by memberships"));
+                }
+                if !matches!(self.class_count, Some(count) if count >= 1) {
+                    errors.push(("class_count", "must be at least 1"));
+                }
+                if !matches!(self.validity_days, Some(days) if (1..=MAX_PLAN_DAYS).contains(&days))
+                {
+                    errors.push(("validity_days", "must be between 1 and 3650"));
+                }
+            }
+        }
+
+        if self.kind == PlanKind::WeeklyLimit {
+            if !matches!(self.classes_per_week, Some(count) if count >= 1) {
+                errors.push(("classes_per_week", "must be at least 1"));
+            }
+        } else if self.classes_per_week.is_some() {
+            errors.push(("classes_per_week", "is only used by weekly limit plans"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlanController {
+    pool: SqlitePool,
+}
+
+impl PlanController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPlanCtrl = Arc<dyn PlanCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PlanCtrlTrait {
+    async fn create_plan(
+        &self,
+        organization_id: Uuid,
+        new_plan: NewPlan,
+        actor: &Actor,
+    ) -> Result<PlanDTO>;
+    async fn update_plan(
+        &self,
+        id: Uuid,
+        update_plan: UpdatePlan,
+        actor: &Actor,
+    ) -> Result<PlanDTO>;
+    /// Takes the plan off sale, it is kept for the purchases made of it.
+    async fn archive_plan(&self, id: Uuid, actor: &Actor) -> Result<PlanDTO>;
+    /// Lists the plans on sale.
+    async fn list_plans(&self, organization_id: Uuid) -> Result<Vec<PlanDTO>>;
+    async fn get_plan(&self, id: Uuid) -> Result<PlanDTO>;
+}
+
+#[async_trait]
+impl PlanCtrlTrait for PlanController {
+    async fn create_plan(
+        &self,
+        organization_id: Uuid,
+        new_plan: NewPlan,
+        actor: &Actor,
+    ) -> Result<PlanDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let organization = organization::get_organization(&mut tx, organization_id).await?;
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let plan = PlanDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            name: new_plan.name,
+            description: new_plan.description.unwrap_or_default(),
+            kind: new_plan.kind,
+            price_amount: new_plan.price_amount,
+            currency: new_plan.currency.unwrap_or(organization.currency),
+            billing_interval: new_plan.billing_interval,
+            trial_days: new_plan.trial_days.unwrap_or(0),
+            classes_per_week: new_plan.classes_per_week,
+            class_count: new_plan.class_count,
+            validity_days: new_plan.validity_days,
+            tax_rate_id: new_plan.tax_rate_id,
+            archived_at: None,
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        plan.validate()?;
+        check_tax_rate(&mut tx, &plan).await?;
+
+        sqlx::query!(
+            r#"insert into "plans" (
+                id, organization_id, name, description, kind,
+                price_amount, currency, billing_interval, trial_days,
+                classes_per_week, class_count, validity_days, tax_rate_id,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7, $8, $9,
+                $10, $11, $12, $13,
+                $14, $15
+            )"#,
+            plan.id,
+            plan.organization_id,
+            plan.name,
+            plan.description,
+            plan.kind,
+            plan.price_amount,
+            plan.currency,
+            plan.billing_interval,
+            plan.trial_days,
+            plan.classes_per_week,
+            plan.class_count,
+            plan.validity_days,
+            plan.tax_rate_id,
+            plan.inserted_at,
+            plan.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::PlanCreated,
+            &plan,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::PlanCreated,
+            plan.id,
+            None,
+            Some(&plan),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(plan)
+    }
+
+    async fn update_plan(
+        &self,
+        id: Uuid,
+        update_plan: UpdatePlan,
+        actor: &Actor,
+    ) -> Result<PlanDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_plan(&mut tx, id).await?;
+        let before = current.clone();
+        let plan = PlanDTO {
+            name: update_plan.name.unwrap_or(current.name),
+            description: update_plan.description.unwrap_or(current.description),
+            price_amount: update_plan.price_amount.unwrap_or(current.price_amount),
+            trial_days: update_plan.trial_days.unwrap_or(current.trial_days),
+            classes_per_week: update_plan.classes_per_week.or(current.classes_per_week),
+            class_count: update_plan.class_count.or(current.class_count),
+            validity_days: update_plan.validity_days.or(current.validity_days),
+            tax_rate_id: update_plan.tax_rate_id.unwrap_or(current.tax_rate_id),
+            updated_at: time::OffsetDateTime::now_utc(),
+            ..current
+        };
+        plan.validate()?;
+        check_tax_rate(&mut tx, &plan).await?;
+
+        sqlx::query!(
+            r#"update plans set
+                name = $1, description = $2, price_amount = $3, trial_days = $4,
+                classes_per_week = $5, class_count = $6, validity_days = $7, tax_rate_id = $8,
+                updated_at = $9
+            where id = $10"#,
+            plan.name,
+            plan.description,
+            plan.price_amount,
+            plan.trial_days,
+            plan.classes_per_week,
+            plan.class_count,
+            plan.validity_days,
+            plan.tax_rate_id,
+            plan.updated_at,
+            plan.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(plan.organization_id),
+            EventType::PlanUpdated,
+            &plan,
+            plan.updated_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            plan.organization_id,
+            AuditAction::PlanUpdated,
+            plan.id,
+            Some(&before),
+            Some(&plan),
+            plan.updated_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(plan)
+    }
+
+    async fn archive_plan(&self, id: Uuid, actor: &Actor) -> Result<PlanDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_plan(&mut tx, id).await?;
+        let before = current.clone();
+        let now = time::OffsetDateTime::now_utc();
+        let plan = PlanDTO {
+            archived_at: current.archived_at.or(Some(now)),
+            updated_at: now,
+            ..current
+        };
+
+        sqlx::query!(
+            "update plans set archived_at = $1, updated_at = $2 where id = $3",
+            plan.archived_at,
+            plan.updated_at,
+            plan.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(plan.organization_id),
+            EventType::PlanArchived,
+            &plan,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            plan.organization_id,
+            AuditAction::PlanArchived,
+            plan.id,
+            Some(&before),
+            Some(&plan),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(plan)
+    }
+
+    async fn list_plans(&self, organization_id: Uuid) -> Result<Vec<PlanDTO>> {
+        let plans = sqlx::query_as!(
+            PlanDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                name, description, kind as "kind: PlanKind",
+                price_amount, currency, billing_interval as "billing_interval: BillingInterval",
+                trial_days, classes_per_week, class_count, validity_days,
+                tax_rate_id as "tax_rate_id: Uuid",
+                archived_at as "archived_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from plans
+            where organization_id = $1 and archived_at is null
+            order by name"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(plans)
+    }
+
+    async fn get_plan(&self, id: Uuid) -> Result<PlanDTO> {
+        get_plan(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+async fn check_tax_rate(conn: &mut SqliteConnection, plan: &PlanDTO) -> Result<()> {
+    let Some(tax_rate_id) = plan.tax_rate_id else {
+        return Ok(());
+    };
+
+    let belongs_to_organization = match tax_rate::get_tax_rate(conn, tax_rate_id).await {
+        Ok(tax_rate) => tax_rate.organization_id == plan.organization_id,
+        Err(Error::NotFound) => false,
+        Err(e) => return Err(e),
+    };
+
+    if !belongs_to_organization {
+        return Err(Error::unprocessable_entity([(
+            "tax_rate_id",
+            "does not match a tax rate of this organization",
+        )]));
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn get_plan(conn: &mut SqliteConnection, id: Uuid) -> Result<PlanDTO> {
+    sqlx::query_as!(
+        PlanDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            name, description, kind as "kind: PlanKind",
+            price_amount, currency, billing_interval as "billing_interval: BillingInterval",
+            trial_days, classes_per_week, class_count, validity_days,
+            tax_rate_id as "tax_rate_id: Uuid",
+            archived_at as "archived_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from plans
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
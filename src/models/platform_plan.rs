@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::plan::BillingInterval;
+
+/// Parts of Rustfit only some platform plans include.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlatformFeature {
+    PromoCodes,
+    Freezes,
+}
+
+impl PlatformFeature {
+    fn describe(self) -> &'static str {
+        match self {
+            PlatformFeature::PromoCodes => "promo codes",
+            PlatformFeature::Freezes => "membership freezes",
+        }
+    }
+}
+
+/// What a platform plan caps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlatformLimit {
+    /// Members on the organization's roster, staff and owners aren't counted.
+    ActiveMembers,
+    Locations,
+}
+
+impl PlatformLimit {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            PlatformLimit::ActiveMembers => "active_members",
+            PlatformLimit::Locations => "locations",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            PlatformLimit::ActiveMembers => "active members",
+            PlatformLimit::Locations => "locations",
+        }
+    }
+}
+
+/// A tier of Rustfit that studios subscribe to. The tiers are set up by migrations.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PlatformPlanDTO {
+    pub id: Uuid,
+    /// A stable name for the tier, e.g. `starter`.
+    pub code: String,
+    pub name: String,
+    /// Tiers are ordered from the smallest up.
+    pub position: i64,
+    /// In minor units of `currency`.
+    pub price_amount: i64,
+    pub currency: String,
+    pub billing_interval: BillingInterval,
+    /// Unlimited if `None`.
+    pub max_active_members: Option<i64>,
+    /// Unlimited if `None`.
+    pub max_locations: Option<i64>,
+    pub features: Json<Vec<PlatformFeature>>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl PlatformPlanDTO {
+    pub(crate) fn max(&self, limit: PlatformLimit) -> Option<i64> {
+        match limit {
+            PlatformLimit::ActiveMembers => self.max_active_members,
+            PlatformLimit::Locations => self.max_locations,
+        }
+    }
+
+    /// Whether the plan has room for `count` of what `limit` caps.
+    pub(crate) fn allows(&self, limit: PlatformLimit, count: i64) -> bool {
+        self.max(limit).is_none_or(|max| count <= max)
+    }
+
+    pub(crate) fn includes(&self, feature: PlatformFeature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+#[derive(Clone)]
+pub struct PlatformPlanController {
+    pool: SqlitePool,
+}
+
+impl PlatformPlanController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPlatformPlanCtrl = Arc<dyn PlatformPlanCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PlatformPlanCtrlTrait {
+    async fn list_platform_plans(&self) -> Result<Vec<PlatformPlanDTO>>;
+    async fn get_platform_plan(&self, id: Uuid) -> Result<PlatformPlanDTO>;
+}
+
+#[async_trait]
+impl PlatformPlanCtrlTrait for PlatformPlanController {
+    async fn list_platform_plans(&self) -> Result<Vec<PlatformPlanDTO>> {
+        list_platform_plans(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn get_platform_plan(&self, id: Uuid) -> Result<PlatformPlanDTO> {
+        get_platform_plan(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+pub(crate) async fn list_platform_plans(
+    conn: &mut SqliteConnection,
+) -> Result<Vec<PlatformPlanDTO>> {
+    let platform_plans = sqlx::query_as!(
+        PlatformPlanDTO,
+        r#"select
+            id as "id: Uuid", code, name, position, price_amount, currency,
+            billing_interval as "billing_interval: BillingInterval",
+            max_active_members, max_locations,
+            features as "features: Json<Vec<PlatformFeature>>",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from platform_plans
+        order by position"#
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(platform_plans)
+}
+
+pub(crate) async fn get_platform_plan(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+) -> Result<PlatformPlanDTO> {
+    sqlx::query_as!(
+        PlatformPlanDTO,
+        r#"select
+            id as "id: Uuid", code, name, position, price_amount, currency,
+            billing_interval as "billing_interval: BillingInterval",
+            max_active_members, max_locations,
+            features as "features: Json<Vec<PlatformFeature>>",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from platform_plans
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Refuses going over the plan's `limit`, pointing at the smallest tier with room.
+pub(crate) async fn check_limit(
+    conn: &mut SqliteConnection,
+    platform_plan: &PlatformPlanDTO,
+    limit: PlatformLimit,
+    count: i64,
+) -> Result<()> {
+    if platform_plan.allows(limit, count) {
+        return Ok(());
+    }
+
+    let upgrade = list_platform_plans(conn)
+        .await?
+        .into_iter()
+        .find(|p| p.position > platform_plan.position && p.allows(limit, count));
+    let upgrade_hint = match upgrade {
+        Some(upgrade) => match upgrade.max(limit) {
+            Some(max) => format!(
+                "{} allows up to {} {}, upgrade to {} for up to {max}",
+                platform_plan.name,
+                platform_plan.max(limit).unwrap_or_default(),
+                limit.describe(),
+                upgrade.name
+            ),
+            None => format!(
+                "{} allows up to {} {}, upgrade to {} for unlimited {}",
+                platform_plan.name,
+                platform_plan.max(limit).unwrap_or_default(),
+                limit.describe(),
+                upgrade.name,
+                limit.describe()
+            ),
+        },
+        None => format!(
+            "{} is Rustfit's largest plan, contact us to raise the limit of {}",
+            platform_plan.name,
+            limit.describe()
+        ),
+    };
+
+    Err(Error::Forbidden {
+        upgrade_hint: Some(upgrade_hint.into()),
+    })
+}
+
+/// Refuses using a feature the plan doesn't include, pointing at the smallest tier that does.
+pub(crate) async fn check_feature(
+    conn: &mut SqliteConnection,
+    platform_plan: &PlatformPlanDTO,
+    feature: PlatformFeature,
+) -> Result<()> {
+    if platform_plan.includes(feature) {
+        return Ok(());
+    }
+
+    let upgrade = list_platform_plans(conn)
+        .await?
+        .into_iter()
+        .find(|p| p.position > platform_plan.position && p.includes(feature));
+    let upgrade_hint = match upgrade {
+        Some(upgrade) => format!(
+            "{} doesn't include {}, upgrade to {} to use them",
+            platform_plan.name,
+            feature.describe(),
+            upgrade.name
+        ),
+        None => format!("{} aren't available yet", feature.describe()),
+    };
+
+    Err(Error::Forbidden {
+        upgrade_hint: Some(upgrade_hint.into()),
+    })
+}
@@ -0,0 +1,446 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::membership::Role;
+use super::platform_plan::{self, PlatformFeature, PlatformLimit, PlatformPlanDTO};
+
+/// Moves the organization to another tier. Upgrades and downgrades take effect straight
+/// away, a downgrade only if the organization fits the smaller tier.
+#[derive(serde::Deserialize)]
+pub struct ChangePlatformPlan {
+    pub platform_plan_id: Uuid,
+}
+
+/// An organization's subscription to Rustfit. Every organization has one, starting on the
+/// smallest tier.
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PlatformSubscriptionDTO {
+    pub organization_id: Uuid,
+    pub platform_plan_id: Uuid,
+    pub current_period_start: OffsetDateTime,
+    pub current_period_end: OffsetDateTime,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// What an organization counts towards its tier's limits right now.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PlatformUsage {
+    pub active_members: i64,
+    pub locations: i64,
+}
+
+impl PlatformUsage {
+    fn count(&self, limit: PlatformLimit) -> i64 {
+        match limit {
+            PlatformLimit::ActiveMembers => self.active_members,
+            PlatformLimit::Locations => self.locations,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct PlatformSubscriptionWithUsageDTO {
+    #[serde(flatten)]
+    pub platform_subscription: PlatformSubscriptionDTO,
+    pub platform_plan: PlatformPlanDTO,
+    pub usage: PlatformUsage,
+}
+
+/// The most an organization used in one of its billing periods, which Rustfit bills from.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PlatformUsageDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    /// The tier the organization was on when the usage was last metered.
+    pub platform_plan_id: Uuid,
+    pub period_start: OffsetDateTime,
+    pub period_end: OffsetDateTime,
+    pub active_members: i64,
+    pub locations: i64,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct PlatformSubscriptionController {
+    pool: SqlitePool,
+}
+
+impl PlatformSubscriptionController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPlatformSubscriptionCtrl = Arc<dyn PlatformSubscriptionCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PlatformSubscriptionCtrlTrait {
+    async fn get_platform_subscription(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<PlatformSubscriptionWithUsageDTO>;
+
+    async fn change_platform_plan(
+        &self,
+        organization_id: Uuid,
+        change: ChangePlatformPlan,
+        actor: &Actor,
+    ) -> Result<PlatformSubscriptionWithUsageDTO>;
+
+    /// The organization's metered usage, latest period first.
+    async fn list_platform_usage(&self, organization_id: Uuid) -> Result<Vec<PlatformUsageDTO>>;
+
+    /// Starts the next billing period of every platform subscription whose period ended, and
+    /// records each organization's usage in its current period, keeping the highest seen.
+    /// Returns how many organizations were metered.
+    async fn meter_usage(&self, now: OffsetDateTime) -> Result<u64>;
+}
+
+#[async_trait]
+impl PlatformSubscriptionCtrlTrait for PlatformSubscriptionController {
+    async fn get_platform_subscription(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<PlatformSubscriptionWithUsageDTO> {
+        let mut conn = self.pool.acquire().await?;
+
+        let platform_subscription = get_platform_subscription(&mut conn, organization_id).await?;
+        let platform_plan =
+            platform_plan::get_platform_plan(&mut conn, platform_subscription.platform_plan_id)
+                .await?;
+        let usage = current_usage(&mut conn, organization_id).await?;
+
+        Ok(PlatformSubscriptionWithUsageDTO {
+            platform_subscription,
+            platform_plan,
+            usage,
+        })
+    }
+
+    async fn change_platform_plan(
+        &self,
+        organization_id: Uuid,
+        change: ChangePlatformPlan,
+        actor: &Actor,
+    ) -> Result<PlatformSubscriptionWithUsageDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_platform_subscription(&mut tx, organization_id).await?;
+        let before = current.clone();
+        let platform_plan =
+            match platform_plan::get_platform_plan(&mut tx, change.platform_plan_id).await {
+                Ok(platform_plan) => platform_plan,
+                Err(Error::NotFound) => {
+                    return Err(Error::unprocessable_entity([(
+                        "platform_plan_id",
+                        "does not match a platform plan",
+                    )]))
+                }
+                Err(e) => return Err(e),
+            };
+
+        let usage = current_usage(&mut tx, organization_id).await?;
+        for limit in [PlatformLimit::ActiveMembers, PlatformLimit::Locations] {
+            let count = usage.count(limit);
+            if !platform_plan.allows(limit, count) {
+                return Err(Error::unprocessable_entity([(
+                    "platform_plan_id",
+                    format!(
+                        "allows up to {} {}, the organization has {count}",
+                        platform_plan.max(limit).unwrap_or_default(),
+                        limit.as_str()
+                    ),
+                )]));
+            }
+        }
+
+        let platform_subscription = PlatformSubscriptionDTO {
+            platform_plan_id: platform_plan.id,
+            updated_at: now,
+            ..current
+        };
+        save(&mut tx, &platform_subscription).await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::PlatformSubscriptionChanged,
+            &platform_subscription,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::PlatformPlanChanged,
+            organization_id,
+            Some(&before),
+            Some(&platform_subscription),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(PlatformSubscriptionWithUsageDTO {
+            platform_subscription,
+            platform_plan,
+            usage,
+        })
+    }
+
+    async fn list_platform_usage(&self, organization_id: Uuid) -> Result<Vec<PlatformUsageDTO>> {
+        let platform_usage = sqlx::query_as!(
+            PlatformUsageDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                platform_plan_id as "platform_plan_id: Uuid",
+                period_start as "period_start: OffsetDateTime",
+                period_end as "period_end: OffsetDateTime",
+                active_members, locations,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from platform_usage
+            where organization_id = $1
+            order by period_start desc"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(platform_usage)
+    }
+
+    async fn meter_usage(&self, now: OffsetDateTime) -> Result<u64> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let platform_subscriptions = sqlx::query_as!(
+            PlatformSubscriptionDTO,
+            r#"select
+                organization_id as "organization_id: Uuid",
+                platform_plan_id as "platform_plan_id: Uuid",
+                current_period_start as "current_period_start: OffsetDateTime",
+                current_period_end as "current_period_end: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from platform_subscriptions"#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut metered = 0;
+        for platform_subscription in platform_subscriptions {
+            let platform_subscription = roll_over(&mut tx, platform_subscription, now).await?;
+            let usage = current_usage(&mut tx, platform_subscription.organization_id).await?;
+            let id = uuid::Uuid::new_v4();
+
+            sqlx::query!(
+                r#"insert into "platform_usage" (
+                    id, organization_id, platform_plan_id, period_start, period_end,
+                    active_members, locations, inserted_at, updated_at
+                ) VALUES (
+                    $1, $2, $3, $4, $5,
+                    $6, $7, $8, $9
+                ) on conflict (organization_id, period_start) do update set
+                    platform_plan_id = excluded.platform_plan_id,
+                    active_members = max(active_members, excluded.active_members),
+                    locations = max(locations, excluded.locations),
+                    updated_at = excluded.updated_at"#,
+                id,
+                platform_subscription.organization_id,
+                platform_subscription.platform_plan_id,
+                platform_subscription.current_period_start,
+                platform_subscription.current_period_end,
+                usage.active_members,
+                usage.locations,
+                now,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            metered += 1;
+        }
+
+        tx.commit().await?;
+
+        Ok(metered)
+    }
+}
+
+/// Puts a new organization on the smallest tier.
+pub(crate) async fn start_platform_subscription(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<PlatformSubscriptionDTO> {
+    let platform_plan = platform_plan::list_platform_plans(&mut *conn)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no platform plans are set up"))?;
+
+    let platform_subscription = PlatformSubscriptionDTO {
+        organization_id,
+        platform_plan_id: platform_plan.id,
+        current_period_start: now,
+        current_period_end: platform_plan.billing_interval.periods_after(now, 1),
+        inserted_at: now,
+        updated_at: now,
+    };
+
+    sqlx::query!(
+        r#"insert into "platform_subscriptions" (
+            organization_id, platform_plan_id, current_period_start, current_period_end,
+            inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4,
+            $5, $6
+        )"#,
+        platform_subscription.organization_id,
+        platform_subscription.platform_plan_id,
+        platform_subscription.current_period_start,
+        platform_subscription.current_period_end,
+        platform_subscription.inserted_at,
+        platform_subscription.updated_at
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(platform_subscription)
+}
+
+pub(crate) async fn get_platform_subscription(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+) -> Result<PlatformSubscriptionDTO> {
+    sqlx::query_as!(
+        PlatformSubscriptionDTO,
+        r#"select
+            organization_id as "organization_id: Uuid",
+            platform_plan_id as "platform_plan_id: Uuid",
+            current_period_start as "current_period_start: OffsetDateTime",
+            current_period_end as "current_period_end: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from platform_subscriptions
+        where organization_id = $1"#,
+        organization_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+pub(crate) async fn current_usage(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+) -> Result<PlatformUsage> {
+    let usage = sqlx::query!(
+        r#"select
+            (select count(*) from memberships where organization_id = $1 and role = $2)
+                as "active_members!: i64",
+            (select count(*) from locations where organization_id = $1) as "locations!: i64""#,
+        organization_id,
+        Role::Member
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(PlatformUsage {
+        active_members: usage.active_members,
+        locations: usage.locations,
+    })
+}
+
+/// Refuses adding one more of what `limit` caps when the organization's tier is full,
+/// with `Error::Forbidden` saying which tier to upgrade to.
+pub(crate) async fn require_capacity(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    limit: PlatformLimit,
+) -> Result<()> {
+    let platform_subscription = get_platform_subscription(&mut *conn, organization_id).await?;
+    let platform_plan =
+        platform_plan::get_platform_plan(&mut *conn, platform_subscription.platform_plan_id)
+            .await?;
+    let usage = current_usage(&mut *conn, organization_id).await?;
+
+    platform_plan::check_limit(conn, &platform_plan, limit, usage.count(limit) + 1).await
+}
+
+/// Refuses using `feature` unless the organization's tier includes it, with
+/// `Error::Forbidden` saying which tier to upgrade to.
+pub(crate) async fn require_feature(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    feature: PlatformFeature,
+) -> Result<()> {
+    let platform_subscription = get_platform_subscription(&mut *conn, organization_id).await?;
+    let platform_plan =
+        platform_plan::get_platform_plan(&mut *conn, platform_subscription.platform_plan_id)
+            .await?;
+
+    platform_plan::check_feature(conn, &platform_plan, feature).await
+}
+
+async fn roll_over(
+    conn: &mut SqliteConnection,
+    platform_subscription: PlatformSubscriptionDTO,
+    now: OffsetDateTime,
+) -> Result<PlatformSubscriptionDTO> {
+    if now < platform_subscription.current_period_end {
+        return Ok(platform_subscription);
+    }
+
+    let platform_plan =
+        platform_plan::get_platform_plan(&mut *conn, platform_subscription.platform_plan_id)
+            .await?;
+    let mut current_period_start = platform_subscription.current_period_start;
+    let mut current_period_end = platform_subscription.current_period_end;
+    while current_period_end <= now {
+        current_period_start = current_period_end;
+        current_period_end = platform_plan
+            .billing_interval
+            .periods_after(current_period_start, 1);
+    }
+
+    let platform_subscription = PlatformSubscriptionDTO {
+        current_period_start,
+        current_period_end,
+        updated_at: now,
+        ..platform_subscription
+    };
+    save(conn, &platform_subscription).await?;
+
+    Ok(platform_subscription)
+}
+
+async fn save(
+    conn: &mut SqliteConnection,
+    platform_subscription: &PlatformSubscriptionDTO,
+) -> Result<()> {
+    sqlx::query!(
+        r#"update platform_subscriptions set
+            platform_plan_id = $1, current_period_start = $2, current_period_end = $3,
+            updated_at = $4
+        where organization_id = $5"#,
+        platform_subscription.platform_plan_id,
+        platform_subscription.current_period_start,
+        platform_subscription.current_period_end,
+        platform_subscription.updated_at,
+        platform_subscription.organization_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
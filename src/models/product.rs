@@ -0,0 +1,503 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::organization::{self, is_currency_code};
+use super::tax_rate;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ProductKind {
+    /// Goods sold over the counter, e.g. water or towels.
+    Retail,
+    /// A pass for a single class, sold as one class credit usable for `validity_days`.
+    DropIn,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewProduct {
+    pub name: String,
+    pub kind: ProductKind,
+    /// In minor units of `currency`, e.g. cents.
+    pub price_amount: i64,
+    /// Leave out to use the organization's currency.
+    pub currency: Option<String>,
+    pub tax_rate_id: Option<Uuid>,
+    /// Items in stock, leave out for products whose stock isn't tracked.
+    pub stock_count: Option<i64>,
+    pub validity_days: Option<i64>,
+}
+
+/// Fields left out keep their current value. The kind and currency can't change once the
+/// product may have been sold, and stock only changes through sales and adjustments.
+#[derive(serde::Deserialize)]
+pub struct UpdateProduct {
+    pub name: Option<String>,
+    pub price_amount: Option<i64>,
+    pub validity_days: Option<i64>,
+    /// `null` stops charging tax on the product.
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub tax_rate_id: Option<Option<Uuid>>,
+}
+
+/// Counts items into or out of stock outside of sales, e.g. a delivery or a broken bottle.
+#[derive(serde::Deserialize)]
+pub struct StockAdjustment {
+    /// Negative to take items out of stock.
+    pub quantity: i64,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ProductDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub kind: ProductKind,
+    pub price_amount: i64,
+    pub currency: String,
+    /// Charged on top of `price_amount` when sold.
+    pub tax_rate_id: Option<Uuid>,
+    /// `None` if stock isn't tracked and the product never sells out.
+    pub stock_count: Option<i64>,
+    pub validity_days: Option<i64>,
+    /// Archived products can't be sold anymore but stay around for past sales.
+    pub archived_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl ProductDTO {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(("name", "can't be blank"));
+        }
+        if self.price_amount < 0 {
+            errors.push(("price_amount", "must not be negative"));
+        }
+        if !is_currency_code(&self.currency) {
+            errors.push(("currency", "must be a three letter ISO 4217 code"));
+        }
+        if self.stock_count.is_some_and(|count| count < 0) {
+            errors.push(("stock_count", "must not be negative"));
+        }
+
+        match self.kind {
+            ProductKind::Retail => {
+                if self.validity_days.is_some() {
+                    errors.push(("validity_days", "is only used by drop-in passes"));
+                }
+            }
+            ProductKind::DropIn => {
+                if !matches!(self.validity_days, Some(days) if days >= 1) {
+                    errors.push(("validity_days", "must be at least 1"));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProductController {
+    pool: SqlitePool,
+}
+
+impl ProductController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynProductCtrl = Arc<dyn ProductCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait ProductCtrlTrait {
+    async fn create_product(
+        &self,
+        organization_id: Uuid,
+        new_product: NewProduct,
+        actor: &Actor,
+    ) -> Result<ProductDTO>;
+    async fn update_product(
+        &self,
+        id: Uuid,
+        update_product: UpdateProduct,
+        actor: &Actor,
+    ) -> Result<ProductDTO>;
+    /// Takes the product off sale, it is kept for the sales made of it.
+    async fn archive_product(&self, id: Uuid, actor: &Actor) -> Result<ProductDTO>;
+    /// Adds to or takes from the product's stock, which can't go below zero.
+    async fn adjust_stock(
+        &self,
+        id: Uuid,
+        adjustment: StockAdjustment,
+        actor: &Actor,
+    ) -> Result<ProductDTO>;
+    /// Lists the products on sale.
+    async fn list_products(&self, organization_id: Uuid) -> Result<Vec<ProductDTO>>;
+    async fn get_product(&self, id: Uuid) -> Result<ProductDTO>;
+}
+
+#[async_trait]
+impl ProductCtrlTrait for ProductController {
+    async fn create_product(
+        &self,
+        organization_id: Uuid,
+        new_product: NewProduct,
+        actor: &Actor,
+    ) -> Result<ProductDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let organization = organization::get_organization(&mut tx, organization_id).await?;
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let product = ProductDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            name: new_product.name,
+            kind: new_product.kind,
+            price_amount: new_product.price_amount,
+            currency: new_product.currency.unwrap_or(organization.currency),
+            tax_rate_id: new_product.tax_rate_id,
+            stock_count: new_product.stock_count,
+            validity_days: new_product.validity_days,
+            archived_at: None,
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        product.validate()?;
+        check_tax_rate(&mut tx, &product).await?;
+
+        sqlx::query!(
+            r#"insert into "products" (
+                id, organization_id, name, kind,
+                price_amount, currency, tax_rate_id, stock_count, validity_days,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4,
+                $5, $6, $7, $8, $9,
+                $10, $11
+            )"#,
+            product.id,
+            product.organization_id,
+            product.name,
+            product.kind,
+            product.price_amount,
+            product.currency,
+            product.tax_rate_id,
+            product.stock_count,
+            product.validity_days,
+            product.inserted_at,
+            product.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::ProductCreated,
+            &product,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::ProductCreated,
+            product.id,
+            None,
+            Some(&product),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(product)
+    }
+
+    async fn update_product(
+        &self,
+        id: Uuid,
+        update_product: UpdateProduct,
+        actor: &Actor,
+    ) -> Result<ProductDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_product(&mut tx, id).await?;
+        let before = current.clone();
+        let product = ProductDTO {
+            name: update_product.name.unwrap_or(current.name),
+            price_amount: update_product.price_amount.unwrap_or(current.price_amount),
+            validity_days: update_product.validity_days.or(current.validity_days),
+            tax_rate_id: update_product.tax_rate_id.unwrap_or(current.tax_rate_id),
+            updated_at: time::OffsetDateTime::now_utc(),
+            ..current
+        };
+        product.validate()?;
+        check_tax_rate(&mut tx, &product).await?;
+
+        sqlx::query!(
+            r#"update products set
+                name = $1, price_amount = $2, validity_days = $3, tax_rate_id = $4,
+                updated_at = $5
+            where id = $6"#,
+            product.name,
+            product.price_amount,
+            product.validity_days,
+            product.tax_rate_id,
+            product.updated_at,
+            product.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(product.organization_id),
+            EventType::ProductUpdated,
+            &product,
+            product.updated_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            product.organization_id,
+            AuditAction::ProductUpdated,
+            product.id,
+            Some(&before),
+            Some(&product),
+            product.updated_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(product)
+    }
+
+    async fn archive_product(&self, id: Uuid, actor: &Actor) -> Result<ProductDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_product(&mut tx, id).await?;
+        let before = current.clone();
+        let now = time::OffsetDateTime::now_utc();
+        let product = ProductDTO {
+            archived_at: current.archived_at.or(Some(now)),
+            updated_at: now,
+            ..current
+        };
+
+        sqlx::query!(
+            "update products set archived_at = $1, updated_at = $2 where id = $3",
+            product.archived_at,
+            product.updated_at,
+            product.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(product.organization_id),
+            EventType::ProductArchived,
+            &product,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            product.organization_id,
+            AuditAction::ProductArchived,
+            product.id,
+            Some(&before),
+            Some(&product),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(product)
+    }
+
+    async fn adjust_stock(
+        &self,
+        id: Uuid,
+        adjustment: StockAdjustment,
+        actor: &Actor,
+    ) -> Result<ProductDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_product(&mut tx, id).await?;
+        if before.stock_count.is_none() {
+            return Err(Error::unprocessable_entity([(
+                "quantity",
+                "can't be counted for a product whose stock isn't tracked",
+            )]));
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        if adjustment.quantity < 0 {
+            take_stock(&mut tx, &before, -adjustment.quantity, now).await?;
+        } else {
+            put_back_stock(&mut tx, before.id, adjustment.quantity, now).await?;
+        }
+        let product = get_product(&mut tx, id).await?;
+
+        event::record(
+            &mut tx,
+            Some(product.organization_id),
+            EventType::ProductStockAdjusted,
+            &product,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            product.organization_id,
+            AuditAction::ProductStockAdjusted,
+            product.id,
+            Some(&before),
+            Some(&product),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(product)
+    }
+
+    async fn list_products(&self, organization_id: Uuid) -> Result<Vec<ProductDTO>> {
+        let products = sqlx::query_as!(
+            ProductDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                name, kind as "kind: ProductKind", price_amount, currency,
+                tax_rate_id as "tax_rate_id: Uuid", stock_count, validity_days,
+                archived_at as "archived_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from products
+            where organization_id = $1 and archived_at is null
+            order by name"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(products)
+    }
+
+    async fn get_product(&self, id: Uuid) -> Result<ProductDTO> {
+        get_product(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+async fn check_tax_rate(conn: &mut SqliteConnection, product: &ProductDTO) -> Result<()> {
+    let Some(tax_rate_id) = product.tax_rate_id else {
+        return Ok(());
+    };
+
+    let belongs_to_organization = match tax_rate::get_tax_rate(conn, tax_rate_id).await {
+        Ok(tax_rate) => tax_rate.organization_id == product.organization_id,
+        Err(Error::NotFound) => false,
+        Err(e) => return Err(e),
+    };
+
+    if !belongs_to_organization {
+        return Err(Error::unprocessable_entity([(
+            "tax_rate_id",
+            "does not match a tax rate of this organization",
+        )]));
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn get_product(conn: &mut SqliteConnection, id: Uuid) -> Result<ProductDTO> {
+    sqlx::query_as!(
+        ProductDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            name, kind as "kind: ProductKind", price_amount, currency,
+            tax_rate_id as "tax_rate_id: Uuid", stock_count, validity_days,
+            archived_at as "archived_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from products
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Takes `quantity` items out of stock. The check and the decrement are one statement, so
+/// two sales of the last item can't both succeed. Products whose stock isn't tracked are
+/// left alone.
+pub(crate) async fn take_stock(
+    conn: &mut SqliteConnection,
+    product: &ProductDTO,
+    quantity: i64,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let taken = sqlx::query!(
+        r#"update products set stock_count = stock_count - $1, updated_at = $2
+        where id = $3 and (stock_count is null or stock_count >= $1)"#,
+        quantity,
+        now,
+        product.id
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+
+    if taken == 0 {
+        return Err(Error::unprocessable_entity([(
+            "quantity",
+            format!("is more than is left of {} in stock", product.name),
+        )]));
+    }
+
+    Ok(())
+}
+
+/// Puts `quantity` items back in stock, e.g. when a sale is voided.
+pub(crate) async fn put_back_stock(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+    quantity: i64,
+    now: OffsetDateTime,
+) -> Result<()> {
+    sqlx::query!(
+        r#"update products set stock_count = stock_count + $1, updated_at = $2
+        where id = $3 and stock_count is not null"#,
+        quantity,
+        now,
+        id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
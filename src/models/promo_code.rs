@@ -0,0 +1,523 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::organization::{self, is_currency_code};
+use super::plan::{self, PlanDTO};
+use super::platform_plan::PlatformFeature;
+use super::platform_subscription;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PromoCodeKind {
+    /// Takes `percent_off` percent off the price.
+    Percent,
+    /// Takes `amount_off` off the price, never below zero.
+    Fixed,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewPromoCode {
+    pub code: String,
+    pub kind: PromoCodeKind,
+    pub percent_off: Option<i64>,
+    pub amount_off: Option<i64>,
+    /// Defaults to the organization's currency for fixed discounts.
+    pub currency: Option<String>,
+    pub duration_periods: Option<i64>,
+    pub starts_at: Option<OffsetDateTime>,
+    pub ends_at: Option<OffsetDateTime>,
+    pub max_redemptions: Option<i64>,
+    /// Defaults to once per member.
+    #[serde(default = "default_max_redemptions_per_member")]
+    pub max_redemptions_per_member: Option<i64>,
+    #[serde(default)]
+    pub plan_ids: Vec<Uuid>,
+}
+
+fn default_max_redemptions_per_member() -> Option<i64> {
+    Some(1)
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct PromoCodeDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    /// What members type in, stored in upper case and matched ignoring case.
+    pub code: String,
+    pub kind: PromoCodeKind,
+    pub percent_off: Option<i64>,
+    /// In minor units of `currency`.
+    pub amount_off: Option<i64>,
+    pub currency: Option<String>,
+    /// How many billing periods of a membership are discounted, all of them if `None`.
+    /// Class packs are discounted once.
+    pub duration_periods: Option<i64>,
+    pub starts_at: Option<OffsetDateTime>,
+    pub ends_at: Option<OffsetDateTime>,
+    /// How many times the code can be used in total, without limit if `None`.
+    pub max_redemptions: Option<i64>,
+    pub max_redemptions_per_member: Option<i64>,
+    /// The plans the code can be used on, any if empty.
+    pub plan_ids: Json<Vec<Uuid>>,
+    pub archived_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl PromoCodeDTO {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        let code_ok = !self.code.is_empty()
+            && self.code.len() <= 32
+            && self
+                .code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !code_ok {
+            errors.push((
+                "code",
+                "must be up to 32 letters, digits, dashes or underscores",
+            ));
+        }
+
+        match self.kind {
+            PromoCodeKind::Percent => {
+                if !matches!(self.percent_off, Some(percent) if (1..=100).contains(&percent)) {
+                    errors.push(("percent_off", "must be between 1 and 100"));
+                }
+                if self.amount_off.is_some() {
+                    errors.push(("amount_off", "is only used by fixed discounts"));
+                }
+            }
+            PromoCodeKind::Fixed => {
+                if !matches!(self.amount_off, Some(amount) if amount >= 1) {
+                    errors.push(("amount_off", "must be at least 1"));
+                }
+                if !self.currency.as_deref().is_some_and(is_currency_code) {
+                    errors.push(("currency", "must be a three letter ISO 4217 code"));
+                }
+                if self.percent_off.is_some() {
+                    errors.push(("percent_off", "is only used by percent discounts"));
+                }
+            }
+        }
+
+        if matches!(self.duration_periods, Some(periods) if periods < 1) {
+            errors.push(("duration_periods", "must be at least 1"));
+        }
+        if matches!((self.starts_at, self.ends_at), (Some(starts_at), Some(ends_at)) if ends_at <= starts_at)
+        {
+            errors.push(("ends_at", "must be after starts_at"));
+        }
+        if matches!(self.max_redemptions, Some(max) if max < 1) {
+            errors.push(("max_redemptions", "must be at least 1"));
+        }
+        if matches!(self.max_redemptions_per_member, Some(max) if max < 1) {
+            errors.push(("max_redemptions_per_member", "must be at least 1"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+
+    /// How much the code takes off `amount`, rounding percentages half up.
+    pub(crate) fn discount_on(&self, amount: i64) -> i64 {
+        let discount = match self.kind {
+            PromoCodeKind::Percent => (amount * self.percent_off.unwrap_or(0) + 50) / 100,
+            PromoCodeKind::Fixed => self.amount_off.unwrap_or(0),
+        };
+
+        discount.clamp(0, amount)
+    }
+
+    /// Whether the code still discounts the `period`th billing period of a membership.
+    pub(crate) fn applies_to_period(&self, period: i64) -> bool {
+        self.duration_periods
+            .is_none_or(|periods| period <= periods)
+    }
+}
+
+#[derive(Clone)]
+pub struct PromoCodeController {
+    pool: SqlitePool,
+}
+
+impl PromoCodeController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynPromoCodeCtrl = Arc<dyn PromoCodeCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait PromoCodeCtrlTrait {
+    async fn create_promo_code(
+        &self,
+        organization_id: Uuid,
+        new_promo_code: NewPromoCode,
+        actor: &Actor,
+    ) -> Result<PromoCodeDTO>;
+    /// Stops the code from being used, discounts already given carry on.
+    async fn archive_promo_code(&self, id: Uuid, actor: &Actor) -> Result<PromoCodeDTO>;
+    async fn list_promo_codes(&self, organization_id: Uuid) -> Result<Vec<PromoCodeDTO>>;
+    async fn get_promo_code(&self, id: Uuid) -> Result<PromoCodeDTO>;
+}
+
+#[async_trait]
+impl PromoCodeCtrlTrait for PromoCodeController {
+    async fn create_promo_code(
+        &self,
+        organization_id: Uuid,
+        new_promo_code: NewPromoCode,
+        actor: &Actor,
+    ) -> Result<PromoCodeDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let organization = organization::get_organization(&mut tx, organization_id).await?;
+        platform_subscription::require_feature(
+            &mut tx,
+            organization_id,
+            PlatformFeature::PromoCodes,
+        )
+        .await?;
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let currency = match new_promo_code.kind {
+            PromoCodeKind::Percent => new_promo_code.currency,
+            PromoCodeKind::Fixed => new_promo_code.currency.or(Some(organization.currency)),
+        };
+        let promo_code = PromoCodeDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            code: new_promo_code.code.trim().to_uppercase(),
+            kind: new_promo_code.kind,
+            percent_off: new_promo_code.percent_off,
+            amount_off: new_promo_code.amount_off,
+            currency,
+            duration_periods: new_promo_code.duration_periods,
+            // Stored in UTC like every other time, whatever offset they were given with.
+            starts_at: new_promo_code
+                .starts_at
+                .map(|at| at.to_offset(UtcOffset::UTC)),
+            ends_at: new_promo_code
+                .ends_at
+                .map(|at| at.to_offset(UtcOffset::UTC)),
+            max_redemptions: new_promo_code.max_redemptions,
+            max_redemptions_per_member: new_promo_code.max_redemptions_per_member,
+            plan_ids: Json(new_promo_code.plan_ids),
+            archived_at: None,
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        promo_code.validate()?;
+
+        for plan_id in promo_code.plan_ids.iter() {
+            let plan = match plan::get_plan(&mut tx, *plan_id).await {
+                Ok(plan) => Some(plan),
+                Err(Error::NotFound) => None,
+                Err(e) => return Err(e),
+            };
+            if plan.is_none_or(|p| p.organization_id != organization_id) {
+                return Err(Error::unprocessable_entity([(
+                    "plan_ids",
+                    format!("{plan_id} does not match a plan of this organization"),
+                )]));
+            }
+        }
+
+        let taken = sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from promo_codes
+            where organization_id = $1 and code = $2"#,
+            organization_id,
+            promo_code.code
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if taken > 0 {
+            return Err(Error::unprocessable_entity([("code", "is already in use")]));
+        }
+
+        sqlx::query!(
+            r#"insert into "promo_codes" (
+                id, organization_id, code, kind, percent_off, amount_off, currency,
+                duration_periods, starts_at, ends_at, max_redemptions, max_redemptions_per_member,
+                plan_ids, inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7,
+                $8, $9, $10, $11, $12,
+                $13, $14, $15
+            )"#,
+            promo_code.id,
+            promo_code.organization_id,
+            promo_code.code,
+            promo_code.kind,
+            promo_code.percent_off,
+            promo_code.amount_off,
+            promo_code.currency,
+            promo_code.duration_periods,
+            promo_code.starts_at,
+            promo_code.ends_at,
+            promo_code.max_redemptions,
+            promo_code.max_redemptions_per_member,
+            promo_code.plan_ids,
+            promo_code.inserted_at,
+            promo_code.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::PromoCodeCreated,
+            &promo_code,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::PromoCodeCreated,
+            promo_code.id,
+            None,
+            Some(&promo_code),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(promo_code)
+    }
+
+    async fn archive_promo_code(&self, id: Uuid, actor: &Actor) -> Result<PromoCodeDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_promo_code(&mut tx, id).await?;
+        let before = current.clone();
+        let now = time::OffsetDateTime::now_utc();
+        let promo_code = PromoCodeDTO {
+            archived_at: current.archived_at.or(Some(now)),
+            updated_at: now,
+            ..current
+        };
+
+        sqlx::query!(
+            r#"update promo_codes set archived_at = $1, updated_at = $2 where id = $3"#,
+            promo_code.archived_at,
+            promo_code.updated_at,
+            promo_code.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(promo_code.organization_id),
+            EventType::PromoCodeArchived,
+            &promo_code,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            promo_code.organization_id,
+            AuditAction::PromoCodeArchived,
+            promo_code.id,
+            Some(&before),
+            Some(&promo_code),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(promo_code)
+    }
+
+    async fn list_promo_codes(&self, organization_id: Uuid) -> Result<Vec<PromoCodeDTO>> {
+        let promo_codes = sqlx::query_as!(
+            PromoCodeDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid", code,
+                kind as "kind: PromoCodeKind", percent_off, amount_off, currency,
+                duration_periods, starts_at as "starts_at: OffsetDateTime",
+                ends_at as "ends_at: OffsetDateTime", max_redemptions, max_redemptions_per_member,
+                plan_ids as "plan_ids: Json<Vec<Uuid>>", archived_at as "archived_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from promo_codes
+            where organization_id = $1 and archived_at is null
+            order by code"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(promo_codes)
+    }
+
+    async fn get_promo_code(&self, id: Uuid) -> Result<PromoCodeDTO> {
+        get_promo_code(&mut *self.pool.acquire().await?, id).await
+    }
+}
+
+pub(crate) async fn get_promo_code(conn: &mut SqliteConnection, id: Uuid) -> Result<PromoCodeDTO> {
+    sqlx::query_as!(
+        PromoCodeDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid", code,
+            kind as "kind: PromoCodeKind", percent_off, amount_off, currency,
+            duration_periods, starts_at as "starts_at: OffsetDateTime",
+            ends_at as "ends_at: OffsetDateTime", max_redemptions, max_redemptions_per_member,
+            plan_ids as "plan_ids: Json<Vec<Uuid>>", archived_at as "archived_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from promo_codes
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Finds the code a member typed in at checkout for `plan`, checking they can still use it.
+pub(crate) async fn find_redeemable(
+    conn: &mut SqliteConnection,
+    code: &str,
+    plan: &PlanDTO,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<PromoCodeDTO> {
+    let code = code.trim().to_uppercase();
+    let promo_code_id = sqlx::query_scalar!(
+        r#"select id as "id: Uuid" from promo_codes
+        where organization_id = $1 and code = $2 and archived_at is null"#,
+        plan.organization_id,
+        code
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| Error::unprocessable_entity([("promo_code", "does not exist")]))?;
+    let promo_code = get_promo_code(conn, promo_code_id).await?;
+
+    if promo_code.starts_at.is_some_and(|at| now < at) {
+        return Err(Error::unprocessable_entity([(
+            "promo_code",
+            "can't be used yet",
+        )]));
+    }
+    if promo_code.ends_at.is_some_and(|at| now >= at) {
+        return Err(Error::unprocessable_entity([("promo_code", "has expired")]));
+    }
+    if !promo_code.plan_ids.is_empty() && !promo_code.plan_ids.contains(&plan.id) {
+        return Err(Error::unprocessable_entity([(
+            "promo_code",
+            "can't be used on this plan",
+        )]));
+    }
+    if promo_code.kind == PromoCodeKind::Fixed
+        && promo_code.currency.as_deref() != Some(plan.currency.as_str())
+    {
+        return Err(Error::unprocessable_entity([(
+            "promo_code",
+            "is for a different currency",
+        )]));
+    }
+
+    let redemptions = sqlx::query!(
+        r#"select
+            count(*) as "total!: i64",
+            coalesce(sum(membership_id = $2), 0) as "by_member!: i64"
+        from promo_redemptions
+        where promo_code_id = $1"#,
+        promo_code.id,
+        membership_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if promo_code
+        .max_redemptions
+        .is_some_and(|max| redemptions.total >= max)
+    {
+        return Err(Error::unprocessable_entity([(
+            "promo_code",
+            "has been used up",
+        )]));
+    }
+    if promo_code
+        .max_redemptions_per_member
+        .is_some_and(|max| redemptions.by_member >= max)
+    {
+        return Err(Error::unprocessable_entity([(
+            "promo_code",
+            "has already been used",
+        )]));
+    }
+
+    Ok(promo_code)
+}
+
+/// Counts a use of the code towards its limits.
+pub(crate) async fn redeem(
+    conn: &mut SqliteConnection,
+    promo_code: &PromoCodeDTO,
+    membership_id: Uuid,
+    subscription_id: Option<Uuid>,
+    invoice_id: Option<Uuid>,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let id = uuid::Uuid::new_v4();
+
+    sqlx::query!(
+        r#"insert into "promo_redemptions" (
+            id, promo_code_id, membership_id, subscription_id, invoice_id,
+            inserted_at
+        ) VALUES (
+            $1, $2, $3, $4, $5,
+            $6
+        )"#,
+        id,
+        promo_code.id,
+        membership_id,
+        subscription_id,
+        invoice_id,
+        now
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    event::record(
+        conn,
+        Some(promo_code.organization_id),
+        EventType::PromoCodeRedeemed,
+        &serde_json::json!({
+            "id": id,
+            "promo_code_id": promo_code.id,
+            "code": promo_code.code,
+            "membership_id": membership_id,
+            "subscription_id": subscription_id,
+            "invoice_id": invoice_id,
+        }),
+        now,
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use crate::http::Result;
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::booking::{self, BookingDTO, BookingStatus};
+use super::class::{self, ClassDTO};
+use super::email;
+use super::job::{self, JobKind, JobPayload};
+use super::notification::{self, NewNotification, NotificationEvent};
+use super::organization;
+
+/// The payload of a `JobKind::ClassReminder` job.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ClassReminder {
+    pub booking_id: Uuid,
+}
+
+impl JobPayload for ClassReminder {
+    const KIND: JobKind = JobKind::ClassReminder;
+}
+
+#[derive(Clone)]
+pub struct ReminderController {
+    pool: SqlitePool,
+}
+
+impl ReminderController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynReminderCtrl = Arc<dyn ReminderCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait ReminderCtrlTrait {
+    /// Runs a `JobKind::ClassReminder` job, notifying the member unless their booking or
+    /// the class was cancelled, or the class already started.
+    async fn send_class_reminder(
+        &self,
+        job_id: Uuid,
+        reminder: ClassReminder,
+        now: OffsetDateTime,
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl ReminderCtrlTrait for ReminderController {
+    async fn send_class_reminder(
+        &self,
+        job_id: Uuid,
+        reminder: ClassReminder,
+        now: OffsetDateTime,
+    ) -> Result<()> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        if job::complete(&mut tx, job_id, now).await?.is_none() {
+            return Ok(());
+        }
+
+        let booking = booking::get_booking(&mut tx, reminder.booking_id).await?;
+        let class = class::get_class(&mut tx, booking.class_id).await?;
+        if booking.status == BookingStatus::Booked
+            && class.cancelled_at.is_none()
+            && class.starts_at > now
+        {
+            notification::notify(
+                &mut tx,
+                NewNotification {
+                    membership_id: booking.membership_id,
+                    event: NotificationEvent::ClassReminder,
+                    variables: vec![
+                        ("class_name", class.name.clone()),
+                        ("starts_at", email::format_time(class.starts_at)),
+                    ],
+                },
+                now,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+fn class_reminder_key(booking_id: Uuid) -> String {
+    format!("class_reminder:{booking_id}")
+}
+
+/// Schedules the member's reminder of the class they booked, the organization's
+/// `class_reminder_hours` before it starts. No reminder is sent if that time has passed.
+pub(crate) async fn schedule_class_reminder(
+    conn: &mut SqliteConnection,
+    class: &ClassDTO,
+    booking: &BookingDTO,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let organization = organization::get_organization(conn, class.organization_id).await?;
+    let Some(hours) = organization.class_reminder_hours else {
+        return Ok(());
+    };
+
+    let run_at = class.starts_at - Duration::hours(hours);
+    if run_at <= now {
+        return Ok(());
+    }
+
+    job::schedule(
+        conn,
+        &class_reminder_key(booking.id),
+        &ClassReminder {
+            booking_id: booking.id,
+        },
+        run_at,
+        now,
+    )
+    .await
+}
+
+/// Calls off the reminder of a booking that was cancelled or taken off the roster.
+pub(crate) async fn cancel_class_reminder(
+    conn: &mut SqliteConnection,
+    booking_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<()> {
+    job::cancel(conn, &class_reminder_key(booking_id), now).await
+}
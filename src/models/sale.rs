@@ -0,0 +1,705 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Date, Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::credit::{self, NewGrant};
+use super::event::{self, EventType};
+use super::invoice::{self, InvoiceDTO, InvoiceStatus, NewInvoice, NewInvoiceLine};
+use super::organization;
+use super::product::{self, ProductKind};
+use super::tax_rate;
+use super::wallet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum SalePaymentMethod {
+    /// Paid in cash into the front desk's drawer.
+    Cash,
+    /// Charged to the member's saved card through the payment provider.
+    Card,
+    /// Put on the member's account, their saved payment method is charged for it later.
+    Account,
+    /// Paid out of the member's wallet, e.g. with a gift card they redeemed.
+    Wallet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum SaleStatus {
+    Completed,
+    /// Taken back before it was paid, its stock and drop-in credits were returned.
+    Voided,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewSaleLine {
+    pub product_id: Uuid,
+    pub quantity: i64,
+}
+
+/// Sells products at the front desk. Walk-ins are added as members first.
+#[derive(serde::Deserialize)]
+pub struct NewSale {
+    pub membership_id: Uuid,
+    pub payment_method: SalePaymentMethod,
+    pub lines: Vec<NewSaleLine>,
+}
+
+/// A checkout at the front desk, what was sold is on its invoice.
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct SaleDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub invoice_id: Uuid,
+    pub payment_method: SalePaymentMethod,
+    pub status: SaleStatus,
+    /// The invoice's total, in minor units of `currency`.
+    pub total_amount: i64,
+    pub currency: String,
+    /// The day the sale counts towards in cash-drawer reports, in UTC.
+    pub business_date: Date,
+    /// The staff member who rang up the sale.
+    pub sold_by_membership_id: Uuid,
+    pub voided_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize)]
+pub struct SaleWithInvoiceDTO {
+    #[serde(flatten)]
+    pub sale: SaleDTO,
+    pub invoice: InvoiceDTO,
+}
+
+/// Closes the day's cash drawer with what was counted in it.
+#[derive(serde::Deserialize)]
+pub struct NewCashDrawerCount {
+    pub business_date: Date,
+    /// Cash put in the drawer at the start of the day, for change.
+    pub float_amount: i64,
+    pub counted_amount: i64,
+    pub note: Option<String>,
+}
+
+/// Amounts are in minor units of the organization's currency.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct CashDrawerCountDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub business_date: Date,
+    pub float_amount: i64,
+    /// The float plus the day's cash sales.
+    pub expected_amount: i64,
+    pub counted_amount: i64,
+    /// `counted_amount` less `expected_amount`, negative if the drawer is short.
+    pub difference_amount: i64,
+    pub note: String,
+    pub counted_by_membership_id: Uuid,
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct SaleTotalDTO {
+    pub payment_method: SalePaymentMethod,
+    pub currency: String,
+    pub sales_count: i64,
+    pub total_amount: i64,
+}
+
+/// The day's sales at the front desk, for reconciling the cash drawer.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CashDrawerReportDTO {
+    pub organization_id: Uuid,
+    pub business_date: Date,
+    /// Completed sales by how they were paid for.
+    pub totals: Vec<SaleTotalDTO>,
+    pub voided_count: i64,
+    /// Cash that should have gone into the drawer, in the organization's currency.
+    pub cash_amount: i64,
+    /// `None` until the drawer is counted.
+    pub count: Option<CashDrawerCountDTO>,
+}
+
+#[derive(Clone)]
+pub struct SaleController {
+    pool: SqlitePool,
+}
+
+impl SaleController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynSaleCtrl = Arc<dyn SaleCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait SaleCtrlTrait {
+    /// Invoices the products, takes them out of stock and grants the credits of drop-in
+    /// passes, all or nothing. Cash and wallet sales are paid straight away and sales on
+    /// account are charged by billing. Card sales are left open for the caller to charge,
+    /// voiding the sale if that fails.
+    async fn create_sale(
+        &self,
+        organization_id: Uuid,
+        sold_by_membership_id: Uuid,
+        new_sale: NewSale,
+        actor: &Actor,
+    ) -> Result<SaleWithInvoiceDTO>;
+
+    /// Takes back a sale that wasn't paid for, returning its stock and the drop-in credits
+    /// left. Sales already paid for are refunded instead.
+    ///
+    /// Audited when `staff` void it, rather than checkout voiding it as its charge failed.
+    async fn void_sale(&self, id: Uuid, staff: Option<&Actor>) -> Result<SaleWithInvoiceDTO>;
+    async fn get_sale(&self, id: Uuid) -> Result<SaleDTO>;
+    async fn list_sales(&self, organization_id: Uuid, business_date: Date) -> Result<Vec<SaleDTO>>;
+
+    async fn get_cash_drawer_report(
+        &self,
+        organization_id: Uuid,
+        business_date: Date,
+    ) -> Result<CashDrawerReportDTO>;
+
+    /// Records what was counted in the drawer at the end of the day, once per day.
+    async fn count_cash_drawer(
+        &self,
+        organization_id: Uuid,
+        counted_by_membership_id: Uuid,
+        new_count: NewCashDrawerCount,
+        actor: &Actor,
+    ) -> Result<CashDrawerCountDTO>;
+}
+
+#[async_trait]
+impl SaleCtrlTrait for SaleController {
+    async fn create_sale(
+        &self,
+        organization_id: Uuid,
+        sold_by_membership_id: Uuid,
+        new_sale: NewSale,
+        actor: &Actor,
+    ) -> Result<SaleWithInvoiceDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let membership_organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            new_sale.membership_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if membership_organization_id != Some(organization_id) {
+            return Err(Error::unprocessable_entity([(
+                "membership_id",
+                "does not match a member of this organization",
+            )]));
+        }
+        if new_sale.lines.is_empty() {
+            return Err(Error::unprocessable_entity([(
+                "lines",
+                "must have at least one product",
+            )]));
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let mut currency = None;
+        let mut lines = Vec::with_capacity(new_sale.lines.len());
+        let mut drop_ins = Vec::new();
+        for line in new_sale.lines {
+            let product = match product::get_product(&mut tx, line.product_id).await {
+                Ok(product)
+                    if product.organization_id == organization_id
+                        && product.archived_at.is_none() =>
+                {
+                    product
+                }
+                Ok(_) | Err(Error::NotFound) => {
+                    return Err(Error::unprocessable_entity([(
+                        "product_id",
+                        "does not match a product on sale at this organization",
+                    )]))
+                }
+                Err(e) => return Err(e),
+            };
+            if line.quantity < 1 {
+                return Err(Error::unprocessable_entity([(
+                    "quantity",
+                    "must be at least 1",
+                )]));
+            }
+            if currency.get_or_insert_with(|| product.currency.clone()) != &product.currency {
+                return Err(Error::unprocessable_entity([(
+                    "lines",
+                    "must all be sold in the same currency",
+                )]));
+            }
+
+            product::take_stock(&mut tx, &product, line.quantity, now).await?;
+
+            let tax_rate_basis_points = match product.tax_rate_id {
+                Some(tax_rate_id) => {
+                    tax_rate::get_tax_rate(&mut tx, tax_rate_id)
+                        .await?
+                        .rate_basis_points
+                }
+                None => 0,
+            };
+            if let (ProductKind::DropIn, Some(validity_days)) =
+                (product.kind, product.validity_days)
+            {
+                let expires_at =
+                    now.checked_add(Duration::days(validity_days))
+                        .ok_or_else(|| {
+                            Error::unprocessable_entity([(
+                                "product_id",
+                                "is valid for too long to sell",
+                            )])
+                        })?;
+                drop_ins.push((line.quantity, expires_at));
+            }
+            lines.push(NewInvoiceLine {
+                description: product.name,
+                plan_id: None,
+                product_id: Some(product.id),
+                quantity: line.quantity,
+                unit_amount: product.price_amount,
+                discount_amount: 0,
+                promo_code_id: None,
+                tax_rate_id: product.tax_rate_id,
+                tax_rate_basis_points,
+            });
+        }
+
+        let next_payment_attempt_at = match new_sale.payment_method {
+            SalePaymentMethod::Account => Some(now),
+            SalePaymentMethod::Cash | SalePaymentMethod::Card | SalePaymentMethod::Wallet => None,
+        };
+        let mut invoice = invoice::create_invoice(
+            &mut tx,
+            NewInvoice {
+                organization_id,
+                membership_id: new_sale.membership_id,
+                subscription_id: None,
+                currency: currency.unwrap_or_default(),
+                period_start: None,
+                period_end: None,
+                next_payment_attempt_at,
+                lines,
+            },
+            now,
+        )
+        .await?;
+        match new_sale.payment_method {
+            SalePaymentMethod::Cash => {
+                invoice = invoice::mark_paid(&mut tx, invoice.id, None, now).await?;
+            }
+            SalePaymentMethod::Wallet if invoice.status == InvoiceStatus::Open => {
+                wallet::pay_from_wallet(&mut tx, &invoice, now).await?;
+                invoice = invoice::mark_paid(&mut tx, invoice.id, None, now).await?;
+            }
+            SalePaymentMethod::Card | SalePaymentMethod::Account | SalePaymentMethod::Wallet => (),
+        }
+
+        for (quantity, expires_at) in drop_ins {
+            credit::grant(
+                &mut tx,
+                NewGrant {
+                    organization_id,
+                    membership_id: new_sale.membership_id,
+                    amount: quantity,
+                    plan_id: None,
+                    invoice_id: Some(invoice.id),
+                    expires_at: Some(expires_at),
+                },
+                now,
+            )
+            .await?;
+        }
+
+        let sale = SaleDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            membership_id: new_sale.membership_id,
+            invoice_id: invoice.id,
+            payment_method: new_sale.payment_method,
+            status: SaleStatus::Completed,
+            total_amount: invoice.total_amount,
+            currency: invoice.currency.clone(),
+            business_date: now.date(),
+            sold_by_membership_id,
+            voided_at: None,
+            inserted_at: now,
+            updated_at: now,
+        };
+
+        sqlx::query!(
+            r#"insert into "sales" (
+                id, organization_id, membership_id, invoice_id,
+                payment_method, status, total_amount, currency,
+                business_date, sold_by_membership_id,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4,
+                $5, $6, $7, $8,
+                $9, $10,
+                $11, $12
+            )"#,
+            sale.id,
+            sale.organization_id,
+            sale.membership_id,
+            sale.invoice_id,
+            sale.payment_method,
+            sale.status,
+            sale.total_amount,
+            sale.currency,
+            sale.business_date,
+            sale.sold_by_membership_id,
+            sale.inserted_at,
+            sale.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(sale.organization_id),
+            EventType::SaleCreated,
+            &sale,
+            sale.inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            sale.organization_id,
+            AuditAction::SaleCreated,
+            sale.id,
+            None,
+            Some(&sale),
+            sale.inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(SaleWithInvoiceDTO { sale, invoice })
+    }
+
+    async fn void_sale(&self, id: Uuid, staff: Option<&Actor>) -> Result<SaleWithInvoiceDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_sale(&mut tx, id).await?;
+        let invoice = invoice::get_invoice(&mut tx, current.invoice_id).await?;
+        if current.status == SaleStatus::Voided {
+            return Ok(SaleWithInvoiceDTO {
+                sale: current,
+                invoice,
+            });
+        }
+        if invoice.status == InvoiceStatus::Paid {
+            return Err(Error::unprocessable_entity([(
+                "sale",
+                "has been paid for, refund it instead",
+            )]));
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let invoice = invoice::void_invoice(&mut tx, invoice.id, now).await?;
+
+        let sold = sqlx::query!(
+            r#"select product_id as "product_id!: Uuid", quantity
+            from invoice_lines
+            where invoice_id = $1 and product_id is not null"#,
+            invoice.id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for line in sold {
+            product::put_back_stock(&mut tx, line.product_id, line.quantity, now).await?;
+        }
+        credit::revoke_invoice_grants(&mut tx, invoice.id, now).await?;
+
+        let sale = SaleDTO {
+            status: SaleStatus::Voided,
+            voided_at: Some(now),
+            updated_at: now,
+            ..current.clone()
+        };
+
+        sqlx::query!(
+            "update sales set status = $1, voided_at = $2, updated_at = $3 where id = $4",
+            sale.status,
+            sale.voided_at,
+            sale.updated_at,
+            sale.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(sale.organization_id),
+            EventType::SaleVoided,
+            &sale,
+            now,
+        )
+        .await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                sale.organization_id,
+                AuditAction::SaleVoided,
+                sale.id,
+                Some(&current),
+                Some(&sale),
+                now,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(SaleWithInvoiceDTO { sale, invoice })
+    }
+
+    async fn get_sale(&self, id: Uuid) -> Result<SaleDTO> {
+        get_sale(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn list_sales(&self, organization_id: Uuid, business_date: Date) -> Result<Vec<SaleDTO>> {
+        let sales = sqlx::query_as!(
+            SaleDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", invoice_id as "invoice_id: Uuid",
+                payment_method as "payment_method: SalePaymentMethod",
+                status as "status: SaleStatus", total_amount, currency,
+                business_date as "business_date: Date",
+                sold_by_membership_id as "sold_by_membership_id: Uuid",
+                voided_at as "voided_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from sales
+            where organization_id = $1 and business_date = $2
+            order by inserted_at"#,
+            organization_id,
+            business_date
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sales)
+    }
+
+    async fn get_cash_drawer_report(
+        &self,
+        organization_id: Uuid,
+        business_date: Date,
+    ) -> Result<CashDrawerReportDTO> {
+        let mut conn = self.pool.acquire().await?;
+
+        let totals = sale_totals(&mut conn, organization_id, business_date).await?;
+        let voided_count = sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from sales
+            where organization_id = $1 and business_date = $2 and status = $3"#,
+            organization_id,
+            business_date,
+            SaleStatus::Voided
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let organization = organization::get_organization(&mut conn, organization_id).await?;
+        let count = sqlx::query_as!(
+            CashDrawerCountDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                business_date as "business_date: Date",
+                float_amount, expected_amount, counted_amount, difference_amount, note,
+                counted_by_membership_id as "counted_by_membership_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime"
+            from cash_drawer_counts
+            where organization_id = $1 and business_date = $2"#,
+            organization_id,
+            business_date
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(CashDrawerReportDTO {
+            organization_id,
+            business_date,
+            cash_amount: cash_amount(&totals, &organization.currency),
+            totals,
+            voided_count,
+            count,
+        })
+    }
+
+    async fn count_cash_drawer(
+        &self,
+        organization_id: Uuid,
+        counted_by_membership_id: Uuid,
+        new_count: NewCashDrawerCount,
+        actor: &Actor,
+    ) -> Result<CashDrawerCountDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let now = time::OffsetDateTime::now_utc();
+        let mut errors = Vec::new();
+        if new_count.business_date > now.date() {
+            errors.push(("business_date", "can't be in the future"));
+        }
+        if new_count.float_amount < 0 {
+            errors.push(("float_amount", "must not be negative"));
+        }
+        if new_count.counted_amount < 0 {
+            errors.push(("counted_amount", "must not be negative"));
+        }
+        if !errors.is_empty() {
+            return Err(Error::unprocessable_entity(errors));
+        }
+
+        let already_counted = sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from cash_drawer_counts
+            where organization_id = $1 and business_date = $2"#,
+            organization_id,
+            new_count.business_date
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if already_counted > 0 {
+            return Err(Error::unprocessable_entity([(
+                "business_date",
+                "has already been counted",
+            )]));
+        }
+
+        let organization = organization::get_organization(&mut tx, organization_id).await?;
+        let totals = sale_totals(&mut tx, organization_id, new_count.business_date).await?;
+        let expected_amount = new_count.float_amount + cash_amount(&totals, &organization.currency);
+
+        let count = CashDrawerCountDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            business_date: new_count.business_date,
+            float_amount: new_count.float_amount,
+            expected_amount,
+            counted_amount: new_count.counted_amount,
+            difference_amount: new_count.counted_amount - expected_amount,
+            note: new_count.note.unwrap_or_default(),
+            counted_by_membership_id,
+            inserted_at: now,
+        };
+
+        sqlx::query!(
+            r#"insert into "cash_drawer_counts" (
+                id, organization_id, business_date,
+                float_amount, expected_amount, counted_amount, difference_amount, note,
+                counted_by_membership_id, inserted_at
+            ) VALUES (
+                $1, $2, $3,
+                $4, $5, $6, $7, $8,
+                $9, $10
+            )"#,
+            count.id,
+            count.organization_id,
+            count.business_date,
+            count.float_amount,
+            count.expected_amount,
+            count.counted_amount,
+            count.difference_amount,
+            count.note,
+            count.counted_by_membership_id,
+            count.inserted_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::CashDrawerCounted,
+            &count,
+            now,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::CashDrawerCounted,
+            count.id,
+            None,
+            Some(&count),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(count)
+    }
+}
+
+pub(crate) async fn get_sale(conn: &mut SqliteConnection, id: Uuid) -> Result<SaleDTO> {
+    sqlx::query_as!(
+        SaleDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", invoice_id as "invoice_id: Uuid",
+            payment_method as "payment_method: SalePaymentMethod",
+            status as "status: SaleStatus", total_amount, currency,
+            business_date as "business_date: Date",
+            sold_by_membership_id as "sold_by_membership_id: Uuid",
+            voided_at as "voided_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from sales
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// The day's completed sales, summed by payment method and currency.
+async fn sale_totals(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    business_date: Date,
+) -> Result<Vec<SaleTotalDTO>> {
+    let totals = sqlx::query_as!(
+        SaleTotalDTO,
+        r#"select
+            payment_method as "payment_method: SalePaymentMethod", currency,
+            count(*) as "sales_count!: i64", sum(total_amount) as "total_amount!: i64"
+        from sales
+        where organization_id = $1 and business_date = $2 and status = $3
+        group by payment_method, currency
+        order by payment_method, currency"#,
+        organization_id,
+        business_date,
+        SaleStatus::Completed
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(totals)
+}
+
+fn cash_amount(totals: &[SaleTotalDTO], currency: &str) -> i64 {
+    totals
+        .iter()
+        .filter(|t| t.payment_method == SalePaymentMethod::Cash && t.currency == currency)
+        .map(|t| t.total_amount)
+        .sum()
+}
@@ -0,0 +1,979 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::{Duration, OffsetDateTime, UtcOffset};
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+use super::invoice::{self, InvoiceStatus};
+use super::plan::{self, BillingInterval, PlanKind};
+use super::platform_plan::PlatformFeature;
+use super::platform_subscription;
+use super::promo_code;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// In the plan's free trial, the first period is billed when it ends.
+    Trialing,
+    Active,
+    /// The first period or a renewal hasn't been paid yet, the member can't book until it is.
+    PastDue,
+    /// Frozen, e.g. while the member is injured or travelling.
+    Paused,
+    /// Won't renew, but runs until the end of the current period.
+    Cancelled,
+    /// Over for good, the member has to buy the plan again.
+    Expired,
+}
+
+impl SubscriptionStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            SubscriptionStatus::Trialing => "trialing",
+            SubscriptionStatus::Active => "active",
+            SubscriptionStatus::PastDue => "past_due",
+            SubscriptionStatus::Paused => "paused",
+            SubscriptionStatus::Cancelled => "cancelled",
+            SubscriptionStatus::Expired => "expired",
+        }
+    }
+
+    /// The subscription lifecycle. Every status change goes through `transition`, which
+    /// refuses anything not listed here.
+    fn can_become(self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+
+        matches!(
+            (self, next),
+            (Trialing, Active | PastDue | Cancelled)
+                | (Active, PastDue | Paused | Cancelled)
+                | (PastDue, Active | Paused | Cancelled)
+                | (Paused, Active | Cancelled)
+                | (Cancelled, Expired)
+        )
+    }
+
+    /// Whether the subscription may still renew, as opposed to running out.
+    pub fn is_live(self) -> bool {
+        !matches!(
+            self,
+            SubscriptionStatus::Cancelled | SubscriptionStatus::Expired
+        )
+    }
+}
+
+/// Buys a membership plan. Staff can subscribe another member by passing `membership_id`.
+#[derive(serde::Deserialize)]
+pub struct NewSubscription {
+    pub plan_id: Uuid,
+    pub membership_id: Option<Uuid>,
+    pub promo_code: Option<String>,
+}
+
+/// Freezes a subscription for a while, e.g. while the member is injured or travelling.
+/// Leave out `starts_at` to freeze it straight away.
+#[derive(serde::Deserialize)]
+pub struct NewFreeze {
+    pub starts_at: Option<OffsetDateTime>,
+    pub ends_at: OffsetDateTime,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct SubscriptionDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    pub plan_id: Uuid,
+    pub status: SubscriptionStatus,
+    /// Billing periods are counted from here, so renewals keep the same day of the month.
+    pub billing_anchor_at: OffsetDateTime,
+    /// How many periods since the anchor have been started, the current one included.
+    pub periods_billed: i64,
+    pub current_period_start: OffsetDateTime,
+    /// When the subscription next renews, or ends once cancelled.
+    pub current_period_end: OffsetDateTime,
+    pub trial_ends_at: Option<OffsetDateTime>,
+    pub paused_at: Option<OffsetDateTime>,
+    /// A freeze, scheduled or under way. The subscription is paused in between and resumes
+    /// on its own at `freeze_ends_at`.
+    pub freeze_starts_at: Option<OffsetDateTime>,
+    pub freeze_ends_at: Option<OffsetDateTime>,
+    pub cancelled_at: Option<OffsetDateTime>,
+    pub ended_at: Option<OffsetDateTime>,
+    /// The promo code the member subscribed with, its discount is applied to each renewal
+    /// it covers.
+    pub promo_code_id: Option<Uuid>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct SubscriptionController {
+    pool: SqlitePool,
+}
+
+impl SubscriptionController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynSubscriptionCtrl = Arc<dyn SubscriptionCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait SubscriptionCtrlTrait {
+    /// Starts the member on the plan, in its trial if it has one, discounted by the promo
+    /// code if given. Without a trial the first period is invoiced straight away and the
+    /// subscription stays `PastDue` until it's paid. Audited when `staff` sign the member up.
+    async fn create_subscription(
+        &self,
+        membership_id: Uuid,
+        plan_id: Uuid,
+        promo_code: Option<&str>,
+        staff: Option<&Actor>,
+    ) -> Result<SubscriptionDTO>;
+    async fn get_subscription(&self, id: Uuid) -> Result<SubscriptionDTO>;
+    async fn list_member_subscriptions(&self, membership_id: Uuid) -> Result<Vec<SubscriptionDTO>>;
+
+    /// Audited when `staff` cancel for the member.
+    async fn cancel_subscription(&self, id: Uuid, staff: Option<&Actor>)
+        -> Result<SubscriptionDTO>;
+    async fn pause_subscription(&self, id: Uuid, actor: &Actor) -> Result<SubscriptionDTO>;
+    async fn resume_subscription(&self, id: Uuid, actor: &Actor) -> Result<SubscriptionDTO>;
+
+    /// Schedules a freeze, starting it straight away if it's due. Bookings for classes during
+    /// the freeze are refused, and the renewal is pushed back by however long it lasts.
+    async fn freeze_subscription(
+        &self,
+        id: Uuid,
+        new_freeze: NewFreeze,
+        actor: &Actor,
+    ) -> Result<SubscriptionDTO>;
+    /// Calls off a scheduled freeze, or ends one under way early.
+    async fn unfreeze_subscription(&self, id: Uuid, actor: &Actor) -> Result<SubscriptionDTO>;
+
+    /// Moves the subscription on once its current period has ended: trials become active,
+    /// active subscriptions renew and cancelled ones expire. Does nothing before then, so
+    /// it is safe to call repeatedly.
+    async fn roll_over_subscription(
+        &self,
+        id: Uuid,
+        now: OffsetDateTime,
+    ) -> Result<SubscriptionDTO>;
+
+    /// Subscriptions whose current period ended by `now` and need rolling over.
+    async fn list_due_subscriptions(&self, now: OffsetDateTime) -> Result<Vec<SubscriptionDTO>>;
+
+    /// Starts or ends the subscription's freeze once it is due, doing nothing before then.
+    async fn apply_freeze(&self, id: Uuid, now: OffsetDateTime) -> Result<SubscriptionDTO>;
+
+    /// Subscriptions with a freeze that is due to start or end by `now`.
+    async fn list_due_freezes(&self, now: OffsetDateTime) -> Result<Vec<SubscriptionDTO>>;
+}
+
+#[async_trait]
+impl SubscriptionCtrlTrait for SubscriptionController {
+    async fn create_subscription(
+        &self,
+        membership_id: Uuid,
+        plan_id: Uuid,
+        promo_code: Option<&str>,
+        staff: Option<&Actor>,
+    ) -> Result<SubscriptionDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let plan = plan::get_plan(&mut tx, plan_id).await?;
+        let membership_organization_id = sqlx::query_scalar!(
+            r#"select organization_id as "organization_id: Uuid" from memberships where id = $1"#,
+            membership_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        if plan.organization_id != membership_organization_id || plan.archived_at.is_some() {
+            return Err(Error::unprocessable_entity([(
+                "plan_id",
+                "does not match a plan on sale at this organization",
+            )]));
+        }
+        let billing_interval = match (plan.kind, plan.billing_interval) {
+            (PlanKind::Unlimited | PlanKind::WeeklyLimit, Some(interval)) => interval,
+            _ => {
+                return Err(Error::unprocessable_entity([(
+                    "plan_id",
+                    "is not a membership plan",
+                )]))
+            }
+        };
+
+        let current = list_member_subscriptions(&mut tx, membership_id).await?;
+        if current
+            .iter()
+            .any(|s| s.plan_id == plan_id && s.status.is_live())
+        {
+            return Err(Error::unprocessable_entity([(
+                "plan_id",
+                "is already subscribed to",
+            )]));
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let promo_code = match promo_code {
+            Some(code) => {
+                Some(promo_code::find_redeemable(&mut tx, code, &plan, membership_id, now).await?)
+            }
+            None => None,
+        };
+
+        let subscription = if plan.trial_days > 0 {
+            let trial_ends_at = now
+                .checked_add(Duration::days(plan.trial_days))
+                .ok_or_else(|| {
+                    Error::unprocessable_entity([("plan_id", "has a trial too long to start")])
+                })?;
+            SubscriptionDTO {
+                status: SubscriptionStatus::Trialing,
+                billing_anchor_at: trial_ends_at,
+                periods_billed: 0,
+                current_period_start: now,
+                current_period_end: trial_ends_at,
+                trial_ends_at: Some(trial_ends_at),
+                promo_code_id: promo_code.as_ref().map(|p| p.id),
+                ..new_subscription(&plan, membership_id, now)
+            }
+        } else {
+            // Access starts once the first period is paid, see `dunning::payment_succeeded`.
+            SubscriptionDTO {
+                status: SubscriptionStatus::PastDue,
+                billing_anchor_at: now,
+                periods_billed: 1,
+                current_period_start: now,
+                current_period_end: billing_interval.periods_after(now, 1),
+                promo_code_id: promo_code.as_ref().map(|p| p.id),
+                ..new_subscription(&plan, membership_id, now)
+            }
+        };
+
+        sqlx::query!(
+            r#"insert into "subscriptions" (
+                id, organization_id, membership_id, plan_id, status,
+                billing_anchor_at, periods_billed, current_period_start, current_period_end,
+                trial_ends_at, promo_code_id, inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7, $8, $9,
+                $10, $11, $12, $13
+            )"#,
+            subscription.id,
+            subscription.organization_id,
+            subscription.membership_id,
+            subscription.plan_id,
+            subscription.status,
+            subscription.billing_anchor_at,
+            subscription.periods_billed,
+            subscription.current_period_start,
+            subscription.current_period_end,
+            subscription.trial_ends_at,
+            subscription.promo_code_id,
+            subscription.inserted_at,
+            subscription.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(subscription.organization_id),
+            EventType::SubscriptionCreated,
+            &subscription,
+            now,
+        )
+        .await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                subscription.organization_id,
+                AuditAction::SubscriptionCreated,
+                subscription.id,
+                None,
+                Some(&subscription),
+                now,
+            )
+            .await?;
+        }
+
+        // Trials are invoiced when they convert, on the first renewal.
+        let invoice = if subscription.status == SubscriptionStatus::PastDue {
+            Some(invoice::invoice_subscription_period(&mut tx, &subscription, &plan, now).await?)
+        } else {
+            None
+        };
+        // Nothing to pay, e.g. a 100% off promo code.
+        let subscription = match &invoice {
+            Some(invoice) if invoice.status == InvoiceStatus::Paid => {
+                transition(&mut tx, subscription, SubscriptionStatus::Active, now).await?
+            }
+            _ => subscription,
+        };
+        if let Some(promo_code) = &promo_code {
+            promo_code::redeem(
+                &mut tx,
+                promo_code,
+                membership_id,
+                Some(subscription.id),
+                invoice.as_ref().map(|i| i.id),
+                now,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    async fn get_subscription(&self, id: Uuid) -> Result<SubscriptionDTO> {
+        get_subscription(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn list_member_subscriptions(&self, membership_id: Uuid) -> Result<Vec<SubscriptionDTO>> {
+        list_member_subscriptions(&mut *self.pool.acquire().await?, membership_id).await
+    }
+
+    async fn cancel_subscription(
+        &self,
+        id: Uuid,
+        staff: Option<&Actor>,
+    ) -> Result<SubscriptionDTO> {
+        self.change_status(id, SubscriptionStatus::Cancelled, staff)
+            .await
+    }
+
+    async fn pause_subscription(&self, id: Uuid, actor: &Actor) -> Result<SubscriptionDTO> {
+        self.change_status(id, SubscriptionStatus::Paused, Some(actor))
+            .await
+    }
+
+    async fn resume_subscription(&self, id: Uuid, actor: &Actor) -> Result<SubscriptionDTO> {
+        let subscription = self.get_subscription(id).await?;
+        if subscription.status != SubscriptionStatus::Paused {
+            return Err(Error::unprocessable_entity([(
+                "status",
+                "only paused subscriptions can be resumed",
+            )]));
+        }
+
+        self.change_status(id, SubscriptionStatus::Active, Some(actor))
+            .await
+    }
+
+    async fn freeze_subscription(
+        &self,
+        id: Uuid,
+        new_freeze: NewFreeze,
+        actor: &Actor,
+    ) -> Result<SubscriptionDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let subscription = get_subscription(&mut tx, id).await?;
+        let before = subscription.clone();
+        platform_subscription::require_feature(
+            &mut tx,
+            subscription.organization_id,
+            PlatformFeature::Freezes,
+        )
+        .await?;
+        if subscription.status != SubscriptionStatus::Active {
+            return Err(Error::unprocessable_entity([(
+                "status",
+                "only active subscriptions can be frozen",
+            )]));
+        }
+        if subscription.freeze_starts_at.is_some() {
+            return Err(Error::unprocessable_entity([(
+                "freeze",
+                "is already scheduled, unfreeze the subscription first",
+            )]));
+        }
+
+        // Stored in UTC, `list_due_freezes` compares them as text.
+        let starts_at = new_freeze
+            .starts_at
+            .unwrap_or(now)
+            .max(now)
+            .to_offset(UtcOffset::UTC);
+        let ends_at = new_freeze.ends_at.to_offset(UtcOffset::UTC);
+        if ends_at <= starts_at {
+            return Err(Error::unprocessable_entity([(
+                "ends_at",
+                "must be after the freeze starts",
+            )]));
+        }
+
+        let subscription = SubscriptionDTO {
+            freeze_starts_at: Some(starts_at),
+            freeze_ends_at: Some(ends_at),
+            updated_at: now,
+            ..subscription
+        };
+        save(&mut tx, &subscription).await?;
+        event::record(
+            &mut tx,
+            Some(subscription.organization_id),
+            EventType::SubscriptionFreezeScheduled,
+            &subscription,
+            now,
+        )
+        .await?;
+        let subscription = apply_freeze(&mut tx, subscription, now).await?;
+        audit::record(
+            &mut tx,
+            actor,
+            subscription.organization_id,
+            AuditAction::SubscriptionFreezeScheduled,
+            subscription.id,
+            Some(&before),
+            Some(&subscription),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    async fn unfreeze_subscription(&self, id: Uuid, actor: &Actor) -> Result<SubscriptionDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let subscription = get_subscription(&mut tx, id).await?;
+        let before = subscription.clone();
+        let subscription = match (subscription.freeze_starts_at, subscription.status) {
+            (None, _) => return Err(Error::unprocessable_entity([("freeze", "isn't scheduled")])),
+            (Some(_), SubscriptionStatus::Paused) => {
+                transition(&mut tx, subscription, SubscriptionStatus::Active, now).await?
+            }
+            (Some(_), _) => clear_freeze(&mut tx, subscription, now).await?,
+        };
+        audit::record(
+            &mut tx,
+            actor,
+            subscription.organization_id,
+            AuditAction::SubscriptionFreezeCleared,
+            subscription.id,
+            Some(&before),
+            Some(&subscription),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    async fn roll_over_subscription(
+        &self,
+        id: Uuid,
+        now: OffsetDateTime,
+    ) -> Result<SubscriptionDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let subscription = get_subscription(&mut tx, id).await?;
+        let subscription = roll_over(&mut tx, subscription, now).await?;
+
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    async fn list_due_subscriptions(&self, now: OffsetDateTime) -> Result<Vec<SubscriptionDTO>> {
+        let subscriptions = sqlx::query_as!(
+            SubscriptionDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", plan_id as "plan_id: Uuid",
+                status as "status: SubscriptionStatus",
+                billing_anchor_at as "billing_anchor_at: OffsetDateTime", periods_billed,
+                current_period_start as "current_period_start: OffsetDateTime",
+                current_period_end as "current_period_end: OffsetDateTime",
+                trial_ends_at as "trial_ends_at: OffsetDateTime",
+                paused_at as "paused_at: OffsetDateTime",
+                freeze_starts_at as "freeze_starts_at: OffsetDateTime",
+                freeze_ends_at as "freeze_ends_at: OffsetDateTime",
+                cancelled_at as "cancelled_at: OffsetDateTime",
+                ended_at as "ended_at: OffsetDateTime",
+                promo_code_id as "promo_code_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from subscriptions
+            where status in ('trialing', 'active', 'cancelled') and current_period_end <= $1
+            order by current_period_end"#,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    async fn apply_freeze(&self, id: Uuid, now: OffsetDateTime) -> Result<SubscriptionDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let subscription = get_subscription(&mut tx, id).await?;
+        let subscription = apply_freeze(&mut tx, subscription, now).await?;
+
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    async fn list_due_freezes(&self, now: OffsetDateTime) -> Result<Vec<SubscriptionDTO>> {
+        let subscriptions = sqlx::query_as!(
+            SubscriptionDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                membership_id as "membership_id: Uuid", plan_id as "plan_id: Uuid",
+                status as "status: SubscriptionStatus",
+                billing_anchor_at as "billing_anchor_at: OffsetDateTime", periods_billed,
+                current_period_start as "current_period_start: OffsetDateTime",
+                current_period_end as "current_period_end: OffsetDateTime",
+                trial_ends_at as "trial_ends_at: OffsetDateTime",
+                paused_at as "paused_at: OffsetDateTime",
+                freeze_starts_at as "freeze_starts_at: OffsetDateTime",
+                freeze_ends_at as "freeze_ends_at: OffsetDateTime",
+                cancelled_at as "cancelled_at: OffsetDateTime",
+                ended_at as "ended_at: OffsetDateTime",
+                promo_code_id as "promo_code_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from subscriptions
+            where freeze_starts_at <= $1 and (status != $2 or freeze_ends_at <= $1)
+            order by freeze_starts_at"#,
+            now,
+            SubscriptionStatus::Paused
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+}
+
+impl SubscriptionController {
+    /// Audited when `staff` is given.
+    async fn change_status(
+        &self,
+        id: Uuid,
+        next: SubscriptionStatus,
+        staff: Option<&Actor>,
+    ) -> Result<SubscriptionDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_subscription(&mut tx, id).await?;
+        let subscription = transition(&mut tx, before.clone(), next, now).await?;
+
+        let action = match next {
+            SubscriptionStatus::Cancelled => Some(AuditAction::SubscriptionCancelled),
+            SubscriptionStatus::Paused => Some(AuditAction::SubscriptionPaused),
+            SubscriptionStatus::Active => Some(AuditAction::SubscriptionResumed),
+            _ => None,
+        };
+        if let (Some(staff), Some(action)) = (staff, action) {
+            audit::record(
+                &mut tx,
+                staff,
+                subscription.organization_id,
+                action,
+                subscription.id,
+                Some(&before),
+                Some(&subscription),
+                now,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+}
+
+fn new_subscription(
+    plan: &plan::PlanDTO,
+    membership_id: Uuid,
+    now: OffsetDateTime,
+) -> SubscriptionDTO {
+    SubscriptionDTO {
+        id: uuid::Uuid::new_v4(),
+        organization_id: plan.organization_id,
+        membership_id,
+        plan_id: plan.id,
+        status: SubscriptionStatus::Active,
+        billing_anchor_at: now,
+        periods_billed: 0,
+        current_period_start: now,
+        current_period_end: now,
+        trial_ends_at: None,
+        paused_at: None,
+        freeze_starts_at: None,
+        freeze_ends_at: None,
+        cancelled_at: None,
+        ended_at: None,
+        promo_code_id: None,
+        inserted_at: now,
+        updated_at: now,
+    }
+}
+
+/// Moves the subscription to `next`, the only place a subscription's status changes.
+/// Changes the lifecycle doesn't allow, e.g. reactivating a cancelled subscription rather
+/// than buying the plan again, are refused.
+pub(crate) async fn transition(
+    conn: &mut SqliteConnection,
+    subscription: SubscriptionDTO,
+    next: SubscriptionStatus,
+    now: OffsetDateTime,
+) -> Result<SubscriptionDTO> {
+    if !subscription.status.can_become(next) {
+        return Err(Error::unprocessable_entity([(
+            "status",
+            format!(
+                "can't change from {} to {}",
+                subscription.status.as_str(),
+                next.as_str()
+            ),
+        )]));
+    }
+
+    // Only the freeze itself starting keeps it, any other change calls it off or ends it.
+    let keeps_freeze = subscription.status == SubscriptionStatus::Active
+        && next == SubscriptionStatus::Paused
+        && subscription.freeze_starts_at.is_some_and(|at| at <= now);
+    let (freeze_starts_at, freeze_ends_at) = if keeps_freeze {
+        (subscription.freeze_starts_at, subscription.freeze_ends_at)
+    } else {
+        (None, None)
+    };
+
+    // Time spent paused isn't charged for, the renewal moves back by as long.
+    let paused_for = match (subscription.status, next, subscription.paused_at) {
+        (SubscriptionStatus::Paused, SubscriptionStatus::Active, Some(paused_at)) => {
+            (now - paused_at).max(Duration::ZERO)
+        }
+        _ => Duration::ZERO,
+    };
+
+    let subscription = SubscriptionDTO {
+        status: next,
+        billing_anchor_at: subscription.billing_anchor_at + paused_for,
+        current_period_end: subscription.current_period_end + paused_for,
+        freeze_starts_at,
+        freeze_ends_at,
+        paused_at: match next {
+            SubscriptionStatus::Paused => Some(now),
+            SubscriptionStatus::Active => None,
+            _ => subscription.paused_at,
+        },
+        cancelled_at: match next {
+            SubscriptionStatus::Cancelled => Some(now),
+            _ => subscription.cancelled_at,
+        },
+        ended_at: match next {
+            SubscriptionStatus::Expired => Some(now),
+            _ => subscription.ended_at,
+        },
+        updated_at: now,
+        ..subscription
+    };
+
+    save(conn, &subscription).await?;
+
+    let event = match next {
+        SubscriptionStatus::Trialing | SubscriptionStatus::Active => {
+            EventType::SubscriptionActivated
+        }
+        SubscriptionStatus::PastDue => EventType::SubscriptionPastDue,
+        SubscriptionStatus::Paused => EventType::SubscriptionPaused,
+        SubscriptionStatus::Cancelled => EventType::SubscriptionCancelled,
+        SubscriptionStatus::Expired => EventType::SubscriptionExpired,
+    };
+    event::record(
+        conn,
+        Some(subscription.organization_id),
+        event,
+        &subscription,
+        now,
+    )
+    .await?;
+
+    Ok(subscription)
+}
+
+/// See `SubscriptionCtrlTrait::apply_freeze`. A freeze that can't start because the
+/// subscription is no longer active is dropped.
+pub(crate) async fn apply_freeze(
+    conn: &mut SqliteConnection,
+    subscription: SubscriptionDTO,
+    now: OffsetDateTime,
+) -> Result<SubscriptionDTO> {
+    let (Some(starts_at), Some(ends_at)) =
+        (subscription.freeze_starts_at, subscription.freeze_ends_at)
+    else {
+        return Ok(subscription);
+    };
+    if now < starts_at {
+        return Ok(subscription);
+    }
+
+    match subscription.status {
+        SubscriptionStatus::Active if now < ends_at => {
+            transition(conn, subscription, SubscriptionStatus::Paused, now).await
+        }
+        SubscriptionStatus::Paused if now < ends_at => Ok(subscription),
+        SubscriptionStatus::Paused => {
+            transition(conn, subscription, SubscriptionStatus::Active, now).await
+        }
+        _ => clear_freeze(conn, subscription, now).await,
+    }
+}
+
+async fn clear_freeze(
+    conn: &mut SqliteConnection,
+    subscription: SubscriptionDTO,
+    now: OffsetDateTime,
+) -> Result<SubscriptionDTO> {
+    let subscription = SubscriptionDTO {
+        freeze_starts_at: None,
+        freeze_ends_at: None,
+        updated_at: now,
+        ..subscription
+    };
+
+    save(conn, &subscription).await?;
+    event::record(
+        conn,
+        Some(subscription.organization_id),
+        EventType::SubscriptionFreezeCleared,
+        &subscription,
+        now,
+    )
+    .await?;
+
+    Ok(subscription)
+}
+
+/// See `SubscriptionCtrlTrait::roll_over_subscription`. Past due and paused subscriptions
+/// are left alone, dunning and resuming move them on.
+pub(crate) async fn roll_over(
+    conn: &mut SqliteConnection,
+    subscription: SubscriptionDTO,
+    now: OffsetDateTime,
+) -> Result<SubscriptionDTO> {
+    if now < subscription.current_period_end {
+        return Ok(subscription);
+    }
+
+    match subscription.status {
+        SubscriptionStatus::Trialing => {
+            let subscription = renew(conn, subscription, now).await?;
+            transition(conn, subscription, SubscriptionStatus::Active, now).await
+        }
+        SubscriptionStatus::Active => renew(conn, subscription, now).await,
+        SubscriptionStatus::Cancelled => {
+            transition(conn, subscription, SubscriptionStatus::Expired, now).await
+        }
+        SubscriptionStatus::PastDue | SubscriptionStatus::Paused | SubscriptionStatus::Expired => {
+            Ok(subscription)
+        }
+    }
+}
+
+/// Starts the next billing period and invoices it.
+async fn renew(
+    conn: &mut SqliteConnection,
+    subscription: SubscriptionDTO,
+    now: OffsetDateTime,
+) -> Result<SubscriptionDTO> {
+    let plan = plan::get_plan(&mut *conn, subscription.plan_id).await?;
+    let billing_interval: BillingInterval = plan.billing_interval.ok_or_else(|| {
+        anyhow::anyhow!("subscription {} has no billing interval", subscription.id)
+    })?;
+
+    let periods_billed = subscription.periods_billed + 1;
+    let subscription = SubscriptionDTO {
+        periods_billed,
+        current_period_start: subscription.current_period_end,
+        current_period_end: billing_interval
+            .periods_after(subscription.billing_anchor_at, periods_billed),
+        updated_at: now,
+        ..subscription
+    };
+
+    save(conn, &subscription).await?;
+    event::record(
+        conn,
+        Some(subscription.organization_id),
+        EventType::SubscriptionRenewed,
+        &subscription,
+        now,
+    )
+    .await?;
+    invoice::invoice_subscription_period(conn, &subscription, &plan, now).await?;
+
+    Ok(subscription)
+}
+
+async fn save(conn: &mut SqliteConnection, subscription: &SubscriptionDTO) -> Result<()> {
+    sqlx::query!(
+        r#"update subscriptions set
+            status = $1, billing_anchor_at = $2, periods_billed = $3,
+            current_period_start = $4, current_period_end = $5,
+            paused_at = $6, freeze_starts_at = $7, freeze_ends_at = $8,
+            cancelled_at = $9, ended_at = $10,
+            updated_at = $11
+        where id = $12"#,
+        subscription.status,
+        subscription.billing_anchor_at,
+        subscription.periods_billed,
+        subscription.current_period_start,
+        subscription.current_period_end,
+        subscription.paused_at,
+        subscription.freeze_starts_at,
+        subscription.freeze_ends_at,
+        subscription.cancelled_at,
+        subscription.ended_at,
+        subscription.updated_at,
+        subscription.id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn get_subscription(
+    conn: &mut SqliteConnection,
+    id: Uuid,
+) -> Result<SubscriptionDTO> {
+    sqlx::query_as!(
+        SubscriptionDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", plan_id as "plan_id: Uuid",
+            status as "status: SubscriptionStatus",
+            billing_anchor_at as "billing_anchor_at: OffsetDateTime", periods_billed,
+            current_period_start as "current_period_start: OffsetDateTime",
+            current_period_end as "current_period_end: OffsetDateTime",
+            trial_ends_at as "trial_ends_at: OffsetDateTime",
+            paused_at as "paused_at: OffsetDateTime",
+            freeze_starts_at as "freeze_starts_at: OffsetDateTime",
+            freeze_ends_at as "freeze_ends_at: OffsetDateTime",
+            cancelled_at as "cancelled_at: OffsetDateTime",
+            ended_at as "ended_at: OffsetDateTime",
+            promo_code_id as "promo_code_id: Uuid",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from subscriptions
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+pub(crate) async fn list_member_subscriptions(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+) -> Result<Vec<SubscriptionDTO>> {
+    let subscriptions = sqlx::query_as!(
+        SubscriptionDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            membership_id as "membership_id: Uuid", plan_id as "plan_id: Uuid",
+            status as "status: SubscriptionStatus",
+            billing_anchor_at as "billing_anchor_at: OffsetDateTime", periods_billed,
+            current_period_start as "current_period_start: OffsetDateTime",
+            current_period_end as "current_period_end: OffsetDateTime",
+            trial_ends_at as "trial_ends_at: OffsetDateTime",
+            paused_at as "paused_at: OffsetDateTime",
+            freeze_starts_at as "freeze_starts_at: OffsetDateTime",
+            freeze_ends_at as "freeze_ends_at: OffsetDateTime",
+            cancelled_at as "cancelled_at: OffsetDateTime",
+            ended_at as "ended_at: OffsetDateTime",
+            promo_code_id as "promo_code_id: Uuid",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from subscriptions
+        where membership_id = $1
+        order by inserted_at desc"#,
+        membership_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(subscriptions)
+}
+
+/// The member's subscriptions that cover a class starting at `starts_at`, with their plans.
+/// Cancelled subscriptions cover classes until their period ends, past due and paused ones
+/// cover nothing.
+pub(crate) async fn list_covering(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    starts_at: OffsetDateTime,
+) -> Result<Vec<(SubscriptionDTO, plan::PlanDTO)>> {
+    let mut covering = Vec::new();
+
+    for subscription in list_member_subscriptions(&mut *conn, membership_id).await? {
+        let covers = match subscription.status {
+            SubscriptionStatus::Trialing | SubscriptionStatus::Active => true,
+            SubscriptionStatus::Cancelled => starts_at < subscription.current_period_end,
+            _ => false,
+        };
+        if covers {
+            let plan = plan::get_plan(&mut *conn, subscription.plan_id).await?;
+            covering.push((subscription, plan));
+        }
+    }
+
+    Ok(covering)
+}
+
+/// Whether the member has a subscription with an unpaid renewal.
+pub(crate) async fn has_past_due(conn: &mut SqliteConnection, membership_id: Uuid) -> Result<bool> {
+    let past_due = sqlx::query_scalar!(
+        r#"select count(*) as "count!: i64" from subscriptions
+        where membership_id = $1 and status = $2"#,
+        membership_id,
+        SubscriptionStatus::PastDue
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(past_due > 0)
+}
+
+/// When the member's freeze covering `at` ends, if they have one.
+pub(crate) async fn frozen_until(
+    conn: &mut SqliteConnection,
+    membership_id: Uuid,
+    at: OffsetDateTime,
+) -> Result<Option<OffsetDateTime>> {
+    let frozen_until = list_member_subscriptions(conn, membership_id)
+        .await?
+        .into_iter()
+        .filter_map(|s| Some((s.freeze_starts_at?, s.freeze_ends_at?)))
+        .filter(|(starts_at, ends_at)| *starts_at <= at && at < *ends_at)
+        .map(|(_, ends_at)| ends_at)
+        .max();
+
+    Ok(frozen_until)
+}
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{self, EventType};
+
+/// A tax charged on top of the price of what the organization sells, e.g. "VAT".
+#[derive(serde::Deserialize)]
+pub struct NewTaxRate {
+    pub name: String,
+    /// In hundredths of a percent, 2000 is 20%.
+    pub rate_basis_points: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct TaxRateDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub rate_basis_points: i64,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl NewTaxRate {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(("name", "can't be blank"));
+        }
+        if !(0..=10_000).contains(&self.rate_basis_points) {
+            errors.push(("rate_basis_points", "must be between 0 and 10000"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TaxRateController {
+    pool: SqlitePool,
+}
+
+impl TaxRateController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynTaxRateCtrl = Arc<dyn TaxRateCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait TaxRateCtrlTrait {
+    async fn create_tax_rate(
+        &self,
+        organization_id: Uuid,
+        new_tax_rate: NewTaxRate,
+        actor: &Actor,
+    ) -> Result<TaxRateDTO>;
+    async fn list_tax_rates(&self, organization_id: Uuid) -> Result<Vec<TaxRateDTO>>;
+}
+
+#[async_trait]
+impl TaxRateCtrlTrait for TaxRateController {
+    async fn create_tax_rate(
+        &self,
+        organization_id: Uuid,
+        new_tax_rate: NewTaxRate,
+        actor: &Actor,
+    ) -> Result<TaxRateDTO> {
+        new_tax_rate.validate()?;
+
+        let id = uuid::Uuid::new_v4();
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let mut tx = super::begin(&self.pool).await?;
+
+        let tax_rate = sqlx::query_as!(
+            TaxRateDTO,
+            r#"insert into "tax_rates" (
+                id, organization_id, name, rate_basis_points,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4,
+                $5, $6
+            ) returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                name, rate_basis_points,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            id,
+            organization_id,
+            new_tax_rate.name,
+            new_tax_rate.rate_basis_points,
+            inserted_at,
+            inserted_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(organization_id),
+            EventType::TaxRateCreated,
+            &tax_rate,
+            inserted_at,
+        )
+        .await?;
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::TaxRateCreated,
+            tax_rate.id,
+            None,
+            Some(&tax_rate),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(tax_rate)
+    }
+
+    async fn list_tax_rates(&self, organization_id: Uuid) -> Result<Vec<TaxRateDTO>> {
+        let tax_rates = sqlx::query_as!(
+            TaxRateDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                name, rate_basis_points,
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from tax_rates
+            where organization_id = $1
+            order by name"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tax_rates)
+    }
+}
+
+pub(crate) async fn get_tax_rate(conn: &mut SqliteConnection, id: Uuid) -> Result<TaxRateDTO> {
+    sqlx::query_as!(
+        TaxRateDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            name, rate_basis_points,
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from tax_rates
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::booking::{self, BookingDTO};
+use super::class::{self, ClassDTO};
+use super::email;
+use super::event::{self, EventType};
+use super::notification::{self, NewNotification, NotificationEvent};
+use super::organization;
+use super::reminder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum WaitlistStatus {
+    Waiting,
+    Promoted,
+    Left,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct WaitlistEntryDTO {
+    pub id: Uuid,
+    pub class_id: Uuid,
+    pub membership_id: Uuid,
+    pub position: i64,
+    pub status: WaitlistStatus,
+    pub booking_id: Option<Uuid>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// A waitlist entry that was turned into a booking.
+pub struct WaitlistPromotion {
+    pub entry: WaitlistEntryDTO,
+    pub booking: BookingDTO,
+}
+
+#[derive(Clone)]
+pub struct WaitlistController {
+    pool: SqlitePool,
+}
+
+impl WaitlistController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynWaitlistCtrl = Arc<dyn WaitlistCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait WaitlistCtrlTrait {
+    /// Adds the member to the end of a full class's waitlist.
+    async fn join_waitlist(&self, class_id: Uuid, membership_id: Uuid) -> Result<WaitlistEntryDTO>;
+    /// Takes the member off the waitlist, audited when `staff` do it for them.
+    async fn leave_waitlist(&self, id: Uuid, staff: Option<&Actor>) -> Result<WaitlistEntryDTO>;
+    async fn get_waitlist_entry(&self, id: Uuid) -> Result<WaitlistEntryDTO>;
+
+    /// Lists the entries still waiting, in promotion order.
+    async fn list_waitlist(&self, class_id: Uuid) -> Result<Vec<WaitlistEntryDTO>>;
+}
+
+#[async_trait]
+impl WaitlistCtrlTrait for WaitlistController {
+    async fn join_waitlist(&self, class_id: Uuid, membership_id: Uuid) -> Result<WaitlistEntryDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let class = class::get_class(&mut tx, class_id).await?;
+        booking::check_can_book(&mut tx, &class, membership_id, now).await?;
+
+        if booking::count_booked(&mut tx, class.id).await? < class.capacity {
+            return Err(Error::unprocessable_entity([(
+                "class",
+                "has open spots, book it instead",
+            )]));
+        }
+
+        let already_waiting = sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from waitlist_entries
+            where class_id = $1 and membership_id = $2 and status = $3"#,
+            class.id,
+            membership_id,
+            WaitlistStatus::Waiting
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if already_waiting > 0 {
+            return Err(Error::unprocessable_entity([(
+                "class",
+                "is already on your waitlist",
+            )]));
+        }
+
+        let position = sqlx::query_scalar!(
+            r#"select coalesce(max(position), 0) + 1 as "position!: i64"
+            from waitlist_entries
+            where class_id = $1"#,
+            class.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let id = uuid::Uuid::new_v4();
+
+        let entry = sqlx::query_as!(
+            WaitlistEntryDTO,
+            r#"insert into "waitlist_entries" (
+                id, class_id, membership_id, position, status,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5,
+                $6, $7
+            ) returning
+                id as "id: Uuid", class_id as "class_id: Uuid",
+                membership_id as "membership_id: Uuid", position,
+                status as "status: WaitlistStatus", booking_id as "booking_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            id,
+            class.id,
+            membership_id,
+            position,
+            WaitlistStatus::Waiting,
+            now,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        event::record(
+            &mut tx,
+            Some(class.organization_id),
+            EventType::WaitlistJoined,
+            &entry,
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(entry)
+    }
+
+    async fn leave_waitlist(&self, id: Uuid, staff: Option<&Actor>) -> Result<WaitlistEntryDTO> {
+        let now = time::OffsetDateTime::now_utc();
+        let mut tx = super::begin(&self.pool).await?;
+
+        let before = get_waitlist_entry(&mut tx, id).await?;
+        let entry = sqlx::query_as!(
+            WaitlistEntryDTO,
+            r#"update waitlist_entries
+            set status = $1, updated_at = $2
+            where id = $3 and status = $4
+            returning
+                id as "id: Uuid", class_id as "class_id: Uuid",
+                membership_id as "membership_id: Uuid", position,
+                status as "status: WaitlistStatus", booking_id as "booking_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            WaitlistStatus::Left,
+            now,
+            id,
+            WaitlistStatus::Waiting
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::unprocessable_entity([("waitlist_entry", "is not waiting")]))?;
+
+        let class = class::get_class(&mut tx, entry.class_id).await?;
+        event::record(
+            &mut tx,
+            Some(class.organization_id),
+            EventType::WaitlistLeft,
+            &entry,
+            now,
+        )
+        .await?;
+        if let Some(staff) = staff {
+            audit::record(
+                &mut tx,
+                staff,
+                class.organization_id,
+                AuditAction::WaitlistEntryRemoved,
+                entry.id,
+                Some(&before),
+                Some(&entry),
+                now,
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(entry)
+    }
+
+    async fn get_waitlist_entry(&self, id: Uuid) -> Result<WaitlistEntryDTO> {
+        get_waitlist_entry(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn list_waitlist(&self, class_id: Uuid) -> Result<Vec<WaitlistEntryDTO>> {
+        list_waiting(&mut *self.pool.acquire().await?, class_id).await
+    }
+}
+
+async fn get_waitlist_entry(conn: &mut SqliteConnection, id: Uuid) -> Result<WaitlistEntryDTO> {
+    sqlx::query_as!(
+        WaitlistEntryDTO,
+        r#"select
+            id as "id: Uuid", class_id as "class_id: Uuid",
+            membership_id as "membership_id: Uuid", position,
+            status as "status: WaitlistStatus", booking_id as "booking_id: Uuid",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from waitlist_entries
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+async fn list_waiting(
+    conn: &mut SqliteConnection,
+    class_id: Uuid,
+) -> Result<Vec<WaitlistEntryDTO>> {
+    let entries = sqlx::query_as!(
+        WaitlistEntryDTO,
+        r#"select
+            id as "id: Uuid", class_id as "class_id: Uuid",
+            membership_id as "membership_id: Uuid", position,
+            status as "status: WaitlistStatus", booking_id as "booking_id: Uuid",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from waitlist_entries
+        where class_id = $1 and status = $2
+        order by position"#,
+        class_id,
+        WaitlistStatus::Waiting
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(entries)
+}
+
+/// Books the first waitlisted member that is still allowed to book the class into a free spot.
+///
+/// Nothing is promoted once the class starts within the organization's
+/// `waitlist_cutoff_minutes`, so members aren't handed a spot they can't reasonably make.
+/// Entries that fail the booking rules are skipped and keep their position. The promoted
+/// member is notified.
+pub(crate) async fn promote_next(
+    conn: &mut SqliteConnection,
+    class: &ClassDTO,
+    now: OffsetDateTime,
+) -> Result<Option<WaitlistPromotion>> {
+    if class.cancelled_at.is_some() {
+        return Ok(None);
+    }
+
+    let organization = organization::get_organization(conn, class.organization_id).await?;
+
+    let cutoff = now.checked_add(time::Duration::minutes(
+        organization.waitlist_cutoff_minutes,
+    ));
+    if cutoff.is_none_or(|cutoff| cutoff >= class.starts_at) {
+        return Ok(None);
+    }
+
+    if booking::count_booked(conn, class.id).await? >= class.capacity {
+        return Ok(None);
+    }
+
+    for entry in list_waiting(conn, class.id).await? {
+        let entitlement = match booking::check_can_book(conn, class, entry.membership_id, now).await
+        {
+            Ok(entitlement) => entitlement,
+            Err(Error::UnprocessableEntity { .. }) => continue,
+            Err(e) => return Err(e),
+        };
+
+        let booking = booking::insert_booking(conn, class.id, entry.membership_id, now).await?;
+        booking::use_entitlement(conn, class, &booking, entitlement, now).await?;
+        reminder::schedule_class_reminder(conn, class, &booking, now).await?;
+        event::record(
+            conn,
+            Some(class.organization_id),
+            EventType::BookingCreated,
+            &booking,
+            now,
+        )
+        .await?;
+
+        let entry = sqlx::query_as!(
+            WaitlistEntryDTO,
+            r#"update waitlist_entries
+            set status = $1, booking_id = $2, updated_at = $3
+            where id = $4
+            returning
+                id as "id: Uuid", class_id as "class_id: Uuid",
+                membership_id as "membership_id: Uuid", position,
+                status as "status: WaitlistStatus", booking_id as "booking_id: Uuid",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            WaitlistStatus::Promoted,
+            booking.id,
+            now,
+            entry.id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        notification::notify(
+            conn,
+            NewNotification {
+                membership_id: entry.membership_id,
+                event: NotificationEvent::WaitlistPromoted,
+                variables: vec![
+                    ("class_name", class.name.clone()),
+                    ("starts_at", email::format_time(class.starts_at)),
+                ],
+            },
+            now,
+        )
+        .await?;
+
+        return Ok(Some(WaitlistPromotion { entry, booking }));
+    }
+
+    Ok(None)
+}
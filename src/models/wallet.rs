@@ -0,0 +1,469 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::credit_note::CreditNoteDTO;
+use super::dunning::{self, DunningOutcome};
+use super::invoice::{self, InvoiceDTO, InvoiceStatus};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum LedgerAccountKind {
+    /// What is left to redeem on a gift card, owned by the card.
+    GiftCard,
+    /// A member's stored value, owned by their membership.
+    Wallet,
+    /// The other side of the gift cards issued, i.e. what was taken for them. Owned by the
+    /// organization.
+    GiftCardsIssued,
+    /// What members paid invoices with out of their wallets, less what was refunded to them.
+    /// Owned by the organization.
+    WalletPayments,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum LedgerTransactionKind {
+    /// A gift card issued for its amount.
+    GiftCardIssue,
+    /// A gift card's balance moved into a member's wallet.
+    GiftCardRedemption,
+    /// An invoice paid out of a wallet.
+    Payment,
+    /// A refund of an invoice paid out of a wallet, back into it.
+    Refund,
+}
+
+/// A movement in or out of a member's wallet.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct WalletEntryDTO {
+    pub transaction_id: Uuid,
+    pub kind: LedgerTransactionKind,
+    /// Positive for money put in the wallet, negative for money spent from it.
+    pub amount: i64,
+    pub currency: String,
+    pub gift_card_id: Option<Uuid>,
+    pub invoice_id: Option<Uuid>,
+    pub credit_note_id: Option<Uuid>,
+    pub inserted_at: OffsetDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct WalletBalanceDTO {
+    pub currency: String,
+    pub balance: i64,
+}
+
+/// A member's stored value, one balance per currency they hold any in. Balances are always
+/// summed from the ledger.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct WalletDTO {
+    pub membership_id: Uuid,
+    pub balances: Vec<WalletBalanceDTO>,
+    pub entries: Vec<WalletEntryDTO>,
+}
+
+/// The accounts of one kind and currency, summed.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct LedgerBalanceDTO {
+    pub kind: LedgerAccountKind,
+    pub currency: String,
+    pub accounts_count: i64,
+    pub balance: i64,
+}
+
+/// The organization's ledger, for reconciling gift cards and wallets against what was taken
+/// for them and spent from them.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LedgerDTO {
+    pub organization_id: Uuid,
+    pub balances: Vec<LedgerBalanceDTO>,
+    /// Whether every currency's balances add up to zero, as they do unless the ledger was
+    /// written to outside of its transactions.
+    pub balanced: bool,
+}
+
+/// Money moved between ledger accounts. Entries are never changed once written.
+pub(crate) struct NewLedgerTransaction {
+    pub organization_id: Uuid,
+    pub kind: LedgerTransactionKind,
+    pub currency: String,
+    pub membership_id: Option<Uuid>,
+    pub gift_card_id: Option<Uuid>,
+    pub invoice_id: Option<Uuid>,
+    pub credit_note_id: Option<Uuid>,
+    /// The amount posted to each account, which must add up to zero.
+    pub entries: Vec<(Uuid, i64)>,
+}
+
+#[derive(Clone)]
+pub struct WalletController {
+    pool: SqlitePool,
+}
+
+impl WalletController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynWalletCtrl = Arc<dyn WalletCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait WalletCtrlTrait {
+    async fn get_wallet(&self, membership_id: Uuid) -> Result<WalletDTO>;
+
+    /// Pays the invoice out of the member's wallet if it holds enough to, for billing to try
+    /// before charging their saved payment method. `None` if it doesn't.
+    async fn pay_invoice(
+        &self,
+        invoice_id: Uuid,
+        now: OffsetDateTime,
+    ) -> Result<Option<DunningOutcome>>;
+
+    async fn get_ledger(&self, organization_id: Uuid) -> Result<LedgerDTO>;
+}
+
+#[async_trait]
+impl WalletCtrlTrait for WalletController {
+    async fn get_wallet(&self, membership_id: Uuid) -> Result<WalletDTO> {
+        let mut conn = self.pool.acquire().await?;
+
+        let balances = sqlx::query_as!(
+            WalletBalanceDTO,
+            r#"select a.currency, coalesce(sum(e.amount), 0) as "balance!: i64"
+            from ledger_accounts a
+            left join ledger_entries e on e.account_id = a.id
+            where a.kind = $1 and a.owner_id = $2
+            group by a.currency
+            order by a.currency"#,
+            LedgerAccountKind::Wallet,
+            membership_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let entries = sqlx::query_as!(
+            WalletEntryDTO,
+            r#"select
+                t.id as "transaction_id: Uuid", t.kind as "kind: LedgerTransactionKind",
+                e.amount, t.currency,
+                t.gift_card_id as "gift_card_id: Uuid", t.invoice_id as "invoice_id: Uuid",
+                t.credit_note_id as "credit_note_id: Uuid",
+                t.inserted_at as "inserted_at: OffsetDateTime"
+            from ledger_entries e
+            join ledger_accounts a on a.id = e.account_id
+            join ledger_transactions t on t.id = e.transaction_id
+            where a.kind = $1 and a.owner_id = $2
+            order by t.inserted_at desc"#,
+            LedgerAccountKind::Wallet,
+            membership_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(WalletDTO {
+            membership_id,
+            balances,
+            entries,
+        })
+    }
+
+    async fn pay_invoice(
+        &self,
+        invoice_id: Uuid,
+        now: OffsetDateTime,
+    ) -> Result<Option<DunningOutcome>> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let invoice = invoice::get_invoice(&mut tx, invoice_id).await?;
+        if invoice.status != InvoiceStatus::Open || invoice.total_amount <= 0 {
+            return Ok(None);
+        }
+        let wallet = ledger_account(
+            &mut tx,
+            invoice.organization_id,
+            LedgerAccountKind::Wallet,
+            invoice.membership_id,
+            &invoice.currency,
+            now,
+        )
+        .await?;
+        if balance(&mut tx, wallet).await? < invoice.total_amount {
+            return Ok(None);
+        }
+
+        pay_from_wallet(&mut tx, &invoice, now).await?;
+        let outcome = dunning::payment_succeeded(&mut tx, invoice, None, now).await?;
+
+        tx.commit().await?;
+
+        Ok(Some(outcome))
+    }
+
+    async fn get_ledger(&self, organization_id: Uuid) -> Result<LedgerDTO> {
+        let balances = sqlx::query_as!(
+            LedgerBalanceDTO,
+            r#"select
+                a.kind as "kind: LedgerAccountKind", a.currency,
+                count(distinct a.id) as "accounts_count!: i64",
+                coalesce(sum(e.amount), 0) as "balance!: i64"
+            from ledger_accounts a
+            left join ledger_entries e on e.account_id = a.id
+            where a.organization_id = $1
+            group by a.kind, a.currency
+            order by a.currency, a.kind"#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let balanced = balances.iter().all(|b| {
+            balances
+                .iter()
+                .filter(|other| other.currency == b.currency)
+                .map(|other| other.balance)
+                .sum::<i64>()
+                == 0
+        });
+
+        Ok(LedgerDTO {
+            organization_id,
+            balances,
+            balanced,
+        })
+    }
+}
+
+/// The account of `kind` owned by `owner_id` in `currency`, opened on first use.
+pub(crate) async fn ledger_account(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+    kind: LedgerAccountKind,
+    owner_id: Uuid,
+    currency: &str,
+    now: OffsetDateTime,
+) -> Result<Uuid> {
+    let id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"insert into "ledger_accounts" (id, organization_id, kind, owner_id, currency, inserted_at)
+        values ($1, $2, $3, $4, $5, $6)
+        on conflict do nothing"#,
+        id,
+        organization_id,
+        kind,
+        owner_id,
+        currency,
+        now
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let id = sqlx::query_scalar!(
+        r#"select id as "id: Uuid" from ledger_accounts
+        where organization_id = $1 and kind = $2 and owner_id = $3 and currency = $4"#,
+        organization_id,
+        kind,
+        owner_id,
+        currency
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(id)
+}
+
+pub(crate) async fn balance(conn: &mut SqliteConnection, account_id: Uuid) -> Result<i64> {
+    let balance = sqlx::query_scalar!(
+        r#"select coalesce(sum(amount), 0) as "balance!: i64" from ledger_entries
+        where account_id = $1"#,
+        account_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(balance)
+}
+
+/// Writes the transaction, refusing one whose entries don't add up to zero.
+pub(crate) async fn post(
+    conn: &mut SqliteConnection,
+    new_transaction: NewLedgerTransaction,
+    now: OffsetDateTime,
+) -> Result<Uuid> {
+    if new_transaction
+        .entries
+        .iter()
+        .map(|(_, amount)| amount)
+        .sum::<i64>()
+        != 0
+    {
+        return Err(anyhow::anyhow!(
+            "{:?} ledger transaction doesn't balance",
+            new_transaction.kind
+        )
+        .into());
+    }
+
+    let id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"insert into "ledger_transactions" (
+            id, organization_id, kind, currency,
+            membership_id, gift_card_id, invoice_id, credit_note_id,
+            inserted_at
+        ) VALUES (
+            $1, $2, $3, $4,
+            $5, $6, $7, $8,
+            $9
+        )"#,
+        id,
+        new_transaction.organization_id,
+        new_transaction.kind,
+        new_transaction.currency,
+        new_transaction.membership_id,
+        new_transaction.gift_card_id,
+        new_transaction.invoice_id,
+        new_transaction.credit_note_id,
+        now
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    for (account_id, amount) in new_transaction.entries {
+        let entry_id = uuid::Uuid::new_v4();
+        sqlx::query!(
+            r#"insert into "ledger_entries" (id, transaction_id, account_id, amount)
+            values ($1, $2, $3, $4)"#,
+            entry_id,
+            id,
+            account_id,
+            amount
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(id)
+}
+
+/// Takes the invoice's total out of the member's wallet, leaving the caller to mark it paid.
+pub(crate) async fn pay_from_wallet(
+    conn: &mut SqliteConnection,
+    invoice: &InvoiceDTO,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let wallet = ledger_account(
+        conn,
+        invoice.organization_id,
+        LedgerAccountKind::Wallet,
+        invoice.membership_id,
+        &invoice.currency,
+        now,
+    )
+    .await?;
+    let payments = ledger_account(
+        conn,
+        invoice.organization_id,
+        LedgerAccountKind::WalletPayments,
+        invoice.organization_id,
+        &invoice.currency,
+        now,
+    )
+    .await?;
+
+    post(
+        conn,
+        NewLedgerTransaction {
+            organization_id: invoice.organization_id,
+            kind: LedgerTransactionKind::Payment,
+            currency: invoice.currency.clone(),
+            membership_id: Some(invoice.membership_id),
+            gift_card_id: None,
+            invoice_id: Some(invoice.id),
+            credit_note_id: None,
+            entries: vec![
+                (wallet, -invoice.total_amount),
+                (payments, invoice.total_amount),
+            ],
+        },
+        now,
+    )
+    .await?;
+
+    // Checked after posting, which takes SQLite's write lock, so two payments at the same
+    // time can't both spend the same balance.
+    if balance(conn, wallet).await? < 0 {
+        return Err(Error::unprocessable_entity([(
+            "payment_method",
+            "the member's wallet doesn't hold enough to pay for this",
+        )]));
+    }
+
+    Ok(())
+}
+
+/// Puts the credit note's amount back in the member's wallet if its invoice was paid out of
+/// it, returning whether it was.
+pub(crate) async fn refund_to_wallet(
+    conn: &mut SqliteConnection,
+    credit_note: &CreditNoteDTO,
+    now: OffsetDateTime,
+) -> Result<bool> {
+    let paid_from_wallet = sqlx::query_scalar!(
+        r#"select count(*) as "count!: i64" from ledger_transactions
+        where invoice_id = $1 and kind = $2"#,
+        credit_note.invoice_id,
+        LedgerTransactionKind::Payment
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if paid_from_wallet == 0 {
+        return Ok(false);
+    }
+
+    let wallet = ledger_account(
+        conn,
+        credit_note.organization_id,
+        LedgerAccountKind::Wallet,
+        credit_note.membership_id,
+        &credit_note.currency,
+        now,
+    )
+    .await?;
+    let payments = ledger_account(
+        conn,
+        credit_note.organization_id,
+        LedgerAccountKind::WalletPayments,
+        credit_note.organization_id,
+        &credit_note.currency,
+        now,
+    )
+    .await?;
+
+    post(
+        conn,
+        NewLedgerTransaction {
+            organization_id: credit_note.organization_id,
+            kind: LedgerTransactionKind::Refund,
+            currency: credit_note.currency.clone(),
+            membership_id: Some(credit_note.membership_id),
+            gift_card_id: None,
+            invoice_id: Some(credit_note.invoice_id),
+            credit_note_id: Some(credit_note.id),
+            entries: vec![
+                (payments, -credit_note.amount),
+                (wallet, credit_note.amount),
+            ],
+        },
+        now,
+    )
+    .await?;
+
+    Ok(true)
+}
@@ -0,0 +1,695 @@
+use std::sync::Arc;
+
+use crate::http::{Error, Result};
+use async_trait::async_trait;
+
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx::types::Json;
+use sqlx::{SqliteConnection, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::audit::{self, Actor, AuditAction};
+use super::event::{EventDTO, EventType};
+use super::job::{self, JobKind, JobPayload};
+use super::organization;
+
+/// How many of an endpoint's deliveries are listed, the most recent first.
+const DELIVERIES_LISTED: i64 = 100;
+
+/// Random characters after `whsec_` in an endpoint's signing secret.
+const SECRET_LENGTH: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    /// Waiting to be posted, also after a failed attempt.
+    Pending,
+    Delivered,
+    /// Every attempt failed. It can still be replayed.
+    Failed,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewWebhookEndpoint {
+    pub url: String,
+    pub description: Option<String>,
+    pub events: Vec<EventType>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateWebhookEndpoint {
+    pub url: Option<String>,
+    #[serde(default, deserialize_with = "super::double_option")]
+    pub description: Option<Option<String>>,
+    pub events: Option<Vec<EventType>>,
+}
+
+/// Where an organization wants its events posted, e.g. a studio's CRM.
+#[derive(Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct WebhookEndpointDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    /// An `https` URL on the public internet.
+    pub url: String,
+    pub description: Option<String>,
+    /// The events posted to the endpoint.
+    pub events: Json<Vec<EventType>>,
+    /// Deliveries are signed with this, see `integrations::SIGNATURE_HEADER`.
+    pub secret: String,
+    pub archived_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl WebhookEndpointDTO {
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if crate::notify::parse_public_url(&self.url).is_err() {
+            errors.push(("url", "must be a public https URL"));
+        }
+        if self.events.is_empty() {
+            errors.push(("events", "must name at least one event"));
+        }
+        if !self
+            .events
+            .iter()
+            .all(|event| event.is_organization_event())
+        {
+            errors.push(("events", "can only name events of the organization"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::unprocessable_entity(errors))
+        }
+    }
+}
+
+/// An event posted, or to be posted, to an endpoint, and how that went.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct WebhookDeliveryDTO {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub endpoint_id: Uuid,
+    /// The event's id in the event log. The same for every delivery of the event, replays
+    /// included, so endpoints can tell they have seen it before.
+    pub event_id: Uuid,
+    pub event: EventType,
+    /// The JSON posted, `{"id", "type", "organization_id", "created_at", "data"}`.
+    pub payload: Json<serde_json::Value>,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i64,
+    /// The status the endpoint answered the last attempt with, if it answered.
+    pub response_status: Option<i64>,
+    pub last_error: Option<String>,
+    /// The delivery this one posts again.
+    pub replay_of: Option<Uuid>,
+    pub delivered_at: Option<OffsetDateTime>,
+    pub inserted_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+/// How an attempt at posting a delivery went.
+pub struct DeliveryAttempt {
+    pub response_status: Option<u16>,
+    /// `None` if the endpoint accepted the delivery.
+    pub error: Option<String>,
+}
+
+/// The payload of a `JobKind::WebhookDelivery` job.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct WebhookDelivery {
+    pub delivery_id: Uuid,
+}
+
+impl JobPayload for WebhookDelivery {
+    const KIND: JobKind = JobKind::WebhookDelivery;
+}
+
+#[derive(Clone)]
+pub struct WebhookController {
+    pool: SqlitePool,
+}
+
+impl WebhookController {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+pub type DynWebhookCtrl = Arc<dyn WebhookCtrlTrait + Send + Sync>;
+#[async_trait]
+pub trait WebhookCtrlTrait {
+    async fn create_endpoint(
+        &self,
+        organization_id: Uuid,
+        new_endpoint: NewWebhookEndpoint,
+        actor: &Actor,
+    ) -> Result<WebhookEndpointDTO>;
+    async fn update_endpoint(
+        &self,
+        id: Uuid,
+        update_endpoint: UpdateWebhookEndpoint,
+        actor: &Actor,
+    ) -> Result<WebhookEndpointDTO>;
+    /// Stops posting events to the endpoint, its deliveries are kept.
+    async fn archive_endpoint(&self, id: Uuid, actor: &Actor) -> Result<WebhookEndpointDTO>;
+    /// Lists the endpoints events are posted to.
+    async fn list_endpoints(&self, organization_id: Uuid) -> Result<Vec<WebhookEndpointDTO>>;
+    async fn get_endpoint(&self, id: Uuid) -> Result<WebhookEndpointDTO>;
+
+    async fn list_deliveries(&self, endpoint_id: Uuid) -> Result<Vec<WebhookDeliveryDTO>>;
+    async fn get_delivery(&self, id: Uuid) -> Result<WebhookDeliveryDTO>;
+
+    /// Posts a delivery's event to its endpoint again, as a new delivery, e.g. once the
+    /// endpoint is fixed or after data was lost on its side.
+    async fn replay_delivery(&self, id: Uuid, actor: &Actor) -> Result<WebhookDeliveryDTO>;
+
+    /// Records an attempt at posting a pending delivery. A failed delivery stays pending
+    /// for the job queue to retry, unless it was the `last` attempt.
+    async fn record_delivery_attempt(
+        &self,
+        id: Uuid,
+        attempt: DeliveryAttempt,
+        last: bool,
+        now: OffsetDateTime,
+    ) -> Result<WebhookDeliveryDTO>;
+}
+
+#[async_trait]
+impl WebhookCtrlTrait for WebhookController {
+    async fn create_endpoint(
+        &self,
+        organization_id: Uuid,
+        new_endpoint: NewWebhookEndpoint,
+        actor: &Actor,
+    ) -> Result<WebhookEndpointDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        organization::get_organization(&mut tx, organization_id).await?;
+        let inserted_at = time::OffsetDateTime::now_utc();
+
+        let endpoint = WebhookEndpointDTO {
+            id: uuid::Uuid::new_v4(),
+            organization_id,
+            url: new_endpoint.url.trim().to_string(),
+            description: new_endpoint.description,
+            events: Json(dedup_events(new_endpoint.events)),
+            secret: generate_secret(),
+            archived_at: None,
+            inserted_at,
+            updated_at: inserted_at,
+        };
+        endpoint.validate()?;
+
+        sqlx::query!(
+            r#"insert into "webhook_endpoints" (
+                id, organization_id, url, description, events, secret,
+                inserted_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6,
+                $7, $8
+            )"#,
+            endpoint.id,
+            endpoint.organization_id,
+            endpoint.url,
+            endpoint.description,
+            endpoint.events,
+            endpoint.secret,
+            endpoint.inserted_at,
+            endpoint.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        audit::record(
+            &mut tx,
+            actor,
+            organization_id,
+            AuditAction::WebhookEndpointCreated,
+            endpoint.id,
+            None,
+            Some(&endpoint),
+            inserted_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(endpoint)
+    }
+
+    async fn update_endpoint(
+        &self,
+        id: Uuid,
+        update_endpoint: UpdateWebhookEndpoint,
+        actor: &Actor,
+    ) -> Result<WebhookEndpointDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_endpoint(&mut tx, id).await?;
+        let before = current.clone();
+        if current.archived_at.is_some() {
+            return Err(Error::unprocessable_entity([(
+                "webhook_endpoint",
+                "is archived",
+            )]));
+        }
+        let endpoint = WebhookEndpointDTO {
+            url: update_endpoint
+                .url
+                .map(|url| url.trim().to_string())
+                .unwrap_or(current.url),
+            description: update_endpoint.description.unwrap_or(current.description),
+            events: update_endpoint
+                .events
+                .map(|events| Json(dedup_events(events)))
+                .unwrap_or(current.events),
+            updated_at: time::OffsetDateTime::now_utc(),
+            ..current
+        };
+        endpoint.validate()?;
+
+        sqlx::query!(
+            r#"update webhook_endpoints set
+                url = $1, description = $2, events = $3, updated_at = $4
+            where id = $5"#,
+            endpoint.url,
+            endpoint.description,
+            endpoint.events,
+            endpoint.updated_at,
+            endpoint.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        audit::record(
+            &mut tx,
+            actor,
+            endpoint.organization_id,
+            AuditAction::WebhookEndpointUpdated,
+            endpoint.id,
+            Some(&before),
+            Some(&endpoint),
+            endpoint.updated_at,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(endpoint)
+    }
+
+    async fn archive_endpoint(&self, id: Uuid, actor: &Actor) -> Result<WebhookEndpointDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let current = get_endpoint(&mut tx, id).await?;
+        let before = current.clone();
+        let now = time::OffsetDateTime::now_utc();
+        let endpoint = WebhookEndpointDTO {
+            archived_at: current.archived_at.or(Some(now)),
+            updated_at: now,
+            ..current
+        };
+
+        sqlx::query!(
+            "update webhook_endpoints set archived_at = $1, updated_at = $2 where id = $3",
+            endpoint.archived_at,
+            endpoint.updated_at,
+            endpoint.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        audit::record(
+            &mut tx,
+            actor,
+            endpoint.organization_id,
+            AuditAction::WebhookEndpointArchived,
+            endpoint.id,
+            Some(&before),
+            Some(&endpoint),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(endpoint)
+    }
+
+    async fn list_endpoints(&self, organization_id: Uuid) -> Result<Vec<WebhookEndpointDTO>> {
+        let endpoints =
+            list_active_endpoints(&mut *self.pool.acquire().await?, organization_id).await?;
+
+        Ok(endpoints)
+    }
+
+    async fn get_endpoint(&self, id: Uuid) -> Result<WebhookEndpointDTO> {
+        get_endpoint(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn list_deliveries(&self, endpoint_id: Uuid) -> Result<Vec<WebhookDeliveryDTO>> {
+        let deliveries = sqlx::query_as!(
+            WebhookDeliveryDTO,
+            r#"select
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                endpoint_id as "endpoint_id: Uuid", event_id as "event_id: Uuid",
+                event as "event: EventType", payload as "payload: Json<serde_json::Value>",
+                status as "status: WebhookDeliveryStatus", attempts, response_status,
+                last_error, replay_of as "replay_of: Uuid",
+                delivered_at as "delivered_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+            from webhook_deliveries
+            where endpoint_id = $1
+            order by inserted_at desc
+            limit $2"#,
+            endpoint_id,
+            DELIVERIES_LISTED
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    async fn get_delivery(&self, id: Uuid) -> Result<WebhookDeliveryDTO> {
+        get_delivery(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn replay_delivery(&self, id: Uuid, actor: &Actor) -> Result<WebhookDeliveryDTO> {
+        let mut tx = super::begin(&self.pool).await?;
+
+        let original = get_delivery(&mut tx, id).await?;
+        let endpoint = get_endpoint(&mut tx, original.endpoint_id).await?;
+        if endpoint.archived_at.is_some() {
+            return Err(Error::unprocessable_entity([(
+                "webhook_endpoint",
+                "is archived",
+            )]));
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let delivery = insert_delivery(
+            &mut tx,
+            &endpoint,
+            original.event_id,
+            original.event,
+            original.payload,
+            Some(original.id),
+            now,
+        )
+        .await?;
+
+        audit::record(
+            &mut tx,
+            actor,
+            delivery.organization_id,
+            AuditAction::WebhookDeliveryReplayed,
+            delivery.id,
+            None,
+            Some(&delivery),
+            now,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(delivery)
+    }
+
+    async fn record_delivery_attempt(
+        &self,
+        id: Uuid,
+        attempt: DeliveryAttempt,
+        last: bool,
+        now: OffsetDateTime,
+    ) -> Result<WebhookDeliveryDTO> {
+        let status = match (&attempt.error, last) {
+            (None, _) => WebhookDeliveryStatus::Delivered,
+            (Some(_), false) => WebhookDeliveryStatus::Pending,
+            (Some(_), true) => WebhookDeliveryStatus::Failed,
+        };
+        let delivered_at = attempt.error.is_none().then_some(now);
+        let response_status = attempt.response_status.map(i64::from);
+
+        let delivery = sqlx::query_as!(
+            WebhookDeliveryDTO,
+            r#"update webhook_deliveries set
+                status = $1, attempts = attempts + 1, response_status = $2,
+                last_error = $3, delivered_at = $4, updated_at = $5
+            where id = $6 and status = $7
+            returning
+                id as "id: Uuid", organization_id as "organization_id: Uuid",
+                endpoint_id as "endpoint_id: Uuid", event_id as "event_id: Uuid",
+                event as "event: EventType", payload as "payload: Json<serde_json::Value>",
+                status as "status: WebhookDeliveryStatus", attempts, response_status,
+                last_error, replay_of as "replay_of: Uuid",
+                delivered_at as "delivered_at: OffsetDateTime",
+                inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime""#,
+            status,
+            response_status,
+            attempt.error,
+            delivered_at,
+            now,
+            id,
+            WebhookDeliveryStatus::Pending
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match delivery {
+            Some(delivery) => Ok(delivery),
+            None => self.get_delivery(id).await,
+        }
+    }
+}
+
+fn generate_secret() -> String {
+    format!(
+        "whsec_{}",
+        Alphanumeric.sample_string(&mut rand::thread_rng(), SECRET_LENGTH)
+    )
+}
+
+fn dedup_events(events: Vec<EventType>) -> Vec<EventType> {
+    let mut unique = Vec::with_capacity(events.len());
+    for event in events {
+        if !unique.contains(&event) {
+            unique.push(event);
+        }
+    }
+
+    unique
+}
+
+async fn list_active_endpoints(
+    conn: &mut SqliteConnection,
+    organization_id: Uuid,
+) -> Result<Vec<WebhookEndpointDTO>> {
+    let endpoints = sqlx::query_as!(
+        WebhookEndpointDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid", url, description,
+            events as "events: Json<Vec<EventType>>", secret,
+            archived_at as "archived_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from webhook_endpoints
+        where organization_id = $1 and archived_at is null
+        order by inserted_at"#,
+        organization_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(endpoints)
+}
+
+async fn get_endpoint(conn: &mut SqliteConnection, id: Uuid) -> Result<WebhookEndpointDTO> {
+    sqlx::query_as!(
+        WebhookEndpointDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid", url, description,
+            events as "events: Json<Vec<EventType>>", secret,
+            archived_at as "archived_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from webhook_endpoints
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+async fn get_delivery(conn: &mut SqliteConnection, id: Uuid) -> Result<WebhookDeliveryDTO> {
+    sqlx::query_as!(
+        WebhookDeliveryDTO,
+        r#"select
+            id as "id: Uuid", organization_id as "organization_id: Uuid",
+            endpoint_id as "endpoint_id: Uuid", event_id as "event_id: Uuid",
+            event as "event: EventType", payload as "payload: Json<serde_json::Value>",
+            status as "status: WebhookDeliveryStatus", attempts, response_status,
+            last_error, replay_of as "replay_of: Uuid",
+            delivered_at as "delivered_at: OffsetDateTime",
+            inserted_at as "inserted_at: OffsetDateTime", updated_at as "updated_at: OffsetDateTime"
+        from webhook_deliveries
+        where id = $1"#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+/// Writes a delivery and schedules the job that posts it.
+async fn insert_delivery(
+    conn: &mut SqliteConnection,
+    endpoint: &WebhookEndpointDTO,
+    event_id: Uuid,
+    event: EventType,
+    payload: Json<serde_json::Value>,
+    replay_of: Option<Uuid>,
+    now: OffsetDateTime,
+) -> Result<WebhookDeliveryDTO> {
+    let delivery = WebhookDeliveryDTO {
+        id: uuid::Uuid::new_v4(),
+        organization_id: endpoint.organization_id,
+        endpoint_id: endpoint.id,
+        event_id,
+        event,
+        payload,
+        status: WebhookDeliveryStatus::Pending,
+        attempts: 0,
+        response_status: None,
+        last_error: None,
+        replay_of,
+        delivered_at: None,
+        inserted_at: now,
+        updated_at: now,
+    };
+
+    sqlx::query!(
+        r#"insert into "webhook_deliveries" (
+            id, organization_id, endpoint_id, event_id, event, payload, status,
+            replay_of, inserted_at, updated_at
+        ) VALUES (
+            $1, $2, $3, $4, $5, $6, $7,
+            $8, $9, $10
+        )"#,
+        delivery.id,
+        delivery.organization_id,
+        delivery.endpoint_id,
+        delivery.event_id,
+        delivery.event,
+        delivery.payload,
+        delivery.status,
+        delivery.replay_of,
+        delivery.inserted_at,
+        delivery.updated_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    job::schedule(
+        conn,
+        &format!("webhook_delivery:{}", delivery.id),
+        &WebhookDelivery {
+            delivery_id: delivery.id,
+        },
+        now,
+        now,
+    )
+    .await?;
+
+    Ok(delivery)
+}
+
+/// Queues a delivery of the event to each of its organization's endpoints that subscribed
+/// to it. Called by `event::record`, in the transaction making the change.
+pub(crate) async fn publish(conn: &mut SqliteConnection, event: &EventDTO) -> Result<()> {
+    let Some(organization_id) = event.organization_id else {
+        return Ok(());
+    };
+    let endpoints = list_active_endpoints(conn, organization_id).await?;
+    let endpoints: Vec<_> = endpoints
+        .into_iter()
+        .filter(|endpoint| endpoint.events.contains(&event.event))
+        .collect();
+    if endpoints.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::json!({
+        "id": event.id,
+        "type": event.event,
+        "organization_id": organization_id,
+        "created_at": event.inserted_at,
+        "data": event.data.0,
+    });
+
+    for endpoint in &endpoints {
+        insert_delivery(
+            conn,
+            endpoint,
+            event.id,
+            event.event,
+            Json(payload.clone()),
+            None,
+            event.inserted_at,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(url: &str) -> WebhookEndpointDTO {
+        let now = OffsetDateTime::now_utc();
+        WebhookEndpointDTO {
+            id: Uuid::new_v4(),
+            organization_id: Uuid::new_v4(),
+            url: url.to_string(),
+            description: None,
+            events: Json(vec![EventType::BookingCreated]),
+            secret: generate_secret(),
+            archived_at: None,
+            inserted_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn https_endpoint_is_accepted() {
+        assert!(endpoint("https://crm.example.com/hooks/rustfit")
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn http_endpoint_is_refused() {
+        assert!(endpoint("http://crm.example.com/hooks/rustfit")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn internal_endpoint_is_refused() {
+        for url in [
+            "http://localhost:3000/hooks",
+            "http://10.0.0.5/hooks",
+            "http://169.254.169.254/latest/meta-data/",
+            "ftp://crm.example.com/hooks",
+        ] {
+            assert!(endpoint(url).validate().is_err(), "{url} should be refused");
+        }
+    }
+}
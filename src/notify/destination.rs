@@ -0,0 +1,213 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Client, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+
+/// Connects over https, to public addresses only.
+pub type PublicConnector = HttpsConnector<HttpConnector<PublicResolver>>;
+
+/// A client for URLs members and organizations give us, which mustn't be able to reach the
+/// server's own network, e.g. a cloud provider's metadata service.
+pub fn public_client() -> Client<PublicConnector> {
+    let mut http = HttpConnector::new_with_resolver(PublicResolver::new());
+    http.enforce_http(false);
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_only()
+        .enable_http1()
+        .wrap_connector(http);
+
+    Client::builder().build(connector)
+}
+
+/// Resolves host names like hyper's own resolver, leaving out the addresses that aren't
+/// public. Checking when connecting, not only when the URL is saved, also catches a host
+/// that has since been pointed somewhere internal.
+#[derive(Clone)]
+pub struct PublicResolver {
+    inner: GaiResolver,
+}
+
+impl PublicResolver {
+    pub fn new() -> Self {
+        Self {
+            inner: GaiResolver::new(),
+        }
+    }
+}
+
+impl Default for PublicResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolving = self.inner.call(name.clone());
+
+        Box::pin(async move {
+            let addrs: Vec<_> = resolving
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{name} doesn't resolve to a public address"),
+                ));
+            }
+
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Refuses URLs whose host is plainly not public, e.g. `localhost` or `10.0.0.1`. Host names
+/// are only resolved when connecting, by `PublicResolver`.
+pub(super) fn check_host(uri: &Uri) -> anyhow::Result<()> {
+    let host = uri.host().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let public = match host.parse::<IpAddr>() {
+        Ok(ip) => is_public(ip),
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host != "localhost" && !host.ends_with(".localhost")
+        }
+    };
+    anyhow::ensure!(public, "{uri} isn't a public address");
+
+    Ok(())
+}
+
+/// Whether `ip` is on the public internet, as opposed to loopback, a private network or
+/// link-local, which includes the metadata service at 169.254.169.254.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) if ip.is_loopback() || ip.is_unspecified() => false,
+        // IPv4 addresses written as IPv6 ones, e.g. `::ffff:127.0.0.1`.
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network", e.g. 0.1.2.3.
+        || a == 0
+        // Shared by carrier-grade NAT.
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking.
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // NAT64 reaches IPv4 addresses through the last 32 bits.
+    if segments[0] == 0x64 && segments[1] == 0xff9b {
+        let [.., a, b, c, d] = ip.octets();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+
+    !(ip.is_multicast()
+        // Unique local, e.g. fd00:ec2::254.
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local.
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation.
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+            "fd00:ec2::254",
+            "fe80::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} should not be public");
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} should be public");
+        }
+    }
+
+    #[test]
+    fn internal_hosts_are_refused() {
+        for url in [
+            "http://localhost:8080/hook",
+            "https://api.localhost/hook",
+            "http://127.0.0.1/hook",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data",
+        ] {
+            let uri: Uri = url.parse().unwrap();
+            assert!(check_host(&uri).is_err(), "{url} should be refused");
+        }
+
+        let uri: Uri = "https://hooks.example.com/rustfit".parse().unwrap();
+        assert!(check_host(&uri).is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_internal_hosts() {
+        let mut resolver = PublicResolver::new();
+        let resolved = resolver.call("localhost".parse().unwrap()).await;
+
+        assert_eq!(
+            resolved.unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+}
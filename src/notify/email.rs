@@ -0,0 +1,39 @@
+use async_trait::async_trait;
+
+use super::{DeliveryError, Notification, NotificationChannel, Result};
+use crate::mail::{DynMailer, MailError, Message};
+
+/// Emails notifications through the mailer.
+pub struct EmailChannel {
+    mailer: DynMailer,
+    from_address: String,
+}
+
+impl EmailChannel {
+    pub fn new(mailer: DynMailer, from_address: String) -> Self {
+        Self {
+            mailer,
+            from_address,
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for EmailChannel {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        let message = Message {
+            id: notification.id,
+            from_name: notification.from_name.clone(),
+            from_address: self.from_address.clone(),
+            to_address: notification.recipient.clone(),
+            subject: notification.subject.clone(),
+            body: notification.body.clone(),
+            date: notification.created_at,
+        };
+
+        self.mailer.send(&message).await.map_err(|e| match e {
+            MailError::Rejected(message) => DeliveryError::Rejected(message),
+            MailError::Unavailable(e) => DeliveryError::Unavailable(e),
+        })
+    }
+}
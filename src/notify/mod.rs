@@ -0,0 +1,161 @@
+//! Delivering notifications to members over the channels they chose.
+//!
+//! Like mail, handlers and jobs only ever see the channels in `Notifiers`, which are chosen
+//! once, from `Config`, when the server starts. Notifications aren't delivered from requests
+//! but written to the database first, see `models::notification`.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use hyper::client::connect::Connect;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::mail::DynMailer;
+
+/// Gives up on a server that stops answering, the notification is retried later.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+mod destination;
+mod email;
+mod sms;
+mod webhook;
+
+pub use destination::{is_public, public_client, PublicConnector};
+pub use email::EmailChannel;
+pub use sms::{HttpSmsChannel, LogSmsChannel};
+pub use webhook::WebhookChannel;
+
+pub type DynNotificationChannel = Arc<dyn NotificationChannel + Send + Sync>;
+
+/// A way of reaching a member, e.g. email or text message.
+#[async_trait]
+pub trait NotificationChannel {
+    async fn deliver(&self, notification: &Notification) -> Result<()>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeliveryError {
+    /// The recipient can't be reached this way for good, e.g. a phone number that doesn't
+    /// exist. Delivering it again won't help.
+    #[error("notification rejected: {0}")]
+    Rejected(String),
+
+    /// The channel couldn't be reached or failed on its side.
+    #[error("notification channel unavailable")]
+    Unavailable(#[from] anyhow::Error),
+}
+
+pub type Result<T, E = DeliveryError> = std::result::Result<T, E>;
+
+/// A notification to a member, as posted to their webhook.
+#[derive(serde::Serialize)]
+pub struct Notification {
+    pub id: Uuid,
+    /// What happened, e.g. `booking_confirmed`.
+    pub event: String,
+    pub organization_id: Uuid,
+    pub membership_id: Uuid,
+    /// The email address, phone number or URL the member gave for the channel.
+    #[serde(skip)]
+    pub recipient: String,
+    /// The organization's name.
+    pub from_name: String,
+    pub subject: String,
+    pub body: String,
+    /// What the notification is about, e.g. the class's name and start time.
+    pub data: serde_json::Value,
+    pub created_at: OffsetDateTime,
+}
+
+/// A channel for each way of reaching members.
+#[derive(Clone)]
+pub struct Notifiers {
+    pub email: DynNotificationChannel,
+    pub sms: DynNotificationChannel,
+    pub webhook: DynNotificationChannel,
+}
+
+/// The channels `config` asks for. Email goes out through `mailer`.
+pub fn from_config(config: &Config, mailer: DynMailer) -> anyhow::Result<Notifiers> {
+    let sms = match &config.sms_gateway_url {
+        Some(url) => Arc::new(HttpSmsChannel::new(url, config.sms_api_key.clone())?)
+            as DynNotificationChannel,
+        None => Arc::new(LogSmsChannel) as DynNotificationChannel,
+    };
+
+    Ok(Notifiers {
+        email: Arc::new(EmailChannel::new(mailer, config.mail_from.clone())),
+        sms,
+        webhook: Arc::new(WebhookChannel::new()),
+    })
+}
+
+/// Parses a URL notifications are posted to, over https.
+pub fn parse_https_url(url: &str) -> anyhow::Result<Uri> {
+    let uri: Uri = url
+        .parse()
+        .with_context(|| format!("invalid URL {url:?}"))?;
+    anyhow::ensure!(
+        uri.scheme_str() == Some("https") && uri.host().is_some(),
+        "URL {url:?} must use https"
+    );
+
+    Ok(uri)
+}
+
+/// Like `parse_https_url`, for URLs members and organizations give us, which are refused
+/// when they plainly point at the server's own network. Post to them with `public_client`,
+/// which checks the addresses they resolve to as well.
+pub fn parse_public_url(url: &str) -> anyhow::Result<Uri> {
+    let uri = parse_https_url(url)?;
+    destination::check_host(&uri)?;
+
+    Ok(uri)
+}
+
+/// Posts `body` as JSON. Most client errors mean the request will never be accepted, while
+/// timeouts, rate limits and server errors are worth trying again.
+async fn post_json<C>(
+    client: &Client<C>,
+    uri: Uri,
+    bearer_token: Option<&str>,
+    body: Vec<u8>,
+) -> Result<()>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(uri.clone())
+        .header("content-type", "application/json");
+    if let Some(token) = bearer_token {
+        request = request.header("authorization", format!("Bearer {token}"));
+    }
+    let request = request
+        .body(Body::from(body))
+        .context("building notification request")?;
+
+    let response = tokio::time::timeout(TIMEOUT, client.request(request))
+        .await
+        .with_context(|| format!("timed out posting to {uri}"))?
+        .with_context(|| format!("posting to {uri}"))?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    // Only the status is kept, what the server answered could be anything.
+    let message = format!("{uri} responded {status}");
+    if status.is_client_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::TOO_MANY_REQUESTS
+    {
+        Err(DeliveryError::Rejected(message))
+    } else {
+        Err(anyhow::anyhow!(message).into())
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use hyper::client::HttpConnector;
+use hyper::{Client, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+
+use super::{Notification, NotificationChannel, Result};
+
+/// Text messages only carry the subject, they have to be short.
+fn text(notification: &Notification) -> String {
+    format!("{}: {}", notification.from_name, notification.subject)
+}
+
+/// Logs text messages instead of sending them, for development.
+pub struct LogSmsChannel;
+
+#[async_trait]
+impl NotificationChannel for LogSmsChannel {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        tracing::info!(
+            to = %notification.recipient,
+            "text message not sent, no SMS gateway is configured: {}",
+            text(notification)
+        );
+
+        Ok(())
+    }
+}
+
+/// Sends text messages through a provider's HTTP API, which is posted
+/// `{"to": "+15550100", "body": "..."}`.
+pub struct HttpSmsChannel {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+    api_key: Option<String>,
+}
+
+impl HttpSmsChannel {
+    pub fn new(url: &str, api_key: Option<String>) -> anyhow::Result<Self> {
+        let url = super::parse_https_url(url).context("invalid SMS gateway URL")?;
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_only()
+            .enable_http1()
+            .build();
+
+        Ok(Self {
+            client: Client::builder().build(connector),
+            url,
+            api_key,
+        })
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for HttpSmsChannel {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        let body = serde_json::to_vec(&serde_json::json!({
+            "to": notification.recipient,
+            "body": text(notification),
+        }))
+        .context("encoding text message")?;
+
+        super::post_json(
+            &self.client,
+            self.url.clone(),
+            self.api_key.as_deref(),
+            body,
+        )
+        .await
+    }
+}
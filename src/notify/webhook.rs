@@ -0,0 +1,36 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use hyper::Client;
+
+use super::{DeliveryError, Notification, NotificationChannel, PublicConnector, Result};
+
+/// Posts notifications as JSON to the URL the member gave, e.g. a push notification relay
+/// for their phone. Only public addresses are reached.
+pub struct WebhookChannel {
+    client: Client<PublicConnector>,
+}
+
+impl WebhookChannel {
+    pub fn new() -> Self {
+        Self {
+            client: super::public_client(),
+        }
+    }
+}
+
+impl Default for WebhookChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for WebhookChannel {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        let uri = super::parse_public_url(&notification.recipient)
+            .map_err(|e| DeliveryError::Rejected(format!("{e:#}")))?;
+        let body = serde_json::to_vec(notification).context("encoding notification")?;
+
+        super::post_json(&self.client, uri, None, body).await
+    }
+}
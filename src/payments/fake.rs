@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{
+    Charge, ChargeStatus, Customer, NewCharge, NewCustomer, NewRefund, PaymentError,
+    PaymentGateway, PaymentMethod, Refund, Result,
+};
+
+/// Payment method tokens containing this are declined when charged, to exercise failed
+/// payments in development.
+pub const DECLINED_TOKEN: &str = "declined";
+
+/// Payment method tokens containing this leave charges pending, as if the provider were
+/// still processing them.
+pub const PENDING_TOKEN: &str = "pending";
+
+/// An in-memory gateway for development, where every charge goes through unless the payment
+/// method was attached with a token containing `DECLINED_TOKEN` or `PENDING_TOKEN`. Nothing
+/// survives a restart.
+#[derive(Default)]
+pub struct FakeGateway {
+    state: Mutex<FakeState>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    /// Customer id to its payment methods, the first being the default.
+    customers: HashMap<String, Vec<PaymentMethod>>,
+    declined_payment_methods: Vec<String>,
+    pending_payment_methods: Vec<String>,
+    charges: HashMap<String, Charge>,
+    /// What has been refunded of each charge.
+    refunded: HashMap<String, i64>,
+    /// Idempotency key to the id of the charge or refund it created.
+    idempotency_keys: HashMap<String, String>,
+    refunds: HashMap<String, Refund>,
+}
+
+impl FakeGateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn new_id(prefix: &str) -> String {
+    format!("{prefix}_fake_{}", uuid::Uuid::new_v4().simple())
+}
+
+#[async_trait]
+impl PaymentGateway for FakeGateway {
+    async fn create_customer(&self, _customer: NewCustomer) -> Result<Customer> {
+        let id = new_id("cus");
+        let mut state = self.state.lock().expect("fake gateway lock poisoned");
+        state.customers.insert(id.clone(), Vec::new());
+
+        Ok(Customer { id })
+    }
+
+    async fn attach_payment_method(&self, customer_id: &str, token: &str) -> Result<PaymentMethod> {
+        let mut state = self.state.lock().expect("fake gateway lock poisoned");
+
+        let payment_method = PaymentMethod {
+            id: new_id("pm"),
+            customer_id: customer_id.to_string(),
+        };
+        if token.contains(DECLINED_TOKEN) {
+            state
+                .declined_payment_methods
+                .push(payment_method.id.clone());
+        } else if token.contains(PENDING_TOKEN) {
+            state
+                .pending_payment_methods
+                .push(payment_method.id.clone());
+        }
+        state
+            .customers
+            .get_mut(customer_id)
+            .ok_or_else(|| PaymentError::Rejected(format!("no such customer: {customer_id}")))?
+            .push(payment_method.clone());
+
+        Ok(payment_method)
+    }
+
+    async fn charge(&self, charge: NewCharge) -> Result<Charge> {
+        let mut state = self.state.lock().expect("fake gateway lock poisoned");
+
+        if let Some(id) = state.idempotency_keys.get(&charge.idempotency_key) {
+            return Ok(state.charges[id].clone());
+        }
+        if charge.amount <= 0 {
+            return Err(PaymentError::Rejected(
+                "amount must be greater than zero".to_string(),
+            ));
+        }
+
+        let payment_methods = state.customers.get(&charge.customer_id).ok_or_else(|| {
+            PaymentError::Rejected(format!("no such customer: {}", charge.customer_id))
+        })?;
+        let payment_method = match &charge.payment_method_id {
+            Some(id) => payment_methods.iter().find(|pm| &pm.id == id),
+            None => payment_methods.first(),
+        };
+
+        let (status, failure_message) = match payment_method {
+            None => (
+                ChargeStatus::Failed,
+                Some("no payment method on file".to_string()),
+            ),
+            Some(pm) if state.declined_payment_methods.contains(&pm.id) => (
+                ChargeStatus::Failed,
+                Some("your card was declined".to_string()),
+            ),
+            Some(pm) if state.pending_payment_methods.contains(&pm.id) => {
+                (ChargeStatus::Pending, None)
+            }
+            Some(_) => (ChargeStatus::Succeeded, None),
+        };
+
+        let created = Charge {
+            id: new_id("ch"),
+            status,
+            amount: charge.amount,
+            currency: charge.currency,
+            failure_message,
+        };
+        state
+            .idempotency_keys
+            .insert(charge.idempotency_key, created.id.clone());
+        state.charges.insert(created.id.clone(), created.clone());
+
+        Ok(created)
+    }
+
+    async fn refund(&self, refund: NewRefund) -> Result<Refund> {
+        let mut state = self.state.lock().expect("fake gateway lock poisoned");
+
+        if let Some(id) = state.idempotency_keys.get(&refund.idempotency_key) {
+            return Ok(state.refunds[id].clone());
+        }
+
+        let charge = state
+            .charges
+            .get(&refund.charge_id)
+            .filter(|c| c.status == ChargeStatus::Succeeded)
+            .ok_or_else(|| {
+                PaymentError::Rejected(format!("no successful charge: {}", refund.charge_id))
+            })?;
+        let refunded = state.refunded.get(&charge.id).copied().unwrap_or(0);
+        let amount = refund.amount.unwrap_or(charge.amount - refunded);
+        if amount <= 0 || refunded + amount > charge.amount {
+            return Err(PaymentError::Rejected(format!(
+                "can't refund {amount} of a charge with {} left",
+                charge.amount - refunded
+            )));
+        }
+
+        let created = Refund {
+            id: new_id("re"),
+            charge_id: refund.charge_id,
+            amount,
+        };
+        state
+            .refunded
+            .insert(created.charge_id.clone(), refunded + amount);
+        state
+            .idempotency_keys
+            .insert(refund.idempotency_key, created.id.clone());
+        state.refunds.insert(created.id.clone(), created.clone());
+
+        Ok(created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::dunning::PaymentAttempt;
+
+    /// Charges a new customer whose payment method was attached with `token`.
+    async fn charge_with(gateway: &FakeGateway, token: &str) -> Charge {
+        let customer = gateway
+            .create_customer(NewCustomer {
+                membership_id: uuid::Uuid::new_v4(),
+                name: "Ada".to_string(),
+                email: "ada@example.com".to_string(),
+            })
+            .await
+            .unwrap();
+        gateway
+            .attach_payment_method(&customer.id, token)
+            .await
+            .unwrap();
+
+        gateway
+            .charge(NewCharge {
+                invoice_id: uuid::Uuid::new_v4(),
+                customer_id: customer.id,
+                payment_method_id: None,
+                amount: 2500,
+                currency: "USD".to_string(),
+                description: "Invoice #1".to_string(),
+                idempotency_key: format!("test-{token}"),
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn charge_succeeds() {
+        let charge = charge_with(&FakeGateway::new(), "tok_visa").await;
+
+        assert_eq!(charge.status, ChargeStatus::Succeeded);
+        assert!(matches!(
+            PaymentAttempt::from_charge(Ok(charge)),
+            Ok(PaymentAttempt::Succeeded { charge_id: Some(_) })
+        ));
+    }
+
+    #[tokio::test]
+    async fn charge_is_declined() {
+        let charge = charge_with(&FakeGateway::new(), "tok_declined").await;
+
+        assert_eq!(charge.status, ChargeStatus::Failed);
+        match PaymentAttempt::from_charge(Ok(charge)) {
+            Ok(PaymentAttempt::Failed { charge_id, message }) => {
+                assert!(charge_id.is_some());
+                assert_eq!(message, "your card was declined");
+            }
+            _ => panic!("expected a failed payment attempt"),
+        }
+    }
+
+    #[tokio::test]
+    async fn charge_is_pending() {
+        let charge = charge_with(&FakeGateway::new(), "tok_pending").await;
+
+        assert_eq!(charge.status, ChargeStatus::Pending);
+        assert!(matches!(
+            PaymentAttempt::from_charge(Ok(charge)),
+            Ok(PaymentAttempt::Pending { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn charge_is_idempotent() {
+        let gateway = FakeGateway::new();
+        let first = charge_with(&gateway, "tok_visa").await;
+        let again = charge_with(&gateway, "tok_visa").await;
+
+        assert_eq!(first.id, again.id);
+    }
+}
@@ -0,0 +1,137 @@
+//! Charging members through an external payment provider.
+//!
+//! Handlers and models only ever see `DynPaymentGateway`, so which provider is used is decided
+//! once, from `Config`, when the server starts.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::config::{Config, PaymentGatewayKind};
+
+mod fake;
+mod stripe;
+pub mod webhook;
+
+pub use fake::FakeGateway;
+pub use stripe::StripeGateway;
+
+pub type DynPaymentGateway = Arc<dyn PaymentGateway + Send + Sync>;
+
+/// A payment provider. Ids are the provider's own, we only store and pass them back.
+#[async_trait]
+pub trait PaymentGateway {
+    async fn create_customer(&self, customer: NewCustomer) -> Result<Customer>;
+
+    /// Saves the payment method behind a token collected by the provider's client-side
+    /// library, so the customer can be charged later without being present.
+    async fn attach_payment_method(&self, customer_id: &str, token: &str) -> Result<PaymentMethod>;
+
+    /// Declined payments are a `ChargeStatus::Failed` charge rather than an error, since
+    /// callers have to handle them as part of billing.
+    async fn charge(&self, charge: NewCharge) -> Result<Charge>;
+
+    /// Refunds all of a charge or, with `amount`, part of it.
+    async fn refund(&self, refund: NewRefund) -> Result<Refund>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PaymentError {
+    /// The provider refused the request itself, e.g. an unknown customer or a refund larger
+    /// than what's left of the charge.
+    #[error("payment provider rejected the request: {0}")]
+    Rejected(String),
+
+    /// The provider couldn't be reached or failed on its side, the request may be retried
+    /// with the same idempotency key.
+    #[error("payment provider unavailable")]
+    Unavailable(#[from] anyhow::Error),
+}
+
+pub type Result<T, E = PaymentError> = std::result::Result<T, E>;
+
+impl From<PaymentError> for crate::http::Error {
+    fn from(e: PaymentError) -> Self {
+        match e {
+            PaymentError::Rejected(message) => Self::unprocessable_entity([("payment", message)]),
+            PaymentError::Unavailable(e) => Self::Anyhow(e),
+        }
+    }
+}
+
+pub struct NewCustomer {
+    pub membership_id: Uuid,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Customer {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PaymentMethod {
+    pub id: String,
+    pub customer_id: String,
+}
+
+/// Amounts are in minor units of `currency`, like everywhere else.
+pub struct NewCharge {
+    /// The invoice being paid, sent along so the provider's webhooks can refer back to it.
+    pub invoice_id: Uuid,
+    pub customer_id: String,
+    /// The customer's default payment method is used when this is `None`.
+    pub payment_method_id: Option<String>,
+    pub amount: i64,
+    pub currency: String,
+    pub description: String,
+    /// Sending the same key again returns the first charge instead of charging twice,
+    /// e.g. the invoice id.
+    pub idempotency_key: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeStatus {
+    Succeeded,
+    /// Still being processed, the outcome arrives later by webhook.
+    Pending,
+    Failed,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Charge {
+    pub id: String,
+    pub status: ChargeStatus,
+    pub amount: i64,
+    pub currency: String,
+    pub failure_message: Option<String>,
+}
+
+pub struct NewRefund {
+    pub charge_id: String,
+    pub amount: Option<i64>,
+    pub idempotency_key: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Refund {
+    pub id: String,
+    pub charge_id: String,
+    pub amount: i64,
+}
+
+/// The gateway `config` asks for.
+pub fn from_config(config: &Config) -> anyhow::Result<DynPaymentGateway> {
+    Ok(match config.payment_gateway {
+        PaymentGatewayKind::Fake => Arc::new(FakeGateway::new()) as DynPaymentGateway,
+        PaymentGatewayKind::Stripe => {
+            let api_key = config.stripe_api_key.clone().ok_or_else(|| {
+                anyhow::anyhow!("STRIPE_API_KEY must be set to use the stripe payment gateway")
+            })?;
+            Arc::new(StripeGateway::new(&config.stripe_api_url, api_key)?) as DynPaymentGateway
+        }
+    })
+}
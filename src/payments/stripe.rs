@@ -0,0 +1,339 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::de::DeserializeOwned;
+
+use super::{
+    Charge, ChargeStatus, Customer, NewCharge, NewCustomer, NewRefund, PaymentError,
+    PaymentGateway, PaymentMethod, Refund, Result,
+};
+
+/// Talks to an API compatible with Stripe's, e.g. `https://api.stripe.com` or `stripe-mock`
+/// over plain `http` in development.
+pub struct StripeGateway {
+    client: Client<HttpsConnector<HttpConnector>>,
+    base_url: String,
+    api_key: String,
+}
+
+#[derive(serde::Deserialize)]
+struct StripeObject {
+    id: String,
+}
+
+#[derive(serde::Deserialize)]
+struct StripeCustomer {
+    invoice_settings: StripeInvoiceSettings,
+}
+
+#[derive(serde::Deserialize)]
+struct StripeInvoiceSettings {
+    default_payment_method: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct StripePaymentIntent {
+    id: String,
+    status: String,
+    amount: i64,
+    currency: String,
+    last_payment_error: Option<StripeErrorDetail>,
+}
+
+#[derive(serde::Deserialize)]
+struct StripeRefund {
+    id: String,
+    amount: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct StripeErrorBody {
+    error: StripeErrorDetail,
+}
+
+#[derive(serde::Deserialize)]
+struct StripeErrorDetail {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    message: Option<String>,
+    payment_intent: Option<Box<StripePaymentIntent>>,
+}
+
+impl StripeGateway {
+    pub fn new(base_url: &str, api_key: String) -> anyhow::Result<Self> {
+        let uri: Uri = base_url
+            .parse()
+            .with_context(|| format!("invalid payment gateway URL {base_url:?}"))?;
+        anyhow::ensure!(
+            matches!(uri.scheme_str(), Some("https" | "http")),
+            "payment gateway URL {base_url:?} must use https or http"
+        );
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Ok(Self {
+            client: Client::builder().build(connector),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        })
+    }
+
+    /// Sends a form encoded request, returning the status and raw body of any response.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        form: &[(&str, &str)],
+        idempotency_key: Option<&str>,
+    ) -> Result<(StatusCode, hyper::body::Bytes)> {
+        let body = serde_urlencoded::to_string(form).context("encoding payment request")?;
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}{path}", self.base_url))
+            .header("authorization", format!("Bearer {}", self.api_key))
+            .header("content-type", "application/x-www-form-urlencoded");
+        if let Some(key) = idempotency_key {
+            request = request.header("idempotency-key", key);
+        }
+        let request = request
+            .body(Body::from(body))
+            .context("building payment request")?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .with_context(|| format!("sending payment request to {path}"))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .with_context(|| format!("reading payment response from {path}"))?;
+
+        Ok((status, body))
+    }
+
+    /// Like `send`, but errors unless the request succeeded.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        form: &[(&str, &str)],
+        idempotency_key: Option<&str>,
+    ) -> Result<T> {
+        let (status, body) = self.send(method, path, form, idempotency_key).await?;
+        if !status.is_success() {
+            return Err(error_from_response(path, status, &body));
+        }
+
+        parse(path, &body)
+    }
+}
+
+fn parse<T: DeserializeOwned>(path: &str, body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body)
+        .with_context(|| format!("unexpected payment response from {path}"))
+        .map_err(PaymentError::Unavailable)
+}
+
+fn error_from_response(path: &str, status: StatusCode, body: &[u8]) -> PaymentError {
+    let message = serde_json::from_slice::<StripeErrorBody>(body)
+        .ok()
+        .and_then(|b| b.error.message)
+        .unwrap_or_else(|| status.to_string());
+
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        PaymentError::Unavailable(anyhow::anyhow!(
+            "payment request to {path} failed with {status}: {message}"
+        ))
+    } else {
+        PaymentError::Rejected(message)
+    }
+}
+
+fn charge_from_intent(intent: StripePaymentIntent) -> Charge {
+    let status = match intent.status.as_str() {
+        "succeeded" => ChargeStatus::Succeeded,
+        "processing" => ChargeStatus::Pending,
+        // Anything else needs the customer, who isn't there for an off session charge.
+        _ => ChargeStatus::Failed,
+    };
+    let failure_message = match status {
+        ChargeStatus::Failed => Some(
+            intent
+                .last_payment_error
+                .and_then(|e| e.message)
+                .unwrap_or_else(|| format!("payment {}", intent.status)),
+        ),
+        _ => None,
+    };
+
+    Charge {
+        id: intent.id,
+        status,
+        amount: intent.amount,
+        currency: intent.currency.to_uppercase(),
+        failure_message,
+    }
+}
+
+#[async_trait]
+impl PaymentGateway for StripeGateway {
+    async fn create_customer(&self, customer: NewCustomer) -> Result<Customer> {
+        let membership_id = customer.membership_id.to_string();
+        let created: StripeObject = self
+            .call(
+                Method::POST,
+                "/v1/customers",
+                &[
+                    ("name", &customer.name),
+                    ("email", &customer.email),
+                    ("metadata[membership_id]", &membership_id),
+                ],
+                Some(&format!("customer-{membership_id}")),
+            )
+            .await?;
+
+        Ok(Customer { id: created.id })
+    }
+
+    async fn attach_payment_method(&self, customer_id: &str, token: &str) -> Result<PaymentMethod> {
+        let attached: StripeObject = self
+            .call(
+                Method::POST,
+                &format!("/v1/payment_methods/{token}/attach"),
+                &[("customer", customer_id)],
+                None,
+            )
+            .await?;
+
+        // Renewals charge the default payment method, so the newest one becomes it.
+        let _: StripeObject = self
+            .call(
+                Method::POST,
+                &format!("/v1/customers/{customer_id}"),
+                &[("invoice_settings[default_payment_method]", &attached.id)],
+                None,
+            )
+            .await?;
+
+        Ok(PaymentMethod {
+            id: attached.id,
+            customer_id: customer_id.to_string(),
+        })
+    }
+
+    async fn charge(&self, charge: NewCharge) -> Result<Charge> {
+        let payment_method_id = match charge.payment_method_id {
+            Some(id) => id,
+            None => {
+                let customer: StripeCustomer = self
+                    .call(
+                        Method::GET,
+                        &format!("/v1/customers/{}", charge.customer_id),
+                        &[],
+                        None,
+                    )
+                    .await?;
+                match customer.invoice_settings.default_payment_method {
+                    Some(id) => id,
+                    None => {
+                        return Ok(Charge {
+                            id: String::new(),
+                            status: ChargeStatus::Failed,
+                            amount: charge.amount,
+                            currency: charge.currency,
+                            failure_message: Some("no payment method on file".to_string()),
+                        })
+                    }
+                }
+            }
+        };
+
+        let path = "/v1/payment_intents";
+        let amount = charge.amount.to_string();
+        let currency = charge.currency.to_lowercase();
+        let invoice_id = charge.invoice_id.to_string();
+        let (status, body) = self
+            .send(
+                Method::POST,
+                path,
+                &[
+                    ("amount", &amount),
+                    ("currency", &currency),
+                    ("customer", &charge.customer_id),
+                    ("payment_method", &payment_method_id),
+                    ("description", &charge.description),
+                    ("metadata[invoice_id]", &invoice_id),
+                    ("confirm", "true"),
+                    ("off_session", "true"),
+                ],
+                Some(&charge.idempotency_key),
+            )
+            .await?;
+
+        if status.is_success() {
+            return Ok(charge_from_intent(parse(path, &body)?));
+        }
+
+        // Declines come back as a card error carrying the failed payment intent.
+        match serde_json::from_slice::<StripeErrorBody>(&body) {
+            Ok(StripeErrorBody {
+                error:
+                    StripeErrorDetail {
+                        kind: Some(kind),
+                        message,
+                        payment_intent,
+                    },
+            }) if kind == "card_error" => Ok(Charge {
+                id: payment_intent.map(|i| i.id).unwrap_or_default(),
+                status: ChargeStatus::Failed,
+                amount: charge.amount,
+                currency: charge.currency,
+                failure_message: Some(message.unwrap_or_else(|| "card declined".to_string())),
+            }),
+            _ => Err(error_from_response(path, status, &body)),
+        }
+    }
+
+    async fn refund(&self, refund: NewRefund) -> Result<Refund> {
+        let amount = refund.amount.map(|a| a.to_string());
+        let mut form = vec![("payment_intent", refund.charge_id.as_str())];
+        if let Some(amount) = &amount {
+            form.push(("amount", amount));
+        }
+
+        let created: StripeRefund = self
+            .call(
+                Method::POST,
+                "/v1/refunds",
+                &form,
+                Some(&refund.idempotency_key),
+            )
+            .await?;
+
+        Ok(Refund {
+            id: created.id,
+            charge_id: refund.charge_id,
+            amount: created.amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_url_may_use_https_or_http() {
+        assert!(StripeGateway::new("https://api.stripe.com", "sk_test".to_string()).is_ok());
+        assert!(StripeGateway::new("http://localhost:12111", "sk_test".to_string()).is_ok());
+        assert!(StripeGateway::new("ftp://api.stripe.com", "sk_test".to_string()).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Verifying and reading the events payment providers send to our webhook.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// How old a signed event may be, so a captured request can't be replayed later.
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 5 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum WebhookProvider {
+    Fake,
+    Stripe,
+}
+
+impl WebhookProvider {
+    /// The provider named in the webhook's path.
+    pub fn from_path(provider: &str) -> Option<Self> {
+        match provider {
+            "fake" => Some(Self::Fake),
+            "stripe" => Some(Self::Stripe),
+            _ => None,
+        }
+    }
+
+    /// The header carrying the signature, `t=<unix time>,v1=<hex HMAC-SHA256 of "t.body">`.
+    pub fn signature_header(self) -> &'static str {
+        match self {
+            Self::Fake => "fake-signature",
+            Self::Stripe => "stripe-signature",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    PaymentSucceeded,
+    PaymentFailed,
+    /// Events we don't act on, they are still recorded.
+    Other,
+}
+
+/// An event in the shape both providers send, the fake one mirroring Stripe's.
+#[derive(Debug)]
+pub struct WebhookEvent {
+    pub id: String,
+    /// The provider's own name for the event, e.g. `payment_intent.succeeded`.
+    pub event_type: String,
+    pub kind: WebhookEventKind,
+    /// The charge the event is about.
+    pub charge_id: Option<String>,
+    /// From the charge's metadata, see `NewCharge::invoice_id`.
+    pub invoice_id: Option<Uuid>,
+    pub failure_message: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct EventBody {
+    id: String,
+    #[serde(rename = "type")]
+    event_type: String,
+    data: EventData,
+}
+
+#[derive(serde::Deserialize)]
+struct EventData {
+    object: EventObject,
+}
+
+#[derive(serde::Deserialize)]
+struct EventObject {
+    id: Option<String>,
+    #[serde(default)]
+    metadata: EventMetadata,
+    last_payment_error: Option<EventError>,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct EventMetadata {
+    invoice_id: Option<Uuid>,
+}
+
+#[derive(serde::Deserialize)]
+struct EventError {
+    message: Option<String>,
+}
+
+/// Checks `signature` is the provider's signature of `body` made with `secret`, and recent.
+pub fn verify_signature(signature: &str, body: &[u8], secret: &str, now: OffsetDateTime) -> bool {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in signature.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", v1)) => signatures.extend(hex::decode(v1).ok()),
+            _ => (),
+        }
+    }
+
+    let Some(timestamp) = timestamp else {
+        return false;
+    };
+    if now.unix_timestamp().abs_diff(timestamp) > SIGNATURE_TOLERANCE_SECONDS as u64 {
+        return false;
+    }
+
+    // Providers send more than one signature while rotating secrets, any may match.
+    signatures.iter().any(|expected| {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac.verify_slice(expected).is_ok()
+    })
+}
+
+/// Reads a verified event, `None` if it isn't one we can make sense of.
+pub fn parse_event(body: &[u8]) -> Option<WebhookEvent> {
+    let event: EventBody = serde_json::from_slice(body).ok()?;
+
+    let kind = match event.event_type.as_str() {
+        "payment_intent.succeeded" => WebhookEventKind::PaymentSucceeded,
+        "payment_intent.payment_failed" => WebhookEventKind::PaymentFailed,
+        _ => WebhookEventKind::Other,
+    };
+    let object = event.data.object;
+
+    Some(WebhookEvent {
+        id: event.id,
+        event_type: event.event_type,
+        kind,
+        charge_id: object.id,
+        invoice_id: object.metadata.invoice_id,
+        failure_message: object.last_payment_error.and_then(|e| e.message),
+    })
+}